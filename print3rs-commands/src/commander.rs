@@ -1,30 +1,110 @@
 use {
     crate::{
         commands::{
+            complete,
             connect::{self, Connection},
-            help, macros, version, Command,
+            dryrun, help,
+            log::{value_columns, Segment},
+            macros, parse_command, settings, status, unquote,
+            validate::{self, Validation, ValidationSettings},
+            variables, version, waitfor, Command,
         },
         response::Response,
         tasks::{
-            send_gcodes, start_logging, start_print_file, start_repeat, BackgroundTask, Tasks,
+            is_gzip, send_gcodes, start_echo_sent, start_logging, start_print_file, start_repeat,
+            start_transcript, BackgroundTask, PauseHandle, PrintScripts, Tasks, STOP_TIMEOUT,
         },
     },
-    print3rs_core::Printer,
-    std::sync::Arc,
-    tokio::{io::BufReader, net::TcpStream},
-    tokio_serial::SerialPortBuilderExt,
+    print3rs_core::{Firmware, Printer, PrinterInfo, Protocol},
+    std::{
+        collections::{HashMap, VecDeque},
+        path::PathBuf,
+        sync::Arc,
+    },
+    tokio::{
+        io::BufReader,
+        net::TcpStream,
+        sync::oneshot::{self, error::TryRecvError},
+        task::AbortHandle,
+    },
+    winnow::Parser,
 };
 
+/// Use the given macro file, or the default one if none was given
+fn macro_file_path(path: Option<&str>) -> Result<PathBuf, macros::MacroFileError> {
+    path.map(PathBuf::from)
+        .or_else(macros::Macros::default_path)
+        .ok_or(macros::MacroFileError::NoConfigDir)
+}
+
+/// How many sourced files can source other files before giving up, in case one sources itself
+const MAX_SOURCE_DEPTH: usize = 16;
+
 type CommandReceiver = tokio::sync::mpsc::Receiver<Command<String>>;
 type ResponseSender = tokio::sync::broadcast::Sender<Response>;
 type ResponseReceiver = tokio::sync::broadcast::Receiver<Response>;
 
+/// Name of the printer session used until another is selected
+pub const DEFAULT_SESSION: &str = "default";
+
+/// A printer which isn't currently selected, along with its own background tasks
+#[derive(Debug, Default)]
+struct Session {
+    printer: Printer,
+    printer_info: PrinterInfo,
+    tasks: Tasks,
+}
+
+/// Macros sent before and after every printed file, if they've been made
+const START_MACRO: &str = "start";
+const END_MACRO: &str = "end";
+
+/// Name of the task recording a transcript, so starting another replaces it
+const TRANSCRIPT_TASK: &str = "transcript";
+
+/// Name of the task showing sent lines, so `echosent off` can stop it
+const ECHO_SENT_TASK: &str = "echosent";
+
+/// Name `stop` takes to give up on a `waitfor`, and `tasks` lists it under
+const WAITFOR_TASK: &str = "waitfor";
+
+/// A `waitfor` in progress, along with the commands held back until it's over
+#[derive(Debug)]
+struct Waiting {
+    result: oneshot::Receiver<Result<Arc<str>, ErrorKindOf>>,
+    abort_handle: AbortHandle,
+    queued: VecDeque<Command<String>>,
+}
+
+/// A `baud` switch in progress, whose rate is only recorded once it has worked
+#[derive(Debug)]
+struct BaudSwitch {
+    rate: u32,
+    switched: oneshot::Receiver<bool>,
+}
+
+/// Gcodes kept by `record`, made into a macro by `endrecord`
+#[derive(Debug)]
+struct Recording {
+    name: String,
+    codes: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct Commander {
     printer: Printer,
+    printer_info: PrinterInfo,
     pub tasks: Tasks,
     pub macros: macros::Macros,
+    pub variables: variables::Variables,
     responder: ResponseSender,
+    source_depth: usize,
+    selected: String,
+    sessions: HashMap<String, Session>,
+    waiting: Option<Waiting>,
+    recording: Option<Recording>,
+    validation: ValidationSettings,
+    baud_switch: Option<BaudSwitch>,
 }
 #[derive(Debug, Clone)]
 pub struct ErrorKindOf(pub String);
@@ -49,9 +129,18 @@ impl Commander {
         let (responder, _) = tokio::sync::broadcast::channel(32);
         Self {
             printer: Default::default(),
+            printer_info: Default::default(),
             responder,
             tasks: Default::default(),
             macros: Default::default(),
+            variables: Default::default(),
+            source_depth: 0,
+            selected: DEFAULT_SESSION.to_string(),
+            sessions: Default::default(),
+            waiting: None,
+            recording: None,
+            validation: Default::default(),
+            baud_switch: None,
         }
     }
 
@@ -60,25 +149,107 @@ impl Commander {
     }
 
     pub fn set_printer(&mut self, printer: Printer) {
+        self.set_printer_with_info(printer, PrinterInfo::default());
+    }
+
+    /// Use a printer along with what is already known about it, such as from `auto_connect`
+    pub fn set_printer_with_info(&mut self, printer: Printer, info: PrinterInfo) {
         self.tasks.clear();
+        self.baud_switch = None;
         self.printer = printer;
+        self.printer_info = info;
+    }
+
+    pub fn printer_info(&self) -> &PrinterInfo {
+        &self.printer_info
+    }
+
+    /// Name of the printer session commands currently go to
+    pub fn selected(&self) -> &str {
+        &self.selected
+    }
+
+    /// Use a printer for the named session, which doesn't need to be the selected one.
+    ///
+    /// Frontends should call this with the session given in `Response::AutoConnect`.
+    pub fn set_session_printer(&mut self, name: &str, printer: Printer, info: PrinterInfo) {
+        if name == self.selected {
+            self.set_printer_with_info(printer, info);
+        } else {
+            let session = self.sessions.entry(name.to_string()).or_default();
+            session.tasks.clear();
+            session.printer = printer;
+            session.printer_info = info;
+        }
+    }
+
+    /// Make the named session the target of commands, starting a disconnected one if it's new.
+    ///
+    /// The previously selected printer keeps running its tasks in the background.
+    fn select(&mut self, name: &str) {
+        if name == self.selected {
+            return;
+        }
+        // a switch still going is for the printer being put aside
+        self.baud_switch = None;
+        let next = self.sessions.remove(name).unwrap_or_default();
+        let previous = Session {
+            printer: std::mem::replace(&mut self.printer, next.printer),
+            printer_info: std::mem::replace(&mut self.printer_info, next.printer_info),
+            tasks: std::mem::replace(&mut self.tasks, next.tasks),
+        };
+        let previous_name = std::mem::replace(&mut self.selected, name.to_string());
+        self.sessions.insert(previous_name, previous);
     }
 
     pub fn subscribe_responses(&self) -> ResponseReceiver {
         self.responder.subscribe()
     }
 
+    /// Completions for the end of a partly typed console line, using the current macros and tasks.
+    ///
+    /// See `commands::complete::complete`
+    pub fn completions(&self, line: &str) -> (usize, Vec<String>) {
+        complete::complete(
+            line,
+            self.macros.iter().map(|(name, _)| name.as_str()),
+            self.tasks.keys().map(String::as_str),
+        )
+    }
+
     fn forward_broadcast(
         mut in_channel: tokio::sync::broadcast::Receiver<Arc<str>>,
         out_channel: tokio::sync::broadcast::Sender<Response>,
     ) {
         tokio::spawn(async move {
             while let Ok(in_message) = in_channel.recv().await {
+                // make sure errors which stop the printer aren't lost among the other output
+                let response = print3rs_core::classify(in_message.as_bytes());
                 out_channel.send(Response::Output(in_message)).unwrap();
+                if let print3rs_core::Response::Error(ref message) = response {
+                    if response.is_fatal() {
+                        let _ = out_channel.send(Response::Alert(
+                            format!("Printer reported an error: {message}\n").into(),
+                        ));
+                    }
+                }
             }
         });
     }
 
+    /// Get the printer's line number in step once it's ready, without holding up commands.
+    /// Firmware that doesn't understand `M110` just won't answer, so only warn if it doesn't.
+    fn resync_in_background(printer: &Printer) {
+        if let Ok(socket) = printer.socket() {
+            let socket = socket.clone();
+            tokio::spawn(async move {
+                if let Err(e) = socket.resync(print3rs_core::DEFAULT_QUERY_TIMEOUT).await {
+                    tracing::warn!("Couldn't reset printer line number: {e}");
+                }
+            });
+        }
+    }
+
     fn add_printer_output_to_responses(&self) {
         if let Ok(print_messages) = self.printer.subscribe_lines() {
             let responder = self.responder.clone();
@@ -86,6 +257,112 @@ impl Commander {
         }
     }
 
+    /// A line for each background task, and any `waitfor` in progress, as listed by `tasks`
+    fn task_list(&self) -> String {
+        let mut list = String::new();
+        for (
+            name,
+            BackgroundTask {
+                description,
+                pause,
+                progress,
+                ..
+            },
+        ) in self.tasks.iter()
+        {
+            let status = if pause.as_ref().is_some_and(PauseHandle::is_paused) {
+                "\tpaused"
+            } else {
+                ""
+            };
+            let progress = match progress {
+                Some(progress) => {
+                    let progress = *progress.borrow();
+                    format!(
+                        "\t{}/{} lines ({:.1}%) in {}s",
+                        progress.line,
+                        progress.total,
+                        progress.percent(),
+                        progress.elapsed.as_secs()
+                    )
+                }
+                None => String::new(),
+            };
+            list.push_str(&format!("{name}\t{description}{progress}{status}\n"));
+        }
+        if let Some(waiting) = &self.waiting {
+            list.push_str(&format!(
+                "{WAITFOR_TASK}\twait\t{} commands held back\n",
+                waiting.queued.len()
+            ));
+        }
+        list
+    }
+
+    /// Gcodes of the named macro with any macros in it expanded, or none if it doesn't exist
+    fn macro_script(&self, name: &str) -> Result<Vec<String>, ErrorKindOf> {
+        match self.macros.get(name) {
+            Some(_) => Ok(self.variables.substitute_all(self.macros.expand([name])?)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn pause_handle(&self, name: &str) -> Result<&PauseHandle, ErrorKindOf> {
+        let task = self
+            .tasks
+            .get(name)
+            .ok_or_else(|| ErrorKindOf(format!("No task named {name}")))?;
+        task.pause
+            .as_ref()
+            .ok_or_else(|| ErrorKindOf(format!("{} tasks can't be paused", task.description)))
+    }
+
+    /// Run the commands a `waitfor` held back, once it's over.
+    ///
+    /// Frontends should call this when they get `Response::WaitOver`.
+    /// If the wait timed out the held back commands are thrown away instead.
+    pub fn finish_waiting(&mut self) {
+        let Some(mut waiting) = self.waiting.take() else {
+            return;
+        };
+        match waiting.result.try_recv() {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                let _ = self.responder.send(Response::Error(ErrorKindOf(format!(
+                    "{}, skipped {} waiting commands",
+                    e.0,
+                    waiting.queued.len()
+                ))));
+                return;
+            }
+            Err(TryRecvError::Empty) => {
+                self.waiting = Some(waiting);
+                return;
+            }
+            // stopped, which already threw the commands away
+            Err(TryRecvError::Closed) => return,
+        }
+        // another waitfor among these holds back the rest again
+        for command in waiting.queued {
+            if let Err(e) = self.dispatch(&command) {
+                let _ = self.responder.send(Response::Error(e));
+            }
+        }
+    }
+
+    /// Record the rate of a finished `baud` switch, if it worked
+    fn finish_baud_switch(&mut self) {
+        let Some(BaudSwitch { rate, switched }) = self.baud_switch.as_mut() else {
+            return;
+        };
+        match switched.try_recv() {
+            Ok(true) => self.printer_info.baud = Some(*rate),
+            Err(TryRecvError::Empty) => return,
+            Ok(false) | Err(TryRecvError::Closed) => {}
+        }
+        self.baud_switch = None;
+    }
+
     pub fn background(mut self, mut commands: CommandReceiver) -> tokio::task::JoinHandle<()> {
         tokio::spawn(async move {
             loop {
@@ -98,12 +375,82 @@ impl Commander {
             }
         })
     }
+    /// Run each line of a file of commands as if it was typed,
+    /// stopping at the first failing line if `strict`, otherwise reporting it and carrying on
+    fn source_lines(
+        &mut self,
+        filename: &str,
+        contents: &str,
+        strict: bool,
+    ) -> Result<(), ErrorKindOf> {
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = match parse_command.parse(line) {
+                Ok(command) => self.dispatch(command),
+                Err(_) => Err(ErrorKindOf::from("invalid command")),
+            };
+            if let Err(e) = result {
+                let e = ErrorKindOf(format!("{filename} line {}: {}", index + 1, e.0));
+                if strict {
+                    return Err(e);
+                }
+                self.responder.send(e.into())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check Gcodes about to be sent as `validate` was set for their protocol,
+    /// warning about problems or erroring if they shouldn't be sent
+    fn validate_gcodes(&self, codes: &[String], protocol: Protocol) -> Result<(), ErrorKindOf> {
+        let validation = self.validation.get(protocol);
+        if validation == Validation::Off {
+            return Ok(());
+        }
+        let problems: Vec<String> = codes
+            .iter()
+            .flat_map(|code| {
+                validate::validate_line(code, protocol)
+                    .into_iter()
+                    .map(move |problem| format!("`{code}` {problem}"))
+            })
+            .collect();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let problems = problems.join("\n");
+        if validation == Validation::Reject {
+            return Err(ErrorKindOf(format!(
+                "Nothing sent, Gcodes have problems:\n{problems}"
+            )));
+        }
+        self.responder
+            .send(format!("Warning, Gcodes have problems:\n{problems}\n").into())?;
+        Ok(())
+    }
+
     pub fn dispatch<'a>(
         &'a mut self,
         command: impl Into<Command<&'a str>>,
     ) -> Result<(), ErrorKindOf> {
         let command = command.into();
+        // tasks like finite repeats end on their own, forget them once they're done
+        self.tasks
+            .retain(|_, task| !task.abort_handle.is_finished());
+        self.finish_baud_switch();
         use Command::*;
+        if let Some(waiting) = self.waiting.as_mut() {
+            match command {
+                Stop(WAITFOR_TASK) | Abort | Tasks | Status | Clear | Quit => {}
+                Help(_) | Version | Comment => {}
+                _ => {
+                    waiting.queued.push_back(command.into_owned());
+                    return Ok(());
+                }
+            }
+        }
         match command {
             Clear => {
                 self.responder.send(Response::Clear)?;
@@ -111,9 +458,96 @@ impl Commander {
             Quit => {
                 self.responder.send(Response::Quit)?;
             }
+            Echo(text) => {
+                self.responder.send(format!("{text}\n").into())?;
+            }
+            Comment => {}
+            Select(name) => {
+                self.select(name);
+            }
+            Printers => {
+                let mut names: Vec<&str> = self
+                    .sessions
+                    .keys()
+                    .map(String::as_str)
+                    .chain([self.selected.as_str()])
+                    .collect();
+                names.sort_unstable();
+                for name in names {
+                    let (printer, tasks) = match self.sessions.get(name) {
+                        Some(session) => (&session.printer, &session.tasks),
+                        None => (&self.printer, &self.tasks),
+                    };
+                    let marker = if name == self.selected { "*" } else { " " };
+                    let status = if printer.is_connected() {
+                        "connected"
+                    } else {
+                        "disconnected"
+                    };
+                    self.responder.send(
+                        format!("{marker} {name}\t{status}\t{} tasks\n", tasks.len()).into(),
+                    )?;
+                }
+            }
+            On(name, command) => {
+                let previous = self.selected.clone();
+                self.select(name);
+                let result = self.dispatch(*command);
+                self.select(&previous);
+                result?;
+            }
+            Source(filename, strict) => {
+                let filename = unquote(filename);
+                if self.source_depth >= MAX_SOURCE_DEPTH {
+                    return Err(ErrorKindOf(format!(
+                        "Can't source {filename}, too many nested sourced files"
+                    )));
+                }
+                let contents = std::fs::read_to_string(&*filename)
+                    .map_err(|e| ErrorKindOf(format!("Can't read {filename}: {e}")))?;
+                self.source_depth += 1;
+                let result = self.source_lines(&filename, &contents, strict);
+                self.source_depth -= 1;
+                result?;
+            }
+            WaitFor(patterns, options) => {
+                let (labels, _) = value_columns(&patterns);
+                if let Some(until) = options
+                    .until
+                    .as_ref()
+                    .filter(|until| !labels.iter().any(|label| label == until.label))
+                {
+                    return Err(ErrorKindOf(format!(
+                        "Can't wait until {until}, {} isn't a value in the pattern",
+                        until.label
+                    )));
+                }
+                let lines = self.printer.subscribe_lines()?;
+                let patterns = patterns
+                    .into_iter()
+                    .map(|pattern| pattern.into_iter().map(Segment::into_owned).collect())
+                    .collect();
+                let options = options.into_owned();
+                let (result_sender, result) = oneshot::channel();
+                let wait_responder = self.responder.clone();
+                let wait_task = tokio::spawn(async move {
+                    let result = waitfor::wait_for(lines, patterns, options).await;
+                    let _ = result_sender.send(result);
+                    let _ = wait_responder.send(Response::WaitOver);
+                });
+                self.waiting = Some(Waiting {
+                    result,
+                    abort_handle: wait_task.abort_handle(),
+                    queued: VecDeque::new(),
+                });
+            }
             Gcodes(codes) => {
                 let socket = self.printer().socket()?.clone();
-                let codes = self.macros.expand(codes);
+                let codes = self.variables.substitute_all(self.macros.expand(codes)?)?;
+                self.validate_gcodes(&codes, socket.protocol())?;
+                if let Some(recording) = self.recording.as_mut() {
+                    recording.codes.extend(codes.iter().cloned());
+                }
                 let task = send_gcodes(socket, codes);
                 static COUNTER: std::sync::atomic::AtomicUsize =
                     std::sync::atomic::AtomicUsize::new(0);
@@ -125,41 +559,151 @@ impl Commander {
                     task,
                 );
             }
-            Print(filename) => {
+            Print(filename, mode) => {
+                let filename = unquote(filename);
                 let socket = self.printer.socket()?.clone();
-                let print = start_print_file(filename, socket);
+                let scripts = PrintScripts {
+                    preamble: self.macro_script(START_MACRO)?,
+                    postamble: self.macro_script(END_MACRO)?,
+                };
+                let print = start_print_file(&filename, socket, mode, scripts, Default::default());
                 self.tasks.insert(filename.to_string(), print);
             }
-            Log(name, pattern) => {
-                let log = start_logging(name, pattern, &self.printer)?;
+            DryRun(filename) => {
+                let filename = unquote(filename).into_owned();
+                let dryrun_responder = self.responder.clone();
+                tokio::spawn(async move {
+                    let max_line_length = dryrun::DEFAULT_MAX_LINE_LENGTH;
+                    let problems = if is_gzip(&filename) {
+                        let gzip_filename = filename.clone();
+                        tokio::task::spawn_blocking(move || {
+                            dryrun::check_gzip_file(&gzip_filename, max_line_length)
+                        })
+                        .await
+                        .unwrap_or_else(|e| Err(e.into()))
+                    } else {
+                        tokio::fs::read_to_string(&filename)
+                            .await
+                            .map(|contents| dryrun::check_file(&contents, max_line_length))
+                    };
+                    let response = match problems {
+                        Ok(problems) => {
+                            Response::Output(dryrun::report(&filename, &problems).into())
+                        }
+                        Err(e) => Response::Error(format!("Can't read {filename}: {e}").into()),
+                    };
+                    let _ = dryrun_responder.send(response);
+                });
+            }
+            Log(name, patterns, options) => {
+                let (labels, _) = value_columns(&patterns);
+                if let Some(alert) = options
+                    .alerts
+                    .iter()
+                    .find(|alert| !labels.iter().any(|label| label == alert.label))
+                {
+                    return Err(ErrorKindOf(format!(
+                        "Can't alert on {}, it isn't a value in the pattern",
+                        alert.label
+                    )));
+                }
+                let log = start_logging(
+                    name,
+                    patterns,
+                    options,
+                    &self.printer,
+                    self.responder.clone(),
+                )?;
                 self.tasks.insert(name.to_string(), log);
             }
-            Repeat(name, gcodes) => {
+            Transcript(filename) => {
+                let filename = unquote(filename);
+                let transcript =
+                    start_transcript(&filename, &self.printer, self.responder.clone())?;
+                self.tasks.insert(TRANSCRIPT_TASK.to_string(), transcript);
+                self.responder
+                    .send(format!("Recording transcript to {filename}\n").into())?;
+            }
+            Repeat(name, gcodes, options) => {
                 let socket = self.printer.socket()?.clone();
-                let gcodes = self.macros.expand(gcodes);
-                let repeat = start_repeat(gcodes, socket);
+                let gcodes = self.variables.substitute_all(self.macros.expand(gcodes)?)?;
+                let repeat = start_repeat(gcodes, socket, options);
                 self.tasks.insert(name.to_string(), repeat);
             }
             Tasks => {
-                for (
-                    name,
-                    BackgroundTask {
-                        description,
-                        abort_handle: _,
-                    },
-                ) in self.tasks.iter()
-                {
-                    self.responder
-                        .send(format!("{name}\t{description}\n").into())?;
+                let tasks = self.task_list();
+                if !tasks.is_empty() {
+                    self.responder.send(tasks.into())?;
+                }
+            }
+            Status => {
+                let Ok(socket) = self.printer.socket() else {
+                    self.responder.send("Disconnected\n".into())?;
+                    return Ok(());
+                };
+                let mut summary = match self.printer_info.connection() {
+                    Some(connection) => format!("Connected to {connection}\n"),
+                    None => "Connected\n".to_string(),
+                };
+                match self.task_list() {
+                    tasks if tasks.is_empty() => summary.push_str("No tasks running\n"),
+                    tasks => {
+                        summary.push_str("Tasks:\n");
+                        summary.push_str(&tasks);
+                    }
+                }
+                let socket = socket.clone();
+                let status_responder = self.responder.clone();
+                tokio::spawn(async move {
+                    summary.push_str(&status::temperature_line(&socket).await);
+                    let _ = status_responder.send(summary.into());
+                });
+            }
+            Stop(WAITFOR_TASK) if self.waiting.is_some() => {
+                if let Some(waiting) = self.waiting.take() {
+                    waiting.abort_handle.abort();
+                    self.responder.send(
+                        format!(
+                            "Stopped waiting, skipped {} waiting commands\n",
+                            waiting.queued.len()
+                        )
+                        .into(),
+                    )?;
                 }
             }
             Stop(name) => {
-                self.tasks.remove(name);
+                // prints finish the line they're on, so it isn't cut off
+                if let Some(task) = self.tasks.remove(name).filter(|task| task.stop.is_some()) {
+                    let name = name.to_owned();
+                    let stop_responder = self.responder.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = task.stop_and_join(STOP_TIMEOUT).await {
+                            let _ = stop_responder
+                                .send(Response::Error(format!("Stopping {name}: {e}").into()));
+                        }
+                    });
+                }
             }
+            Abort => {
+                // stop anything sending lines first, so they don't queue more behind the clear
+                let mut stopped = 0;
+                self.tasks.retain(|_, task| {
+                    let sends_lines = task.sends_lines();
+                    stopped += usize::from(sends_lines);
+                    !sends_lines
+                });
+                self.printer.clear_queue()?;
+                self.responder.send(
+                    format!("Stopped {stopped} tasks sending lines and cleared queued lines\n")
+                        .into(),
+                )?;
+            }
+            Pause(name) => self.pause_handle(name)?.pause(),
+            Resume(name) => self.pause_handle(name)?.resume(),
             Macro(name, commands) => {
-                if self.macros.add(name, commands).is_err() {
+                if let Err(e) = self.macros.add(name, commands) {
                     self.responder
-                        .send("Infinite macro detected! Macro not added.\n".into())?;
+                        .send(format!("Infinite macro detected! Macro not added, {e}\n").into())?;
                 }
             }
             Macros => {
@@ -172,17 +716,93 @@ impl Commander {
             DeleteMacro(name) => {
                 self.macros.remove(name);
             }
-            Connect(connection) => {
+            SaveMacros(path) => {
+                let path = macro_file_path(path.map(unquote).as_deref())?;
+                self.macros.save_to(&path)?;
+                self.responder
+                    .send(format!("Saved macros to {}\n", path.display()).into())?;
+            }
+            LoadMacros(path, replace) => {
+                let path = macro_file_path(path.map(unquote).as_deref())?;
+                self.macros.load_from(&path, replace)?;
+                self.responder
+                    .send(format!("Loaded macros from {}\n", path.display()).into())?;
+            }
+            ExportMacros(path) => {
+                let script = self.macros.to_script();
+                match path {
+                    Some(path) => {
+                        let path = unquote(path);
+                        std::fs::write(&*path, script)
+                            .map_err(|e| ErrorKindOf(format!("Can't write {path}: {e}")))?;
+                        self.responder
+                            .send(format!("Exported macros to {path}\n").into())?;
+                    }
+                    None => {
+                        self.responder.send(script.into())?;
+                    }
+                }
+            }
+            Record(name) => {
+                if let Some(recording) = &self.recording {
+                    return Err(ErrorKindOf(format!(
+                        "Already recording {}, use endrecord first",
+                        recording.name
+                    )));
+                }
+                self.recording = Some(Recording {
+                    name: name.to_owned(),
+                    codes: Vec::new(),
+                });
+                self.responder.send(
+                    format!("Recording Gcodes into macro {name}, endrecord to stop\n").into(),
+                )?;
+            }
+            EndRecord => {
+                let Recording { name, codes } = self
+                    .recording
+                    .take()
+                    .ok_or_else(|| ErrorKindOf::from("Not recording"))?;
+                self.macros
+                    .add(&name, codes.iter().map(String::as_str))
+                    .map_err(|e| ErrorKindOf(format!("Macro {name} not made, {e}")))?;
+                self.responder
+                    .send(format!("Recorded {} Gcodes into macro {name}\n", codes.len()).into())?;
+            }
+            Connect(connection, options) => {
                 self.tasks.clear();
                 match connection {
                     Connection::Auto => {
                         self.tasks.clear();
                         self.responder.send("Connecting...\n".into())?;
                         let autoconnect_responder = self.responder.clone();
+                        let session = self.selected.clone();
                         tokio::spawn(async move {
-                            let printer = connect::auto_connect().await;
+                            let (printer, info) =
+                                connect::auto_connect_with(&connect::AutoConnectOptions {
+                                    serial: options.serial,
+                                    ..Default::default()
+                                })
+                                .await;
+                            let firmware = info.firmware();
+                            let protocol = options.protocol.unwrap_or(firmware.protocol());
+                            let printer = printer.with_protocol(protocol);
+                            if printer.is_connected() {
+                                if let Err(e) =
+                                    printer.resync(print3rs_core::DEFAULT_QUERY_TIMEOUT).await
+                                {
+                                    tracing::warn!("Couldn't reset printer line number: {e}");
+                                }
+                            }
                             let response = if printer.is_connected() {
-                                Response::Output("Found Printer!\n".into())
+                                let mut found = match info.baud {
+                                    Some(baud) => format!("Found Printer at {baud} baud!\n"),
+                                    None => "Found Printer!\n".to_string(),
+                                };
+                                if firmware != Firmware::Unknown {
+                                    found.push_str(&format!("Running {firmware} firmware\n"));
+                                }
+                                Response::Output(found.into())
                             } else {
                                 Response::Error("No printer found.\n".into())
                             };
@@ -190,17 +810,24 @@ impl Commander {
                                 let forward_responder = autoconnect_responder.clone();
                                 Self::forward_broadcast(printer_responses, forward_responder);
                             }
-                            let _ = autoconnect_responder.send(printer.into());
+                            let _ = autoconnect_responder.send((session, printer, info).into());
                             let _ = autoconnect_responder.send(response);
                         });
                     }
                     Connection::Serial { port, baud } => {
-                        let connection =
-                            tokio_serial::new(port, baud.unwrap_or(115200)).open_native_async()?;
+                        let baud = baud.unwrap_or(115200);
+                        let connection = options.serial.open(port, baud)?;
                         let connection = BufReader::new(connection);
                         self.tasks.clear();
-                        self.printer.connect(connection);
+                        self.printer = Printer::new(connection)
+                            .with_protocol(options.protocol.unwrap_or_default());
+                        self.printer_info = print3rs_core::PrinterInfo {
+                            port: Some(port.to_string()),
+                            baud: Some(baud),
+                            ..Default::default()
+                        };
                         self.add_printer_output_to_responses();
+                        Self::resync_in_background(&self.printer);
                     }
                     Connection::Tcp { hostname, port } => {
                         let addr = if let Some(port) = port {
@@ -208,11 +835,75 @@ impl Commander {
                         } else {
                             hostname.to_owned()
                         };
-                        let connection = std::net::TcpStream::connect(addr)?;
-                        let connection = BufReader::new(TcpStream::from_std(connection)?);
+                        let connection = std::net::TcpStream::connect(&addr)?;
+                        connection.set_nonblocking(true)?;
+                        let printer = Printer::from_tcp(TcpStream::from_std(connection)?)?
+                            .with_protocol(options.protocol.unwrap_or_default());
                         self.tasks.clear();
-                        self.printer.connect(connection);
+                        self.printer = printer;
+                        self.printer_info = print3rs_core::PrinterInfo {
+                            port: Some(addr),
+                            ..Default::default()
+                        };
                         self.add_printer_output_to_responses();
+                        Self::resync_in_background(&self.printer);
+                    }
+                    Connection::Moonraker { url } => {
+                        self.responder.send("Connecting...\n".into())?;
+                        let moonraker_responder = self.responder.clone();
+                        let session = self.selected.clone();
+                        let url = url.to_owned();
+                        tokio::spawn(async move {
+                            let printer = match Printer::connect_moonraker(&url).await {
+                                Ok(printer) => printer,
+                                Err(e) => {
+                                    let _ = moonraker_responder.send(Response::Error(e.into()));
+                                    return;
+                                }
+                            };
+                            if let Ok(printer_responses) = printer.subscribe_lines() {
+                                let forward_responder = moonraker_responder.clone();
+                                Self::forward_broadcast(printer_responses, forward_responder);
+                            }
+                            let info = print3rs_core::PrinterInfo {
+                                port: Some(url.clone()),
+                                ..Default::default()
+                            };
+                            let _ = moonraker_responder.send((session, printer, info).into());
+                            let _ = moonraker_responder.send(Response::Output(
+                                format!("Connected to Moonraker at {url}\n").into(),
+                            ));
+                        });
+                    }
+                    #[cfg(unix)]
+                    Connection::Unix { path } => {
+                        let unix_responder = self.responder.clone();
+                        let session = self.selected.clone();
+                        let path = path.to_owned();
+                        let protocol = options.protocol.unwrap_or_default();
+                        tokio::spawn(async move {
+                            let printer = match Printer::connect_unix(&path).await {
+                                Ok(printer) => printer.with_protocol(protocol),
+                                Err(e) => {
+                                    let _ = unix_responder.send(Response::Error(e.into()));
+                                    return;
+                                }
+                            };
+                            if let Ok(printer_responses) = printer.subscribe_lines() {
+                                let forward_responder = unix_responder.clone();
+                                Self::forward_broadcast(printer_responses, forward_responder);
+                            }
+                            Self::resync_in_background(&printer);
+                            let info = print3rs_core::PrinterInfo {
+                                port: Some(path),
+                                ..Default::default()
+                            };
+                            let _ = unix_responder.send((session, printer, info).into());
+                        });
+                    }
+                    #[cfg(not(unix))]
+                    Connection::Unix { .. } => {
+                        return Err("Unix sockets aren't available on this platform".into());
                     }
                     Connection::Mqtt {
                         hostname: _,
@@ -222,9 +913,61 @@ impl Commander {
                     } => todo!(),
                 };
             }
+            Baud(rate) => {
+                let switching = connect::set_baud(&self.printer, rate)?;
+                let (switched_sender, switched) = oneshot::channel();
+                let baud_responder = self.responder.clone();
+                tokio::spawn(async move {
+                    let result = switching.await;
+                    let _ = switched_sender.send(result.is_ok());
+                    let response = match result {
+                        Ok(()) => Response::Output(format!("Switched to {rate} baud\n").into()),
+                        Err(e) => Response::Error(e.into()),
+                    };
+                    let _ = baud_responder.send(response);
+                });
+                self.baud_switch = Some(BaudSwitch { rate, switched });
+            }
             Disconnect => {
                 self.tasks.clear();
-                self.printer.disconnect()
+                // let anything already sent finish reaching the printer
+                let mut printer = std::mem::take(&mut self.printer);
+                tokio::spawn(async move {
+                    let _ = printer.shutdown(print3rs_core::DEFAULT_QUERY_TIMEOUT).await;
+                });
+                self.printer_info = Default::default();
+                self.baud_switch = None;
+            }
+            Ports => {
+                self.responder.send(connect::list_ports().into())?;
+            }
+            PrinterInfo => {
+                self.responder.send(self.printer_info.to_string().into())?;
+            }
+            Settings(action) => {
+                let socket = self.printer.socket()?.clone();
+                let action = action.into_owned();
+                let settings_responder = self.responder.clone();
+                tokio::spawn(async move {
+                    let response = match settings::settings(&socket, action.to_borrowed()).await {
+                        Ok(output) => Response::Output(output.into()),
+                        Err(e) => Response::Error(e),
+                    };
+                    let _ = settings_responder.send(response);
+                });
+            }
+            Set(name, value) => {
+                self.variables.set(name, value);
+            }
+            Unset(name) => {
+                if self.variables.remove(name).is_none() {
+                    return Err(ErrorKindOf(format!("No variable named {name}")));
+                }
+            }
+            Variables => {
+                for (name, value) in self.variables.iter() {
+                    self.responder.send(format!("${name} = {value}\n").into())?;
+                }
             }
             Help(subcommand) => {
                 self.responder.send(help::help(subcommand).into())?;
@@ -232,6 +975,37 @@ impl Commander {
             Version => {
                 self.responder.send(version::VERSION.into())?;
             }
+            EchoSent(true) => {
+                let echo = start_echo_sent(&self.printer, self.responder.clone())?;
+                self.tasks.insert(ECHO_SENT_TASK.to_string(), echo);
+            }
+            EchoSent(false) => {
+                self.tasks.remove(ECHO_SENT_TASK);
+            }
+            Validate(validation, protocol) => {
+                self.validation.set(protocol, validation);
+                let protocols = match protocol {
+                    Some(Protocol::Marlin) => "Marlin",
+                    Some(Protocol::Klipper) => "Klipper",
+                    None => "every protocol",
+                };
+                let checking = match validation {
+                    Validation::Off => "won't be checked",
+                    Validation::Warn => "will be checked, warning about problems",
+                    Validation::Reject => "will be checked, not sending any with problems",
+                };
+                self.responder
+                    .send(format!("Gcodes for {protocols} {checking}\n").into())?;
+            }
+            Uppercase(on) => {
+                self.macros.set_preserve_case(!on);
+                let message = if on {
+                    "Gcodes will be uppercased\n"
+                } else {
+                    "Gcodes will be sent as written\n"
+                };
+                self.responder.send(message.into())?;
+            }
             _ => {
                 self.responder.send("Unsupported command!\n".into())?;
             }
@@ -239,3 +1013,57 @@ impl Commander {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::tasks::RepeatOptions,
+        print3rs_core::{duplex_printer, LoopbackTransport},
+        tokio::io::AsyncBufReadExt,
+    };
+
+    fn connected_commander() -> (Commander, tokio::io::Lines<LoopbackTransport>) {
+        let (printer, _, host_side) = duplex_printer();
+        let mut commander = Commander::new();
+        commander.set_printer(printer);
+        (commander, host_side.lines())
+    }
+
+    #[tokio::test]
+    async fn abort_stops_repeats() {
+        let (mut commander, mut host_side) = connected_commander();
+        let mut responses = commander.subscribe_responses();
+        commander
+            .dispatch(Command::Repeat(
+                "temps",
+                vec!["M105"],
+                RepeatOptions::default(),
+            ))
+            .unwrap();
+        let idle = tokio::spawn(std::future::pending::<()>());
+        commander.tasks.insert(
+            "idle".to_string(),
+            BackgroundTask {
+                description: "log",
+                abort_handle: idle.abort_handle(),
+                pause: None,
+                progress: None,
+                stop: None,
+            },
+        );
+        assert_eq!(host_side.next_line().await.unwrap().unwrap(), "M105");
+        let repeat = commander.tasks["temps"].abort_handle.clone();
+
+        commander.dispatch(Command::Abort).unwrap();
+        assert!(!commander.tasks.contains_key("temps"));
+        assert!(commander.tasks.contains_key("idle"));
+        while !repeat.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            responses.recv().await.unwrap(),
+            Response::Output(output) if output.starts_with("Stopped 1 tasks")
+        ));
+    }
+}
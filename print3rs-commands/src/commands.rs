@@ -1,39 +1,54 @@
 use {
     self::{
-        connect::Connection,
-        log::{parse_logger, Segment},
+        connect::{ConnectOptions, Connection},
+        log::{parse_logger, LogOptions, Segment},
+        settings::{parse_settings, SettingsAction},
+        validate::{parse_validate, Validation},
+        waitfor::{parse_waitfor, WaitOptions},
+    },
+    crate::{
+        commands::connect::parse_connection,
+        tasks::{PrintMode, RepeatOptions, Sweep},
     },
-    crate::commands::connect::parse_connection,
     core::borrow::Borrow,
-    std::fmt::Debug,
+    print3rs_core::Protocol,
+    std::{borrow::Cow, fmt::Debug, time::Duration},
     winnow::{
-        ascii::digit1,
-        combinator::terminated,
+        ascii::{dec_uint, digit1},
+        combinator::{delimited, eof, terminated},
         stream::{AsChar, Stream},
-        token::take_while,
+        token::{none_of, one_of, take_until, take_while},
     },
 };
 
 use winnow::{
     ascii::{alpha1, space0, space1},
-    combinator::{alt, dispatch, empty, fail, opt, preceded, rest, separated},
+    combinator::{alt, dispatch, empty, fail, opt, preceded, repeat, rest, separated},
     prelude::*,
     token::take_till,
 };
 
+pub mod complete;
 pub mod connect;
+pub mod dryrun;
 pub mod help;
 pub mod log;
 pub mod macros;
+pub mod settings;
+pub mod status;
+pub mod validate;
+pub mod variables;
 pub mod version;
+pub mod waitfor;
+
+const NAME_CHARS: (
+    std::ops::RangeInclusive<char>,
+    std::ops::RangeInclusive<char>,
+    std::ops::RangeInclusive<char>,
+    [char; 3],
+) = ('a'..='z', 'A'..='Z', '0'..='9', ['-', '_', '.']);
 
 pub fn identifier<'a>(input: &mut &'a str) -> PResult<&'a str> {
-    const NAME_CHARS: (
-        std::ops::RangeInclusive<char>,
-        std::ops::RangeInclusive<char>,
-        std::ops::RangeInclusive<char>,
-        [char; 3],
-    ) = ('a'..='z', 'A'..='Z', '0'..='9', ['-', '_', '.']);
     take_while(1.., NAME_CHARS)
         .verify(|ident| plausible_code.parse(ident).is_err())
         .parse_next(input)
@@ -43,18 +58,63 @@ pub fn identifier<'a>(input: &mut &'a str) -> PResult<&'a str> {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Command<S> {
     Gcodes(Vec<S>),
-    Print(S),
-    Log(S, Vec<Segment<S>>),
-    Repeat(S, Vec<S>),
+    Print(S, PrintMode),
+    DryRun(S),
+    Log(S, Vec<Vec<Segment<S>>>, LogOptions<S>),
+    /// Record every line sent and received into a file
+    Transcript(S),
+    Repeat(S, Vec<S>, RepeatOptions),
     Tasks,
+    /// Summarize the connection, background tasks and temperatures
+    Status,
     Stop(S),
-    Connect(Connection<S>),
+    /// Stop any prints, repeats and Gcodes being sent, and throw away the lines still queued for the printer
+    Abort,
+    Pause(S),
+    Resume(S),
+    Connect(Connection<S>, ConnectOptions),
+    /// List serial ports with what's known about the device behind each
+    Ports,
+    /// Change the baud rate of the serial port the printer is connected through
+    Baud(u32),
     Disconnect,
+    PrinterInfo,
+    Settings(SettingsAction<S>),
     Macro(S, Vec<S>),
     Macros,
     DeleteMacro(S),
+    SaveMacros(Option<S>),
+    LoadMacros(Option<S>, bool),
+    /// Write macros as console commands to a file, or show them if no file is given
+    ExportMacros(Option<S>),
+    /// Start keeping every Gcode sent, to make into a macro with the given name
+    Record(S),
+    /// Stop recording and make the recorded Gcodes into a macro
+    EndRecord,
+    /// Whether Gcodes are uppercased before they're sent or stored in macros
+    Uppercase(bool),
+    /// Whether every line sent to the printer is shown in the console
+    EchoSent(bool),
+    /// How Gcodes entered are checked before they're sent, for one protocol or all of them
+    Validate(Validation, Option<Protocol>),
+    /// Set a variable filled into Gcodes wherever `$name` appears
+    Set(S, S),
+    Unset(S),
+    /// List every variable set
+    Variables,
     Help(S),
     Version,
+    Echo(S),
+    Source(S, bool),
+    /// Hold back the commands after this one until the printer sends a line matching a pattern
+    WaitFor(Vec<Vec<Segment<S>>>, WaitOptions<S>),
+    /// Switch which printer commands are sent to
+    Select(S),
+    /// List every printer session
+    Printers,
+    /// Run a command on a printer other than the selected one
+    On(S, Box<Command<S>>),
+    Comment,
     Clear,
     Quit,
     Unrecognized,
@@ -65,27 +125,66 @@ impl<'a> Command<&'a str> {
         use Command::*;
         match self {
             Gcodes(codes) => Gcodes(codes.into_iter().map(str::to_owned).collect()),
-            Print(filename) => Print(filename.to_owned()),
-            Log(name, pattern) => Log(
+            Print(filename, mode) => Print(filename.to_owned(), mode),
+            DryRun(filename) => DryRun(filename.to_owned()),
+            Log(name, patterns, options) => Log(
                 name.to_owned(),
-                pattern.into_iter().map(Segment::into_owned).collect(),
+                patterns
+                    .into_iter()
+                    .map(|pattern| pattern.into_iter().map(Segment::into_owned).collect())
+                    .collect(),
+                options.into_owned(),
             ),
-            Repeat(name, codes) => Repeat(
+            Transcript(filename) => Transcript(filename.to_owned()),
+            Repeat(name, codes, options) => Repeat(
                 name.to_owned(),
                 codes.into_iter().map(str::to_owned).collect(),
+                options,
             ),
             Tasks => Tasks,
+            Status => Status,
             Stop(s) => Stop(s.to_owned()),
-            Connect(connection) => Connect(connection.into_owned()),
+            Abort => Abort,
+            Pause(s) => Pause(s.to_owned()),
+            Resume(s) => Resume(s.to_owned()),
+            Connect(connection, options) => Connect(connection.into_owned(), options),
+            Ports => Ports,
+            Baud(rate) => Baud(rate),
             Disconnect => Disconnect,
+            PrinterInfo => PrinterInfo,
+            Settings(action) => Settings(action.into_owned()),
             Macro(name, codes) => Macro(
                 name.to_owned(),
                 codes.into_iter().map(str::to_owned).collect(),
             ),
             Macros => Macros,
             DeleteMacro(s) => DeleteMacro(s.to_owned()),
+            SaveMacros(path) => SaveMacros(path.map(str::to_owned)),
+            LoadMacros(path, replace) => LoadMacros(path.map(str::to_owned), replace),
+            ExportMacros(path) => ExportMacros(path.map(str::to_owned)),
+            Record(name) => Record(name.to_owned()),
+            EndRecord => EndRecord,
+            Uppercase(on) => Uppercase(on),
+            EchoSent(on) => EchoSent(on),
+            Validate(validation, protocol) => Validate(validation, protocol),
+            Set(name, value) => Set(name.to_owned(), value.to_owned()),
+            Unset(name) => Unset(name.to_owned()),
+            Variables => Variables,
             Help(s) => Help(s.to_owned()),
             Version => Version,
+            Echo(s) => Echo(s.to_owned()),
+            Source(filename, strict) => Source(filename.to_owned(), strict),
+            WaitFor(patterns, options) => WaitFor(
+                patterns
+                    .into_iter()
+                    .map(|pattern| pattern.into_iter().map(Segment::into_owned).collect())
+                    .collect(),
+                options.into_owned(),
+            ),
+            Select(name) => Select(name.to_owned()),
+            Printers => Printers,
+            On(name, command) => On(name.to_owned(), Box::new(command.into_owned())),
+            Comment => Comment,
             Clear => Clear,
             Quit => Quit,
             Unrecognized => Unrecognized,
@@ -101,23 +200,63 @@ impl Command<String> {
         use Command::*;
         match self {
             Gcodes(codes) => Gcodes(codes.iter().map(|s| s.borrow()).collect()),
-            Print(filename) => Print(filename.borrow()),
-            Log(name, pattern) => Log(
+            Print(filename, mode) => Print(filename.borrow(), *mode),
+            DryRun(filename) => DryRun(filename.borrow()),
+            Log(name, patterns, options) => Log(
                 name.borrow(),
-                pattern.iter().map(Segment::to_borrowed).collect(),
+                patterns
+                    .iter()
+                    .map(|pattern| pattern.iter().map(Segment::to_borrowed).collect())
+                    .collect(),
+                options.to_borrowed(),
+            ),
+            Transcript(filename) => Transcript(filename.borrow()),
+            Repeat(name, codes, options) => Repeat(
+                name.borrow(),
+                codes.iter().map(|s| s.borrow()).collect(),
+                *options,
             ),
-            Repeat(name, codes) => {
-                Repeat(name.borrow(), codes.iter().map(|s| s.borrow()).collect())
-            }
             Tasks => Tasks,
+            Status => Status,
             Stop(s) => Stop(s.borrow()),
-            Connect(connection) => Connect(connection.to_borrowed()),
+            Abort => Abort,
+            Pause(s) => Pause(s.borrow()),
+            Resume(s) => Resume(s.borrow()),
+            Connect(connection, options) => Connect(connection.to_borrowed(), *options),
+            Ports => Ports,
+            Baud(rate) => Baud(*rate),
             Disconnect => Disconnect,
+            PrinterInfo => PrinterInfo,
+            Settings(action) => Settings(action.to_borrowed()),
             Macro(name, codes) => Macro(name.borrow(), codes.iter().map(|s| s.borrow()).collect()),
             Macros => Macros,
             DeleteMacro(s) => DeleteMacro(s.borrow()),
+            SaveMacros(path) => SaveMacros(path.as_ref().map(|s| s.borrow())),
+            LoadMacros(path, replace) => LoadMacros(path.as_ref().map(|s| s.borrow()), *replace),
+            ExportMacros(path) => ExportMacros(path.as_ref().map(|s| s.borrow())),
+            Record(name) => Record(name.borrow()),
+            EndRecord => EndRecord,
+            Uppercase(on) => Uppercase(*on),
+            EchoSent(on) => EchoSent(*on),
+            Validate(validation, protocol) => Validate(*validation, *protocol),
+            Set(name, value) => Set(name.borrow(), value.borrow()),
+            Unset(name) => Unset(name.borrow()),
+            Variables => Variables,
             Help(s) => Help(s.borrow()),
             Version => Version,
+            Echo(s) => Echo(s.borrow()),
+            Source(filename, strict) => Source(filename.borrow(), *strict),
+            WaitFor(patterns, options) => WaitFor(
+                patterns
+                    .iter()
+                    .map(|pattern| pattern.iter().map(Segment::to_borrowed).collect())
+                    .collect(),
+                options.to_borrowed(),
+            ),
+            Select(name) => Select(name.borrow()),
+            Printers => Printers,
+            On(name, command) => On(name.borrow(), Box::new(command.to_borrowed())),
+            Comment => Comment,
             Clear => Clear,
             Quit => Quit,
             Unrecognized => Unrecognized,
@@ -137,20 +276,172 @@ impl<'a> From<&'a Command<String>> for Command<&'a str> {
     }
 }
 
+/// Everything up to the next `;` that isn't inside double quotes, so a quoted `;`,
+/// like in Klipper's `RESPOND MSG="a;b"`, stays part of the Gcode along with its quotes.
+/// Inside quotes `\"` doesn't end them, and `\\` is a backslash that doesn't escape what follows.
+/// A quote that's never closed runs to the end of the line.
+fn till_separator<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    let end = input
+        .char_indices()
+        .find(|&(_, c)| {
+            if std::mem::take(&mut escaped) {
+                return false;
+            }
+            match c {
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                _ => {}
+            }
+            c == ';' && !quoted
+        })
+        .map_or(input.len(), |(index, _)| index);
+    Ok(input.next_slice(end))
+}
+
 fn plausible_code<'a>(input: &mut &'a str) -> PResult<&'a str> {
     let checkpoint = input.checkpoint();
     let _ = preceded(space0, (take_while(1, AsChar::is_alpha), digit1)).parse_next(input)?;
     input.reset(&checkpoint);
-    take_till(2.., ';').parse_next(input)
+    till_separator
+        .verify(|code: &str| code.len() >= 2)
+        .parse_next(input)
+}
+
+/// Macro name followed by any arguments for it
+fn macro_call<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    (space0, identifier, till_separator)
+        .recognize()
+        .parse_next(input)
+}
+
+/// Text in double quotes, along with the quotes, where `\"` is a quote and `\\` a backslash.
+///
+/// The quotes and escapes are only taken out by `unquote`, since parsed commands borrow
+/// from the line they were parsed from.
+fn quoted<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    let escape = preceded('\\', one_of(['"', '\\'])).void();
+    (
+        '"',
+        repeat::<_, _, (), _, _>(0.., alt((escape, none_of('"').void()))),
+        '"',
+    )
+        .recognize()
+        .parse_next(input)
+}
+
+/// An argument as it's used, without the double quotes and escapes it was written with,
+/// like a file name given as `"my \"best\" print.gcode"`. Unquoted arguments are kept as they are,
+/// so backslashes in paths like `C:\prints\benchy.gcode` don't need escaping.
+pub fn unquote(argument: &str) -> Cow<'_, str> {
+    if quoted.parse(argument).is_err() {
+        return Cow::Borrowed(argument);
+    }
+    let inner = &argument[1..argument.len() - 1];
+    if !inner.contains('\\') {
+        return Cow::Borrowed(inner);
+    }
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.clone().next(), Some('"' | '\\')) => {
+                unquoted.extend(chars.next())
+            }
+            c => unquoted.push(c),
+        }
+    }
+    Cow::Owned(unquoted)
+}
+
+/// A file path running to the end of the line, which can be put in double quotes
+/// like `"my print.gcode"`, see `unquote`
+pub(crate) fn path<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    alt((terminated(quoted, (space0, eof)), rest)).parse_next(input)
 }
 
 fn parse_gcodes<'a>(input: &mut &'a str) -> PResult<Vec<&'a str>> {
-    terminated(separated(0.., plausible_code, ';'), opt(";")).parse_next(input)
+    terminated(
+        separated(0.., alt((plausible_code, macro_call)), ';'),
+        opt(";"),
+    )
+    .parse_next(input)
+}
+
+/// A whole number of milliseconds, seconds or minutes, like `500ms`, `10s` or `5m`
+fn parse_duration(input: &mut &str) -> PResult<Duration> {
+    (
+        dec_uint::<_, u64, _>,
+        alt(("ms".value(1), "s".value(1000), "m".value(60_000))),
+    )
+        .map(|(count, millis)| Duration::from_millis(count * millis))
+        .parse_next(input)
+}
+
+enum RepeatFlag {
+    Every(Duration),
+    Times(u32),
+    Sweep(Sweep),
+}
+
+fn sweep_number(input: &mut &str) -> PResult<f64> {
+    take_till(1.., ' ')
+        .parse_to()
+        .verify(|number: &f64| number.is_finite())
+        .parse_next(input)
+}
+
+/// `<start>..<end>`, optionally followed by `step <step>`, stepping by 1 if it isn't given
+fn parse_sweep(input: &mut &str) -> PResult<Sweep> {
+    (
+        take_until(1.., "..")
+            .parse_to()
+            .verify(|start: &f64| start.is_finite()),
+        preceded("..", sweep_number),
+        opt(preceded(
+            (space1, "step", space1),
+            sweep_number.verify(|step| *step > 0.0),
+        )),
+    )
+        .map(|(start, end, step)| Sweep {
+            start,
+            end,
+            step: step.unwrap_or(1.0),
+        })
+        .parse_next(input)
+}
+
+fn parse_repeat_flag(input: &mut &str) -> PResult<RepeatFlag> {
+    alt((
+        preceded(("--every", space1), parse_duration).map(RepeatFlag::Every),
+        preceded(("--times", space1), dec_uint).map(RepeatFlag::Times),
+        preceded(("--range", space1), parse_sweep).map(RepeatFlag::Sweep),
+    ))
+    .parse_next(input)
+}
+
+fn parse_repeat_options(input: &mut &str) -> PResult<RepeatOptions> {
+    let flags: Vec<RepeatFlag> =
+        repeat(0.., terminated(parse_repeat_flag, space1)).parse_next(input)?;
+    let mut options = RepeatOptions::default();
+    for flag in flags {
+        match flag {
+            RepeatFlag::Every(every) => options.every = Some(every),
+            RepeatFlag::Times(times) => options.times = Some(times),
+            RepeatFlag::Sweep(sweep) => options.sweep = Some(sweep),
+        }
+    }
+    Ok(options)
 }
 
 fn parse_repeater<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
-    (preceded(space0, identifier), preceded(space1, parse_gcodes))
-        .map(|(name, gcodes)| Command::Repeat(name, gcodes))
+    (
+        preceded(space0, parse_repeat_options),
+        identifier,
+        preceded(space1, parse_gcodes),
+    )
+        .map(|(options, name, gcodes)| Command::Repeat(name, gcodes, options))
         .parse_next(input)
 }
 
@@ -160,20 +451,105 @@ fn parse_macro<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
     Ok(Command::Macro(name, steps))
 }
 
+fn parse_print<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    (preceded(space0, opt(terminated("--raw", space1))), path)
+        .map(|(raw, filename)| {
+            let mode = if raw.is_some() {
+                PrintMode::Raw
+            } else {
+                PrintMode::Filtered
+            };
+            Command::Print(filename, mode)
+        })
+        .parse_next(input)
+}
+
+fn parse_source<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    (preceded(space0, opt(terminated("--strict", space1))), path)
+        .map(|(strict, filename)| Command::Source(filename, strict.is_some()))
+        .parse_next(input)
+}
+
+fn parse_macro_file<'a>(input: &mut &'a str) -> PResult<Option<&'a str>> {
+    preceded(
+        space0,
+        opt(alt((
+            quoted,
+            take_till(1.., ' ').verify(|path: &str| !path.starts_with("--")),
+        ))),
+    )
+    .parse_next(input)
+}
+
+fn parse_load_macros<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    (
+        parse_macro_file,
+        terminated(opt(preceded(space0, "--replace")), space0),
+    )
+        .map(|(path, replace)| Command::LoadMacros(path, replace.is_some()))
+        .parse_next(input)
+}
+
+fn variable_name<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    preceded(space0, take_while(1.., variables::is_name_char)).parse_next(input)
+}
+
+/// `set <name> <value>` sets a variable, plain `set` lists them
+fn parse_set<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    alt((
+        (
+            variable_name,
+            preceded(
+                space1,
+                rest.map(str::trim_end)
+                    .verify(|value: &str| !value.is_empty()),
+            ),
+        )
+            .map(|(name, value)| Command::Set(name, value)),
+        space0.map(|_| Command::Variables),
+    ))
+    .parse_next(input)
+}
+
 fn inner_command<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
     dispatch! {preceded(space0, alpha1);
         "log" => parse_logger,
+        "transcript" => preceded(space0, path).map(Command::Transcript),
         "repeat" => parse_repeater,
-        "print" => preceded(space0, rest).map(Command::Print),
+        "print" => parse_print,
+        "dryrun" => preceded(space0, path).map(Command::DryRun),
         "tasks" => empty.map(|_| Command::Tasks),
+        "status" => empty.map(|_| Command::Status),
         "stop" => preceded(space0, rest).map(Command::Stop),
+        "abort" => empty.map(|_| Command::Abort),
+        "pause" => preceded(space0, rest).map(Command::Pause),
+        "resume" => preceded(space0, rest).map(Command::Resume),
         "help" => rest.map(Command::Help),
         "version" => empty.map(|_| Command::Version),
         "disconnect" => empty.map(|_| Command::Disconnect),
         "connect" => parse_connection,
+        "ports" => empty.map(|_| Command::Ports),
+        "baud" => delimited(space0, dec_uint, space0).map(Command::Baud),
+        "printerinfo" => empty.map(|_| Command::PrinterInfo),
+        "settings" => parse_settings,
         "macro" => parse_macro,
         "macros" => empty.map(|_| Command::Macros),
         "delmacro" => preceded(space0, rest).map(Command::DeleteMacro),
+        "savemacros" => terminated(parse_macro_file, space0).map(Command::SaveMacros),
+        "loadmacros" => parse_load_macros,
+        "exportmacros" => terminated(parse_macro_file, space0).map(Command::ExportMacros),
+        "record" => delimited(space0, identifier, space0).map(Command::Record),
+        "endrecord" => empty.map(|_| Command::EndRecord),
+        "uppercase" => delimited(space0, alt(("on".value(true), "off".value(false))), space0).map(Command::Uppercase),
+        "echosent" => delimited(space0, alt(("on".value(true), "off".value(false))), space0).map(Command::EchoSent),
+        "validate" => parse_validate,
+        "set" => parse_set,
+        "unset" => terminated(variable_name, space0).map(Command::Unset),
+        "echo" => preceded(space0, rest).map(Command::Echo),
+        "source" => parse_source,
+        "waitfor" => parse_waitfor,
+        "select" => preceded(space0, take_while(1.., NAME_CHARS)).map(Command::Select),
+        "printers" => empty.map(|_| Command::Printers),
         "clear" => empty.map(|_| Command::Clear),
         "quit" | "exit" => empty.map(|_| Command::Quit),
         _ => fail
@@ -181,8 +557,27 @@ fn inner_command<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
     .parse_next(input)
 }
 
+/// Lines starting with `#` are comments, so scripts of commands can be annotated
+fn comment<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    (space0, '#', rest)
+        .map(|_| Command::Comment)
+        .parse_next(input)
+}
+
+/// `@name <command>` runs a command on the printer session called `name`
+fn on_printer<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    (
+        preceded((space0, '@'), take_while(1.., NAME_CHARS)),
+        preceded(space1, parse_command),
+    )
+        .map(|(name, command)| Command::On(name, Box::new(command)))
+        .parse_next(input)
+}
+
 pub fn parse_command<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
     alt((
+        comment,
+        on_printer,
         inner_command,
         parse_gcodes.map(|gcodes| {
             let gcodes = gcodes.into_iter().collect();
@@ -191,3 +586,347 @@ pub fn parse_command<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
     ))
     .parse_next(input)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quoted_arguments() {
+        assert_eq!(
+            parse_command.parse("print \"my benchy.gcode\"").unwrap(),
+            Command::Print("\"my benchy.gcode\"", PrintMode::Filtered)
+        );
+        assert_eq!(
+            parse_command.parse("print --raw my benchy.gcode").unwrap(),
+            Command::Print("my benchy.gcode", PrintMode::Raw)
+        );
+        assert_eq!(
+            parse_command
+                .parse("savemacros \"my macros.toml\"")
+                .unwrap(),
+            Command::SaveMacros(Some("\"my macros.toml\""))
+        );
+        assert_eq!(
+            parse_command
+                .parse("loadmacros \"shared macros.toml\" --replace")
+                .unwrap(),
+            Command::LoadMacros(Some("\"shared macros.toml\""), true)
+        );
+        assert_eq!(
+            parse_command.parse("exportmacros").unwrap(),
+            Command::ExportMacros(None)
+        );
+        assert_eq!(
+            parse_command.parse("exportmacros macros.txt").unwrap(),
+            Command::ExportMacros(Some("macros.txt"))
+        );
+        assert_eq!(
+            parse_command.parse("record level").unwrap(),
+            Command::Record("level")
+        );
+        assert_eq!(
+            parse_command.parse("endrecord").unwrap(),
+            Command::EndRecord
+        );
+        assert_eq!(
+            parse_command
+                .parse("source --strict \"setup; part 1.txt\"")
+                .unwrap(),
+            Command::Source("\"setup; part 1.txt\"", true)
+        );
+    }
+
+    #[test]
+    fn quote_escapes() {
+        assert_eq!(
+            parse_command
+                .parse(r#"print "my \"best\" benchy.gcode""#)
+                .unwrap(),
+            Command::Print(r#""my \"best\" benchy.gcode""#, PrintMode::Filtered)
+        );
+        assert_eq!(
+            unquote(r#""my \"best\" benchy.gcode""#),
+            r#"my "best" benchy.gcode"#
+        );
+        assert_eq!(unquote(r#""prints\\""#), r"prints\");
+        assert_eq!(unquote(r#""my benchy.gcode""#), "my benchy.gcode");
+        // only quoted arguments have escapes
+        assert_eq!(
+            unquote(r"C:\prints\benchy.gcode"),
+            r"C:\prints\benchy.gcode"
+        );
+        assert_eq!(unquote(r#""C:\my prints""#), r"C:\my prints");
+        assert_eq!(unquote(r#""unclosed \""#), r#""unclosed \""#);
+        assert_eq!(
+            parse_command.parse(r#"print "a\\" b""#).unwrap(),
+            Command::Print(r#""a\\" b""#, PrintMode::Filtered)
+        );
+        assert_eq!(unquote(r#""a\\" b""#), r#""a\\" b""#);
+
+        assert_eq!(
+            parse_command
+                .parse(r#"RESPOND MSG="say \"a;b\"";G28"#)
+                .unwrap(),
+            Command::Gcodes(vec![r#"RESPOND MSG="say \"a;b\"""#, "G28"])
+        );
+        assert_eq!(
+            parse_command.parse(r#"M117 "a\\";G28"#).unwrap(),
+            Command::Gcodes(vec![r#"M117 "a\\""#, "G28"])
+        );
+    }
+
+    #[test]
+    fn quoted_semicolons() {
+        assert_eq!(
+            parse_command.parse("RESPOND MSG=\"a;b\";G28").unwrap(),
+            Command::Gcodes(vec!["RESPOND MSG=\"a;b\"", "G28"])
+        );
+        assert_eq!(
+            parse_command
+                .parse("macro hello M117 \"hi; there\";M400")
+                .unwrap(),
+            Command::Macro("hello", vec!["M117 \"hi; there\"", "M400"])
+        );
+        assert_eq!(
+            parse_command.parse("M117 \"unclosed;G28").unwrap(),
+            Command::Gcodes(vec!["M117 \"unclosed;G28"])
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            parse_command.parse("# connect first").unwrap(),
+            Command::Comment
+        );
+        assert_eq!(parse_command.parse("  #").unwrap(), Command::Comment);
+        assert_eq!(
+            parse_command.parse("G1 X10 # not a comment").unwrap(),
+            Command::Gcodes(vec!["G1 X10 # not a comment"])
+        );
+    }
+
+    #[test]
+    fn abort() {
+        assert_eq!(parse_command.parse("abort").unwrap(), Command::Abort);
+    }
+
+    #[test]
+    fn status() {
+        assert_eq!(parse_command.parse("status").unwrap(), Command::Status);
+    }
+
+    #[test]
+    fn ports() {
+        assert_eq!(parse_command.parse("ports").unwrap(), Command::Ports);
+    }
+
+    #[test]
+    fn echo() {
+        assert_eq!(
+            parse_command.parse("echo Heating up...").unwrap(),
+            Command::Echo("Heating up...")
+        );
+        assert_eq!(parse_command.parse("echo").unwrap(), Command::Echo(""));
+    }
+
+    #[test]
+    fn printer_sessions() {
+        assert_eq!(
+            parse_command.parse("select P1").unwrap(),
+            Command::Select("P1")
+        );
+        assert_eq!(
+            parse_command.parse("@ender-3 print cube.gcode").unwrap(),
+            Command::On(
+                "ender-3",
+                Box::new(Command::Print("cube.gcode", PrintMode::Filtered))
+            )
+        );
+        assert_eq!(
+            parse_command.parse("@P1 G28").unwrap(),
+            Command::On("P1", Box::new(Command::Gcodes(vec!["G28"])))
+        );
+    }
+
+    #[test]
+    fn repeat_interval() {
+        assert_eq!(
+            parse_command.parse("repeat temps M105").unwrap(),
+            Command::Repeat("temps", vec!["M105"], RepeatOptions::default())
+        );
+        assert_eq!(
+            parse_command
+                .parse("repeat --every 1s temps M105;M114")
+                .unwrap(),
+            Command::Repeat(
+                "temps",
+                vec!["M105", "M114"],
+                RepeatOptions {
+                    every: Some(Duration::from_secs(1)),
+                    times: None,
+                    sweep: None
+                }
+            )
+        );
+        assert_eq!(
+            parse_command
+                .parse("repeat --every 250ms poll M27")
+                .unwrap(),
+            Command::Repeat(
+                "poll",
+                vec!["M27"],
+                RepeatOptions {
+                    every: Some(Duration::from_millis(250)),
+                    times: None,
+                    sweep: None
+                }
+            )
+        );
+        assert_eq!(
+            parse_command
+                .parse("repeat --times 10 --every 5m level G29")
+                .unwrap(),
+            Command::Repeat(
+                "level",
+                vec!["G29"],
+                RepeatOptions {
+                    every: Some(Duration::from_secs(300)),
+                    times: Some(10),
+                    sweep: None
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn repeat_sweep() {
+        assert_eq!(
+            parse_command
+                .parse("repeat --range 0..100 step 10 --every 5s probe G1 X{i};G30")
+                .unwrap(),
+            Command::Repeat(
+                "probe",
+                vec!["G1 X{i}", "G30"],
+                RepeatOptions {
+                    every: Some(Duration::from_secs(5)),
+                    times: None,
+                    sweep: Some(Sweep {
+                        start: 0.0,
+                        end: 100.0,
+                        step: 10.0
+                    })
+                }
+            )
+        );
+        let Command::Repeat(_, _, options) = parse_command
+            .parse("repeat --range -0.2..0.2 zoffset M851 Z{i}")
+            .unwrap()
+        else {
+            panic!("not a repeat");
+        };
+        assert_eq!(
+            options.sweep,
+            Some(Sweep {
+                start: -0.2,
+                end: 0.2,
+                step: 1.0
+            })
+        );
+        assert!(parse_sweep.parse("0..10 step 0").is_err());
+        assert!(parse_sweep.parse("0..inf").is_err());
+    }
+
+    #[test]
+    fn baud() {
+        assert_eq!(
+            parse_command.parse("baud 250000").unwrap(),
+            Command::Baud(250000)
+        );
+        // not a rate, so taken as a macro instead
+        assert_eq!(
+            parse_command.parse("baud fast").unwrap(),
+            Command::Gcodes(vec!["baud fast"])
+        );
+    }
+
+    #[test]
+    fn uppercase() {
+        assert_eq!(
+            parse_command.parse("uppercase off").unwrap(),
+            Command::Uppercase(false)
+        );
+        assert_eq!(
+            parse_command.parse("uppercase on ").unwrap(),
+            Command::Uppercase(true)
+        );
+    }
+
+    #[test]
+    fn echo_sent() {
+        assert_eq!(
+            parse_command.parse("echosent on").unwrap(),
+            Command::EchoSent(true)
+        );
+        assert_eq!(
+            parse_command.parse("echosent off").unwrap(),
+            Command::EchoSent(false)
+        );
+        // still just text to echo
+        assert_eq!(parse_command.parse("echo on").unwrap(), Command::Echo("on"));
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(
+            parse_command.parse("validate warn").unwrap(),
+            Command::Validate(Validation::Warn, None)
+        );
+        assert_eq!(
+            parse_command.parse("validate reject --klipper").unwrap(),
+            Command::Validate(Validation::Reject, Some(Protocol::Klipper))
+        );
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            parse_command.parse("set temp 210").unwrap(),
+            Command::Set("temp", "210")
+        );
+        assert_eq!(
+            parse_command.parse("set msg Hello there ").unwrap(),
+            Command::Set("msg", "Hello there")
+        );
+        assert_eq!(parse_command.parse("set").unwrap(), Command::Variables);
+        assert_eq!(
+            parse_command.parse("unset temp").unwrap(),
+            Command::Unset("temp")
+        );
+        assert!(!matches!(
+            parse_command.parse("set temp"),
+            Ok(Command::Set(..))
+        ));
+    }
+
+    #[test]
+    fn transcript() {
+        assert_eq!(
+            parse_command.parse("transcript session.txt").unwrap(),
+            Command::Transcript("session.txt")
+        );
+    }
+
+    #[test]
+    fn source() {
+        assert_eq!(
+            parse_command.parse("source setup.txt").unwrap(),
+            Command::Source("setup.txt", false)
+        );
+        assert_eq!(
+            parse_command.parse("source --strict setup.txt").unwrap(),
+            Command::Source("setup.txt", true)
+        );
+    }
+}
@@ -0,0 +1,121 @@
+/// Every console command name
+pub const COMMAND_NAMES: [&str; 40] = [
+    "log",
+    "transcript",
+    "repeat",
+    "print",
+    "dryrun",
+    "tasks",
+    "status",
+    "stop",
+    "abort",
+    "pause",
+    "resume",
+    "help",
+    "version",
+    "echo",
+    "echosent",
+    "validate",
+    "source",
+    "waitfor",
+    "disconnect",
+    "select",
+    "printers",
+    "connect",
+    "ports",
+    "baud",
+    "printerinfo",
+    "settings",
+    "macro",
+    "macros",
+    "delmacro",
+    "savemacros",
+    "loadmacros",
+    "exportmacros",
+    "record",
+    "endrecord",
+    "uppercase",
+    "set",
+    "unset",
+    "clear",
+    "quit",
+    "exit",
+];
+
+fn matching<'a>(
+    partial: &str,
+    names: impl IntoIterator<Item = &'a str>,
+    ignore_case: bool,
+) -> Vec<String> {
+    let mut found: Vec<String> = names
+        .into_iter()
+        .filter(|name| {
+            name.get(..partial.len()).is_some_and(|start| {
+                start == partial || (ignore_case && start.eq_ignore_ascii_case(partial))
+            })
+        })
+        .map(str::to_string)
+        .collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// Find completions for the word being typed at the end of `line`.
+///
+/// The first word completes to a command or macro name, the argument of
+/// `stop`, `pause` and `resume` to a task name, `help` to a command name and
+/// `delmacro` to a macro name. Macro names are matched ignoring case, like
+/// when they're run.
+///
+/// Returns the byte offset in `line` where the word being completed starts,
+/// along with the candidates that could replace it.
+pub fn complete<'a>(
+    line: &str,
+    macros: impl IntoIterator<Item = &'a str>,
+    tasks: impl IntoIterator<Item = &'a str>,
+) -> (usize, Vec<String>) {
+    let start = line.rfind(char::is_whitespace).map_or(0, |index| {
+        index + line[index..].chars().next().unwrap().len_utf8()
+    });
+    let partial = &line[start..];
+    let before: Vec<&str> = line[..start].split_whitespace().collect();
+    let candidates = match before[..] {
+        [] => {
+            let mut candidates = matching(partial, COMMAND_NAMES, false);
+            candidates.extend(matching(partial, macros, true));
+            candidates
+        }
+        ["stop" | "pause" | "resume"] => matching(partial, tasks, false),
+        ["help"] => matching(partial, COMMAND_NAMES, false),
+        ["delmacro"] => matching(partial, macros, true),
+        _ => Vec::new(),
+    };
+    (start, candidates)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_names() {
+        let (start, candidates) = complete("mac", ["HOME"], []);
+        assert_eq!(start, 0);
+        assert_eq!(candidates, vec!["macro", "macros"]);
+        let (_, candidates) = complete("h", ["HOME", "HEAT"], []);
+        assert_eq!(candidates, vec!["help", "HEAT", "HOME"]);
+        assert_eq!(complete("", [], []).1.len(), COMMAND_NAMES.len());
+    }
+
+    #[test]
+    fn arguments() {
+        let tasks = ["print", "log_temps", "poll"];
+        let (start, candidates) = complete("stop p", [], tasks);
+        assert_eq!(start, 5);
+        assert_eq!(candidates, vec!["poll", "print"]);
+        assert_eq!(complete("help dis", [], []).1, vec!["disconnect"]);
+        assert_eq!(complete("delmacro ho", ["HOME"], []).1, vec!["HOME"]);
+        assert!(complete("print f", ["FOO"], tasks).1.is_empty());
+    }
+}
@@ -1,53 +1,325 @@
 use {
     super::Command,
-    print3rs_core::Printer,
-    std::{borrow::Borrow, str::FromStr, time::Duration},
+    print3rs_core::{Printer, PrinterInfo, Protocol},
+    std::{borrow::Borrow, collections::HashSet, future::Future, str::FromStr, time::Duration},
     tokio::{
         io::BufReader,
+        task::JoinSet,
         time::{sleep, timeout},
     },
-    tokio_serial::{available_ports, SerialPort, SerialPortBuilderExt, SerialPortInfo},
+    tokio_serial::{
+        available_ports, FlowControl, Parity, SerialPort, SerialPortBuilderExt, SerialPortInfo,
+        SerialPortType, SerialStream, UsbPortInfo,
+    },
     winnow::{
-        ascii::{alpha0, dec_uint, space0},
-        combinator::{alt, dispatch, empty, opt, preceded, terminated},
+        ascii::{alpha0, alpha1, dec_uint, space0, space1},
+        combinator::{alt, dispatch, empty, fail, opt, preceded, repeat, terminated},
         prelude::*,
         token::take_till,
     },
 };
 
-/// Attempt to enumerate and establish a connection to a device,
-/// connecting and returning to said device if any were successful.
-///
-/// If no valid device is found, return a disconnected device.
-pub async fn auto_connect() -> Printer {
-    async fn check_port(port: SerialPortInfo) -> Option<Printer> {
-        tracing::debug!("checking port {}...", port.port_name);
-        let mut printer_port = tokio_serial::new(port.port_name, 115200)
+/// Baud rates tried by `auto_connect`, most common first
+pub const DEFAULT_BAUD_RATES: [u32; 4] = [115200, 250000, 57600, 230400];
+
+/// How long to wait for a reply at each baud rate before trying the next
+const BAUD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How a serial port is opened, besides its baud rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialOptions {
+    /// Assert DTR once the port is open. Most Arduino based boards restart when DTR
+    /// is asserted, so turn this off to connect to a printer without restarting it,
+    /// though some systems assert DTR whenever a port is opened anyway.
+    pub dtr: bool,
+    pub flow_control: FlowControl,
+    pub parity: Parity,
+}
+
+impl Default for SerialOptions {
+    fn default() -> Self {
+        Self {
+            dtr: true,
+            flow_control: FlowControl::None,
+            parity: Parity::None,
+        }
+    }
+}
+
+impl SerialOptions {
+    /// Open a serial port at the given baud rate with these options
+    pub fn open(&self, port_name: &str, baud: u32) -> tokio_serial::Result<SerialStream> {
+        let mut port = tokio_serial::new(port_name, baud)
+            .flow_control(self.flow_control)
+            .parity(self.parity)
             .timeout(Duration::from_secs(10))
-            .open_native_async()
-            .ok()?;
-        printer_port.write_data_terminal_ready(true).ok()?;
-        let printer = Printer::new(BufReader::new(printer_port));
+            .open_native_async()?;
+        port.write_data_terminal_ready(self.dtr)?;
+        Ok(port)
+    }
+}
+
+/// Switch the serial port a printer is connected through to another baud rate,
+/// keeping line subscriptions and sockets. The printer has to be told to switch as well,
+/// usually with `M575`, and that should be acknowledged before this is awaited.
+///
+/// Gives `Error::UnsupportedTransport` if the printer isn't connected by serial port.
+pub fn set_baud(
+    printer: &Printer,
+    baud: u32,
+) -> Result<impl Future<Output = Result<(), print3rs_core::Error>>, print3rs_core::Error> {
+    printer.configure_transport(move |port: &mut BufReader<SerialStream>| {
+        port.get_mut()
+            .set_baud_rate(baud)
+            .map_err(|e| std::io::Error::from(e).into())
+    })
+}
+
+/// Whether a line got its `ok` within `wait`, rather than an error such as the connection closing
+async fn acknowledged<E>(ack: impl Future<Output = Result<(), E>>, wait: Duration) -> bool {
+    matches!(timeout(wait, ack).await, Ok(Ok(_)))
+}
+
+/// Try to talk to a device on a port at a single baud rate,
+/// returning it if it acknowledges an `M115`
+async fn check_port(
+    port_name: &str,
+    baud: u32,
+    serial: SerialOptions,
+) -> Option<(Printer, PrinterInfo)> {
+    tracing::debug!("checking port {port_name} at {baud} baud...");
+    let printer_port = serial.open(port_name, baud).ok()?;
+    let printer = Printer::new(BufReader::new(printer_port));
+    let mut lines = printer.subscribe_lines().ok()?;
+
+    sleep(Duration::from_secs(1)).await;
+
+    let look_for_ok = printer.send_unsequenced(b"M115\n").await.ok()?;
+
+    if acknowledged(look_for_ok, BAUD_TIMEOUT).await {
+        // the whole M115 reply has been received once the ok arrives
+        let mut info = PrinterInfo {
+            baud: Some(baud),
+            port: Some(port_name.to_string()),
+            ..Default::default()
+        };
+        while let Ok(line) = lines.try_recv() {
+            info.parse_line(&line);
+        }
+        Some((printer, info))
+    } else {
+        None
+    }
+}
+
+/// USB vendor, and optionally product, id of a serial device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbId {
+    pub vid: u16,
+    /// Any product from the vendor matches if not given
+    pub pid: Option<u16>,
+}
+
+impl UsbId {
+    /// Match every device from a vendor
+    pub const fn vendor(vid: u16) -> Self {
+        Self { vid, pid: None }
+    }
+
+    /// Match a single product from a vendor
+    pub const fn product(vid: u16, pid: u16) -> Self {
+        Self {
+            vid,
+            pid: Some(pid),
+        }
+    }
+
+    pub fn matches(&self, vid: u16, pid: u16) -> bool {
+        self.vid == vid && self.pid.map_or(true, |own_pid| own_pid == pid)
+    }
+}
+
+/// USB to serial chips and microcontrollers commonly found on printer boards
+pub const KNOWN_PRINTER_USB_IDS: [UsbId; 7] = [
+    // WCH CH340/CH341
+    UsbId::vendor(0x1a86),
+    // FTDI
+    UsbId::vendor(0x0403),
+    // Prolific PL2303
+    UsbId::vendor(0x067b),
+    // Silicon Labs CP210x
+    UsbId::vendor(0x10c4),
+    // Arduino
+    UsbId::vendor(0x2341),
+    // STM32 native USB, used by Marlin on most 32 bit boards
+    UsbId::vendor(0x0483),
+    // LPC176x native USB, used by Marlin on SKR 1.x and Smoothieboard
+    UsbId::vendor(0x1d50),
+];
+
+/// How `auto_connect_with` looks for a printer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoConnectOptions {
+    /// Baud rates to try on each port, in order
+    pub baud_rates: Vec<u32>,
+    /// USB devices which are probably printers, these ports are tried first
+    pub usb_ids: Vec<UsbId>,
+    /// Only try ports matching `usb_ids`, instead of trying every other port afterwards
+    pub only_known: bool,
+    /// How each port is opened
+    pub serial: SerialOptions,
+}
+
+impl Default for AutoConnectOptions {
+    fn default() -> Self {
+        Self {
+            baud_rates: DEFAULT_BAUD_RATES.to_vec(),
+            usb_ids: KNOWN_PRINTER_USB_IDS.to_vec(),
+            only_known: false,
+            serial: SerialOptions::default(),
+        }
+    }
+}
 
-        sleep(Duration::from_secs(1)).await;
+impl AutoConnectOptions {
+    /// Order to try a port in, lower first, or `None` if it shouldn't be tried at all.
+    ///
+    /// Known USB devices come first, then other USB devices, then anything else,
+    /// with Bluetooth ports last since they tend to hang until timing out.
+    fn port_priority(&self, port_type: &SerialPortType) -> Option<u8> {
+        let priority = match port_type {
+            SerialPortType::UsbPort(usb)
+                if self.usb_ids.iter().any(|id| id.matches(usb.vid, usb.pid)) =>
+            {
+                0
+            }
+            SerialPortType::UsbPort(_) => 1,
+            SerialPortType::PciPort | SerialPortType::Unknown => 2,
+            SerialPortType::BluetoothPort => 3,
+        };
+        (priority == 0 || !self.only_known).then_some(priority)
+    }
 
-        let look_for_ok = printer.send_unsequenced(b"M115\n").await.ok()?;
+    /// Ports worth trying, in the order to try them, with only one port for each device
+    fn order_ports(&self, ports: Vec<SerialPortInfo>) -> Vec<SerialPortInfo> {
+        let mut ports: Vec<_> = ports
+            .into_iter()
+            .filter_map(|port| Some((self.port_priority(&port.port_type)?, port)))
+            .collect();
+        ports.sort_by_key(|(priority, _)| *priority);
+        let mut devices = HashSet::new();
+        ports
+            .into_iter()
+            .map(|(_, port)| port)
+            .filter(|port| devices.insert(device_key(port)))
+            .collect()
+    }
+}
 
-        if timeout(Duration::from_secs(5), look_for_ok).await.is_ok() {
-            Some(printer)
-        } else {
-            None
+/// Identify the physical device behind a port, which may be listed under more than one name,
+/// like both `/dev/tty.*` and `/dev/cu.*` on macOS or through symlinks
+fn device_key(port: &SerialPortInfo) -> String {
+    if let SerialPortType::UsbPort(UsbPortInfo {
+        vid,
+        pid,
+        serial_number: Some(serial_number),
+        ..
+    }) = &port.port_type
+    {
+        return format!("usb:{vid:04x}:{pid:04x}:{serial_number}");
+    }
+    let path = std::fs::canonicalize(&port.port_name)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| port.port_name.clone());
+    path.replacen("/dev/tty.", "/dev/cu.", 1)
+}
+
+/// One line describing a port for `list_ports`: its name, then for USB ports the
+/// product and manufacturer where the device reports them, its USB ids and serial number
+pub fn describe_port(port: &SerialPortInfo) -> String {
+    let details = match &port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            let mut details = String::from("USB");
+            for name in [&usb.product, &usb.manufacturer].into_iter().flatten() {
+                details.push(' ');
+                details.push_str(name);
+            }
+            details.push_str(&format!(" [{:04x}:{:04x}]", usb.vid, usb.pid));
+            // tells apart several of the same printer
+            if let Some(serial_number) = &usb.serial_number {
+                details.push_str(&format!(" serial {serial_number}"));
+            }
+            if KNOWN_PRINTER_USB_IDS
+                .iter()
+                .any(|id| id.matches(usb.vid, usb.pid))
+            {
+                details.push_str(", likely a printer");
+            }
+            details
         }
+        SerialPortType::PciPort => "PCI".to_string(),
+        SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+        SerialPortType::Unknown => "unknown device".to_string(),
+    };
+    format!("{}\t{details}\n", port.port_name)
+}
+
+/// Describe ports in order of their names, followed by how to connect to one
+pub fn describe_ports(mut ports: Vec<SerialPortInfo>) -> String {
+    if ports.is_empty() {
+        return "No serial ports found\n".to_string();
     }
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+    let mut description: String = ports.iter().map(describe_port).collect();
+    description.push_str("Connect to one with `connect serial <port> <baud?>`\n");
+    description
+}
+
+/// Describe every serial port available, to help pick one for `connect serial`
+pub fn list_ports() -> String {
+    match available_ports() {
+        Ok(ports) => describe_ports(ports),
+        Err(e) => format!("Couldn't list serial ports: {e}\n"),
+    }
+}
+
+/// Attempt to enumerate and establish a connection to a device,
+/// connecting and returning to said device if any were successful,
+/// along with whatever it reported about itself.
+///
+/// Every port is probed at once, each at all of `DEFAULT_BAUD_RATES` in turn,
+/// and the first to answer is used. The baud rate that worked is kept in the returned info.
+///
+/// If no valid device is found, return a disconnected device.
+pub async fn auto_connect() -> (Printer, PrinterInfo) {
+    auto_connect_with(&AutoConnectOptions::default()).await
+}
+
+/// Same as `auto_connect`, choosing ports and baud rates with the given options
+pub async fn auto_connect_with(options: &AutoConnectOptions) -> (Printer, PrinterInfo) {
     if let Ok(ports) = available_ports() {
         tracing::info!("found available ports: {ports:?}");
-        for port in ports {
-            if let Some(printer) = check_port(port).await {
-                return printer;
+        let mut probes = JoinSet::new();
+        for port in options.order_ports(ports) {
+            let baud_rates = options.baud_rates.clone();
+            let serial = options.serial;
+            probes.spawn(async move {
+                for baud in baud_rates {
+                    if let Some(connected) = check_port(&port.port_name, baud, serial).await {
+                        return Some(connected);
+                    }
+                }
+                None
+            });
+        }
+        // dropping the set once one answers aborts the rest, closing their ports
+        while let Some(probe) = probes.join_next().await {
+            if let Ok(Some(connected)) = probe {
+                return connected;
             }
         }
     }
-    Printer::Disconnected
+    (Printer::Disconnected, PrinterInfo::default())
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -82,6 +354,12 @@ pub enum Connection<S> {
         in_topic: Option<S>,
         out_topic: Option<S>,
     },
+    Moonraker {
+        url: S,
+    },
+    Unix {
+        path: S,
+    },
 }
 
 impl<T> Connection<T> {
@@ -92,6 +370,8 @@ impl<T> Connection<T> {
             Connection::Serial { .. } => "Serial",
             Connection::Tcp { .. } => "TCP/IP",
             Connection::Mqtt { .. } => "Mqtt",
+            Connection::Moonraker { .. } => "Moonraker",
+            Connection::Unix { .. } => "Unix socket",
         }
     }
 }
@@ -120,6 +400,12 @@ impl<'a> Connection<&'a str> {
                 in_topic: in_topic.map(|s| s.to_owned()),
                 out_topic: out_topic.map(|s| s.to_owned()),
             },
+            Connection::Moonraker { url } => Connection::Moonraker {
+                url: url.to_owned(),
+            },
+            Connection::Unix { path } => Connection::Unix {
+                path: path.to_owned(),
+            },
         }
     }
 }
@@ -150,6 +436,10 @@ impl Connection<String> {
                 in_topic: in_topic.as_ref().map(|s| s.borrow()),
                 out_topic: out_topic.as_ref().map(|s| s.borrow()),
             },
+            Connection::Moonraker { url } => Connection::Moonraker { url: url.borrow() },
+            Connection::Unix { path } => Connection::Unix {
+                path: path.borrow(),
+            },
         }
     }
 }
@@ -194,21 +484,213 @@ fn parse_mqtt_connection<'a>(input: &mut &'a str) -> PResult<Connection<&'a str>
     })
 }
 
+fn parse_moonraker_connection<'a>(input: &mut &'a str) -> PResult<Connection<&'a str>> {
+    let url = terminated(preceded(space0, take_till(1.., ' ')), space0).parse_next(input)?;
+    Ok(Connection::Moonraker { url })
+}
+
+fn parse_unix_connection<'a>(input: &mut &'a str) -> PResult<Connection<&'a str>> {
+    let path = terminated(preceded(space0, take_till(1.., ' ')), space0).parse_next(input)?;
+    Ok(Connection::Unix { path })
+}
+
+/// How to talk to a printer once connected, given as flags before or after the connection details
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectOptions {
+    /// Protocol to talk to the printer with. When not given, autoconnecting picks it by
+    /// the firmware the printer reports, otherwise the Marlin protocol is used.
+    pub protocol: Option<Protocol>,
+    /// Used for serial ports, including the ones tried by autoconnecting
+    pub serial: SerialOptions,
+}
+
+#[derive(Clone)]
+enum ConnectFlag {
+    Protocol(Protocol),
+    NoDtr,
+    FlowControl(FlowControl),
+    Parity(Parity),
+}
+
+fn parse_flow_control(input: &mut &str) -> PResult<FlowControl> {
+    dispatch! {alpha1;
+        "none" => empty.value(FlowControl::None),
+        "software" => empty.value(FlowControl::Software),
+        "hardware" => empty.value(FlowControl::Hardware),
+        _ => fail,
+    }
+    .parse_next(input)
+}
+
+fn parse_parity(input: &mut &str) -> PResult<Parity> {
+    dispatch! {alpha1;
+        "none" => empty.value(Parity::None),
+        "odd" => empty.value(Parity::Odd),
+        "even" => empty.value(Parity::Even),
+        _ => fail,
+    }
+    .parse_next(input)
+}
+
+fn parse_connect_flag(input: &mut &str) -> PResult<ConnectFlag> {
+    preceded(
+        space0,
+        alt((
+            "--klipper".value(ConnectFlag::Protocol(Protocol::Klipper)),
+            "--marlin".value(ConnectFlag::Protocol(Protocol::Marlin)),
+            "--no-dtr".value(ConnectFlag::NoDtr),
+            preceded(("--flow", space1), parse_flow_control).map(ConnectFlag::FlowControl),
+            preceded(("--parity", space1), parse_parity).map(ConnectFlag::Parity),
+        )),
+    )
+    .parse_next(input)
+}
+
+fn parse_connect_flags(input: &mut &str) -> PResult<Vec<ConnectFlag>> {
+    repeat(0.., parse_connect_flag).parse_next(input)
+}
+
 /// Parse connection details from a string, for any known protocol
+///
+/// Flags can go before or after the details: `--klipper` and `--marlin` choose the
+/// protocol instead of leaving it to autodetection, and `--no-dtr`, `--flow <none|software|hardware>` and `--parity <none|odd|even>`
+/// change how serial ports are opened.
 pub fn parse_connection<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    let mut flags = parse_connect_flags.parse_next(input)?;
     let connection = dispatch! { preceded(space0, alpha0);
         "serial" => parse_serial_connection,
         "tcp" | "ip" => parse_tcp_connection,
         "mqtt" => parse_mqtt_connection,
+        "moonraker" => parse_moonraker_connection,
+        "unix" => parse_unix_connection,
         _ => empty.map(|_| Connection::Auto),
     }
     .parse_next(input)?;
-    Ok(Command::Connect(connection))
+    flags.extend(terminated(parse_connect_flags, space0).parse_next(input)?);
+    let mut options = ConnectOptions::default();
+    for flag in flags {
+        match flag {
+            ConnectFlag::Protocol(protocol) => options.protocol = Some(protocol),
+            ConnectFlag::NoDtr => options.serial.dtr = false,
+            ConnectFlag::FlowControl(flow_control) => options.serial.flow_control = flow_control,
+            ConnectFlag::Parity(parity) => options.serial.parity = parity,
+        }
+    }
+    Ok(Command::Connect(connection, options))
 }
 
 #[cfg(test)]
 mod test {
-    use super::*;
+    use {super::*, print3rs_core::duplex_printer, tokio::io::AsyncWriteExt};
+
+    #[tokio::test]
+    async fn acknowledgement() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let ack = printer.send_unsequenced("M115").await.unwrap();
+        host_side.write_all(b"ok\n").await.unwrap();
+        assert!(acknowledged(ack, BAUD_TIMEOUT).await);
+
+        // a closed connection gives up on the line straight away, which isn't an ok
+        let ack = printer.send_unsequenced("M115").await.unwrap();
+        drop(host_side);
+        assert!(!acknowledged(ack, BAUD_TIMEOUT).await);
+    }
+
+    #[test]
+    fn usb_id_matching() {
+        assert!(UsbId::vendor(0x1a86).matches(0x1a86, 0x7523));
+        assert!(UsbId::product(0x1a86, 0x7523).matches(0x1a86, 0x7523));
+        assert!(!UsbId::product(0x1a86, 0x7523).matches(0x1a86, 0x5523));
+        assert!(!UsbId::vendor(0x1a86).matches(0x0403, 0x7523));
+    }
+
+    #[test]
+    fn port_order() {
+        let port = |name: &str, port_type| SerialPortInfo {
+            port_name: name.to_string(),
+            port_type,
+        };
+        let ports = vec![
+            port("bluetooth", SerialPortType::BluetoothPort),
+            port("unknown", SerialPortType::Unknown),
+        ];
+        let ordered = AutoConnectOptions::default().order_ports(ports.clone());
+        assert_eq!(ordered[0].port_name, "unknown");
+        assert_eq!(ordered[1].port_name, "bluetooth");
+        let only_known = AutoConnectOptions {
+            only_known: true,
+            ..Default::default()
+        };
+        assert!(only_known.order_ports(ports).is_empty());
+    }
+
+    #[test]
+    fn port_descriptions() {
+        let usb = SerialPortInfo {
+            port_name: "COM3".to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: 0x2c99,
+                pid: 0x0002,
+                serial_number: None,
+                manufacturer: Some("Prusa Research (prusa3d.com)".to_string()),
+                product: Some("Original Prusa i3 MK3".to_string()),
+            }),
+        };
+        assert_eq!(
+            describe_port(&usb),
+            "COM3\tUSB Original Prusa i3 MK3 Prusa Research (prusa3d.com) [2c99:0002]\n"
+        );
+        let ch340 = SerialPortInfo {
+            port_name: "/dev/ttyUSB0".to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: 0x1a86,
+                pid: 0x7523,
+                serial_number: None,
+                manufacturer: None,
+                product: None,
+            }),
+        };
+        assert_eq!(
+            describe_port(&ch340),
+            "/dev/ttyUSB0\tUSB [1a86:7523], likely a printer\n"
+        );
+    }
+
+    #[test]
+    fn port_listing() {
+        let port = |port_name: &str, serial_number: Option<&str>| SerialPortInfo {
+            port_name: port_name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: 0x0483,
+                pid: 0x5740,
+                serial_number: serial_number.map(str::to_string),
+                manufacturer: None,
+                product: None,
+            }),
+        };
+        assert_eq!(
+            describe_ports(vec![port("COM7", Some("A1")), port("COM3", None)]),
+            "COM3\tUSB [0483:5740], likely a printer\n\
+             COM7\tUSB [0483:5740] serial A1, likely a printer\n\
+             Connect to one with `connect serial <port> <baud?>`\n"
+        );
+        assert_eq!(describe_ports(Vec::new()), "No serial ports found\n");
+    }
+
+    #[test]
+    fn same_device_once() {
+        let port = |name: &str| SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: SerialPortType::Unknown,
+        };
+        let ordered = AutoConnectOptions::default().order_ports(vec![
+            port("/dev/cu.usbserial-1410"),
+            port("/dev/tty.usbserial-1410"),
+            port("/dev/cu.usbserial-1420"),
+        ]);
+        let names: Vec<_> = ordered.iter().map(|port| port.port_name.as_str()).collect();
+        assert_eq!(names, ["/dev/cu.usbserial-1410", "/dev/cu.usbserial-1420"]);
+    }
 
     #[test]
     fn serial_space_parsing() {
@@ -272,6 +754,36 @@ mod test {
         );
     }
 
+    #[test]
+    fn moonraker_parsing() {
+        let moonraker = parse_moonraker_connection
+            .parse(" http://printer.local:7125 ")
+            .unwrap();
+        assert_eq!(
+            moonraker,
+            Connection::Moonraker {
+                url: "http://printer.local:7125"
+            }
+        );
+    }
+
+    #[test]
+    fn unix_parsing() {
+        let Command::Connect(unix, options) = parse_connection
+            .parse(" unix /tmp/printer --klipper")
+            .unwrap()
+        else {
+            panic!("not a connect");
+        };
+        assert_eq!(
+            unix,
+            Connection::Unix {
+                path: "/tmp/printer"
+            }
+        );
+        assert_eq!(options.protocol, Some(Protocol::Klipper));
+    }
+
     #[test]
     fn mqtt_default_parsing() {
         let mqtt = parse_mqtt_connection.parse("printer.local").unwrap();
@@ -336,10 +848,81 @@ mod test {
         let command = parse_connection.parse(input).unwrap();
         assert_eq!(
             command,
-            Command::Connect(Connection::Serial {
-                port: "COM1",
-                baud: Some(9600)
-            })
+            Command::Connect(
+                Connection::Serial {
+                    port: "COM1",
+                    baud: Some(9600)
+                },
+                ConnectOptions::default()
+            )
+        );
+    }
+
+    #[test]
+    fn klipper_flag_parse() {
+        let command = parse_connection
+            .parse(" tcp printer.local:8888 --klipper")
+            .unwrap();
+        assert_eq!(
+            command,
+            Command::Connect(
+                Connection::Tcp {
+                    hostname: "printer.local",
+                    port: Some(8888)
+                },
+                ConnectOptions {
+                    protocol: Some(Protocol::Klipper),
+                    ..Default::default()
+                }
+            )
         );
+        let command = parse_connection.parse(" --klipper").unwrap();
+        assert_eq!(
+            command,
+            Command::Connect(
+                Connection::Auto,
+                ConnectOptions {
+                    protocol: Some(Protocol::Klipper),
+                    ..Default::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn serial_flags_parse() {
+        let Command::Connect(connection, options) = parse_connection
+            .parse(" serial /dev/ttyUSB0 250000 --no-dtr --flow hardware --parity even")
+            .unwrap()
+        else {
+            panic!("not a connect command");
+        };
+        assert_eq!(
+            connection,
+            Connection::Serial {
+                port: "/dev/ttyUSB0",
+                baud: Some(250000)
+            }
+        );
+        assert_eq!(
+            options.serial,
+            SerialOptions {
+                dtr: false,
+                flow_control: FlowControl::Hardware,
+                parity: Parity::Even,
+            }
+        );
+        let Command::Connect(_, options) = parse_connection.parse(" --marlin").unwrap() else {
+            panic!("not a connect command");
+        };
+        assert_eq!(options.protocol, Some(Protocol::Marlin));
+        let Command::Connect(connection, options) =
+            parse_connection.parse(" --no-dtr --klipper").unwrap()
+        else {
+            panic!("not a connect command");
+        };
+        assert_eq!(connection, Connection::Auto);
+        assert_eq!(options.protocol, Some(Protocol::Klipper));
+        assert!(!options.serial.dtr);
     }
 }
@@ -0,0 +1,201 @@
+use {
+    crate::tasks::{gcode_line, gzip_lines},
+    print3rs_serializer::{Error as LineError, Sequenced},
+    std::fmt::Display,
+};
+
+/// Longest line, including sequence number and checksum, a default Marlin build accepts
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 96;
+
+/// Commands whose arguments are free text instead of letter/number parameters
+const TEXT_COMMANDS: [&str; 8] = ["M23", "M28", "M30", "M32", "M33", "M117", "M118", "M928"];
+
+/// Something wrong with a line of Gcode
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// First word isn't a G, M or T code
+    UnknownCommand(String),
+    /// Parameter isn't a letter followed by an optional number
+    InvalidParameter(String),
+    /// Same parameter letter given more than once
+    DuplicateParameter(char),
+    /// Line would be too long to send once numbered and checksummed
+    LineTooLong { length: usize, max: usize },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::UnknownCommand(command) => write!(f, "unknown command `{command}`"),
+            Problem::InvalidParameter(parameter) => write!(f, "invalid parameter `{parameter}`"),
+            Problem::DuplicateParameter(letter) => write!(f, "parameter {letter} given twice"),
+            Problem::LineTooLong { length, max } => {
+                write!(f, "line is {length} bytes when sent, limit is {max}")
+            }
+        }
+    }
+}
+
+/// Split Gcode into letter/value words, which may or may not be separated by spaces.
+///
+/// Anything which isn't a letter followed by a number ends up in a word on its own.
+pub(crate) fn words(line: &str) -> Vec<&str> {
+    let is_value = |c: char| c.is_ascii_digit() || matches!(c, '.' | '-' | '+');
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while let Some(first) = rest.chars().next() {
+        let end = if first.is_ascii_alphabetic() {
+            1 + rest[1..]
+                .find(|c: char| !is_value(c))
+                .unwrap_or(rest.len() - 1)
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        words.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    words
+}
+
+fn valid_word(word: &str, letters: &str) -> bool {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .is_some_and(|letter| letters.contains(letter.to_ascii_uppercase()))
+        && (chars.as_str().is_empty() || chars.as_str().parse::<f32>().is_ok())
+}
+
+/// Check a single line of Gcode, which shouldn't have comments.
+///
+/// `serializer` numbers lines the same way they would be when printed,
+/// so it should be given each line of a file in order.
+pub fn check_line(line: &str, serializer: &Sequenced) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let words = words(line);
+    if let Some((command, parameters)) = words.split_first() {
+        let command = command.to_ascii_uppercase();
+        if !valid_word(&command, "GMT") || command.len() < 2 {
+            problems.push(Problem::UnknownCommand(command));
+        } else if !TEXT_COMMANDS.contains(&command.as_str()) {
+            let mut seen = Vec::new();
+            for parameter in parameters {
+                if !valid_word(parameter, "ABCDEFGHIJKLMNOPQRSTUVWXYZ") {
+                    problems.push(Problem::InvalidParameter(parameter.to_string()));
+                    continue;
+                }
+                let letter = parameter.as_bytes()[0].to_ascii_uppercase() as char;
+                if seen.contains(&letter) {
+                    problems.push(Problem::DuplicateParameter(letter));
+                }
+                seen.push(letter);
+            }
+        }
+    }
+    if let Err(LineError::LineTooLong { length, max }) = serializer.try_serialize(line) {
+        problems.push(Problem::LineTooLong { length, max });
+    }
+    problems
+}
+
+/// Check every line in the contents of a Gcode file, the same way they'd be sent by `print`.
+///
+/// Returns problems found along with their 1-based line number in the file.
+pub fn check_file(contents: &str, max_line_length: usize) -> Vec<(usize, Problem)> {
+    check_lines(contents.lines(), max_line_length)
+}
+
+/// Check lines of a Gcode file as they're read, see `check_file`
+pub fn check_lines(
+    lines: impl IntoIterator<Item = impl AsRef<str>>,
+    max_line_length: usize,
+) -> Vec<(usize, Problem)> {
+    let serializer = Sequenced::new().with_max_line_length(max_line_length);
+    let mut problems = Vec::new();
+    for (index, line) in lines.into_iter().enumerate() {
+        if let Some(line) = gcode_line(line.as_ref()) {
+            problems.extend(
+                check_line(line, &serializer)
+                    .into_iter()
+                    .map(|problem| (index + 1, problem)),
+            );
+        }
+    }
+    problems
+}
+
+/// Check a gzip compressed Gcode file, decompressing it as it's checked, see `check_file`
+pub fn check_gzip_file(
+    filename: &str,
+    max_line_length: usize,
+) -> std::io::Result<Vec<(usize, Problem)>> {
+    let mut error = None;
+    let lines = gzip_lines(filename)?.map_while(|line| line.map_err(|e| error = Some(e)).ok());
+    let problems = check_lines(lines, max_line_length);
+    match error {
+        Some(e) => Err(e),
+        None => Ok(problems),
+    }
+}
+
+/// Summarize problems found in a file for display
+pub fn report(filename: &str, problems: &[(usize, Problem)]) -> String {
+    if problems.is_empty() {
+        return format!("No problems found in {filename}\n");
+    }
+    let mut report = format!("{} problems found in {filename}:\n", problems.len());
+    for (line, problem) in problems {
+        report.push_str(&format!("line {line}: {problem}\n"));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_words() {
+        assert_eq!(words("G1 X10.5 Y-2"), vec!["G1", "X10.5", "Y-2"]);
+        assert_eq!(words("G1X10Y20"), vec!["G1", "X10", "Y20"]);
+        assert_eq!(words("G28 X Y"), vec!["G28", "X", "Y"]);
+        assert_eq!(words("G1 X=10"), vec!["G1", "X", "=10"]);
+    }
+
+    #[test]
+    fn valid_lines() {
+        let serializer = Sequenced::new().with_max_line_length(DEFAULT_MAX_LINE_LENGTH);
+        assert!(check_line("G1 X10 Y20 E0.5 F1200", &serializer).is_empty());
+        assert!(check_line("G28 X Y", &serializer).is_empty());
+        assert!(check_line("T1", &serializer).is_empty());
+        assert!(check_line("M117 Hello: world!", &serializer).is_empty());
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let serializer = Sequenced::new().with_max_line_length(DEFAULT_MAX_LINE_LENGTH);
+        assert_eq!(
+            check_line("Q1 X10", &serializer),
+            vec![Problem::UnknownCommand("Q1".to_string())]
+        );
+        assert_eq!(
+            check_line("G1 X1.2.3", &serializer),
+            vec![Problem::InvalidParameter("X1.2.3".to_string())]
+        );
+        assert_eq!(
+            check_line("G1 X1 X2", &serializer),
+            vec![Problem::DuplicateParameter('X')]
+        );
+        let long = format!("M118 {}", "a".repeat(100));
+        assert!(matches!(
+            check_line(&long, &serializer)[..],
+            [Problem::LineTooLong { max: 96, .. }]
+        ));
+    }
+
+    #[test]
+    fn file_line_numbers() {
+        let problems = check_file("; start\nG28\n\nG1 X1 X2 ; move\n", DEFAULT_MAX_LINE_LENGTH);
+        assert_eq!(problems, vec![(4, Problem::DuplicateParameter('X'))]);
+        assert!(report("test.gcode", &problems).contains("line 4: parameter X given twice"));
+    }
+}
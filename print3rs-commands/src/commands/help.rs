@@ -1,10 +1,19 @@
 static FULL_HELP: &str = "    
 Anything entered not matching one of the following commands is uppercased and sent to
-the printer for it to interpret.
+the printer for it to interpret, use `uppercase off` to send it as written instead.
 
 Some commands cannot be ran until a printer is connected.
 
-Multiple Gcodes can be sent on the same line by separating with ';'.
+Multiple Gcodes can be sent on the same line by separating with ';'. A ';' inside double
+quotes is kept, like `RESPOND MSG=\"a;b\"`. File names with spaces can be put in double quotes,
+where `\\\"` is a quote and `\\\\` a backslash.
+
+Gcodes and macros can use variables made with `set`, e.g. `set temp 210` then `M104 S$temp`.
+
+Lines starting with '#' are comments and are ignored.
+
+Several printers can be used at once, each with its own tasks. Commands go to the selected
+printer, start a line with '@<name>' to send a command to another printer instead.
 
 Arguments with ? are optional.
 
@@ -12,26 +21,64 @@ Available commands:
 help         <command?>       display this message or details for specified command
 version                       display version
 clear                         clear all text on the screen
+echo         <text>           display the given text
+source       <--strict?> <file> run each line of a file as a command
+waitfor      <--timeout?> <--until?> <pattern> hold back later commands until the printer sends a matching line
 printerinfo                   display any information found about the connected printer
-print        <file>           send gcodes from file to printer
+settings     <action?>        display the printer's stored settings, or backup <file>, store or load them
+print        <--raw?> <file>  send gcodes from file to printer
+dryrun       <file>           check gcodes in file for problems without sending them
 log          <name> <pattern> begin logging parsed output from printer
-repeat       <name> <gcodes>  run the given gcodes in a loop until stop
+transcript   <file>           record every line sent to and received from the printer
+repeat       <--every?> <--times?> <--range?> <name> <gcodes> run the given gcodes in a loop until stop
+status                        show the connection, running tasks and temperatures together
 stop         <name>           stop an active print, log, or repeat
+abort                         stop prints and repeats, throwing away lines queued for the printer
+pause        <name>           pause an active print or repeat after the current line
+resume       <name>           continue a paused print or repeat
 macro        <name> <gcodes>  make an alias for a set of gcodes
 delmacro     <name>           remove an existing alias for set of gcodes
 macros                        list existing command aliases and contents           
+savemacros   <file?>          save macros to a file, or the default macro file
+loadmacros   <file?> <--replace?> load macros from a file, or the default macro file
+exportmacros <file?>          write macros as commands `source` can run, or show them
+record       <name>           keep every Gcode sent until endrecord, then make them a macro
+endrecord                     stop recording and make the recorded Gcodes into a macro
+uppercase    <on|off>         uppercase Gcodes before sending them, on by default
+echosent     <on|off>         show every line sent to the printer, off by default
+validate     <off|warn|reject> <--marlin|--klipper?> check Gcodes for malformed words before sending
+set          <name?> <value?> set a variable to fill into Gcodes as $name, or list them
+unset        <name>           remove a variable
 connect      <proto?> <args?> connect to a device using protocol and args, or attempt to autoconnect
+ports                         list serial ports, with the USB device behind each where known
+baud         <rate>           switch the connected serial port to another baud rate
 disconnect                    disconnect from printer
+select       <name>           send commands to the named printer, starting a new session if needed
+printers                      list printer sessions, the selected one is marked with *
 quit                          exit program
 \n";
 
-static PRINT_HELP: &str = "print: execute every line of G-code sequentially from the given file. The print job is added as a task which runs in the background with the filename as the task name. Other commands can be sent while a print is running, and a print can be stopped at any time with `stop`\n";
-static LOG_HELP: &str = "log: begin logging the specified pattern from the printer into a csv with the `name` given. This operation runs in the background and is added as a task which can be stopped with `stop`. The pattern given will be used to parse the logs, with values wrapped in `{}` being given a column of whatever is between the `{}`, and pulling a number in its place. If your pattern needs to include a literal `{` or `}`, double them up like `{{` or `}}` to have the parser read it as just a `{` or `}` in the output.\n";
-static REPEAT_HELP: &str = "repeat: repeat the given Gcodes (separated by gcode comment character `;`) in a loop until stopped. \n";
-static STOP_HELP: &str = "stop: stops a task running in the background. All background tasks are required to have a name, thus this command can be used to stop them. Tasks can also stop themselves if they fail or can complete, after which running this will do nothing.\n";
-static CONNECT_HELP: &str = "connect: Manually connect to a printer by specifying a protocol and some arguments. Arguments depend on protocol. For serial connection specify its path and optionally its baudrate. On windows this looks like `connect serial COM3 115200`, on linux more like `connect serial /dev/tty/ACM0 250000`. This does not test if the printer is capable of responding to messages, it will only open the port. Specifying no arguments will attempt autoconnection using serial.\n";
+static PRINT_HELP: &str = "print: execute every line of G-code sequentially from the given file. The print job is added as a task which runs in the background with the filename as the task name. Other commands can be sent while a print is running, and a print can be stopped at any time with `stop`. Blank lines and comments are skipped, use `print --raw <file>` to send every line exactly as written. Files ending in `.gz` are decompressed as they're printed. If there's a macro named `start` it's sent before the file, like to home and heat up, and a macro named `end` is sent after, like to cool down and park. The `end` macro is still sent if the print fails partway, but not if it's stopped.\n";
+static LOG_HELP: &str = "log: begin logging the specified pattern from the printer into a csv with the `name` given. This operation runs in the background and is added as a task which can be stopped with `stop`. The pattern given will be used to parse the logs, with values wrapped in `{}` being given a column of whatever is between the `{}`, and pulling a number in its place. Values are read as decimal numbers by default, add a type after the name like `{ticks:u32}`, `{count:i32}` or `{flags:hex}` to read them as exact integers or hexadecimal instead. Add a count like `{temp*4}` to capture that many values in a row, such as the temperature of each tool in `T:210 T:211`, read from wherever the next value starts and written in columns numbered from 0 like `temp_0`, `temp_1` and so on. If your pattern needs to include a literal `{` or `}`, double them up like `{{` or `}}` to have the parser read it as just a `{` or `}` in the output. Several patterns can be logged to the same file by separating them with ` --or `, like `log temps T:{T} --or X:{X}`, each line is matched against them in order and fills in the columns of the first one that matches. Add `--timestamp` before the name to include a first `time` column with the seconds since logging started that each line was received. Add `--format jsonl` to write one JSON object per line keyed by the value names instead of a csv. Add `--alert <value><comparison><number>`, e.g. `--alert T>250`, to be warned whenever a logged value goes from not meeting the condition to meeting it, comparisons can be <, <=, >, >=, == or !=. Add `--debug` to find out why a pattern isn't matching: every line received is written, with the line as the printer sent it in a last `line` column and empty values when no pattern matched it. If nothing has matched a minute after starting a warning says so, add `--summary <time>` like `--summary 30s` to be told how many lines have matched that often instead.\n";
+static REPEAT_HELP: &str = "repeat: repeat the given Gcodes (separated by gcode comment character `;`) in a loop until stopped. Add `--every <time>` before the name to wait between each time through the Gcodes, e.g. `repeat --every 1s temps M105` to poll temperatures each second. Times can be given in milliseconds, seconds or minutes, like `500ms`, `10s` or `5m`. Add `--times <count>` to go through the Gcodes only that many times, after which the task finishes by itself. Add `--range <start>..<end>` to sweep a value across the times through, filled in wherever the Gcodes have `{i}`, e.g. `repeat --range 0..200 step 50 sweep G1 X{i};G30` probes at X0, X50, X100, X150 and X200. The value counts up by 1 unless given `step <step>`, goes down if the end is below the start, and the task finishes after the end.\n";
+static STATUS_HELP: &str = "status: show what's going on in one place. You get where the printer is connected, the background tasks running as listed by `tasks`, and the current temperatures, which are asked for with `M105`. When no printer is connected it just says so.\n";
+static STOP_HELP: &str = "stop: stops a task running in the background. All background tasks are required to have a name, thus this command can be used to stop them. Prints finish sending the line they're on and wait for the printer to acknowledge it before stopping, for up to 10 seconds before they're cut off. Tasks can also stop themselves if they fail or can complete, after which running this will do nothing.\n";
+static ABORT_HELP: &str = "abort: stop every print, repeat and batch of Gcodes sending lines and throw away the lines already queued to be sent to the printer, without disconnecting. Unlike an emergency stop with `M112` the printer isn't halted, it finishes the few lines it has already been sent and is ready for more, so this is for abandoning a print you've changed your mind about. The end of print macro isn't run, so heaters and motors are left as they were. Other tasks like logs and transcripts carry on, use `stop` for those.\n";
+static PORTS_HELP: &str = "ports: list the serial ports a printer could be connected to with `connect serial <port>`. USB ports show the product and manufacturer the device reports, where it does, its USB vendor and product ids, and its serial number if it has one, so it's easier to tell which of `COM3` and `COM7` is the printer. Ports are listed in order of their names. Ports using a USB to serial chip often found on printer boards are marked as likely printers.\n";
+static CONNECT_HELP: &str = "connect: Manually connect to a printer by specifying a protocol and some arguments. Arguments depend on protocol. For serial connection specify its path and optionally its baudrate. On windows this looks like `connect serial COM3 115200`, on linux more like `connect serial /dev/tty/ACM0 250000`. This does not test if the printer is capable of responding to messages, it will only open the port. Specifying no arguments will attempt autoconnection using serial, trying each port at 115200, 250000, 57600 and 230400 baud. Autoconnecting picks the protocol by the firmware the printer reports. Add `--klipper` when connecting to a Klipper printer any other way, which doesn't accept line numbers or checksums, or `--marlin` to use line numbers and checksums whatever the printer reports. Serial ports can be opened with `--no-dtr`, to avoid restarting boards that reset when DTR is asserted, `--flow <none|software|hardware>` and `--parity <none|odd|even>`, which also apply to autoconnection. Klipper printers behind Moonraker can be reached through its API with `connect moonraker http://printer.local:7125`. On Linux and macOS a printer exposed on a Unix domain socket can be connected to with `connect unix <path>`.\n";
+static SOURCE_HELP: &str = "source: run every line of the given file as if it was typed into the console, useful for connecting and setting up macros in one go. Blank lines and lines starting with `#` are skipped. If a line fails its line number is reported and the rest of the file still runs, use `source --strict <file>` to stop at the first failure instead.\n";
+static WAITFOR_HELP: &str = "waitfor: wait until the printer sends a line matching the given pattern before running any of the commands given after it, e.g. `waitfor echo:busy: paused for user` in a sourced script. Commands typed or sourced while waiting are held back and run in order once a line matches, `tasks` shows how many are waiting and `stop waitfor` gives up waiting and throws them away. Patterns are the same as for `log`, so a plain piece of text matches any line containing it, and `{value}`s can be used with `--until <value><comparison><number>` to wait for a value to reach a condition, e.g. `waitfor --until T>=200 T:{T}` to wait for the hotend to heat up. Gives up and throws away the held back commands after 10 minutes, use `--timeout <time>` like `--timeout 30s` to wait a different time.\n";
+static SETTINGS_HELP: &str = "settings: display the settings stored in the printer's EEPROM, as reported by `M503`, grouped under the headings the printer gives them. Use `settings backup <file>` to save them as Gcode which puts every setting back the way it was when sent with `print`, a good idea before changing anything. `settings store` saves the settings currently in use to EEPROM with `M500` so they survive a restart, and `settings load` throws away unsaved changes by reloading them from EEPROM with `M501`.\n";
+static BAUD_HELP: &str = "baud: switch the serial port the printer is connected through to a different baud rate without disconnecting, so running tasks and logs carry on. The printer has to switch too, which for Marlin means sending `M575 B<rate>` first, e.g. `M575 B250000` then `baud 250000`.\n";
+static TRANSCRIPT_HELP: &str = "transcript: record every line sent to the printer and every line it sends back into the given file, marked `>` and `<` respectively after the seconds since recording started. Sent lines are written exactly as they went out, with line numbers, checksums and resends, which makes this the place to look when a printer misbehaves. Runs as the `transcript` task until stopped with `stop transcript` or the printer disconnects, starting another replaces it.\n";
+static EXPORTMACROS_HELP: &str = "exportmacros: write every macro as a `macro <name> <steps>` command, one per line, to share them as plain text. With a file the commands are written there, otherwise they're shown in the console to copy. `source <file>` or pasting the lines into the console makes the same macros again. Use `savemacros` and `loadmacros` to keep macros between sessions instead.\n";
+static RECORD_HELP: &str = "record: keep every Gcode sent from the console from now on, then make them into a macro with the given name when `endrecord` is entered, e.g. `record level`, jog and probe by hand, then `endrecord`. Gcodes are kept as they're sent, with macros expanded and variables filled in, including Gcodes from `source`. Lines sent by prints and repeats aren't kept. An existing macro with the same name is replaced.\n";
+static UPPERCASE_HELP: &str = "uppercase: choose whether Gcodes entered, and the steps of macros made or loaded afterwards, are uppercased before they're sent. It's on by default since Marlin expects uppercase, turn it off with `uppercase off` for Klipper commands with case sensitive values like `RESPOND MSG=\"Hello\"`. Macro names are case insensitive either way.\n";
+static ECHOSENT_HELP: &str = "echosent: choose whether every line sent to the printer is shown in the console, marked with `>>`. Lines are shown exactly as they went out, after macros and variables are filled in, uppercased, and with any line number and checksum, which helps check what a macro really sends. Lines from prints and repeats are shown too. It runs as the `echosent` task, so it stops when the printer disconnects, and `echosent off` or `stop echosent` turn it off. Use `transcript` to keep a record in a file instead.\n";
+static VALIDATE_HELP: &str = "validate: check Gcodes entered in the console before they're sent, for move letters without a value like the `X` in `G1 X Y10`, and for parentheses which aren't closed or opened. Each problem is shown with its column. `validate warn` sends the Gcodes anyway after showing what's wrong, `validate reject` sends none of the Gcodes entered on that line, and `validate off` stops checking, which is the default. The choice is kept for each protocol, since firmware tolerate different things, add `--marlin` or `--klipper` to change only that protocol, otherwise both are changed. With Klipper, parentheses and `;` inside double quotes are left alone. Prints aren't checked, use `dryrun` on the file instead.\n";
+static SET_HELP: &str = "set: set a variable the host fills into Gcodes and macros wherever `$name` appears, e.g. `set temp 210` then `M104 S$temp`, or `macro heat M104 S$temp` to heat to whatever `temp` is when `heat` is used. Names are case insensitive and made of letters, numbers and `_`, values are everything after the name. Sending a Gcode using a variable that isn't set fails instead of sending it, use `$$` for a literal `$`. `set` on its own lists every variable and `unset <name>` removes one.\n";
 static DISCONNECT_HELP: &str = "disconnect: disconnect from the currently connected printer. All active tasks will be stopped\n";
-static MACRO_HELP: &str = "create a case-insensitve alias to some set of gcodes, even containing other macros recursively to build up complex sets of builds with a single word. Macro names cannot be a single uppercase letter followed by a number, e.g. H105, to avoid conflict with Gcodes. Names can have any mix of alphanumeric, -, ., and _ characters. Commands in a macro are separated by ';', and macros can be used anywhere Gcodes are passed, including repeat commands and sends.\n";
+static MACRO_HELP: &str = "create a case-insensitve alias to some set of gcodes, even containing other macros recursively to build up complex sets of builds with a single word. Macro names cannot be a single uppercase letter followed by a number, e.g. H105, to avoid conflict with Gcodes. Names can have any mix of alphanumeric, -, ., and _ characters. Commands in a macro are separated by ';', and macros can be used anywhere Gcodes are passed, including repeat commands and sends. Macros can take arguments after their name, separated by spaces, which fill in {0}, {1}, etc. in the macro's commands, e.g. `macro center G0 X{0} Y{1}` then `center 100 100`. Use {{ and }} for literal braces.\n";
 
 /// Gives additional information about commands available or details for a specific command
 pub fn help(command: &str) -> &'static str {
@@ -40,11 +87,25 @@ pub fn help(command: &str) -> &'static str {
     match command {
         "print" => PRINT_HELP,
         "log" => LOG_HELP,
+        "transcript" => TRANSCRIPT_HELP,
         "repeat" => REPEAT_HELP,
+        "status" => STATUS_HELP,
         "stop" => STOP_HELP,
+        "abort" => ABORT_HELP,
         "connect" => CONNECT_HELP,
+        "ports" => PORTS_HELP,
+        "baud" => BAUD_HELP,
         "disconnect" => DISCONNECT_HELP,
+        "source" => SOURCE_HELP,
+        "settings" => SETTINGS_HELP,
+        "waitfor" => WAITFOR_HELP,
         "macro" => MACRO_HELP,
+        "exportmacros" => EXPORTMACROS_HELP,
+        "record" | "endrecord" => RECORD_HELP,
+        "uppercase" => UPPERCASE_HELP,
+        "echosent" => ECHOSENT_HELP,
+        "validate" => VALIDATE_HELP,
+        "set" | "unset" => SET_HELP,
         _ => FULL_HELP,
     }
 }
@@ -55,9 +116,23 @@ fn test_help() {
     assert_eq!(help(""), FULL_HELP);
     assert_eq!(help("print"), PRINT_HELP);
     assert_eq!(help("log"), LOG_HELP);
+    assert_eq!(help("transcript"), TRANSCRIPT_HELP);
     assert_eq!(help("repeat"), REPEAT_HELP);
+    assert_eq!(help("status"), STATUS_HELP);
     assert_eq!(help("stop"), STOP_HELP);
+    assert_eq!(help("abort"), ABORT_HELP);
     assert_eq!(help("connect"), CONNECT_HELP);
+    assert_eq!(help("ports"), PORTS_HELP);
+    assert_eq!(help("baud"), BAUD_HELP);
     assert_eq!(help("disconnect"), DISCONNECT_HELP);
+    assert_eq!(help("source"), SOURCE_HELP);
+    assert_eq!(help("settings"), SETTINGS_HELP);
+    assert_eq!(help("waitfor"), WAITFOR_HELP);
     assert_eq!(help("macro"), MACRO_HELP);
+    assert_eq!(help("exportmacros"), EXPORTMACROS_HELP);
+    assert_eq!(help("endrecord"), RECORD_HELP);
+    assert_eq!(help("uppercase"), UPPERCASE_HELP);
+    assert_eq!(help("echosent"), ECHOSENT_HELP);
+    assert_eq!(help("validate"), VALIDATE_HELP);
+    assert_eq!(help("unset"), SET_HELP);
 }
@@ -1,21 +1,233 @@
 use winnow::{
-    ascii::{float, space1},
-    combinator::{alt, delimited, dispatch, empty, fail, preceded, repeat, rest},
+    ascii::{alphanumeric1, dec_int, dec_uint, float, hex_uint, space1},
+    combinator::{
+        alt, delimited, dispatch, empty, fail, opt, preceded, repeat, rest, separated, terminated,
+    },
     prelude::*,
     stream::AsChar,
     token::{take, take_till, take_until},
 };
 use {
-    crate::commands::{identifier, Command},
+    crate::commands::{identifier, parse_duration, Command},
     core::borrow::Borrow,
+    std::{borrow::Cow, fmt::Display, time::Duration},
     winnow::ascii::space0,
 };
 
+/// How a captured value should be read, given as a hint like `{name:u32}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueType {
+    #[default]
+    Float,
+    Unsigned,
+    Signed,
+    Hex,
+}
+
+/// A single value captured from a log line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogValue {
+    Float(f32),
+    Unsigned(u32),
+    Signed(i32),
+    Hex(u32),
+}
+
+impl LogValue {
+    pub fn as_f64(self) -> f64 {
+        match self {
+            LogValue::Float(value) => value.into(),
+            LogValue::Unsigned(value) | LogValue::Hex(value) => value.into(),
+            LogValue::Signed(value) => value.into(),
+        }
+    }
+
+    /// Value as a JSON literal, non-finite floats become `null` and hex is kept as a string
+    fn to_json(self) -> String {
+        match self {
+            LogValue::Float(value) if !value.is_finite() => "null".to_string(),
+            LogValue::Hex(_) => format!("\"{self}\""),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl Display for LogValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogValue::Float(value) => write!(f, "{value}"),
+            LogValue::Unsigned(value) => write!(f, "{value}"),
+            LogValue::Signed(value) => write!(f, "{value}"),
+            LogValue::Hex(value) => write!(f, "{value:#x}"),
+        }
+    }
+}
+
+/// File format a log is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line, keyed by value labels
+    JsonLines,
+}
+
+impl LogFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            LogFormat::Csv => "csv",
+            LogFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// How an alert compares a value with its threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    pub fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Less => value < threshold,
+            Comparison::LessEqual => value <= threshold,
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterEqual => value >= threshold,
+            Comparison::Equal => value == threshold,
+            Comparison::NotEqual => value != threshold,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        })
+    }
+}
+
+/// Warn when a logged value meets a condition, given like `--alert temp>250`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert<S> {
+    /// Label of the `{value}` in the pattern to check
+    pub label: S,
+    pub comparison: Comparison,
+    pub threshold: f64,
+}
+
+impl<S> Alert<S> {
+    pub fn triggered_by(&self, value: LogValue) -> bool {
+        self.comparison.holds(value.as_f64(), self.threshold)
+    }
+}
+
+impl<S: Display> Display for Alert<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{}", self.label, self.comparison, self.threshold)
+    }
+}
+
+/// Extra settings for a log, given as flags before its name
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LogOptions<S> {
+    /// Add a first column with seconds since logging started
+    pub timestamp: bool,
+    pub format: LogFormat,
+    pub alerts: Vec<Alert<S>>,
+    /// Write every line received, with the raw line in a last `line` column,
+    /// leaving the value columns empty for lines no pattern matches
+    pub debug: bool,
+    /// Report how many lines have matched this often. Without it, a warning is only
+    /// given if nothing has matched after `NO_MATCH_WARNING`.
+    pub summary: Option<Duration>,
+}
+
+/// How long a log without `--summary` waits before warning that nothing has matched
+pub const NO_MATCH_WARNING: Duration = Duration::from_secs(60);
+
+impl LogOptions<String> {
+    pub fn to_borrowed<Borrowed: ?Sized>(&self) -> LogOptions<&Borrowed>
+    where
+        String: Borrow<Borrowed>,
+    {
+        LogOptions {
+            timestamp: self.timestamp,
+            format: self.format,
+            alerts: self
+                .alerts
+                .iter()
+                .map(|alert| Alert {
+                    label: alert.label.borrow(),
+                    comparison: alert.comparison,
+                    threshold: alert.threshold,
+                })
+                .collect(),
+            debug: self.debug,
+            summary: self.summary,
+        }
+    }
+}
+
+impl<'a> LogOptions<&'a str> {
+    pub fn into_owned(self) -> LogOptions<String> {
+        LogOptions {
+            timestamp: self.timestamp,
+            format: self.format,
+            alerts: self
+                .alerts
+                .into_iter()
+                .map(|alert| Alert {
+                    label: alert.label.to_owned(),
+                    comparison: alert.comparison,
+                    threshold: alert.threshold,
+                })
+                .collect(),
+            debug: self.debug,
+            summary: self.summary,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum LogFlag<'a> {
+    Timestamp,
+    Format(LogFormat),
+    Alert(Alert<&'a str>),
+    Debug,
+    Summary(Duration),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Segment<S> {
     Tag(S),
     Escaped(char),
-    Value(S),
+    /// A value to capture with its label, or several in a row like `{temp*4}`
+    Value(S, ValueType, usize),
+}
+
+impl<S: AsRef<str>> Segment<S> {
+    /// Labels of the columns a segment's values go in, numbered from `label_0` when repeated
+    pub fn labels(&self) -> Vec<Cow<'_, str>> {
+        match self {
+            Segment::Value(label, _, 1) => vec![Cow::Borrowed(label.as_ref())],
+            Segment::Value(label, _, count) => (0..*count)
+                .map(|index| Cow::Owned(format!("{}_{index}", label.as_ref())))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl Segment<String> {
@@ -26,7 +238,7 @@ impl Segment<String> {
         match self {
             Segment::Tag(s) => Segment::Tag(s.borrow()),
             Segment::Escaped(c) => Segment::Escaped(*c),
-            Segment::Value(s) => Segment::Value(s.borrow()),
+            Segment::Value(s, kind, count) => Segment::Value(s.borrow(), *kind, *count),
         }
     }
 }
@@ -36,13 +248,23 @@ impl<'a> Segment<&'a str> {
         match self {
             Segment::Tag(s) => Segment::Tag(s.to_owned()),
             Segment::Escaped(c) => Segment::Escaped(c),
-            Segment::Value(s) => Segment::Value(s.to_owned()),
+            Segment::Value(s, kind, count) => Segment::Value(s.to_owned(), kind, count),
         }
     }
 }
 
+/// Goes between patterns logged to the same file
+const PATTERN_SEPARATOR: &str = " --or ";
+
+/// Text up to the next value, escape or pattern separator
 fn parse_tag<'a>(input: &mut &'a str) -> PResult<Segment<&'a str>> {
-    Ok(Segment::Tag(take_till(1.., ('{', '}')).parse_next(input)?))
+    let end = input.find(|c| c == '{' || c == '}').unwrap_or(input.len());
+    let end = input[..end].find(PATTERN_SEPARATOR).unwrap_or(end);
+    Ok(Segment::Tag(
+        take(end)
+            .verify(|tag: &str| !tag.is_empty())
+            .parse_next(input)?,
+    ))
 }
 
 fn parse_escape<'a>(input: &mut &'a str) -> PResult<Segment<&'a str>> {
@@ -54,9 +276,33 @@ fn parse_escape<'a>(input: &mut &'a str) -> PResult<Segment<&'a str>> {
     .parse_next(input)
 }
 
+fn parse_value_type(input: &mut &str) -> PResult<ValueType> {
+    dispatch! {alphanumeric1;
+        "f32" => empty.value(ValueType::Float),
+        "u32" => empty.value(ValueType::Unsigned),
+        "i32" => empty.value(ValueType::Signed),
+        "hex" => empty.value(ValueType::Hex),
+        _ => fail,
+    }
+    .parse_next(input)
+}
+
+/// `{name}`, optionally with a type like `{name:u32}` and a count like `{name*4}` or `{name:u32*4}`
 fn parse_value<'a>(input: &mut &'a str) -> PResult<Segment<&'a str>> {
+    let (name, kind, count) = delimited(
+        "{",
+        (
+            identifier,
+            opt(preceded(':', parse_value_type)),
+            opt(preceded('*', dec_uint.verify(|count: &usize| *count > 0))),
+        ),
+        "}",
+    )
+    .parse_next(input)?;
     Ok(Segment::Value(
-        delimited("{", identifier, "}").parse_next(input)?,
+        name,
+        kind.unwrap_or_default(),
+        count.unwrap_or(1),
     ))
 }
 
@@ -68,69 +314,211 @@ pub fn parse_segments<'a>(input: &mut &'a str) -> PResult<Vec<Segment<&'a str>>>
     repeat(1.., parse_segment).parse_next(input)
 }
 
+/// One or more patterns separated by ` --or `
+pub fn parse_patterns<'a>(input: &mut &'a str) -> PResult<Vec<Vec<Segment<&'a str>>>> {
+    separated(1.., parse_segments, PATTERN_SEPARATOR).parse_next(input)
+}
+
+fn parse_log_format(input: &mut &str) -> PResult<LogFormat> {
+    dispatch! {alphanumeric1;
+        "csv" => empty.value(LogFormat::Csv),
+        "jsonl" => empty.value(LogFormat::JsonLines),
+        _ => fail,
+    }
+    .parse_next(input)
+}
+
+fn parse_comparison(input: &mut &str) -> PResult<Comparison> {
+    alt((
+        "<=".value(Comparison::LessEqual),
+        ">=".value(Comparison::GreaterEqual),
+        "==".value(Comparison::Equal),
+        "!=".value(Comparison::NotEqual),
+        "<".value(Comparison::Less),
+        ">".value(Comparison::Greater),
+    ))
+    .parse_next(input)
+}
+
+pub fn parse_alert<'a>(input: &mut &'a str) -> PResult<Alert<&'a str>> {
+    (identifier, parse_comparison, float)
+        .map(|(label, comparison, threshold)| Alert {
+            label,
+            comparison,
+            threshold,
+        })
+        .parse_next(input)
+}
+
+fn parse_log_flag<'a>(input: &mut &'a str) -> PResult<LogFlag<'a>> {
+    alt((
+        "--timestamp".value(LogFlag::Timestamp),
+        "--debug".value(LogFlag::Debug),
+        preceded(("--summary", space1), parse_duration).map(LogFlag::Summary),
+        preceded(("--format", space1), parse_log_format).map(LogFlag::Format),
+        preceded(("--alert", space1), parse_alert).map(LogFlag::Alert),
+    ))
+    .parse_next(input)
+}
+
+fn parse_log_options<'a>(input: &mut &'a str) -> PResult<LogOptions<&'a str>> {
+    let flags: Vec<LogFlag> = repeat(0.., terminated(parse_log_flag, space1)).parse_next(input)?;
+    let mut options = LogOptions::default();
+    for flag in flags {
+        match flag {
+            LogFlag::Timestamp => options.timestamp = true,
+            LogFlag::Format(format) => options.format = format,
+            LogFlag::Alert(alert) => options.alerts.push(alert),
+            LogFlag::Debug => options.debug = true,
+            LogFlag::Summary(every) => options.summary = Some(every),
+        }
+    }
+    Ok(options)
+}
+
 pub fn parse_logger<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
     (
-        preceded(space0, identifier),
-        preceded(space1, parse_segments),
+        preceded(space0, parse_log_options),
+        identifier,
+        preceded(space1, parse_patterns),
     )
-        .map(|(name, segments)| Command::Log(name, segments))
+        .map(|(options, name, patterns)| Command::Log(name, patterns, options))
         .parse_next(input)
 }
 
-pub fn make_parser(segments: Vec<Segment<&str>>) -> impl FnMut(&mut &[u8]) -> PResult<Vec<f32>> {
-    let mut owned_segments = Vec::new();
-    for segment in segments {
-        owned_segments.push(segment.into_owned());
+fn parse_log_value(kind: ValueType, input: &mut &[u8]) -> PResult<LogValue> {
+    match kind {
+        ValueType::Float => float.map(LogValue::Float).parse_next(input),
+        ValueType::Unsigned => dec_uint.map(LogValue::Unsigned).parse_next(input),
+        ValueType::Signed => dec_int.map(LogValue::Signed).parse_next(input),
+        ValueType::Hex => preceded(opt(alt(("0x", "0X"))), hex_uint)
+            .map(LogValue::Hex)
+            .parse_next(input),
     }
-    let segments = owned_segments;
-    move |input: &mut &[u8]| -> PResult<Vec<f32>> {
-        let mut values = vec![];
+}
 
-        // skips up to pattern start
-        if let Some(first) = segments.first() {
-            match first {
-                Segment::Tag(tag) => {
-                    take_until(0.., tag.as_bytes()).void().parse_next(input)?;
-                }
-                Segment::Escaped(c) => {
-                    take_till(0.., |i| (*c as u8) == i)
-                        .void()
-                        .parse_next(input)?;
-                }
-                Segment::Value(_) => {
-                    take_till(0.., |i: u8| i.is_dec_digit() || [b'.', b'-'].contains(&i))
-                        .void()
-                        .parse_next(input)?;
-                }
-            };
+/// Whether a value of the given type could start with this byte
+fn starts_value(kind: ValueType, byte: u8) -> bool {
+    match kind {
+        ValueType::Float => byte.is_dec_digit() || matches!(byte, b'.' | b'-' | b'+'),
+        ValueType::Unsigned => byte.is_dec_digit(),
+        ValueType::Signed => byte.is_dec_digit() || matches!(byte, b'-' | b'+'),
+        ValueType::Hex => byte.is_hex_digit(),
+    }
+}
+
+/// Skip ahead to the first place a value of the given type can be read from,
+/// passing over anything that only looks like its start, like a `.` ending a sentence
+fn skip_to_value(kind: ValueType, input: &mut &[u8]) -> PResult<()> {
+    loop {
+        take_till(0.., |i: u8| starts_value(kind, i))
+            .void()
+            .parse_next(input)?;
+        let mut attempt = *input;
+        if input.is_empty() || parse_log_value(kind, &mut attempt).is_ok() {
+            return Ok(());
         }
-        for segment in segments.iter() {
-            match segment {
-                Segment::Tag(ref s) => {
-                    s.as_bytes().parse_next(input)?;
-                }
-                Segment::Escaped(mut c) => {
-                    c.parse_next(input)?;
-                }
-                Segment::Value(_) => {
-                    values.push(float.parse_next(input)?);
+        *input = &input[1..];
+    }
+}
+
+/// Match a line against one pattern, giving the values it captured
+fn parse_pattern(segments: &[Segment<String>], input: &mut &[u8]) -> PResult<Vec<LogValue>> {
+    let mut values = vec![];
+
+    // skips up to pattern start
+    if let Some(first) = segments.first() {
+        match first {
+            Segment::Tag(tag) => {
+                take_until(0.., tag.as_bytes()).void().parse_next(input)?;
+            }
+            Segment::Escaped(c) => {
+                take_till(0.., |i| (*c as u8) == i)
+                    .void()
+                    .parse_next(input)?;
+            }
+            Segment::Value(_, kind, _) => skip_to_value(*kind, input)?,
+        };
+    }
+    for segment in segments.iter() {
+        match segment {
+            Segment::Tag(ref s) => {
+                s.as_bytes().parse_next(input)?;
+            }
+            Segment::Escaped(mut c) => {
+                c.parse_next(input)?;
+            }
+            Segment::Value(_, kind, count) => {
+                values.push(parse_log_value(*kind, input)?);
+                // repeats are whatever values come next, like each tool's in `T:210 T:211`
+                for _ in 1..*count {
+                    skip_to_value(*kind, input)?;
+                    values.push(parse_log_value(*kind, input)?);
                 }
-            };
+            }
+        };
+    }
+    // ignores rest of pattern
+    rest.parse_next(input)?;
+    Ok(values)
+}
+
+/// Build a parser trying each pattern in order, giving the index of the first to match with its values
+pub fn make_parser(
+    patterns: Vec<Vec<Segment<&str>>>,
+) -> impl FnMut(&mut &[u8]) -> PResult<(usize, Vec<LogValue>)> {
+    let patterns: Vec<Vec<Segment<String>>> = patterns
+        .into_iter()
+        .map(|segments| segments.into_iter().map(Segment::into_owned).collect())
+        .collect();
+    move |input: &mut &[u8]| -> PResult<(usize, Vec<LogValue>)> {
+        let start = *input;
+        for (index, segments) in patterns.iter().enumerate() {
+            match parse_pattern(segments, input) {
+                Ok(values) => return Ok((index, values)),
+                Err(_) => *input = start,
+            }
         }
-        // ignores rest of pattern
-        rest.parse_next(input)?;
-        Ok(values)
+        fail.parse_next(input)
     }
 }
 
-pub fn get_headers(segments: &[Segment<impl AsRef<str>>]) -> String {
-    let mut s = String::new();
-    for segment in segments {
-        if let Segment::Value(label) = segment {
-            s.push_str(label.as_ref());
-            s.push(',');
+/// Labels of every value across all patterns, along with which of those columns each pattern's values go in.
+///
+/// Values with the same label share a column, even when they come from different patterns.
+/// Repeated values have a column each, see `Segment::labels`.
+pub fn value_columns<S: AsRef<str>>(
+    patterns: &[Vec<Segment<S>>],
+) -> (Vec<Cow<'_, str>>, Vec<Vec<usize>>) {
+    let mut labels: Vec<Cow<str>> = Vec::new();
+    let mut columns = Vec::new();
+    for segments in patterns {
+        let mut pattern_columns = Vec::new();
+        for segment in segments {
+            for label in segment.labels() {
+                let column = match labels.iter().position(|existing| *existing == label) {
+                    Some(column) => column,
+                    None => {
+                        labels.push(label);
+                        labels.len() - 1
+                    }
+                };
+                pattern_columns.push(column);
+            }
         }
+        columns.push(pattern_columns);
+    }
+    (labels, columns)
+}
+
+/// CSV header line naming each captured value, after `time` if timestamps are included
+pub fn get_headers<S: AsRef<str>>(patterns: &[Vec<Segment<S>>], timestamp: bool) -> String {
+    let (labels, _) = value_columns(patterns);
+    let mut s = String::new();
+    if timestamp {
+        s.push_str("time,");
     }
+    s.push_str(&labels.join(","));
     // strip trailing
     if s.ends_with(',') {
         s.pop();
@@ -139,6 +527,106 @@ pub fn get_headers(segments: &[Segment<impl AsRef<str>>]) -> String {
     s
 }
 
+/// Format a line matched by the log as a record in its file.
+///
+/// `row` has an entry for each column named in `labels`, empty when the matching pattern doesn't capture it.
+pub fn format_record(
+    format: LogFormat,
+    labels: &[String],
+    row: &[Option<LogValue>],
+    time: Option<Duration>,
+) -> String {
+    let mut record = String::new();
+    match format {
+        LogFormat::Csv => {
+            if let Some(time) = time {
+                record.push_str(&format!("{:.3},", time.as_secs_f64()));
+            }
+            for value in row {
+                if let Some(value) = value {
+                    record.push_str(&value.to_string());
+                }
+                record.push(',');
+            }
+            record.pop(); // remove trailing ','
+        }
+        LogFormat::JsonLines => {
+            record.push('{');
+            if let Some(time) = time {
+                record.push_str(&format!("\"time\":{:.3},", time.as_secs_f64()));
+            }
+            for (label, value) in labels.iter().zip(row) {
+                if let Some(value) = value {
+                    record.push_str(&format!("\"{label}\":{},", value.to_json()));
+                }
+            }
+            if record.ends_with(',') {
+                record.pop();
+            }
+            record.push('}');
+        }
+    }
+    record.push('\n');
+    record
+}
+
+/// Label of the column `--debug` logs put each raw line in
+pub const RAW_LINE_LABEL: &str = "line";
+
+/// A CSV field, quoted if it has anything a reader would otherwise take apart
+fn csv_field(field: &str) -> String {
+    let needs_quotes = field.contains([',', '"', '\n', '\r'])
+        || field.starts_with(char::is_whitespace)
+        || field.ends_with(char::is_whitespace);
+    if needs_quotes {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A JSON string literal
+fn json_string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_control() => literal.push_str(&format!("\\u{:04x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Add the raw line a record came from to the end of it, for `--debug` logs.
+///
+/// Also gives a CSV header its `line` column, from `RAW_LINE_LABEL` as the line.
+pub fn add_raw_line(format: LogFormat, record: &str, line: &str) -> String {
+    let record = record.strip_suffix('\n').unwrap_or(record);
+    let line = line.trim_end_matches(['\r', '\n']);
+    match format {
+        LogFormat::Csv if record.is_empty() => format!("{}\n", csv_field(line)),
+        LogFormat::Csv => format!("{record},{}\n", csv_field(line)),
+        LogFormat::JsonLines => {
+            let fields = record
+                .strip_prefix('{')
+                .and_then(|record| record.strip_suffix('}'))
+                .unwrap_or_default();
+            let separator = if fields.is_empty() { "" } else { "," };
+            format!(
+                "{{{fields}{separator}\"{RAW_LINE_LABEL}\":{}}}\n",
+                json_string(line)
+            )
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -149,9 +637,9 @@ mod tests {
         let input = " this {is}so12.?me{segm_2-ents}";
         let expected: &[Segment<&str>] = &[
             Tag(" this "),
-            Value("is"),
+            Value("is", ValueType::Float, 1),
             Tag("so12.?me"),
-            Value("segm_2-ents"),
+            Value("segm_2-ents", ValueType::Float, 1),
         ];
         let parsed = parse_segments.parse(input).unwrap();
         assert_eq!(expected, parsed);
@@ -159,20 +647,127 @@ mod tests {
 
     #[test]
     fn test_headers() {
-        let segments = [Tag("one"), Value("two"), Tag("three"), Value("four")];
-        let headers = get_headers(&segments);
+        let segments = [
+            Tag("one"),
+            Value("two", ValueType::Float, 1),
+            Tag("three"),
+            Value("four", ValueType::Hex, 1),
+        ];
+        let headers = get_headers(&[segments.to_vec()], false);
         assert_eq!(&headers, "two,four\n");
+        let headers = get_headers(&[segments.to_vec()], true);
+        assert_eq!(&headers, "time,two,four\n");
     }
 
     #[test]
     fn test_parsed_parser() {
         let parse_pattern = "millis: {millis},pos:{pos},current:{current}";
         let segments = parse_segments.parse(parse_pattern).unwrap();
-        let mut parser = make_parser(segments);
+        let mut parser = make_parser(vec![segments]);
         let final_out = parser
             .parse(b"millis: 1234.5,pos:-4.0,current:100")
             .unwrap();
-        assert_eq!(final_out, vec![1234.5, -4.0, 100.0]);
+        assert_eq!(
+            final_out.1,
+            vec![
+                LogValue::Float(1234.5),
+                LogValue::Float(-4.0),
+                LogValue::Float(100.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn typed_values() {
+        let parse_pattern = "ticks:{ticks:u32} enc:{enc:i32} flags:{flags:hex} t:{t:f32}";
+        let segments = parse_segments.parse(parse_pattern).unwrap();
+        assert_eq!(segments[1], Value("ticks", ValueType::Unsigned, 1));
+        assert_eq!(segments[5], Value("flags", ValueType::Hex, 1));
+        let mut parser = make_parser(vec![segments]);
+        let final_out = parser
+            .parse(b"ticks:4000000001 enc:-12345 flags:0x1F t:20.5")
+            .unwrap()
+            .1;
+        assert_eq!(
+            final_out,
+            vec![
+                LogValue::Unsigned(4000000001),
+                LogValue::Signed(-12345),
+                LogValue::Hex(0x1f),
+                LogValue::Float(20.5)
+            ]
+        );
+        let csv: Vec<String> = final_out.iter().map(ToString::to_string).collect();
+        assert_eq!(csv, ["4000000001", "-12345", "0x1f", "20.5"]);
+    }
+
+    #[test]
+    fn signs_and_exponents() {
+        let segments = parse_segments.parse("A:{a} B:{b} C:{c}").unwrap();
+        let mut parser = make_parser(vec![segments]);
+        assert_eq!(
+            parser.parse(b"A:+1.0 B:1e-3 C:-0.5").unwrap().1,
+            vec![
+                LogValue::Float(1.0),
+                LogValue::Float(1e-3),
+                LogValue::Float(-0.5)
+            ]
+        );
+        assert_eq!(
+            parser.parse(b"A:-2.5E2 B:+0 C:25").unwrap().1,
+            vec![
+                LogValue::Float(-250.0),
+                LogValue::Float(0.0),
+                LogValue::Float(25.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn leading_value() {
+        let segments = parse_segments.parse("{load}% busy").unwrap();
+        let mut parser = make_parser(vec![segments]);
+        // a lone `.` or `-` before the value isn't taken as its start
+        assert_eq!(
+            parser.parse(b"cpu. - +1.5e1% busy").unwrap().1,
+            vec![LogValue::Float(15.0)]
+        );
+        let segments = parse_segments.parse("{count:u32} left").unwrap();
+        let mut parser = make_parser(vec![segments]);
+        assert_eq!(
+            parser.parse(b"-.12 left").unwrap().1,
+            vec![LogValue::Unsigned(12)]
+        );
+        assert!(parser.parse(b"none left").is_err());
+    }
+
+    #[test]
+    fn repeated_values() {
+        let segments = parse_patterns.parse("T:{temp*2} E:{steps:u32*3}").unwrap();
+        assert_eq!(segments[0][1], Value("temp", ValueType::Float, 2));
+        assert_eq!(segments[0][3], Value("steps", ValueType::Unsigned, 3));
+        assert_eq!(
+            get_headers(&segments, false),
+            "temp_0,temp_1,steps_0,steps_1,steps_2\n"
+        );
+        let mut parser = make_parser(segments);
+        assert_eq!(
+            parser.parse(b"T:210.5 T:211 E:10,20,30").unwrap().1,
+            vec![
+                LogValue::Float(210.5),
+                LogValue::Float(211.0),
+                LogValue::Unsigned(10),
+                LogValue::Unsigned(20),
+                LogValue::Unsigned(30)
+            ]
+        );
+        assert!(parser.parse(b"T:210.5 E:10,20,30").is_err());
+        assert!(parse_segments.parse("T:{temp*0}").is_err());
+    }
+
+    #[test]
+    fn unknown_type_hint() {
+        assert!(parse_segments.parse("a:{a:u8}").is_err());
     }
 
     #[test]
@@ -185,7 +780,7 @@ mod tests {
                 Segment::Tag("some"),
                 Segment::Escaped('{'),
                 Segment::Tag("nested:"),
-                Segment::Value("stuff"),
+                Segment::Value("stuff", ValueType::Float, 1),
                 Segment::Escaped('}')
             ]
         );
@@ -195,11 +790,49 @@ mod tests {
     fn test_ignores_rest() {
         let parse_pattern = "millis: {millis},pos:{pos},current:{current}";
         let segments = parse_segments.parse(parse_pattern).unwrap();
-        let mut parser = make_parser(segments);
+        let mut parser = make_parser(vec![segments]);
         let final_out = parser
             .parse(b"a bunch of stuff{}{}{{}}.028millis: 1234.5,pos:-4.0,current:100,and a bunch of other stuff{}{}{{}}.028")
             .unwrap();
-        assert_eq!(final_out, vec![1234.5, -4.0, 100.0]);
+        assert_eq!(
+            final_out.1,
+            vec![
+                LogValue::Float(1234.5),
+                LogValue::Float(-4.0),
+                LogValue::Float(100.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn multiple_patterns() {
+        let segments = parse_patterns
+            .parse("T:{T} B:{B} --or X:{X} Y:{Y}|Z:{Z} T:{T}")
+            .unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1][4], Tag("|Z:"));
+        assert_eq!(get_headers(&segments, false), "T,B,X,Y,Z\n");
+        let (_, columns) = value_columns(&segments);
+        assert_eq!(columns, vec![vec![0, 1], vec![2, 3, 4, 0]]);
+
+        let mut parser = make_parser(segments);
+        assert_eq!(
+            parser.parse(b"X:1 Y:2|Z:3 T:4").unwrap(),
+            (
+                1,
+                vec![
+                    LogValue::Float(1.0),
+                    LogValue::Float(2.0),
+                    LogValue::Float(3.0),
+                    LogValue::Float(4.0)
+                ]
+            )
+        );
+        assert_eq!(
+            parser.parse(b"ok T:200 B:60").unwrap(),
+            (0, vec![LogValue::Float(200.0), LogValue::Float(60.0)])
+        );
+        assert!(parser.parse(b"echo:busy").is_err());
     }
 
     #[test]
@@ -208,6 +841,155 @@ mod tests {
         let _cmd = parse_logger.parse(log_cmd).unwrap();
     }
 
+    #[test]
+    fn timestamp_flag() {
+        let cmd = parse_logger.parse(" --timestamp temps T:{T}").unwrap();
+        assert_eq!(
+            cmd,
+            Command::Log(
+                "temps",
+                vec![vec![Tag("T:"), Value("T", ValueType::Float, 1)]],
+                LogOptions {
+                    timestamp: true,
+                    ..Default::default()
+                }
+            )
+        );
+        let cmd = parse_logger.parse(" temps T:{T}").unwrap();
+        assert!(matches!(
+            cmd,
+            Command::Log(
+                "temps",
+                _,
+                LogOptions {
+                    timestamp: false,
+                    ..
+                }
+            )
+        ));
+    }
+
+    #[test]
+    fn format_flag() {
+        let cmd = parse_logger
+            .parse(" --format jsonl --timestamp temps T:{T}")
+            .unwrap();
+        assert!(matches!(
+            cmd,
+            Command::Log(
+                "temps",
+                _,
+                LogOptions {
+                    timestamp: true,
+                    format: LogFormat::JsonLines,
+                    ..
+                }
+            )
+        ));
+        assert!(parse_logger.parse(" --format xml temps T:{T}").is_err());
+    }
+
+    #[test]
+    fn debug_flag() {
+        let cmd = parse_logger.parse(" --debug temps T:{T}").unwrap();
+        assert!(matches!(
+            cmd,
+            Command::Log("temps", _, LogOptions { debug: true, .. })
+        ));
+    }
+
+    #[test]
+    fn summary_flag() {
+        let Command::Log(_, _, options) = parse_logger
+            .parse(" --summary 30s --timestamp temps T:{T}")
+            .unwrap()
+        else {
+            panic!("not a log command");
+        };
+        assert_eq!(options.summary, Some(Duration::from_secs(30)));
+        assert!(options.timestamp);
+    }
+
+    #[test]
+    fn alert_flags() {
+        let cmd = parse_logger
+            .parse(" --alert T>=250 --alert pos<-0.5 temps T:{T} X:{pos}")
+            .unwrap();
+        let Command::Log(_, _, options) = cmd else {
+            panic!("not a log command");
+        };
+        assert_eq!(
+            options.alerts,
+            vec![
+                Alert {
+                    label: "T",
+                    comparison: Comparison::GreaterEqual,
+                    threshold: 250.0
+                },
+                Alert {
+                    label: "pos",
+                    comparison: Comparison::Less,
+                    threshold: -0.5
+                }
+            ]
+        );
+        assert_eq!(options.alerts[1].to_string(), "pos<-0.5");
+        assert!(options.alerts[0].triggered_by(LogValue::Float(250.0)));
+        assert!(!options.alerts[0].triggered_by(LogValue::Unsigned(249)));
+        assert!(options.alerts[1].triggered_by(LogValue::Signed(-1)));
+    }
+
+    #[test]
+    fn records() {
+        let labels = ["millis".to_string(), "pos".to_string(), "flags".to_string()];
+        let row = [
+            Some(LogValue::Float(1234.5)),
+            None,
+            Some(LogValue::Hex(0x1f)),
+        ];
+        assert_eq!(
+            format_record(LogFormat::Csv, &labels, &row, None),
+            "1234.5,,0x1f\n"
+        );
+        assert_eq!(
+            format_record(LogFormat::JsonLines, &labels, &row, None),
+            "{\"millis\":1234.5,\"flags\":\"0x1f\"}\n"
+        );
+        assert_eq!(
+            format_record(
+                LogFormat::JsonLines,
+                &labels[..1],
+                &[Some(LogValue::Float(f32::NAN))],
+                Some(Duration::from_millis(1500))
+            ),
+            "{\"time\":1.500,\"millis\":null}\n"
+        );
+    }
+
+    #[test]
+    fn raw_lines() {
+        assert_eq!(
+            add_raw_line(LogFormat::Csv, "time,T\n", RAW_LINE_LABEL),
+            "time,T,line\n"
+        );
+        assert_eq!(
+            add_raw_line(LogFormat::Csv, "1.000,\n", " T:20.5 /0.0, B:19.8\n"),
+            "1.000,,\" T:20.5 /0.0, B:19.8\"\n"
+        );
+        assert_eq!(
+            add_raw_line(LogFormat::Csv, "\n", "echo:\"busy\""),
+            "\"echo:\"\"busy\"\"\"\n"
+        );
+        assert_eq!(
+            add_raw_line(LogFormat::JsonLines, "{\"T\":20.5}\n", "T:20.5\n"),
+            "{\"T\":20.5,\"line\":\"T:20.5\"}\n"
+        );
+        assert_eq!(
+            add_raw_line(LogFormat::JsonLines, "{}\n", "say \"hi\"\\"),
+            "{\"line\":\"say \\\"hi\\\"\\\\\"}\n"
+        );
+    }
+
     #[test]
     fn conversion() {
         let input = ",millis:{millis},PBT:{PBT} {{PBT0:{PBT0},PBT1:{PBT1}}}";
@@ -1,19 +1,130 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
+/// A macro expands back into itself, holding the chain of macro names that leads there
 #[derive(Debug)]
-pub struct InfiniteRecursion;
+pub struct InfiniteRecursion(pub Vec<String>);
+
+impl std::fmt::Display for InfiniteRecursion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "macro cycle detected: {}", self.0.join(" -> "))
+    }
+}
+
+impl std::error::Error for InfiniteRecursion {}
+
+/// A macro was used without enough arguments to fill in its `{n}` placeholders
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("macro {name} is missing argument {{{index}}}")]
+pub struct MissingArgument {
+    pub name: String,
+    pub index: usize,
+}
+
+/// Fill `{n}` placeholders in a macro step with the nth argument, `{{` and `}}` are literal braces.
+///
+/// With `keep_unfilled`, placeholders without an argument and escaped braces are left as they are,
+/// so the step can be filled in again later. Otherwise a missing argument gives its index.
+fn substitute(step: &str, args: &[&str], keep_unfilled: bool) -> Result<String, usize> {
+    let mut filled = String::with_capacity(step.len());
+    let mut rest = step;
+    while let Some(brace) = rest.find(|c| c == '{' || c == '}') {
+        filled.push_str(&rest[..brace]);
+        rest = &rest[brace..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            filled.push_str(if keep_unfilled {
+                &rest[..2]
+            } else {
+                &rest[..1]
+            });
+            rest = &rest[2..];
+            continue;
+        }
+        let placeholder = rest
+            .find('}')
+            .and_then(|close| Some((rest[1..close].parse::<usize>().ok()?, close)));
+        match placeholder {
+            Some((index, close)) => {
+                match args.get(index) {
+                    Some(arg) => filled.push_str(arg),
+                    None if keep_unfilled => filled.push_str(&rest[..=close]),
+                    None => return Err(index),
+                }
+                rest = &rest[close + 1..];
+            }
+            None => {
+                filled.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+/// Split a macro invocation into its name and any arguments after it
+fn name_and_args(code: &str) -> (&str, Vec<&str>) {
+    let mut words = code.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    (name, words.collect())
+}
+
+/// Problems saving or loading a macro file
+#[derive(Debug, thiserror::Error)]
+pub enum MacroFileError {
+    #[error("can't access macro file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid macro file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("can't write macros: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("macro {0} refers to itself")]
+    SelfReference(String),
+    #[error("no config directory to keep macros in, give a file path")]
+    NoConfigDir,
+}
 
 type MacrosInner = HashMap<String, Vec<String>>;
 
 /// Holder for G code macros.
 /// Handles storage, lookup and expansion.
+///
+/// Gcodes are uppercased as they're expanded or stored unless case is preserved,
+/// see `set_preserve_case`. Macro names are always case insensitive.
 #[derive(Debug, Default, Clone, PartialEq)]
-pub struct Macros(MacrosInner);
+pub struct Macros {
+    macros: MacrosInner,
+    preserve_case: bool,
+}
 
 impl Macros {
     /// Empty holder
     pub fn new() -> Self {
-        Self(MacrosInner::new())
+        Self::default()
+    }
+
+    /// Keep Gcodes as they're written instead of uppercasing them, for firmware like Klipper
+    /// whose extended commands take case sensitive values, e.g. `RESPOND MSG="Hello"`.
+    ///
+    /// Only affects macros added or loaded afterwards, and Gcodes expanded from then on.
+    pub fn set_preserve_case(&mut self, preserve_case: bool) {
+        self.preserve_case = preserve_case;
+    }
+
+    /// Whether Gcodes are kept as written, see `set_preserve_case`
+    pub fn preserves_case(&self) -> bool {
+        self.preserve_case
+    }
+
+    /// A Gcode as it should be sent or stored
+    fn cased(&self, code: &str) -> String {
+        if self.preserve_case {
+            code.to_owned()
+        } else {
+            code.to_ascii_uppercase()
+        }
     }
 
     /// Add a new macro with case insensitive name, stores the expansion.
@@ -25,44 +136,105 @@ impl Macros {
         steps: impl IntoIterator<Item = &'a str>,
     ) -> Result<Option<Vec<String>>, InfiniteRecursion> {
         let commands = self.expand_for_insertion(steps)?;
-        Ok(self.0.insert(name.to_ascii_uppercase(), commands))
+        Ok(self.macros.insert(name.to_ascii_uppercase(), commands))
     }
 
     /// Lookup a macro by case insensitive name, return its expansion if defined
     pub fn get(&self, name: &str) -> Option<&Vec<String>> {
-        self.0.get(&name.to_ascii_uppercase())
+        self.macros.get(&name.to_ascii_uppercase())
     }
 
     /// Remove a macro by case insensitive name.
     /// If a macro with the same name existed, the previous expansion is returned.
     pub fn remove(&mut self, name: &str) -> Option<Vec<String>> {
-        self.0.remove(&name.to_ascii_uppercase())
+        self.macros.remove(&name.to_ascii_uppercase())
     }
 
     /// Iterate (name, expansions) stored
     pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, Vec<String>> {
-        self.0.iter()
+        self.macros.iter()
+    }
+
+    /// Every macro as a `macro <name> <steps>` console command, one per line and sorted by name,
+    /// for sharing macros as text that `source` turns back into the same macros.
+    ///
+    /// Steps are stored already expanded, so they only name macros that were defined later.
+    pub fn to_script(&self) -> String {
+        let mut names: Vec<&String> = self.macros.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| format!("macro {name} {}\n", self.macros[name].join(";")))
+            .collect()
+    }
+
+    /// Where macros are kept when no other file is given, under the OS config directory
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("print3rs").join("macros.toml"))
+    }
+
+    /// Write all macros to a TOML file, creating parent directories as needed
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<(), MacroFileError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string(&self.macros)?)?;
+        Ok(())
+    }
+
+    /// Read macros from a TOML file written by `save_to`.
+    ///
+    /// Loaded macros are added to existing ones, replacing any with the same name.
+    /// If `replace` is set, existing macros are all removed first.
+    /// Nothing is changed if any macro in the file refers to itself.
+    pub fn load_from(
+        &mut self,
+        path: impl AsRef<Path>,
+        replace: bool,
+    ) -> Result<(), MacroFileError> {
+        let contents = std::fs::read_to_string(path)?;
+        let loaded: MacrosInner = toml::from_str(&contents)?;
+        let mut validated = MacrosInner::with_capacity(loaded.len());
+        for (name, steps) in loaded {
+            let name = name.to_ascii_uppercase();
+            let steps: Vec<String> = steps.iter().map(|step| self.cased(step)).collect();
+            if steps.iter().any(|step| step.eq_ignore_ascii_case(&name)) {
+                return Err(MacroFileError::SelfReference(name));
+            }
+            validated.insert(name, steps);
+        }
+        if replace {
+            self.macros.clear();
+        }
+        self.macros.extend(validated);
+        Ok(())
     }
 
     fn expand_recursive(
         &self,
         expanded: &mut Vec<String>,
         code: &str,
-        already_expanded: Option<Vec<&str>>,
+        already_expanded: &mut Vec<String>,
     ) -> Result<(), InfiniteRecursion> {
-        // track expressions already expanded to prevent infinite recursion
-        let mut already_expanded = already_expanded.unwrap_or_default();
-        if already_expanded.contains(&code) {
-            return Err(InfiniteRecursion);
+        // track macros being expanded to prevent infinite recursion
+        let (name, args) = name_and_args(code);
+        let name = name.to_ascii_uppercase();
+        if already_expanded.contains(&name) {
+            already_expanded.push(name);
+            return Err(InfiniteRecursion(already_expanded.clone()));
         }
-        match self.get(code) {
+        match self.get(&name) {
             Some(expansion) => {
-                already_expanded.push(code);
+                already_expanded.push(name);
                 for extra in expansion {
-                    self.expand_recursive(expanded, extra, Some(already_expanded.clone()))?
+                    // arguments not given here are left for when this macro is used
+                    let extra = substitute(extra, &args, true).unwrap_or_else(|_| extra.clone());
+                    self.expand_recursive(expanded, &extra, already_expanded)?
                 }
+                already_expanded.pop();
             }
-            None => expanded.push(code.to_ascii_uppercase()),
+            None => expanded.push(self.cased(code)),
         };
         Ok(())
     }
@@ -76,27 +248,44 @@ impl Macros {
         let mut expanded = vec![];
 
         for code in codes {
-            self.expand_recursive(&mut expanded, code.as_ref(), None)?;
+            self.expand_recursive(&mut expanded, code.as_ref(), &mut vec![])?;
         }
         Ok(expanded)
     }
 
     /// Given a list of Gcodes and/or macros, replace any defined macros in the sequence with its expansion.
-    pub fn expand<'a>(&self, codes: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    ///
+    /// Macros may be followed by space separated arguments, which replace `{0}`, `{1}`... in its steps.
+    pub fn expand<'a>(
+        &self,
+        codes: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<String>, MissingArgument> {
         let mut expanded = vec![];
         for code in codes {
-            match self.get(code) {
-                Some(expansion) => expanded.extend(expansion.iter().cloned()),
-                None => expanded.push(code.to_ascii_uppercase()),
+            let (name, args) = name_and_args(code);
+            match self.get(name) {
+                Some(expansion) => {
+                    for step in expansion {
+                        let step =
+                            substitute(step, &args, false).map_err(|index| MissingArgument {
+                                name: name.to_ascii_uppercase(),
+                                index,
+                            })?;
+                        expanded.push(step);
+                    }
+                }
+                None => expanded.push(self.cased(code)),
             }
         }
-        expanded
+        Ok(expanded)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::commands::{parse_command, Command};
+    use winnow::Parser;
 
     #[test]
     fn macro_storage() {
@@ -113,7 +302,7 @@ mod test {
     fn macro_expansion_empty() {
         let macros = Macros::new();
         let input = vec!["G0", "ONE", "G1"];
-        let output = macros.expand(input.clone());
+        let output = macros.expand(input.clone()).unwrap();
         assert_eq!(input, output)
     }
 
@@ -121,10 +310,26 @@ mod test {
     fn macro_expansion() {
         let mut macros = Macros::new();
         macros.add("one", ["step1", "step2"]).unwrap();
-        let output = macros.expand(["G0", "one", "G1"]);
+        let output = macros.expand(["G0", "one", "G1"]).unwrap();
         assert_eq!(output, vec!["G0", "STEP1", "STEP2", "G1"]);
     }
 
+    #[test]
+    fn preserved_case() {
+        let mut macros = Macros::new();
+        macros.set_preserve_case(true);
+        macros
+            .add("greet", ["RESPOND MSG=\"Hello\"", "save_config"])
+            .unwrap();
+        let output = macros.expand(["Greet", "M117 Hi there"]).unwrap();
+        assert_eq!(
+            output,
+            vec!["RESPOND MSG=\"Hello\"", "save_config", "M117 Hi there"]
+        );
+        macros.set_preserve_case(false);
+        assert_eq!(macros.expand(["m117 hi"]).unwrap(), vec!["M117 HI"]);
+    }
+
     #[test]
     fn iteration() {
         let mut macros = Macros::new();
@@ -154,6 +359,121 @@ mod test {
         macros.add("one", ["zero", "one", "two"]).unwrap_err();
     }
 
+    fn temp_macro_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("print3rs-{}-{name}.toml", std::process::id()))
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = temp_macro_file("save_and_load");
+        let mut macros = Macros::new();
+        macros.add("home", ["G28", "G0 Z10"]).unwrap();
+        macros.save_to(&path).unwrap();
+
+        let mut loaded = Macros::new();
+        loaded.add("extra", ["M104 S200"]).unwrap();
+        loaded.load_from(&path, false).unwrap();
+        assert_eq!(loaded.get("home"), macros.get("home"));
+        assert!(loaded.get("extra").is_some());
+
+        loaded.load_from(&path, true).unwrap();
+        assert_eq!(loaded, macros);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn script_round_trip() {
+        let mut macros = Macros::new();
+        macros.add("home", ["G28", "G0 Z10"]).unwrap();
+        macros.add("center", ["G0 X{0} Y{1}"]).unwrap();
+        macros.add("greet", ["M117 \"hi; there\"", "home"]).unwrap();
+        let script = macros.to_script();
+        assert_eq!(
+            script,
+            "macro CENTER G0 X{0} Y{1}\n\
+             macro GREET M117 \"HI; THERE\";G28;G0 Z10\n\
+             macro HOME G28;G0 Z10\n"
+        );
+
+        let mut sourced = Macros::new();
+        for line in script.lines() {
+            let Ok(Command::Macro(name, steps)) = parse_command.parse(line) else {
+                panic!("{line} isn't a macro command");
+            };
+            sourced.add(name, steps).unwrap();
+        }
+        assert_eq!(sourced, macros);
+    }
+
+    #[test]
+    fn load_rejects_self_reference() {
+        let path = temp_macro_file("self_reference");
+        std::fs::write(&path, "loop = [\"G0\", \"LOOP\"]\n").unwrap();
+        let mut macros = Macros::new();
+        assert!(matches!(
+            macros.load_from(&path, false),
+            Err(MacroFileError::SelfReference(name)) if name == "LOOP"
+        ));
+        assert!(macros.get("loop").is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cycle_names_macros() {
+        let mut macros = Macros::new();
+        macros.add("zero", ["G0", "zero"]).unwrap();
+        let InfiniteRecursion(cycle) = macros.add("one", ["Zero"]).unwrap_err();
+        assert_eq!(cycle, vec!["ZERO", "ZERO"]);
+    }
+
+    #[test]
+    fn mutual_macros_expand() {
+        let mut macros = Macros::new();
+        macros.add("a", ["b"]).unwrap();
+        macros.add("b", ["a"]).unwrap();
+        assert_eq!(macros.expand(["a"]).unwrap(), vec!["B"]);
+        assert_eq!(macros.expand(["b"]).unwrap(), vec!["B"]);
+    }
+
+    #[test]
+    fn positional_arguments() {
+        let mut macros = Macros::new();
+        macros.add("center", ["G0 X{0} Y{1}"]).unwrap();
+        assert_eq!(
+            macros.expand(["center 100 120", "G28"]).unwrap(),
+            vec!["G0 X100 Y120", "G28"]
+        );
+        assert_eq!(
+            macros.expand(["center 100"]).unwrap_err(),
+            MissingArgument {
+                name: "CENTER".to_string(),
+                index: 1
+            }
+        );
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let mut macros = Macros::new();
+        macros.add("say", ["M117 {{{0}}}"]).unwrap();
+        assert_eq!(macros.expand(["say hi"]).unwrap(), vec!["M117 {hi}"]);
+    }
+
+    #[test]
+    fn nested_arguments() {
+        let mut macros = Macros::new();
+        macros.add("center", ["G0 X{0} Y{1}"]).unwrap();
+        macros.add("column", ["center {0} 50", "G0 Z{1}"]).unwrap();
+        assert_eq!(
+            macros.get("column").unwrap(),
+            &vec!["G0 X{0} Y50", "G0 Z{1}"]
+        );
+        assert_eq!(
+            macros.expand(["column 10 5"]).unwrap(),
+            vec!["G0 X10 Y50", "G0 Z5"]
+        );
+    }
+
     #[test]
     fn mutual_ref_not_recursive() {
         let mut macros = Macros::new();
@@ -0,0 +1,227 @@
+use {
+    super::{path, Command},
+    crate::commander::ErrorKindOf,
+    core::borrow::Borrow,
+    print3rs_core::{Socket, DEFAULT_QUERY_TIMEOUT},
+    std::sync::Arc,
+    winnow::{
+        ascii::{space0, space1},
+        combinator::{alt, empty, preceded, terminated},
+        prelude::*,
+    },
+};
+
+/// What to do with the settings a printer keeps in its EEPROM
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsAction<S> {
+    /// Display the current settings, from `M503`
+    Show,
+    /// Write the current settings into a Gcode file that restores them
+    Backup(S),
+    /// Save the current settings to EEPROM with `M500`
+    Store,
+    /// Reload the settings saved in EEPROM with `M501`
+    Load,
+}
+
+impl<'a> SettingsAction<&'a str> {
+    pub fn into_owned(self) -> SettingsAction<String> {
+        match self {
+            SettingsAction::Show => SettingsAction::Show,
+            SettingsAction::Backup(filename) => SettingsAction::Backup(filename.to_owned()),
+            SettingsAction::Store => SettingsAction::Store,
+            SettingsAction::Load => SettingsAction::Load,
+        }
+    }
+}
+
+impl SettingsAction<String> {
+    pub fn to_borrowed<Borrowed: ?Sized>(&self) -> SettingsAction<&Borrowed>
+    where
+        String: Borrow<Borrowed>,
+    {
+        match self {
+            SettingsAction::Show => SettingsAction::Show,
+            SettingsAction::Backup(filename) => SettingsAction::Backup(filename.borrow()),
+            SettingsAction::Store => SettingsAction::Store,
+            SettingsAction::Load => SettingsAction::Load,
+        }
+    }
+}
+
+pub fn parse_settings<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    preceded(
+        space0,
+        alt((
+            preceded(("backup", space1), path).map(SettingsAction::Backup),
+            terminated("store", space0).map(|_| SettingsAction::Store),
+            terminated("load", space0).map(|_| SettingsAction::Load),
+            empty.map(|_| SettingsAction::Show),
+        )),
+    )
+    .map(Command::Settings)
+    .parse_next(input)
+}
+
+/// Text of a line from `M503` without the `echo:` Marlin puts in front of each one
+fn setting_text(line: &str) -> &str {
+    let line = line.trim();
+    line.strip_prefix("echo:").unwrap_or(line).trim()
+}
+
+/// Settings are reported as Gcodes, anything else describes the settings after it
+fn is_gcode(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| matches!(c, 'G' | 'M'))
+        && chars.next().is_some_and(|c| c.is_ascii_digit())
+}
+
+/// Group the reply to `M503` under the heading given for each set of settings.
+///
+/// Settings before the first heading are grouped under an empty one.
+pub fn categories(reply: &[Arc<str>]) -> Vec<(String, Vec<String>)> {
+    let mut categories: Vec<(String, Vec<String>)> = Vec::new();
+    for line in reply {
+        let text = setting_text(line);
+        if text.is_empty() || text.starts_with("ok") {
+            continue;
+        }
+        if is_gcode(text) {
+            match categories.last_mut() {
+                Some((_, settings)) => settings.push(text.to_string()),
+                None => categories.push((String::new(), vec![text.to_string()])),
+            }
+        } else {
+            let heading = text.trim_start_matches(';').trim().trim_end_matches(':');
+            categories.push((heading.to_string(), Vec::new()));
+        }
+    }
+    categories
+}
+
+/// Settings from `M503` arranged for display
+pub fn report(reply: &[Arc<str>]) -> String {
+    let mut report = String::new();
+    for (heading, settings) in categories(reply) {
+        if !heading.is_empty() {
+            report.push_str(&format!("{heading}:\n"));
+        }
+        for setting in settings {
+            report.push_str(&format!("  {setting}\n"));
+        }
+    }
+    if report.is_empty() {
+        report.push_str("Printer didn't report any settings\n");
+    }
+    report
+}
+
+/// Gcode which sets every setting in the reply to `M503` back the way it was,
+/// with headings kept as comments
+pub fn settings_gcode(reply: &[Arc<str>]) -> String {
+    let mut gcode = String::new();
+    for (heading, settings) in categories(reply) {
+        if !heading.is_empty() {
+            gcode.push_str(&format!("; {heading}:\n"));
+        }
+        for setting in settings {
+            gcode.push_str(&setting);
+            gcode.push('\n');
+        }
+    }
+    gcode
+}
+
+/// Carry out a settings command on the printer behind `socket`, giving the text to display
+pub async fn settings(
+    socket: &Socket,
+    action: SettingsAction<&str>,
+) -> Result<String, ErrorKindOf> {
+    match action {
+        SettingsAction::Show => {
+            let reply = socket
+                .send_and_collect("M503", DEFAULT_QUERY_TIMEOUT)
+                .await?;
+            Ok(report(&reply))
+        }
+        SettingsAction::Backup(filename) => {
+            let reply = socket
+                .send_and_collect("M503", DEFAULT_QUERY_TIMEOUT)
+                .await?;
+            tokio::fs::write(filename, settings_gcode(&reply))
+                .await
+                .map_err(|e| ErrorKindOf(format!("Can't write {filename}: {e}")))?;
+            Ok(format!("Saved printer settings to {filename}\n"))
+        }
+        SettingsAction::Store => {
+            socket
+                .send_and_collect("M500", DEFAULT_QUERY_TIMEOUT)
+                .await?;
+            Ok("Stored settings in the printer's EEPROM\n".to_string())
+        }
+        SettingsAction::Load => {
+            socket
+                .send_and_collect("M501", DEFAULT_QUERY_TIMEOUT)
+                .await?;
+            Ok("Reloaded settings from the printer's EEPROM\n".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn m503() -> Vec<Arc<str>> {
+        [
+            "echo:; Linear Units:",
+            "echo:  G21 ; (mm)",
+            "echo:; Steps per unit:",
+            "echo: M92 X80.00 Y80.00 Z400.00 E93.00",
+            "echo:; Home offset:",
+            "echo:  M206 X0.00 Y0.00 Z0.00",
+            "ok",
+        ]
+        .into_iter()
+        .map(Arc::from)
+        .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let parse = |input| parse_settings.parse(input).unwrap();
+        assert_eq!(parse(""), Command::Settings(SettingsAction::Show));
+        assert_eq!(
+            parse(" backup ender.gcode"),
+            Command::Settings(SettingsAction::Backup("ender.gcode"))
+        );
+        assert_eq!(parse(" store"), Command::Settings(SettingsAction::Store));
+        assert_eq!(parse(" load "), Command::Settings(SettingsAction::Load));
+    }
+
+    #[test]
+    fn grouped_settings() {
+        let categories = categories(&m503());
+        assert_eq!(categories.len(), 3);
+        assert_eq!(
+            categories[1],
+            (
+                "Steps per unit".to_string(),
+                vec!["M92 X80.00 Y80.00 Z400.00 E93.00".to_string()]
+            )
+        );
+        assert!(report(&m503()).contains("Home offset:\n  M206 X0.00 Y0.00 Z0.00\n"));
+        assert_eq!(
+            report(&[Arc::from("ok")]),
+            "Printer didn't report any settings\n"
+        );
+    }
+
+    #[test]
+    fn backup_gcode() {
+        assert_eq!(
+            settings_gcode(&m503()),
+            "; Linear Units:\nG21 ; (mm)\n; Steps per unit:\nM92 X80.00 Y80.00 Z400.00 E93.00\n; Home offset:\nM206 X0.00 Y0.00 Z0.00\n"
+        );
+    }
+}
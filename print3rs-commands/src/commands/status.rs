@@ -0,0 +1,68 @@
+use print3rs_core::{Socket, Temperature, TemperatureReport};
+
+/// A heater's temperature, with its target when it's heating
+fn describe_heater(name: &str, temperature: Temperature) -> String {
+    match temperature.target {
+        Some(target) if target > 0.0 => {
+            format!("{name} {:.1}/{target:.1}°C", temperature.current)
+        }
+        _ => format!("{name} {:.1}°C", temperature.current),
+    }
+}
+
+/// Every heater in a report on one line, like `hotend 210.0/215.0°C, bed 60.0°C`
+pub fn describe_temperatures(report: &TemperatureReport) -> String {
+    let mut heaters = Vec::new();
+    if let Some(hotend) = report.hotend {
+        heaters.push(describe_heater("hotend", hotend));
+    }
+    // a single extruder is already reported as the hotend
+    if report.extruders.len() > 1 {
+        for (index, extruder) in report.extruders.iter().enumerate() {
+            heaters.push(describe_heater(&format!("T{index}"), *extruder));
+        }
+    }
+    if let Some(bed) = report.bed {
+        heaters.push(describe_heater("bed", bed));
+    }
+    if heaters.is_empty() {
+        return "none reported".to_string();
+    }
+    heaters.join(", ")
+}
+
+/// Line of `status` giving the printer's temperatures, asked for with `M105`
+pub async fn temperature_line(socket: &Socket) -> String {
+    match socket.read_temperatures().await {
+        Ok(report) => format!("Temperatures: {}\n", describe_temperatures(&report)),
+        Err(e) => format!("Temperatures: unavailable, {e}\n"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn temperature_descriptions() {
+        let report = TemperatureReport {
+            hotend: Some(Temperature {
+                current: 210.04,
+                target: Some(215.0),
+            }),
+            bed: Some(Temperature {
+                current: 24.8,
+                target: Some(0.0),
+            }),
+            extruders: Vec::new(),
+        };
+        assert_eq!(
+            describe_temperatures(&report),
+            "hotend 210.0/215.0°C, bed 24.8°C"
+        );
+        assert_eq!(
+            describe_temperatures(&TemperatureReport::default()),
+            "none reported"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use {
+    super::{dryrun::words, Command},
+    print3rs_core::Protocol,
+    winnow::{
+        ascii::{space0, space1},
+        combinator::{alt, opt, preceded, terminated},
+        prelude::*,
+    },
+};
+
+/// What to do with Gcodes entered in the console which fail validation
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Send them without checking
+    #[default]
+    Off,
+    /// Send them, but show what's wrong first
+    Warn,
+    /// Send none of the Gcodes entered along with them
+    Reject,
+}
+
+/// Validation chosen for each protocol, since Marlin and Klipper tolerate different things
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ValidationSettings {
+    pub marlin: Validation,
+    pub klipper: Validation,
+}
+
+impl ValidationSettings {
+    /// Validation of Gcodes sent with the given protocol
+    pub fn get(&self, protocol: Protocol) -> Validation {
+        match protocol {
+            Protocol::Marlin => self.marlin,
+            Protocol::Klipper => self.klipper,
+        }
+    }
+
+    /// Choose validation for one protocol, or for every protocol if none is given
+    pub fn set(&mut self, protocol: Option<Protocol>, validation: Validation) {
+        match protocol {
+            Some(Protocol::Marlin) => self.marlin = validation,
+            Some(Protocol::Klipper) => self.klipper = validation,
+            None => {
+                self.marlin = validation;
+                self.klipper = validation;
+            }
+        }
+    }
+}
+
+/// What's wrong with an outgoing line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationErrorKind {
+    #[error("{0} has no value")]
+    MissingValue(char),
+    #[error("`(` is never closed")]
+    UnclosedParenthesis,
+    #[error("`)` has no matching `(`")]
+    UnopenedParenthesis,
+}
+
+/// Something wrong with an outgoing line, at the 1-based column it was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("column {column}: {kind}")]
+pub struct ValidationError {
+    pub column: usize,
+    pub kind: ValidationErrorKind,
+}
+
+/// Moves and position resets, whose axis letters are ignored or rejected without a value
+fn is_move(command: &str) -> bool {
+    let mut chars = command.chars();
+    chars
+        .next()
+        .is_some_and(|letter| letter.eq_ignore_ascii_case(&'G'))
+        && matches!(chars.as_str().parse::<u32>(), Ok(0..=3 | 92))
+}
+
+/// Part of a line before any `;` comment. Klipper keeps a `;` inside double quotes.
+fn without_comment(line: &str, protocol: Protocol) -> &str {
+    let mut quoted = false;
+    let end = line
+        .char_indices()
+        .find(|&(_, c)| {
+            if c == '"' && protocol == Protocol::Klipper {
+                quoted = !quoted;
+            }
+            c == ';' && !quoted
+        })
+        .map_or(line.len(), |(index, _)| index);
+    &line[..end]
+}
+
+/// Letters of a move given without a number, like the `X` in `G1 X Y10`
+fn missing_values(code: &str) -> Vec<ValidationError> {
+    let words = words(code);
+    let Some((command, parameters)) = words.split_first() else {
+        return Vec::new();
+    };
+    if !is_move(command) {
+        return Vec::new();
+    }
+    parameters
+        .iter()
+        .filter(|parameter| parameter.len() == 1 && parameter.as_bytes()[0].is_ascii_alphabetic())
+        .map(|parameter| {
+            // words are slices of the code, so their offset gives the column
+            let offset = parameter.as_ptr() as usize - code.as_ptr() as usize;
+            ValidationError {
+                column: code[..offset].chars().count() + 1,
+                kind: ValidationErrorKind::MissingValue(
+                    parameter.as_bytes()[0].to_ascii_uppercase() as char,
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Parentheses without a partner. Klipper ignores those inside double quotes.
+fn unbalanced_parentheses(code: &str, protocol: Protocol) -> Vec<ValidationError> {
+    let mut open = Vec::new();
+    let mut problems = Vec::new();
+    let mut quoted = false;
+    for (index, c) in code.chars().enumerate() {
+        let column = index + 1;
+        match c {
+            '"' if protocol == Protocol::Klipper => quoted = !quoted,
+            _ if quoted => {}
+            '(' => open.push(column),
+            ')' if open.pop().is_none() => problems.push(ValidationError {
+                column,
+                kind: ValidationErrorKind::UnopenedParenthesis,
+            }),
+            _ => {}
+        }
+    }
+    problems.extend(open.into_iter().map(|column| ValidationError {
+        column,
+        kind: ValidationErrorKind::UnclosedParenthesis,
+    }));
+    problems
+}
+
+/// Check a line about to be sent with the given protocol for letters missing their value
+/// and unbalanced parentheses, which firmware may misread.
+///
+/// Problems are given in the order of their columns, anything after a `;` comment is skipped.
+pub fn validate_line(line: &str, protocol: Protocol) -> Vec<ValidationError> {
+    let code = without_comment(line, protocol);
+    let mut problems = missing_values(code);
+    problems.extend(unbalanced_parentheses(code, protocol));
+    problems.sort_by_key(|problem| problem.column);
+    problems
+}
+
+fn parse_protocol(input: &mut &str) -> PResult<Protocol> {
+    alt((
+        "--marlin".value(Protocol::Marlin),
+        "--klipper".value(Protocol::Klipper),
+    ))
+    .parse_next(input)
+}
+
+pub fn parse_validate<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    (
+        preceded(
+            space0,
+            alt((
+                "off".value(Validation::Off),
+                "warn".value(Validation::Warn),
+                "reject".value(Validation::Reject),
+            )),
+        ),
+        terminated(opt(preceded(space1, parse_protocol)), space0),
+    )
+        .map(|(validation, protocol)| Command::Validate(validation, protocol))
+        .parse_next(input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn problem(column: usize, kind: ValidationErrorKind) -> ValidationError {
+        ValidationError { column, kind }
+    }
+
+    #[test]
+    fn valid_lines() {
+        for protocol in [Protocol::Marlin, Protocol::Klipper] {
+            assert!(validate_line("G1 X10 Y-2.5 F1200", protocol).is_empty());
+            assert!(validate_line("G28 X Y", protocol).is_empty());
+            assert!(validate_line("M117 (hello)", protocol).is_empty());
+            assert!(validate_line("G1 X10 ; move (", protocol).is_empty());
+        }
+    }
+
+    #[test]
+    fn dangling_letters() {
+        assert_eq!(
+            validate_line("G1 X Y10 e", Protocol::Marlin),
+            vec![
+                problem(4, ValidationErrorKind::MissingValue('X')),
+                problem(10, ValidationErrorKind::MissingValue('E')),
+            ]
+        );
+        assert_eq!(
+            validate_line("G92E", Protocol::Klipper),
+            vec![problem(4, ValidationErrorKind::MissingValue('E'))]
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(
+            validate_line("M117 (a) b) (c", Protocol::Marlin),
+            vec![
+                problem(11, ValidationErrorKind::UnopenedParenthesis),
+                problem(13, ValidationErrorKind::UnclosedParenthesis),
+            ]
+        );
+        // Klipper keeps quoted text as it is, Marlin doesn't know about quotes
+        let respond = "RESPOND MSG=\"(; ok\"";
+        assert!(validate_line(respond, Protocol::Klipper).is_empty());
+        assert_eq!(
+            validate_line(respond, Protocol::Marlin),
+            vec![problem(14, ValidationErrorKind::UnclosedParenthesis)]
+        );
+        assert_eq!(
+            problem(14, ValidationErrorKind::UnclosedParenthesis).to_string(),
+            "column 14: `(` is never closed"
+        );
+    }
+
+    #[test]
+    fn settings_per_protocol() {
+        let mut settings = ValidationSettings::default();
+        settings.set(Some(Protocol::Klipper), Validation::Reject);
+        assert_eq!(settings.get(Protocol::Marlin), Validation::Off);
+        assert_eq!(settings.get(Protocol::Klipper), Validation::Reject);
+        settings.set(None, Validation::Warn);
+        assert_eq!(settings.get(Protocol::Marlin), Validation::Warn);
+        assert_eq!(settings.get(Protocol::Klipper), Validation::Warn);
+    }
+}
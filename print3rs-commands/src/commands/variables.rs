@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+/// A Gcode used a `$name` that hasn't been set
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("variable ${0} isn't set")]
+pub struct UndefinedVariable(pub String);
+
+/// Characters variable names are made of
+pub(crate) fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Named values kept by the host, filled into Gcodes wherever `$name` appears.
+///
+/// Names are case insensitive, since Gcodes and macro steps may have been uppercased.
+/// Values are filled in as they're set, `$$` is a literal `$`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Variables {
+    values: HashMap<String, String>,
+}
+
+impl Variables {
+    /// No variables set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a variable, returning the value it had before if it was already set
+    pub fn set(&mut self, name: &str, value: &str) -> Option<String> {
+        self.values
+            .insert(name.to_ascii_uppercase(), value.to_owned())
+    }
+
+    /// Value of a variable by case insensitive name, if it's set
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .get(&name.to_ascii_uppercase())
+            .map(String::as_str)
+    }
+
+    /// Remove a variable, returning its value if it was set
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.values.remove(&name.to_ascii_uppercase())
+    }
+
+    /// Iterate (name, value) of every variable set
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, String> {
+        self.values.iter()
+    }
+
+    /// Fill the value of each `$name` into a Gcode.
+    ///
+    /// A `$` not followed by a name is left as it is.
+    pub fn substitute(&self, code: &str) -> Result<String, UndefinedVariable> {
+        let mut filled = String::with_capacity(code.len());
+        let mut rest = code;
+        while let Some(dollar) = rest.find('$') {
+            filled.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+            if let Some(escaped) = rest.strip_prefix('$') {
+                filled.push('$');
+                rest = escaped;
+                continue;
+            }
+            let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            let name = &rest[..end];
+            if name.is_empty() {
+                filled.push('$');
+                continue;
+            }
+            let value = self
+                .get(name)
+                .ok_or_else(|| UndefinedVariable(name.to_owned()))?;
+            filled.push_str(value);
+            rest = &rest[end..];
+        }
+        filled.push_str(rest);
+        Ok(filled)
+    }
+
+    /// Fill in the variables of each Gcode in turn, see `substitute`
+    pub fn substitute_all(
+        &self,
+        codes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Vec<String>, UndefinedVariable> {
+        codes
+            .into_iter()
+            .map(|code| self.substitute(code.as_ref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn substitution() {
+        let mut variables = Variables::new();
+        variables.set("temp", "210");
+        variables.set("bed_temp", "60");
+        assert_eq!(variables.substitute("M104 S$temp").unwrap(), "M104 S210");
+        assert_eq!(
+            variables.substitute("M190 S$BED_TEMP;M109 S$Temp").unwrap(),
+            "M190 S60;M109 S210"
+        );
+        assert_eq!(variables.substitute("M117 $$5 $").unwrap(), "M117 $5 $");
+        assert_eq!(
+            variables.substitute("G1 X$x"),
+            Err(UndefinedVariable("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn names() {
+        let mut variables = Variables::new();
+        variables.set("speed", "100");
+        assert_eq!(variables.set("SPEED", "200").as_deref(), Some("100"));
+        assert_eq!(variables.remove("Speed").as_deref(), Some("200"));
+        assert_eq!(variables.get("speed"), None);
+    }
+}
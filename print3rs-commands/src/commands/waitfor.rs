@@ -0,0 +1,227 @@
+use {
+    super::{
+        log::{make_parser, parse_alert, parse_patterns, Alert, LogValue, Segment},
+        parse_duration, Command,
+    },
+    crate::commander::ErrorKindOf,
+    core::borrow::Borrow,
+    std::{sync::Arc, time::Duration},
+    tokio::sync::broadcast,
+    winnow::{
+        ascii::{space0, space1},
+        combinator::{alt, preceded, repeat, terminated},
+        prelude::*,
+    },
+};
+
+/// How long `waitfor` waits for a matching line unless given `--timeout`
+pub const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Extra settings for a `waitfor`, given as flags before its pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaitOptions<S> {
+    /// Give up waiting after this long
+    pub timeout: Duration,
+    /// Only finish once a value in the matching line meets a condition, like `--until T>=200`
+    pub until: Option<Alert<S>>,
+}
+
+impl<S> Default for WaitOptions<S> {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_WAIT_TIMEOUT,
+            until: None,
+        }
+    }
+}
+
+impl WaitOptions<String> {
+    pub fn to_borrowed<Borrowed: ?Sized>(&self) -> WaitOptions<&Borrowed>
+    where
+        String: Borrow<Borrowed>,
+    {
+        WaitOptions {
+            timeout: self.timeout,
+            until: self.until.as_ref().map(|alert| Alert {
+                label: alert.label.borrow(),
+                comparison: alert.comparison,
+                threshold: alert.threshold,
+            }),
+        }
+    }
+}
+
+impl<'a> WaitOptions<&'a str> {
+    pub fn into_owned(self) -> WaitOptions<String> {
+        WaitOptions {
+            timeout: self.timeout,
+            until: self.until.map(|alert| Alert {
+                label: alert.label.to_owned(),
+                comparison: alert.comparison,
+                threshold: alert.threshold,
+            }),
+        }
+    }
+}
+
+enum WaitFlag<'a> {
+    Timeout(Duration),
+    Until(Alert<&'a str>),
+}
+
+fn parse_wait_flag<'a>(input: &mut &'a str) -> PResult<WaitFlag<'a>> {
+    alt((
+        preceded(("--timeout", space1), parse_duration).map(WaitFlag::Timeout),
+        preceded(("--until", space1), parse_alert).map(WaitFlag::Until),
+    ))
+    .parse_next(input)
+}
+
+fn parse_wait_options<'a>(input: &mut &'a str) -> PResult<WaitOptions<&'a str>> {
+    let flags: Vec<WaitFlag> =
+        repeat(0.., terminated(parse_wait_flag, space1)).parse_next(input)?;
+    let mut options = WaitOptions::default();
+    for flag in flags {
+        match flag {
+            WaitFlag::Timeout(timeout) => options.timeout = timeout,
+            WaitFlag::Until(alert) => options.until = Some(alert),
+        }
+    }
+    Ok(options)
+}
+
+pub fn parse_waitfor<'a>(input: &mut &'a str) -> PResult<Command<&'a str>> {
+    (preceded(space0, parse_wait_options), parse_patterns)
+        .map(|(options, patterns)| Command::WaitFor(patterns, options))
+        .parse_next(input)
+}
+
+/// The value captured for `label` by a pattern's values, if the pattern has one
+fn value_named<S: AsRef<str>>(
+    pattern: &[Segment<S>],
+    values: &[LogValue],
+    label: &str,
+) -> Option<LogValue> {
+    pattern
+        .iter()
+        .flat_map(Segment::labels)
+        .zip(values)
+        .find_map(|(name, value)| (name == label).then_some(*value))
+}
+
+/// Wait for a line from the printer matching one of `patterns`, giving back the line.
+///
+/// Patterns are the same as for `log`, so a plain pattern matches any line containing it.
+/// With an `until` condition only a line whose captured value meets it will do.
+pub async fn wait_for(
+    mut lines: broadcast::Receiver<Arc<str>>,
+    patterns: Vec<Vec<Segment<String>>>,
+    options: WaitOptions<String>,
+) -> Result<Arc<str>, ErrorKindOf> {
+    let mut parser = make_parser(
+        patterns
+            .iter()
+            .map(|pattern| pattern.iter().map(Segment::to_borrowed).collect())
+            .collect(),
+    );
+    let matching = async {
+        loop {
+            let line = match lines.recv().await {
+                Ok(line) => line,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(ErrorKindOf::from("Printer disconnected while waiting"))
+                }
+            };
+            let Ok((index, values)) = parser.parse(line.as_bytes()) else {
+                continue;
+            };
+            let met = match &options.until {
+                Some(until) => value_named(&patterns[index], &values, &until.label)
+                    .is_some_and(|value| until.triggered_by(value)),
+                None => true,
+            };
+            if met {
+                return Ok(line);
+            }
+        }
+    };
+    tokio::time::timeout(options.timeout, matching)
+        .await
+        .map_err(|_| {
+            ErrorKindOf(format!(
+                "Gave up waiting after {}s",
+                options.timeout.as_secs_f32()
+            ))
+        })?
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::log::Comparison, *};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_waitfor.parse(" echo:busy: paused for user").unwrap(),
+            Command::WaitFor(
+                vec![vec![Segment::Tag("echo:busy: paused for user")]],
+                WaitOptions::default()
+            )
+        );
+        let Command::WaitFor(patterns, options) = parse_waitfor
+            .parse(" --timeout 5m --until T>=200 T:{T}")
+            .unwrap()
+        else {
+            panic!("not a waitfor");
+        };
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(options.timeout, Duration::from_secs(300));
+        assert_eq!(
+            options.until,
+            Some(Alert {
+                label: "T",
+                comparison: Comparison::GreaterEqual,
+                threshold: 200.0
+            })
+        );
+    }
+
+    fn owned_patterns(input: &str) -> Vec<Vec<Segment<String>>> {
+        parse_patterns
+            .parse(input)
+            .unwrap()
+            .into_iter()
+            .map(|pattern| pattern.into_iter().map(Segment::into_owned).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn waits_for_value() {
+        let (sender, lines) = broadcast::channel(8);
+        for line in ["ok", "T:150.0 /200.0", "T:199.9 /200.0", "T:200.1 /200.0"] {
+            sender.send(Arc::from(line)).unwrap();
+        }
+        let options = parse_wait_options
+            .parse("--until T>=200 ")
+            .unwrap()
+            .into_owned();
+        let line = wait_for(lines, owned_patterns("T:{T}"), options)
+            .await
+            .unwrap();
+        assert_eq!(&*line, "T:200.1 /200.0");
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let (sender, lines) = broadcast::channel(8);
+        sender.send(Arc::from("echo:busy: processing")).unwrap();
+        let options = WaitOptions {
+            timeout: Duration::from_millis(10),
+            until: None,
+        };
+        let result = wait_for(lines, owned_patterns("paused for user"), options).await;
+        assert!(result.is_err());
+        drop(sender);
+    }
+}
@@ -1,6 +1,6 @@
 use {
     crate::commander::ErrorKindOf,
-    print3rs_core::Printer,
+    print3rs_core::{Printer, PrinterInfo},
     std::sync::{Arc, Mutex},
 };
 
@@ -10,7 +10,12 @@ use {
 pub enum Response {
     Output(Arc<str>),
     Error(ErrorKindOf),
-    AutoConnect(Arc<Mutex<Printer>>),
+    /// Warning from a background task that needs attention, such as a logged value out of range
+    Alert(Arc<str>),
+    /// Printer found by autoconnecting, for the named printer session
+    AutoConnect(Arc<str>, Arc<Mutex<Printer>>, Arc<PrinterInfo>),
+    /// A `waitfor` is over, frontends should call `Commander::finish_waiting` to run what it held back
+    WaitOver,
     Clear,
     Quit,
 }
@@ -33,8 +38,12 @@ impl From<ErrorKindOf> for Response {
     }
 }
 
-impl From<Printer> for Response {
-    fn from(value: Printer) -> Self {
-        Response::AutoConnect(Arc::new(Mutex::new(value)))
+impl From<(String, Printer, PrinterInfo)> for Response {
+    fn from((session, printer, info): (String, Printer, PrinterInfo)) -> Self {
+        Response::AutoConnect(
+            Arc::from(session),
+            Arc::new(Mutex::new(printer)),
+            Arc::new(info),
+        )
     }
 }
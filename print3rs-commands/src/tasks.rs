@@ -1,110 +1,751 @@
 use {
-    crate::commands::log::{get_headers, make_parser, Segment},
-    print3rs_core::{Error as PrinterError, Printer, Socket},
+    crate::{
+        commands::log::{
+            add_raw_line, format_record, get_headers, make_parser, value_columns, LogFormat,
+            LogOptions, Segment, NO_MATCH_WARNING, RAW_LINE_LABEL,
+        },
+        response::Response,
+    },
+    flate2::read::GzDecoder,
+    print3rs_core::{Error as PrinterError, LineStream, Printer, Socket},
     std::{
+        borrow::Cow,
         collections::HashMap,
-        time::{SystemTime, UNIX_EPOCH},
+        io::{BufRead, BufReader},
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    tokio::{
+        io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
+        sync::{broadcast, mpsc, watch, Notify},
+        task::JoinHandle,
     },
-    tokio::{io::AsyncWriteExt, task::JoinHandle},
     winnow::Parser,
 };
 
+/// How far along a print is, counting only lines which are sent
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PrintProgress {
+    /// Lines acknowledged by the printer so far
+    pub line: usize,
+    pub total: usize,
+    pub elapsed: Duration,
+}
+
+impl PrintProgress {
+    /// Percentage of lines done, 0 if there's nothing to print
+    pub fn percent(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.line as f32 * 100.0 / self.total as f32
+        }
+    }
+}
+
+/// Which lines of a file `start_print_file` sends
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PrintMode {
+    /// Skip blank lines and comments, and strip comments after commands
+    #[default]
+    Filtered,
+    /// Send every line exactly as it is in the file
+    Raw,
+}
+
+/// How long a print waits for the ok to each line, and how many times the line is sent again before giving up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineTimeout {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for LineTimeout {
+    fn default() -> Self {
+        Self {
+            // long enough for heating with M109/M190, which only oks once at temperature
+            timeout: Duration::from_secs(600),
+            retries: 1,
+        }
+    }
+}
+
+/// How long `stop` waits for a print to finish the line it's sending before aborting it,
+/// see `BackgroundTask::stop_and_join`
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether a line's ok arrived within `timeout`, failing if the printer gave up on it another way
+async fn acknowledged_within(
+    response: impl std::future::Future<Output = Result<(), PrinterError>>,
+    timeout: Duration,
+) -> Result<bool, TaskError> {
+    // the printer connection gives up on an ok by itself if it's not busy with the line
+    match tokio::time::timeout(timeout, response).await {
+        Ok(Ok(())) => Ok(true),
+        Ok(Err(PrinterError::Timeout)) | Err(_) => Ok(false),
+        Ok(Err(e)) => Err(e.into()),
+    }
+}
+
+/// Send a line, sending it again whenever its ok doesn't arrive in time.
+///
+/// It's sent again with the same line number, so a printer which only lost the ok
+/// doesn't take it for the next line and run it twice.
+async fn send_acknowledged(
+    socket: &Socket,
+    line: &str,
+    line_timeout: LineTimeout,
+) -> Result<(), TaskError> {
+    let (sent, response) = socket.send_resendable(line).await?;
+    let mut attempts = 1;
+    let mut acknowledged = acknowledged_within(response, line_timeout.timeout).await?;
+    while !acknowledged {
+        if attempts > line_timeout.retries {
+            return Err(TaskError::NoResponse {
+                line: line.to_owned(),
+                attempts,
+            });
+        }
+        tracing::warn!("No ok for `{line}` from printer, sending it again");
+        let response = socket.resend(&sent).await?;
+        acknowledged = acknowledged_within(response, line_timeout.timeout).await?;
+        attempts += 1;
+    }
+    Ok(())
+}
+
+/// Gcode from a line of a file, without comments, if there's any
+pub(crate) fn gcode_line(line: &str) -> Option<&str> {
+    let line = match line.split_once(';') {
+        Some((s, _)) => s,
+        None => line,
+    };
+    let line = line.trim();
+    (!line.is_empty()).then_some(line)
+}
+
+/// Whether a file should be decompressed, going by a `.gz` extension
+pub(crate) fn is_gzip(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"))
+}
+
+/// Lines of a gzip compressed file, decompressed as they're read
+pub(crate) fn gzip_lines(
+    filename: &str,
+) -> std::io::Result<impl Iterator<Item = std::io::Result<String>>> {
+    let file = std::fs::File::open(filename)?;
+    Ok(BufReader::new(GzDecoder::new(file)).lines())
+}
+
+/// A line from a file, if `mode` sends it
+fn print_line(line: String, mode: PrintMode) -> Option<String> {
+    match mode {
+        PrintMode::Filtered => gcode_line(&line).map(str::to_owned),
+        PrintMode::Raw => Some(line),
+    }
+}
+
+/// Lines of a gzip compressed file which `mode` would send
+fn gzip_print_lines(
+    filename: &str,
+    mode: PrintMode,
+) -> std::io::Result<impl Iterator<Item = std::io::Result<String>>> {
+    Ok(gzip_lines(filename)?
+        .filter_map(move |line| line.map(|line| print_line(line, mode)).transpose()))
+}
+
+type PrintLines = mpsc::Receiver<std::io::Result<String>>;
+
+/// Stream lines to print out of a gzip compressed file.
+///
+/// The file is read twice, first to count the lines for progress, so the
+/// decompressed contents never need to be held in memory.
+async fn stream_gzip_print(
+    filename: String,
+    mode: PrintMode,
+) -> std::io::Result<(usize, PrintLines)> {
+    let count_filename = filename.clone();
+    let total = tokio::task::spawn_blocking(move || {
+        gzip_print_lines(&count_filename, mode)?
+            .try_fold(0usize, |count, line| line.map(|_| count + 1))
+    })
+    .await??;
+    let lines = gzip_print_lines(&filename, mode)?;
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::task::spawn_blocking(move || {
+        for line in lines {
+            let failed = line.is_err();
+            if sender.blocking_send(line).is_err() || failed {
+                return;
+            }
+        }
+    });
+    Ok((total, receiver))
+}
+
+/// Stream lines to print out of a file.
+///
+/// Like `stream_gzip_print`, the file is read once to count lines, then again as it's sent.
+async fn stream_print(filename: String, mode: PrintMode) -> std::io::Result<(usize, PrintLines)> {
+    let mut total = 0;
+    let file = tokio::fs::File::open(&filename).await?;
+    let mut lines = tokio::io::BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+        if print_line(line, mode).is_some() {
+            total += 1;
+        }
+    }
+    let file = tokio::fs::File::open(&filename).await?;
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(file).lines();
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => match print_line(line, mode) {
+                    Some(line) => Ok(line),
+                    None => continue,
+                },
+                Ok(None) => return,
+                Err(e) => Err(e),
+            };
+            let failed = line.is_err();
+            if sender.send(line).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+    Ok((total, receiver))
+}
+
+/// Gcodes sent around a file by `start_print_file`, such as to heat up before it and cool down after
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrintScripts {
+    /// Sent before the first line of the file
+    pub preamble: Vec<String>,
+    /// Sent after the file, even if printing it failed
+    pub postamble: Vec<String>,
+}
+
 /// Starts a background task which reads a .gcode file and sends the commands in sequence
-pub fn start_print_file(filename: &str, socket: Socket) -> BackgroundTask {
+///
+/// Lines are sent as they're read, so large files start printing right away.
+/// Files ending in `.gz` are decompressed while printing.
+/// The print stops if a line isn't acknowledged within `line_timeout`, after retrying it,
+/// or if the file can't be read.
+/// Once the file can be read, the preamble of `scripts` is sent first, and the postamble
+/// is sent after the file is done or has failed, so heaters still get turned off.
+/// If both fail, the print's error is returned and the postamble's is logged.
+/// Stopping the task skips the postamble.
+/// The task can be stopped between lines with `BackgroundTask::stop_and_join`.
+pub fn start_print_file(
+    filename: &str,
+    socket: Socket,
+    mode: PrintMode,
+    scripts: PrintScripts,
+    line_timeout: LineTimeout,
+) -> BackgroundTask {
     let filename = filename.to_owned();
+    let pause = PauseHandle::default();
+    let print_pause = pause.clone();
+    let (progress_sender, progress) = watch::channel(PrintProgress::default());
+    let (stop_sender, mut stop_request) = watch::channel(false);
     let task: JoinHandle<Result<(), TaskError>> = tokio::spawn(async move {
-        if let Ok(file) = tokio::fs::read_to_string(filename).await {
-            for line in file.lines() {
-                let line = match line.split_once(';') {
-                    Some((s, _)) => s,
-                    None => line,
-                };
-                if line.is_empty() {
-                    continue;
-                };
-                socket.send(line).await?.await?;
+        let (total, mut lines) = if is_gzip(&filename) {
+            stream_gzip_print(filename, mode).await?
+        } else {
+            stream_print(filename, mode).await?
+        };
+        // gives whether the print was stopped before the end
+        let printed = async {
+            for line in &scripts.preamble {
+                if *stop_request.borrow() {
+                    return Ok(true);
+                }
+                send_acknowledged(&socket, line, line_timeout).await?;
+            }
+            let start = Instant::now();
+            let mut progress = PrintProgress {
+                total,
+                ..Default::default()
+            };
+            progress_sender.send_replace(progress);
+            while let Some(line) = lines.recv().await {
+                let line = line?;
+                tokio::select! {
+                    biased;
+                    Ok(_) = stop_request.wait_for(|stop| *stop) => return Ok(true),
+                    _ = print_pause.wait_while_paused() => {},
+                }
+                send_acknowledged(&socket, &line, line_timeout).await?;
+                progress.line += 1;
+                progress.elapsed = start.elapsed();
+                progress_sender.send_replace(progress);
             }
+            Ok::<_, TaskError>(false)
         }
-        Ok(())
+        .await;
+        match printed {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(ref e) => tracing::warn!("Print failed, sending end Gcodes: {e}"),
+        }
+        for line in &scripts.postamble {
+            if let Err(e) = send_acknowledged(&socket, line, line_timeout).await {
+                // the print failing is the more useful error to report
+                if printed.is_ok() {
+                    return Err(e);
+                }
+                tracing::error!("End Gcodes failed after the print did: {e}");
+                break;
+            }
+        }
+        printed.map(|_| ())
     });
     BackgroundTask {
         description: "print",
         abort_handle: task.abort_handle(),
+        pause: Some(pause),
+        progress: Some(progress),
+        stop: Some(StopHandle {
+            requested: stop_sender,
+            task,
+        }),
     }
 }
 
+/// Why a background task failed
 #[derive(Debug, thiserror::Error)]
-enum TaskError {
+pub enum TaskError {
     #[error("{0}")]
     Printer(#[from] print3rs_core::Error),
     #[error("failed in background: {0}")]
     Join(#[from] tokio::task::JoinError),
+    #[error("printer didn't acknowledge `{line}` after sending it {attempts} times")]
+    NoResponse { line: String, attempts: u32 },
+    #[error("can't read file: {0}")]
+    File(#[from] std::io::Error),
+    #[error("didn't stop within {}s, so it was aborted", .0.as_secs_f32())]
+    StopTimeout(Duration),
 }
 
-/// Starts a background task which listens for a pattern an writes it in a file
+/// Starts a background task which listens for patterns and writes them in a file.
+///
+/// Each line is matched against the patterns in order, the first match fills in its columns
+/// and leaves the columns only other patterns capture empty.
+/// Records are written as CSV or JSON lines depending on `options`.
+/// Alerts are sent to `responder` when a value first meets an alert's condition,
+/// whether or not the file can be written.
 pub fn start_logging(
     name: &str,
-    pattern: Vec<Segment<&'_ str>>,
+    patterns: Vec<Vec<Segment<&'_ str>>>,
+    options: LogOptions<&'_ str>,
     printer: &Printer,
+    responder: broadcast::Sender<Response>,
 ) -> std::result::Result<BackgroundTask, print3rs_core::Error> {
     let filename = format!(
-        "{name}_{timestamp}.csv",
+        "{name}_{timestamp}.{extension}",
         timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
-            .as_secs()
+            .as_secs(),
+        extension = options.format.extension()
     );
-    let header = get_headers(&pattern);
+    let header = match options.format {
+        LogFormat::Csv if options.debug => add_raw_line(
+            LogFormat::Csv,
+            &get_headers(&patterns, options.timestamp),
+            RAW_LINE_LABEL,
+        ),
+        LogFormat::Csv => get_headers(&patterns, options.timestamp),
+        LogFormat::JsonLines => String::new(),
+    };
+    let (labels, columns) = value_columns(&patterns);
+    let labels: Vec<String> = labels.into_iter().map(Cow::into_owned).collect();
+    let LogOptions {
+        timestamp,
+        format,
+        alerts,
+        debug,
+        summary,
+    } = options.into_owned();
+    let alerts: Vec<_> = alerts
+        .into_iter()
+        .filter_map(|alert| {
+            let column = labels.iter().position(|label| *label == alert.label)?;
+            Some((alert, column))
+        })
+        .collect();
+    let name = name.to_owned();
 
-    let mut parser = make_parser(pattern);
+    let mut parser = make_parser(patterns);
     let mut log_printer_reader = printer.subscribe_lines()?;
     let log_task_handle = tokio::spawn(async move {
-        let mut log_file = tokio::fs::File::create(filename).await.unwrap();
-        log_file.write_all(header.as_bytes()).await.unwrap();
-        while let Ok(log_line) = log_printer_reader.recv().await {
-            if let Ok(parsed) = parser.parse(log_line.as_bytes()) {
-                let mut record_bytes = String::new();
-                for val in parsed {
-                    record_bytes.push_str(&val.to_string());
-                    record_bytes.push(',');
-                }
-                record_bytes.pop(); // remove trailing ','
-                record_bytes.push('\n');
+        let mut log_file = match tokio::fs::File::create(&filename).await {
+            Ok(mut log_file) => {
                 log_file
-                    .write_all(record_bytes.as_bytes())
+                    .write_all(header.as_bytes())
                     .await
                     .unwrap_or_default();
+                Some(log_file)
+            }
+            Err(e) => {
+                let _ = responder.send(Response::Error(
+                    format!("Can't create {filename}: {e}").into(),
+                ));
+                None
+            }
+        };
+        let mut alerting = vec![false; alerts.len()];
+        let start = Instant::now();
+        let report_every = summary.unwrap_or(NO_MATCH_WARNING);
+        let mut report = tokio::time::interval_at((start + report_every).into(), report_every);
+        let (mut matched, mut total) = (0u64, 0u64);
+        let mut warned = false;
+        loop {
+            let log_line = tokio::select! {
+                log_line = log_printer_reader.recv() => match log_line {
+                    Ok(log_line) => log_line,
+                    Err(_) => break,
+                },
+                _ = report.tick() => {
+                    if summary.is_some() {
+                        let _ = responder.send(Response::Output(
+                            format!("{name}: matched {matched}/{total} lines\n").into(),
+                        ));
+                    } else if matched == 0 && total > 0 && !warned {
+                        warned = true;
+                        let _ = responder.send(Response::Alert(
+                            format!("{name}: none of the {total} lines received so far matched, `log --debug` shows what they were").into(),
+                        ));
+                    }
+                    continue;
+                },
+            };
+            let received = start.elapsed();
+            total += 1;
+            let mut row = vec![None; labels.len()];
+            match parser.parse(log_line.as_bytes()) {
+                Ok((index, parsed)) => {
+                    matched += 1;
+                    for (val, &column) in parsed.into_iter().zip(&columns[index]) {
+                        row[column] = Some(val);
+                    }
+                }
+                // only debug logs keep lines no pattern matched, to show why
+                Err(_) if debug => {}
+                Err(_) => continue,
             }
+            for ((alert, column), active) in alerts.iter().zip(&mut alerting) {
+                let Some(value) = row[*column] else {
+                    continue;
+                };
+                let triggered = alert.triggered_by(value);
+                if triggered && !*active {
+                    let _ = responder.send(Response::Alert(
+                        format!("{name}: {} is {value}, alert at {alert}", alert.label).into(),
+                    ));
+                }
+                *active = triggered;
+            }
+            let Some(log_file) = log_file.as_mut() else {
+                continue;
+            };
+            let received = timestamp.then_some(received);
+            let mut record_bytes = format_record(format, &labels, &row, received);
+            if debug {
+                record_bytes = add_raw_line(format, &record_bytes, &log_line);
+            }
+            log_file
+                .write_all(record_bytes.as_bytes())
+                .await
+                .unwrap_or_default();
         }
     });
     Ok(BackgroundTask {
         description: "log",
         abort_handle: log_task_handle.abort_handle(),
+        pause: None,
+        progress: None,
+        stop: None,
     })
 }
 
-/// Starts a background task sending Gcodes one-at-a-time in an infinite loop
-pub fn start_repeat(gcodes: Vec<String>, socket: Socket) -> BackgroundTask {
+/// One line of a transcript, `>` for a line sent to the printer and `<` for one received,
+/// after the seconds since the transcript started
+fn transcript_entry(elapsed: Duration, direction: char, line: &str) -> String {
+    format!(
+        "{:.3} {direction} {}\n",
+        elapsed.as_secs_f64(),
+        line.trim_end()
+    )
+}
+
+/// Write every line sent and received into `transcript` until the printer disconnects
+async fn write_transcript(
+    mut sent: LineStream,
+    mut received: LineStream,
+    mut transcript: impl AsyncWrite + Unpin,
+) -> std::io::Result<()> {
+    let start = Instant::now();
+    loop {
+        // sent lines go first so each appears before the reply to it
+        let (direction, line) = tokio::select! {
+            biased;
+            line = sent.recv() => ('>', line),
+            line = received.recv() => ('<', line),
+        };
+        let entry = match line {
+            Ok(line) => transcript_entry(start.elapsed(), direction, &line),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                format!("# fell behind, skipped {skipped} lines\n")
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        transcript.write_all(entry.as_bytes()).await?;
+    }
+    transcript.flush().await
+}
+
+/// Starts a background task recording everything sent to and received from the printer
+/// into `filename`, each line timestamped, to see exactly what happened on the connection.
+pub fn start_transcript(
+    filename: &str,
+    printer: &Printer,
+    responder: broadcast::Sender<Response>,
+) -> std::result::Result<BackgroundTask, print3rs_core::Error> {
+    let sent = printer.subscribe_sent()?;
+    let received = printer.subscribe_lines()?;
+    let filename = filename.to_owned();
+    let task = tokio::spawn(async move {
+        let written = match tokio::fs::File::create(&filename).await {
+            Ok(file) => write_transcript(sent, received, tokio::io::BufWriter::new(file)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            let _ = responder.send(Response::Error(
+                format!("Can't write transcript {filename}: {e}").into(),
+            ));
+        }
+    });
+    Ok(BackgroundTask {
+        description: "transcript",
+        abort_handle: task.abort_handle(),
+        pause: None,
+        progress: None,
+        stop: None,
+    })
+}
+
+/// Console output showing a line sent to the printer
+fn echo_sent_line(line: &str) -> String {
+    format!(">> {}\n", line.trim_end())
+}
+
+/// Starts a background task showing every line sent to the printer in the console, after `>>`.
+///
+/// Lines are shown exactly as they went out, after macros and variables are filled in
+/// and with any line number and checksum, so it's clear what the printer was actually sent.
+pub fn start_echo_sent(
+    printer: &Printer,
+    responder: broadcast::Sender<Response>,
+) -> std::result::Result<BackgroundTask, print3rs_core::Error> {
+    let mut sent = printer.subscribe_sent()?;
+    let task = tokio::spawn(async move {
+        loop {
+            let output = match sent.recv().await {
+                Ok(line) => echo_sent_line(&line),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    format!(">> fell behind, skipped {skipped} lines\n")
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if responder.send(output.into()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(BackgroundTask {
+        description: "echo",
+        abort_handle: task.abort_handle(),
+        pause: None,
+        progress: None,
+        stop: None,
+    })
+}
+
+/// Values a repeat sweeps through, one for each cycle, from `start` to `end` inclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sweep {
+    pub start: f64,
+    pub end: f64,
+    /// Distance between values, always positive, the sweep goes down if `end` is below `start`
+    pub step: f64,
+}
+
+impl Sweep {
+    /// Value for the cycle with the given index, if the sweep hasn't ended by then
+    fn value(&self, index: u64) -> Option<f64> {
+        let step = if self.end < self.start {
+            -self.step
+        } else {
+            self.step
+        };
+        let value = self.start + step * index as f64;
+        // allow for rounding in steps like 0.1 so the end is still reached
+        let overshoot = (value - self.end) * step.signum();
+        (overshoot <= self.step * 1e-9).then_some(value)
+    }
+}
+
+/// Gcode with the value of this cycle of a sweep in place of its `{i}`
+fn fill_sweep_value(gcode: &str, value: f64) -> String {
+    // without the float error in values like 0.1 * 3
+    let value = ((value * 1e9).round() / 1e9).to_string();
+    gcode.replace("{i}", &value).replace("{I}", &value)
+}
+
+/// How `start_repeat` loops over its Gcodes
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RepeatOptions {
+    /// Time to wait after each full cycle through the Gcodes before starting the next
+    pub every: Option<Duration>,
+    /// Finish after this many cycles instead of looping forever
+    pub times: Option<u32>,
+    /// Fill each cycle's value of a sweep into the Gcodes' `{i}`, finishing after the last
+    pub sweep: Option<Sweep>,
+}
+
+/// Starts a background task sending Gcodes one-at-a-time in a loop,
+/// which runs until stopped unless given a number of `times` to run or a `sweep` to finish
+pub fn start_repeat(gcodes: Vec<String>, socket: Socket, options: RepeatOptions) -> BackgroundTask {
+    let pause = PauseHandle::default();
+    let repeat_pause = pause.clone();
     let task: JoinHandle<Result<(), TaskError>> = tokio::spawn(async move {
-        for ref line in gcodes.into_iter().cycle() {
-            let _ = socket.send_unsequenced(line).await?.await;
+        if gcodes.is_empty() || options.times == Some(0) {
+            return Ok(());
+        }
+        for cycle in 1u64.. {
+            let sweep_value = match options.sweep {
+                Some(sweep) => match sweep.value(cycle - 1) {
+                    Some(value) => Some(value),
+                    None => break,
+                },
+                None => None,
+            };
+            for line in &gcodes {
+                repeat_pause.wait_while_paused().await;
+                let line = match sweep_value {
+                    Some(value) => Cow::Owned(fill_sweep_value(line, value)),
+                    None => Cow::Borrowed(line.as_str()),
+                };
+                let _ = socket.send_unsequenced(line).await?.await;
+            }
+            if options.times.is_some_and(|times| cycle >= u64::from(times)) {
+                break;
+            }
+            if let Some(every) = options.every {
+                tokio::time::sleep(every).await;
+            }
         }
         Ok(())
     });
     BackgroundTask {
         description: "repeat",
         abort_handle: task.abort_handle(),
+        pause: Some(pause),
+        progress: None,
+        stop: None,
     }
 }
 
 pub type Tasks = HashMap<String, BackgroundTask>;
 
+#[derive(Debug, Default)]
+struct PauseState {
+    paused: AtomicBool,
+    resumed: Notify,
+}
+
+/// Shared switch to hold a background task before it sends its next line
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle(Arc<PauseState>);
+
+impl PauseHandle {
+    /// Stop sending once the line in progress is done
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Continue sending from where the task was paused
+    pub fn resume(&self) {
+        self.0.paused.store(false, Ordering::SeqCst);
+        self.0.resumed.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    async fn wait_while_paused(&self) {
+        loop {
+            // register before checking, so a resume in between isn't missed
+            let resumed = self.0.resumed.notified();
+            if !self.is_paused() {
+                return;
+            }
+            resumed.await;
+        }
+    }
+}
+
+/// Asks a background task to stop once it's done with the line it's sending,
+/// rather than aborting it part way through
+#[derive(Debug)]
+pub struct StopHandle {
+    requested: watch::Sender<bool>,
+    task: JoinHandle<Result<(), TaskError>>,
+}
+
 /// Handle for a concurrent task with description.
 /// Task is cancelled on drop.
 #[derive(Debug)]
 pub struct BackgroundTask {
     pub description: &'static str,
     pub abort_handle: tokio::task::AbortHandle,
+    /// Present for tasks which can be paused
+    pub pause: Option<PauseHandle>,
+    /// Present for tasks which work through a known amount of lines
+    pub progress: Option<watch::Receiver<PrintProgress>>,
+    /// Present for tasks which can stop between lines
+    pub stop: Option<StopHandle>,
+}
+
+impl BackgroundTask {
+    /// Whether the task sends lines to the printer, which `abort` needs to stop before clearing the queue
+    pub fn sends_lines(&self) -> bool {
+        matches!(self.description, "print" | "repeat" | "gcodes")
+    }
+
+    /// Stop the task once it's done with the line it's sending, giving its result.
+    ///
+    /// If it hasn't stopped within `timeout` it's aborted, giving `TaskError::StopTimeout`.
+    /// Tasks without a `stop` handle are aborted straight away, the same as dropping them.
+    pub async fn stop_and_join(mut self, timeout: Duration) -> Result<(), TaskError> {
+        let Some(StopHandle { requested, task }) = self.stop.take() else {
+            return Ok(());
+        };
+        requested.send_replace(true);
+        match tokio::time::timeout(timeout, task).await {
+            Ok(joined) => joined?,
+            Err(_) => Err(TaskError::StopTimeout(timeout)),
+        }
+    }
 }
 
 impl Drop for BackgroundTask {
@@ -114,6 +755,9 @@ impl Drop for BackgroundTask {
 }
 
 /// Starts a background task which sends given Gcodes one-at-a-time
+///
+/// Lines are sent without line numbers or checksums, and a missing ok is ignored,
+/// so this is the quickest way to send a few commands. See `send_gcodes_sync` to confirm each one.
 pub fn send_gcodes(socket: Socket, codes: Vec<String>) -> BackgroundTask {
     let task: JoinHandle<Result<(), PrinterError>> = tokio::spawn(async move {
         for code in codes {
@@ -124,5 +768,449 @@ pub fn send_gcodes(socket: Socket, codes: Vec<String>) -> BackgroundTask {
     BackgroundTask {
         description: "gcodes",
         abort_handle: task.abort_handle(),
+        pause: None,
+        progress: None,
+        stop: None,
+    }
+}
+
+/// Starts a background task which sends given Gcodes one-at-a-time, confirming each arrived.
+///
+/// Like a print, lines are numbered and checksummed so the printer can ask for them again,
+/// and the task stops if one isn't acknowledged. Progress is published on the task as each ok arrives.
+/// The extra bytes on each line and giving up on missing oks make this slower than `send_gcodes`,
+/// which is better for sending a few commands quickly.
+pub fn send_gcodes_sync(socket: Socket, codes: Vec<String>) -> BackgroundTask {
+    let (progress_sender, progress) = watch::channel(PrintProgress {
+        total: codes.len(),
+        ..Default::default()
+    });
+    let task: JoinHandle<Result<(), TaskError>> = tokio::spawn(async move {
+        let start = Instant::now();
+        for code in codes {
+            send_acknowledged(&socket, &code, LineTimeout::default()).await?;
+            progress_sender.send_modify(|progress| {
+                progress.line += 1;
+                progress.elapsed = start.elapsed();
+            });
+        }
+        Ok(())
+    });
+    BackgroundTask {
+        description: "gcodes",
+        abort_handle: task.abort_handle(),
+        pause: None,
+        progress: Some(progress),
+        stop: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use print3rs_core::duplex_printer;
+
+    #[test]
+    fn gcode_lines() {
+        let file = "; header\nG28 ; home\n\n   \nG1 X10\n";
+        let lines: Vec<&str> = file.lines().filter_map(gcode_line).collect();
+        assert_eq!(lines, vec!["G28", "G1 X10"]);
+    }
+
+    #[test]
+    fn gzip_files() {
+        use std::io::Write;
+
+        assert!(is_gzip("benchy.gcode.gz"));
+        assert!(is_gzip("BENCHY.GCODE.GZ"));
+        assert!(!is_gzip("benchy.gcode"));
+
+        let path = std::env::temp_dir().join(format!("print3rs_{}.gcode.gz", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder
+            .write_all(b"; header\nG28 ; home\n\nG1 X10\n")
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let path = path.to_str().unwrap();
+        let filtered: Vec<String> = gzip_print_lines(path, PrintMode::Filtered)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(filtered, vec!["G28", "G1 X10"]);
+        let raw: Vec<String> = gzip_print_lines(path, PrintMode::Raw)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(raw.len(), 4);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn progress_percent() {
+        assert_eq!(PrintProgress::default().percent(), 0.0);
+        let progress = PrintProgress {
+            line: 1,
+            total: 4,
+            ..Default::default()
+        };
+        assert_eq!(progress.percent(), 25.0);
+    }
+
+    #[test]
+    fn transcript_entries() {
+        assert_eq!(
+            transcript_entry(Duration::from_millis(1500), '>', "N1G28*18\n"),
+            "1.500 > N1G28*18\n"
+        );
+        assert_eq!(
+            transcript_entry(Duration::ZERO, '<', "ok N1\r\n"),
+            "0.000 < ok N1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn transcript_both_directions() {
+        let (transport, _) = print3rs_core::MockPrinter::new().start();
+        let printer = Printer::new(transport);
+        let mut transcript = Vec::new();
+        let writing = write_transcript(
+            printer.subscribe_sent().unwrap(),
+            printer.subscribe_lines().unwrap(),
+            &mut transcript,
+        );
+        let sending = async {
+            printer
+                .send_unsequenced("M115")
+                .await
+                .unwrap()
+                .await
+                .unwrap();
+            drop(printer);
+        };
+        let (written, ()) = tokio::join!(writing, sending);
+        written.unwrap();
+
+        let transcript = String::from_utf8(transcript).unwrap();
+        let entries: Vec<&str> = transcript
+            .lines()
+            .map(|entry| entry.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(entries.first(), Some(&"> M115"));
+        assert!(entries[1].starts_with("< FIRMWARE_NAME:Marlin"));
+        assert_eq!(entries.last(), Some(&"< ok"));
+    }
+
+    #[tokio::test]
+    async fn echoing_sent_lines() {
+        let (transport, _) = print3rs_core::MockPrinter::new().start();
+        let printer = Printer::new(transport);
+        let (responder, mut responses) = broadcast::channel(8);
+        let _echo = start_echo_sent(&printer, responder).unwrap();
+
+        printer.send("G28").await.unwrap().await.unwrap();
+        let Response::Output(echoed) = responses.recv().await.unwrap() else {
+            panic!("sent line wasn't echoed as output");
+        };
+        assert!(echoed.starts_with(">> N1G28*"), "{echoed}");
+        assert!(echoed.ends_with('\n'));
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_line_retries() {
+        let (_printer, socket, host_side) = duplex_printer();
+        let line_timeout = LineTimeout {
+            timeout: Duration::from_millis(10),
+            retries: 2,
+        };
+
+        let result = send_acknowledged(&socket, "G28", line_timeout).await;
+        assert!(matches!(
+            result,
+            Err(TaskError::NoResponse { attempts: 3, .. })
+        ));
+        let mut host_side = host_side.lines();
+        // every try is the same line, not a new one
+        for _ in 0..3 {
+            assert_eq!(host_side.next_line().await.unwrap().unwrap(), "N1G28*50");
+        }
+    }
+
+    #[tokio::test]
+    async fn gcodes_sync_progress() {
+        let (_printer, socket, mut host_side) = duplex_printer();
+
+        let task = send_gcodes_sync(socket, vec!["G28".to_string(), "G1 X10".to_string()]);
+        let mut progress = task.progress.clone().unwrap();
+        for sequence in 1..=2 {
+            let mut line = String::new();
+            host_side.read_line(&mut line).await.unwrap();
+            assert!(line.starts_with(&format!("N{sequence}")));
+            host_side
+                .get_mut()
+                .write_all(format!("ok N{sequence}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+        let done = *progress
+            .wait_for(|progress| progress.line == 2)
+            .await
+            .unwrap();
+        assert_eq!(done.total, 2);
+        assert_eq!(done.percent(), 100.0);
+    }
+
+    #[tokio::test]
+    async fn print_scripts() {
+        let path =
+            std::env::temp_dir().join(format!("print3rs_{}_scripts.gcode", std::process::id()));
+        std::fs::write(&path, "G1 X10 ; move\n").unwrap();
+        let (_printer, socket, mut host_side) = duplex_printer();
+
+        let scripts = PrintScripts {
+            preamble: vec!["G28".to_string()],
+            postamble: vec!["M104 S0".to_string()],
+        };
+        let task = start_print_file(
+            path.to_str().unwrap(),
+            socket,
+            PrintMode::Filtered,
+            scripts,
+            LineTimeout::default(),
+        );
+        for (sequence, expected) in (1..).zip(["G28", "G1 X10", "M104 S0"]) {
+            let mut line = String::new();
+            host_side.read_line(&mut line).await.unwrap();
+            assert!(line.starts_with(&format!("N{sequence}{expected}")));
+            host_side
+                .get_mut()
+                .write_all(format!("ok N{sequence}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+        let progress = *task.progress.unwrap().borrow();
+        assert_eq!(progress.total, 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_print_keeps_its_error() {
+        let path =
+            std::env::temp_dir().join(format!("print3rs_{}_failed.gcode", std::process::id()));
+        std::fs::write(&path, "G1 X10\n").unwrap();
+        let (_printer, socket, host_side) = duplex_printer();
+        let scripts = PrintScripts {
+            postamble: vec!["M104 S0".to_string()],
+            ..Default::default()
+        };
+        let line_timeout = LineTimeout {
+            timeout: Duration::from_millis(10),
+            retries: 0,
+        };
+
+        let task = start_print_file(
+            path.to_str().unwrap(),
+            socket,
+            PrintMode::Filtered,
+            scripts,
+            line_timeout,
+        );
+        // neither line gets an ok, the postamble still gets sent
+        let mut host_side = host_side.lines();
+        for expected in ["G1 X10", "M104 S0"] {
+            let line = host_side.next_line().await.unwrap().unwrap();
+            assert!(line.contains(expected), "{line}");
+        }
+        let result = task.stop_and_join(Duration::from_secs(5)).await;
+        assert!(
+            matches!(result, Err(TaskError::NoResponse { ref line, .. }) if line == "G1 X10"),
+            "{result:?}"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stopping_between_lines() {
+        let path = std::env::temp_dir().join(format!("print3rs_{}_stop.gcode", std::process::id()));
+        std::fs::write(&path, "G1 X10\nG1 X20\n").unwrap();
+        let (_printer, socket, mut host_side) = duplex_printer();
+        let scripts = PrintScripts {
+            postamble: vec!["M104 S0".to_string()],
+            ..Default::default()
+        };
+
+        let task = start_print_file(
+            path.to_str().unwrap(),
+            socket.clone(),
+            PrintMode::Filtered,
+            scripts,
+            LineTimeout::default(),
+        );
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.contains("G1 X10"));
+        // the line being sent still gets its ok before the print stops
+        let (stopped, _) = tokio::join!(task.stop_and_join(Duration::from_secs(5)), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            host_side.get_mut().write_all(b"ok N1\n").await.unwrap();
+        });
+        stopped.unwrap();
+        line.clear();
+        let next = tokio::time::timeout(Duration::from_millis(50), host_side.read_line(&mut line));
+        assert!(next.await.is_err(), "sent `{line}` after stopping");
+
+        // a print stuck waiting on an ok is aborted
+        let task = start_print_file(
+            path.to_str().unwrap(),
+            socket,
+            PrintMode::Filtered,
+            PrintScripts::default(),
+            LineTimeout::default(),
+        );
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.contains("G1 X10"));
+        let stopped = task.stop_and_join(Duration::from_millis(20)).await;
+        assert!(matches!(stopped, Err(TaskError::StopTimeout(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn print_against_mock_printer() {
+        let path = std::env::temp_dir().join(format!("print3rs_{}_mock.gcode", std::process::id()));
+        std::fs::write(
+            &path,
+            "; cube\nG28\nM109 S200\nG1 X10 Y10 ; corner\n\nG1 X0 Y0\n",
+        )
+        .unwrap();
+        let (transport, received) = print3rs_core::MockPrinter::new().start();
+        let printer = Printer::new(transport);
+        let socket = printer.socket().unwrap().clone();
+
+        let task = start_print_file(
+            path.to_str().unwrap(),
+            socket,
+            PrintMode::Filtered,
+            PrintScripts::default(),
+            LineTimeout::default(),
+        );
+        let done = *task
+            .progress
+            .clone()
+            .unwrap()
+            .wait_for(|progress| progress.total > 0 && progress.line == progress.total)
+            .await
+            .unwrap();
+        assert_eq!(done.total, 4);
+        drop(task);
+        drop(printer);
+        assert_eq!(
+            received.await.unwrap(),
+            ["G28", "M109 S200", "G1 X10 Y10", "G1 X0 Y0"]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finite_repeat() {
+        let (_printer, socket, mut host_side) = duplex_printer();
+
+        let options = RepeatOptions {
+            times: Some(2),
+            ..Default::default()
+        };
+        let task = start_repeat(vec!["G29".to_string(), "M500".to_string()], socket, options);
+        for expected in ["G29", "M500", "G29", "M500"] {
+            let mut line = String::new();
+            host_side.read_line(&mut line).await.unwrap();
+            assert_eq!(line.trim(), expected);
+            host_side.get_mut().write_all(b"ok\n").await.unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !task.abort_handle.is_finished() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn sweep_values() {
+        let values = |sweep: Sweep| -> Vec<String> {
+            (0..)
+                .map_while(|index| sweep.value(index))
+                .map(|value| fill_sweep_value("{i}", value))
+                .collect()
+        };
+        let up = Sweep {
+            start: 0.0,
+            end: 30.0,
+            step: 10.0,
+        };
+        assert_eq!(values(up), ["0", "10", "20", "30"]);
+        let down = Sweep {
+            start: 0.3,
+            end: 0.0,
+            step: 0.1,
+        };
+        assert_eq!(values(down), ["0.3", "0.2", "0.1", "0"]);
+        let uneven = Sweep {
+            start: 200.0,
+            end: 212.0,
+            step: 5.0,
+        };
+        assert_eq!(values(uneven), ["200", "205", "210"]);
+        assert_eq!(fill_sweep_value("G1 Z{I}", 0.25), "G1 Z0.25");
+    }
+
+    #[tokio::test]
+    async fn sweeping_repeat() {
+        let (_printer, socket, mut host_side) = duplex_printer();
+
+        let options = RepeatOptions {
+            sweep: Some(Sweep {
+                start: 0.0,
+                end: 20.0,
+                step: 10.0,
+            }),
+            ..Default::default()
+        };
+        let task = start_repeat(vec!["G1 X{I}".to_string()], socket, options);
+        for expected in ["G1 X0", "G1 X10", "G1 X20"] {
+            let mut line = String::new();
+            host_side.read_line(&mut line).await.unwrap();
+            assert_eq!(line.trim(), expected);
+            host_side.get_mut().write_all(b"ok\n").await.unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !task.abort_handle.is_finished() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let pause = PauseHandle::default();
+        pause.wait_while_paused().await;
+
+        pause.pause();
+        assert!(pause.is_paused());
+        let waiting = tokio::spawn({
+            let pause = pause.clone();
+            async move { pause.wait_while_paused().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        pause.resume();
+        waiting.await.unwrap();
+        assert!(!pause.is_paused());
     }
 }
@@ -1,4 +1,6 @@
-use std::{collections::HashMap, ops::Deref};
+use std::{collections::HashMap, fmt::Display, ops::Deref};
+
+use crate::Protocol;
 
 /// Generic type for holding arbitrary device information
 #[derive(Debug, Clone, PartialEq, PartialOrd, Default)]
@@ -17,6 +19,33 @@ impl From<Info> for bool {
     }
 }
 
+impl From<&str> for Info {
+    /// Interpret a reported value as the most specific type it fits
+    fn from(value: &str) -> Self {
+        if value.is_empty() {
+            Info::Key
+        } else if let Ok(int) = value.parse() {
+            Info::Int(int)
+        } else if let Ok(float) = value.parse() {
+            Info::Float(float)
+        } else {
+            Info::Str(value.to_string())
+        }
+    }
+}
+
+impl Display for Info {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Info::Key => Ok(()),
+            Info::Str(s) => write!(f, "{s}"),
+            Info::Int(i) => write!(f, "{i}"),
+            Info::Float(float) => write!(f, "{float}"),
+            Info::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
 impl Info {
     /// Asses truthiness for any possible contained type
     ///
@@ -117,6 +146,208 @@ impl InfoMap {
     }
 }
 
+/// Split a line into `KEY:value` pairs, where keys are uppercase words starting a line or following whitespace.
+///
+/// Values run until the next key, so they may contain spaces.
+fn key_values(line: &str) -> Vec<(&str, &str)> {
+    let is_key = |word: &str| {
+        word.starts_with(|c: char| c.is_ascii_uppercase())
+            && word
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+    };
+    let mut keys = Vec::new();
+    let mut word_start = true;
+    for (start, c) in line.char_indices() {
+        if word_start {
+            let word = &line[start..];
+            if let Some(end) = word.find(|c: char| c == ':' || c.is_whitespace()) {
+                let key = &word[..end];
+                if word[end..].starts_with(':') && is_key(key) {
+                    keys.push((start, key));
+                }
+            }
+        }
+        word_start = c.is_whitespace();
+    }
+    keys.iter()
+        .enumerate()
+        .map(|(n, &(start, key))| {
+            let value_end = keys.get(n + 1).map_or(line.len(), |&(next, _)| next);
+            (key, line[start + key.len() + 1..value_end].trim())
+        })
+        .collect()
+}
+
+/// Kind of firmware a printer runs, going by the name it reports to `M115`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    Marlin,
+    Klipper,
+    RepRapFirmware,
+    /// Prusa's own firmware, which is based on Marlin
+    Prusa,
+    /// Didn't report a name, or reported one that isn't known
+    #[default]
+    Unknown,
+}
+
+impl Firmware {
+    /// Recognize a firmware from its `FIRMWARE_NAME`
+    pub fn from_name(name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        if name.contains("klipper") {
+            Firmware::Klipper
+        } else if name.contains("prusa") {
+            Firmware::Prusa
+        } else if name.contains("reprapfirmware") {
+            Firmware::RepRapFirmware
+        } else if name.contains("marlin") {
+            Firmware::Marlin
+        } else {
+            Firmware::Unknown
+        }
+    }
+
+    /// Protocol to talk to this firmware with, unknown firmware gets the
+    /// Marlin protocol, which most firmware understands
+    pub fn protocol(self) -> Protocol {
+        match self {
+            Firmware::Klipper => Protocol::Klipper,
+            _ => Protocol::Marlin,
+        }
+    }
+}
+
+impl Display for Firmware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Firmware::Marlin => "Marlin",
+            Firmware::Klipper => "Klipper",
+            Firmware::RepRapFirmware => "RepRapFirmware",
+            Firmware::Prusa => "Prusa",
+            Firmware::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Identity and capabilities a printer reports in response to `M115`
+#[derive(Debug, Default, Clone)]
+pub struct PrinterInfo {
+    pub firmware_name: Option<String>,
+    pub machine_type: Option<String>,
+    pub uuid: Option<String>,
+    /// `Cap:` lines, check with `InfoMap::has_capability`
+    pub capabilities: InfoMap,
+    /// Any other reported `KEY:value` pairs
+    pub other: InfoMap,
+    /// Serial baud rate the printer was found at, when it was found by autoconnecting
+    pub baud: Option<u32>,
+    /// Serial port, address or path the printer is connected through, when known
+    pub port: Option<String>,
+}
+
+impl PrinterInfo {
+    /// Collect information from all lines of an `M115` response
+    pub fn from_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut info = Self::default();
+        for line in lines {
+            info.parse_line(line);
+        }
+        info
+    }
+
+    /// Add information from a single line of an `M115` response.
+    ///
+    /// Understands Marlin style `Cap:NAME:value` lines, and `FIRMWARE_NAME:...` lines
+    /// from Marlin and RepRapFirmware. Anything else, like `ok` or temperature reports, is ignored.
+    pub fn parse_line(&mut self, line: &str) {
+        let line = line.trim();
+        if let Some(capability) = line.strip_prefix("Cap:") {
+            let (name, value) = capability.split_once(':').unwrap_or((capability, ""));
+            self.capabilities
+                .0
+                .insert(name.trim().to_string(), Info::from(value.trim()));
+        } else if line.contains("FIRMWARE_NAME:") {
+            for (key, value) in key_values(line) {
+                match key {
+                    "FIRMWARE_NAME" => self.firmware_name = Some(value.to_string()),
+                    "MACHINE_TYPE" => self.machine_type = Some(value.to_string()),
+                    "UUID" => self.uuid = Some(value.to_string()),
+                    _ => {
+                        self.other.0.insert(key.to_string(), Info::from(value));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Kind of firmware the printer reported running
+    pub fn firmware(&self) -> Firmware {
+        self.firmware_name
+            .as_deref()
+            .map_or(Firmware::Unknown, Firmware::from_name)
+    }
+
+    /// Check if nothing was learned about the printer from its `M115` response
+    /// Where the printer is connected, like `COM3@115200` or `octopi.local:8080`,
+    /// with the baud rate when it's known
+    pub fn connection(&self) -> Option<String> {
+        let port = self.port.as_deref()?;
+        Some(match self.baud {
+            Some(baud) => format!("{port}@{baud}"),
+            None => port.to_string(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.firmware_name.is_none()
+            && self.machine_type.is_none()
+            && self.uuid.is_none()
+            && self.capabilities.is_empty()
+            && self.other.is_empty()
+    }
+}
+
+impl Display for PrinterInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.port, self.baud) {
+            (Some(port), Some(baud)) => writeln!(f, "Connected to {port} at {baud} baud")?,
+            (Some(port), None) => writeln!(f, "Connected to {port}")?,
+            (None, Some(baud)) => writeln!(f, "Connected at {baud} baud")?,
+            (None, None) => {}
+        }
+        if self.is_empty() {
+            return writeln!(f, "No information found about printer");
+        }
+        if let Some(ref firmware_name) = self.firmware_name {
+            writeln!(f, "Firmware: {firmware_name}")?;
+            writeln!(f, "Firmware flavor: {}", self.firmware())?;
+        }
+        if let Some(ref machine_type) = self.machine_type {
+            writeln!(f, "Machine: {machine_type}")?;
+        }
+        if let Some(ref uuid) = self.uuid {
+            writeln!(f, "UUID: {uuid}")?;
+        }
+        let mut other: Vec<_> = self.other.iter().collect();
+        other.sort_unstable_by_key(|(key, _)| *key);
+        for (key, value) in other {
+            writeln!(f, "{key}: {value}")?;
+        }
+        if !self.capabilities.is_empty() {
+            writeln!(f, "Capabilities:")?;
+            let mut capabilities: Vec<_> = self.capabilities.iter().collect();
+            capabilities.sort_unstable_by_key(|(key, _)| *key);
+            for (key, value) in capabilities {
+                writeln!(f, "  {key}: {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -150,6 +381,104 @@ mod test {
         assert_eq!(og, converted.into())
     }
 
+    #[test]
+    fn marlin_m115() {
+        let info = PrinterInfo::from_lines([
+            "FIRMWARE_NAME:Marlin 2.1.2 (Sep 10 2023 12:34:56) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0 MACHINE_TYPE:Ender-3 EXTRUDER_COUNT:1 UUID:cede2a2f-41a2-4748-9b12-c55c62f367ff\n",
+            "Cap:AUTOREPORT_TEMP:1\n",
+            "Cap:ARCS:0\n",
+            "ok\n",
+        ]);
+        assert_eq!(
+            info.firmware_name.as_deref(),
+            Some("Marlin 2.1.2 (Sep 10 2023 12:34:56)")
+        );
+        assert_eq!(info.machine_type.as_deref(), Some("Ender-3"));
+        assert_eq!(info.firmware(), Firmware::Marlin);
+        assert_eq!(
+            info.uuid.as_deref(),
+            Some("cede2a2f-41a2-4748-9b12-c55c62f367ff")
+        );
+        assert_eq!(info.other.get("EXTRUDER_COUNT"), Some(&Info::Int(1)));
+        assert_eq!(info.other.get("PROTOCOL_VERSION"), Some(&Info::Float(1.0)));
+        assert!(info.capabilities.has_capability(Capability::AutoreportTemp));
+        assert!(!info.capabilities.has_capability(Capability::Arcs));
+    }
+
+    #[test]
+    fn reprap_m115() {
+        let info = PrinterInfo::from_lines([
+            "FIRMWARE_NAME: RepRapFirmware for Duet 2 WiFi/Ethernet FIRMWARE_VERSION: 3.4.5 ELECTRONICS: Duet WiFi 1.02 or later FIRMWARE_DATE: 2022-11-30 19:36:12",
+        ]);
+        assert_eq!(
+            info.firmware_name.as_deref(),
+            Some("RepRapFirmware for Duet 2 WiFi/Ethernet")
+        );
+        assert_eq!(
+            info.other.get("FIRMWARE_DATE"),
+            Some(&Info::Str("2022-11-30 19:36:12".to_string()))
+        );
+        assert_eq!(
+            info.other.get("ELECTRONICS"),
+            Some(&Info::Str("Duet WiFi 1.02 or later".to_string()))
+        );
+        assert!(info.capabilities.is_empty());
+        assert_eq!(info.firmware(), Firmware::RepRapFirmware);
+    }
+
+    #[test]
+    fn firmware_flavor() {
+        let klipper = PrinterInfo::from_lines([
+            "FIRMWARE_NAME:Klipper FIRMWARE_VERSION:v0.12.0-85-gd785b396",
+        ]);
+        assert_eq!(klipper.firmware(), Firmware::Klipper);
+        assert_eq!(klipper.firmware().protocol(), Protocol::Klipper);
+        let prusa = PrinterInfo::from_lines([
+            "FIRMWARE_NAME:Prusa-Firmware 3.13.2 based on Marlin FIRMWARE_URL:https://github.com/prusa3d/Prusa-Firmware",
+        ]);
+        assert_eq!(prusa.firmware(), Firmware::Prusa);
+        assert_eq!(prusa.firmware().protocol(), Protocol::Marlin);
+        assert_eq!(PrinterInfo::default().firmware(), Firmware::Unknown);
+        assert_eq!(
+            Firmware::from_name("Smoothieware").protocol(),
+            Protocol::Marlin
+        );
+    }
+
+    #[test]
+    fn unrelated_lines_ignored() {
+        let info = PrinterInfo::from_lines(["ok T:25.0 /0.0 B:24.8 /0.0", "echo:busy: processing"]);
+        assert!(info.is_empty());
+    }
+
+    #[test]
+    fn display_baud() {
+        let info = PrinterInfo {
+            baud: Some(250000),
+            ..Default::default()
+        };
+        assert!(info.is_empty());
+        assert_eq!(
+            info.to_string(),
+            "Connected at 250000 baud\nNo information found about printer\n"
+        );
+    }
+
+    #[test]
+    fn connection_details() {
+        let mut info = PrinterInfo {
+            port: Some("COM3".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(info.connection().as_deref(), Some("COM3"));
+        info.baud = Some(115200);
+        assert_eq!(info.connection().as_deref(), Some("COM3@115200"));
+        assert!(info
+            .to_string()
+            .starts_with("Connected to COM3 at 115200 baud\n"));
+        assert_eq!(PrinterInfo::default().connection(), None);
+    }
+
     #[test]
     fn info_conversion() {
         let cap = Capability::AutoreportPos;
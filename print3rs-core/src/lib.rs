@@ -1,55 +1,312 @@
-use std::{collections::BTreeMap, fmt::Debug, future::Future, sync::Arc};
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    fmt::{Debug, Display},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use serde::Serialize;
-use winnow::Parser;
 
 mod info;
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
+mod moonraker;
+mod motion;
+pub mod protocol;
 mod response;
 
-use response::response;
-pub use response::Response;
+pub use info::{Capability, Firmware, Info, InfoMap, PrinterInfo};
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::{duplex_printer, duplex_printer_with_config, LoopbackTransport, MockPrinter};
+pub use moonraker::{MoonrakerTransport, DEFAULT_MOONRAKER_PORT};
+pub use motion::{MotionMode, Positioning, Units};
+pub use protocol::Protocol;
+pub use response::{
+    classify, position, temperatures, Position, Response, Temperature, TemperatureReport,
+};
 
-use print3rs_serializer::{serialize_unsequenced, Sequenced};
+use print3rs_serializer::{verify_checksum, ChecksumError, FieldNames, Sequenced, SEQUENCE_START};
 
 use tokio::{
-    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
-    sync::{broadcast, mpsc, oneshot},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{broadcast, mpsc, oneshot, watch, Notify, OwnedSemaphorePermit, Semaphore},
     task::JoinHandle,
 };
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
 
 pub type LineStream = broadcast::Receiver<Arc<str>>;
+pub type EventStream = broadcast::Receiver<PrinterEvent>;
+
+/// Item of `Socket::lines_stream`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamedLine {
+    /// A line from the printer
+    Line(Arc<str>),
+    /// How many lines were dropped since the last item, for not being read fast enough
+    Skipped(u64),
+}
+
+/// A line as it was written by `Socket::send_resendable`, to send again unchanged with `Socket::resend`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentLine {
+    sequence: Option<i32>,
+    content: Box<[u8]>,
+}
+
+impl SentLine {
+    /// Line number the line was sent with, which Klipper lines don't have
+    pub fn sequence(&self) -> Option<i32> {
+        self.sequence
+    }
+}
+
+/// Something that happened on a printer connection, see `Printer::subscribe_events`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrinterEvent {
+    /// Communication started, including after a reconnect
+    Connected,
+    /// Communication stopped, because the connection closed or failed, or was disconnected
+    Disconnected,
+    /// A line from the printer, already classified
+    Response(Response),
+}
+
+/// Whether a printer can currently be talked to, see `Printer::watch_connection`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    #[default]
+    Disconnected,
+    /// Waiting for a new connection in `Printer::reconnect_with_backoff`
+    Reconnecting,
+}
+
+/// Where the background task reports the connection starting and stopping
+#[derive(Debug, Clone)]
+struct Status {
+    events: broadcast::Sender<PrinterEvent>,
+    sent: broadcast::Sender<Arc<str>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    unacked: Arc<Mutex<Unacked>>,
+    motion: Arc<Mutex<MotionMode>>,
+    /// Which firmware's responses to expect
+    protocol: Arc<watch::Sender<Protocol>>,
+    /// Asks the background task to throw away the lines queued for it, see `Socket::clear_queue`
+    clear: Arc<Notify>,
+}
+
+impl Status {
+    fn new(capacity: usize) -> Self {
+        Self {
+            events: broadcast::channel(capacity).0,
+            sent: broadcast::channel(capacity).0,
+            state: Arc::new(watch::channel(ConnectionState::Connected).0),
+            unacked: Default::default(),
+            motion: Default::default(),
+            protocol: Arc::new(watch::channel(Protocol::default()).0),
+            clear: Default::default(),
+        }
+    }
+
+    fn connected(&self) {
+        self.state.send_replace(ConnectionState::Connected);
+        let _ = self.events.send(PrinterEvent::Connected);
+    }
+
+    fn disconnected(&self) {
+        self.state.send_replace(ConnectionState::Disconnected);
+        let _ = self.events.send(PrinterEvent::Disconnected);
+    }
+
+    /// Pass on each line written to the printer to anyone watching what's sent
+    fn wrote(&self, content: &[u8]) {
+        let lines = content.split_inclusive(|&byte| byte == b'\n');
+        let mut motion = self.motion.lock().expect("motion tracking never panics");
+        for line in lines.clone() {
+            motion.track(line);
+        }
+        drop(motion);
+        // nothing to decode lines for if nobody is watching
+        if self.sent.receiver_count() == 0 {
+            return;
+        }
+        for line in lines {
+            let _ = self.sent.send(decode_line(line, InvalidUtf8::Replace));
+        }
+    }
+}
+
+/// Most unacknowledged lines kept track of for `Socket::missing_acks`, older ones are forgotten
+const ACK_TRACKING_DEPTH: usize = 256;
+
+/// Numbered lines sent to the printer that haven't been acknowledged with an `ok` yet
+#[derive(Debug, Default)]
+struct Unacked {
+    /// oldest first, with when each was sent
+    lines: VecDeque<(i32, Instant)>,
+    last_sent: Option<i32>,
+}
+
+impl Unacked {
+    fn sent(&mut self, sequence: i32) {
+        // lines from before a sequence change will never be acknowledged by number
+        if self.last_sent.is_some_and(|last| last + 1 != sequence) {
+            self.lines.clear();
+        }
+        self.last_sent = Some(sequence);
+        if self.lines.len() >= ACK_TRACKING_DEPTH {
+            self.lines.pop_front();
+        }
+        self.lines.push_back((sequence, Instant::now()));
+    }
+
+    fn acknowledged(&mut self, sequence: i32) {
+        if let Some(index) = self.lines.iter().position(|(sent, _)| *sent == sequence) {
+            self.lines.remove(index);
+        }
+    }
+
+    /// an `ok` without a line number is for the oldest line still waiting for one, which is given
+    fn acknowledged_oldest(&mut self) -> Option<i32> {
+        self.lines.pop_front().map(|(sequence, _)| sequence)
+    }
+
+    fn missing(&self, window: Duration) -> Vec<i32> {
+        self.lines
+            .iter()
+            .filter(|(_, sent)| sent.elapsed() >= window)
+            .map(|(sequence, _)| *sequence)
+            .collect()
+    }
+}
+
+/// Typical number of commands Marlin can buffer before it stops responding with `ok`
+pub const DEFAULT_FLOW_WINDOW: usize = 4;
+
+/// How long to wait for the answer to a query like `M105` before giving up
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves a sent line's future when its `ok` arrives, or with why it never will
+type Responder = oneshot::Sender<Result<(), Error>>;
 
 #[derive(Debug)]
 struct SendContent {
     content: Box<[u8]>,
     sequence: Option<i32>,
-    responder: Option<oneshot::Sender<()>>,
+    responder: Option<Responder>,
+    permit: Option<OwnedSemaphorePermit>,
+    batch: Vec<BatchedLine>,
+    /// sent again with the line number it already had, so it's already in the resend history
+    resent: bool,
+}
+
+/// One of several lines written to the printer together, see `Socket::send_batch`
+#[derive(Debug)]
+struct BatchedLine {
+    sequence: Option<i32>,
+    /// where the line ends in the batch's content
+    end: usize,
+    permit: Option<OwnedSemaphorePermit>,
 }
 
 impl SendContent {
-    const fn new(
-        content: Box<[u8]>,
-        sequence: Option<i32>,
-        responder: Option<oneshot::Sender<()>>,
-    ) -> Self {
+    const fn new(content: Box<[u8]>, sequence: Option<i32>, responder: Option<Responder>) -> Self {
         Self {
             content,
             sequence,
             responder,
+            permit: None,
+            batch: Vec::new(),
+            resent: false,
+        }
+    }
+
+    /// several complete lines in `content`, each tracked as if it were sent on its own
+    fn batch(content: Box<[u8]>, batch: Vec<BatchedLine>) -> Self {
+        Self {
+            batch,
+            ..Self::new(content, None, None)
         }
     }
+
+    /// hold a slot in a flow window until an `ok` is seen for this content
+    fn with_permit(mut self, permit: Option<OwnedSemaphorePermit>) -> Self {
+        self.permit = permit;
+        self
+    }
+
+    /// a line already sent once with the same line number, see `Socket::resend`
+    fn resent(mut self) -> Self {
+        self.resent = true;
+        self
+    }
 }
 
-impl From<(Box<[u8]>, Option<i32>, Option<oneshot::Sender<()>>)> for SendContent {
-    fn from(value: (Box<[u8]>, Option<i32>, Option<oneshot::Sender<()>>)) -> Self {
+impl From<(Box<[u8]>, Option<i32>, Option<Responder>)> for SendContent {
+    fn from(value: (Box<[u8]>, Option<i32>, Option<Responder>)) -> Self {
         SendContent::new(value.0, value.1, value.2)
     }
 }
 
+/// Every line from a printer copied into several writers, see `Socket::tee`.
+///
+/// Each writer is fed by its own task, so a slow writer only makes itself fall behind.
+/// Copying stops when this is dropped.
+#[derive(Debug)]
+pub struct Tee {
+    lagged: Vec<Arc<AtomicU64>>,
+    tasks: Vec<JoinHandle<std::io::Result<()>>>,
+}
+
+impl Tee {
+    /// Lines each writer missed by not keeping up with the printer, in the order the writers were given
+    pub fn lagged_lines(&self) -> Vec<u64> {
+        self.lagged
+            .iter()
+            .map(|lagged| lagged.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Wait for every writer to finish, which happens when the printer disconnects
+    /// or when writing fails, giving the result for each writer in order
+    pub async fn finished(&mut self) -> Vec<std::io::Result<()>> {
+        let mut results = Vec::with_capacity(self.tasks.len());
+        for task in std::mem::take(&mut self.tasks) {
+            results.push(task.await.unwrap_or_else(|e| Err(std::io::Error::other(e))));
+        }
+        results
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Socket {
     sender: mpsc::Sender<SendContent>,
     serializer: Sequenced,
+    flow_window: Option<Arc<Semaphore>>,
+    /// shared with every clone and the background task, so a change reaches all of them
+    protocol: Arc<watch::Sender<Protocol>>,
+    lagged: u64,
+    unacked: Arc<Mutex<Unacked>>,
+    motion: Arc<Mutex<MotionMode>>,
+    sent: broadcast::Sender<Arc<str>>,
+    clear: Arc<Notify>,
     pub responses: broadcast::Receiver<Arc<str>>,
 }
 
@@ -58,12 +315,89 @@ impl Clone for Socket {
         Self {
             sender: self.sender.clone(),
             serializer: self.serializer.clone(),
+            flow_window: self.flow_window.clone(),
+            protocol: self.protocol.clone(),
+            lagged: 0,
+            unacked: self.unacked.clone(),
+            motion: self.motion.clone(),
+            sent: self.sent.clone(),
+            clear: self.clear.clone(),
             responses: self.responses.resubscribe(),
         }
     }
 }
 
 impl Socket {
+    /// Only allow `window` commands sent through this socket (and its clones) to be
+    /// in flight at once, further sends wait until the printer responds with an `ok`.
+    ///
+    /// This keeps from flooding printers with small receive buffers,
+    /// `DEFAULT_FLOW_WINDOW` matches the buffer depth of typical Marlin printers.
+    /// Without a window, sends are only limited by the capacity of the send queue.
+    pub fn with_flow_window(mut self, window: usize) -> Self {
+        self.flow_window = Some(Arc::new(Semaphore::new(window)));
+        self
+    }
+
+    /// Frame lines sent by this socket for the given firmware protocol.
+    ///
+    /// The protocol is shared with every clone of this socket, including ones taken
+    /// earlier, and with how the printer's responses are read, see `Printer::with_protocol`.
+    pub fn with_protocol(self, protocol: Protocol) -> Self {
+        self.protocol.send_replace(protocol);
+        self
+    }
+
+    /// Protocol that `send` frames lines for
+    pub fn protocol(&self) -> Protocol {
+        *self.protocol.borrow()
+    }
+
+    /// The serializer, naming struct fields the way `protocol` expects
+    fn serializer_for(&self, protocol: Protocol) -> Sequenced {
+        self.serializer.clone().with_field_names(match protocol {
+            Protocol::Marlin => FieldNames::FirstLetter,
+            Protocol::Klipper => FieldNames::KeyValue,
+        })
+    }
+
+    fn serialize(&self, gcode: impl Serialize) -> (Option<i32>, Box<[u8]>) {
+        let protocol = self.protocol();
+        let serializer = self.serializer_for(protocol);
+        match protocol {
+            Protocol::Marlin => {
+                let (sequence, bytes) = serializer.serialize(gcode);
+                (Some(sequence), bytes)
+            }
+            Protocol::Klipper => (None, serializer.serialize_unsequenced(gcode)),
+        }
+    }
+
+    async fn acquire_flow(&self) -> Result<Option<OwnedSemaphorePermit>, Error> {
+        match self.flow_window {
+            Some(ref window) => Ok(Some(
+                window
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|_| Error::Disconnected)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn try_acquire_flow(&self) -> Result<Option<OwnedSemaphorePermit>, Error> {
+        match self.flow_window {
+            Some(ref window) => Ok(Some(
+                window
+                    .clone()
+                    .try_acquire_owned()
+                    .map_err(|_| Error::FlowWindowFull)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
     /// Serialize a struct implementing Serialize and send the bytes to the printer
     ///
     /// Sent bytes will include a sequence number and checksum.
@@ -77,11 +411,51 @@ impl Socket {
         &self,
         gcode: impl Serialize + Debug,
     ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
+        let permit = self.acquire_flow().await?;
+        let send_slot = self.sender.reserve().await?;
+        let (sequence, bytes) = self.serialize(gcode);
+        let (responder, response) = oneshot::channel();
+        send_slot.send(SendContent::new(bytes, sequence, Some(responder)).with_permit(permit));
+        let response = async { response.await.unwrap_or(Err(Error::WontRespond)) };
+        Ok(response)
+    }
+
+    /// Same as `send`, also giving the line as it was written, so it can be sent again
+    /// with `resend` if its ok goes missing.
+    pub async fn send_resendable(
+        &self,
+        gcode: impl Serialize + Debug,
+    ) -> Result<(SentLine, impl Future<Output = Result<(), Error>>), Error> {
+        let permit = self.acquire_flow().await?;
+        let send_slot = self.sender.reserve().await?;
+        let (sequence, bytes) = self.serialize(gcode);
+        let line = SentLine {
+            sequence,
+            content: bytes.clone(),
+        };
+        let (responder, response) = oneshot::channel();
+        send_slot.send(SendContent::new(bytes, sequence, Some(responder)).with_permit(permit));
+        let response = async { response.await.unwrap_or(Err(Error::WontRespond)) };
+        Ok((line, response))
+    }
+
+    /// Send a line from `send_resendable` again exactly as it was, waiting for a new ok.
+    ///
+    /// The line keeps its line number, so a printer which already ran it can tell it's
+    /// the same line rather than a new one, and the numbering of later lines isn't affected.
+    pub async fn resend(
+        &self,
+        line: &SentLine,
+    ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
+        let permit = self.acquire_flow().await?;
         let send_slot = self.sender.reserve().await?;
-        let (sequence, bytes) = self.serializer.serialize(gcode);
         let (responder, response) = oneshot::channel();
-        send_slot.send(SendContent::new(bytes, Some(sequence), Some(responder)));
-        let response = async { response.await.map_err(|_| Error::WontRespond) };
+        send_slot.send(
+            SendContent::new(line.content.clone(), line.sequence, Some(responder))
+                .with_permit(permit)
+                .resent(),
+        );
+        let response = async { response.await.unwrap_or(Err(Error::WontRespond)) };
         Ok(response)
     }
 
@@ -93,11 +467,12 @@ impl Socket {
         &self,
         gcode: impl Serialize + Debug,
     ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
+        let permit = self.try_acquire_flow()?;
         let send_slot = self.sender.try_reserve()?;
-        let (sequence, bytes) = self.serializer.serialize(gcode);
+        let (sequence, bytes) = self.serialize(gcode);
         let (responder, response) = oneshot::channel();
-        send_slot.send(SendContent::new(bytes, Some(sequence), Some(responder)));
-        let response = async { response.await.map_err(|_| Error::WontRespond) };
+        send_slot.send(SendContent::new(bytes, sequence, Some(responder)).with_permit(permit));
+        let response = async { response.await.unwrap_or(Err(Error::WontRespond)) };
         Ok(response)
     }
 
@@ -112,11 +487,14 @@ impl Socket {
         &self,
         gcode: impl Serialize + Debug,
     ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
-        let bytes = serialize_unsequenced(gcode);
+        let bytes = self
+            .serializer_for(self.protocol())
+            .serialize_unsequenced(gcode);
         let (responder, response) = oneshot::channel();
+        let permit = self.acquire_flow().await?;
         let send_slot = self.sender.reserve().await?;
-        send_slot.send(SendContent::new(bytes, None, Some(responder)));
-        let response = async { response.await.map_err(|_| Error::WontRespond) };
+        send_slot.send(SendContent::new(bytes, None, Some(responder)).with_permit(permit));
+        let response = async { response.await.unwrap_or(Err(Error::WontRespond)) };
         Ok(response)
     }
 
@@ -127,15 +505,58 @@ impl Socket {
         &self,
         gcode: impl Serialize + Debug,
     ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
-        let bytes = serialize_unsequenced(gcode);
+        let bytes = self
+            .serializer_for(self.protocol())
+            .serialize_unsequenced(gcode);
         let (responder, response) = oneshot::channel();
+        let permit = self.try_acquire_flow()?;
         let send_slot = self.sender.try_reserve()?;
-        send_slot.send(SendContent::new(bytes, None, Some(responder)));
-        let response = async { response.await.map_err(|_| Error::WontRespond) };
+        send_slot.send(SendContent::new(bytes, None, Some(responder)).with_permit(permit));
+        let response = async { response.await.unwrap_or(Err(Error::WontRespond)) };
+        Ok(response)
+    }
+
+    /// Send a line which is already numbered and checksummed, like `N12 G28*20`, as it is.
+    ///
+    /// The line number is taken up by the socket, so the next line from `send` is numbered after it
+    /// and the printer doesn't ask for a resend. Fails without sending if the line doesn't start
+    /// with a line number or its checksum is wrong. A newline is added if the line doesn't have one.
+    pub async fn send_prechecksummed(
+        &self,
+        line: &[u8],
+    ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
+        let sequence = embedded_sequence(line)?;
+        let mut content = line.to_owned();
+        if !content.ends_with(b"\n") {
+            content.push(b'\n');
+        }
+        let permit = self.acquire_flow().await?;
+        let send_slot = self.sender.reserve().await?;
+        // taken after waiting, so it isn't undone by lines serialized in the meantime
+        self.serializer.set_sequence(sequence + 1);
+        let (responder, response) = oneshot::channel();
+        send_slot.send(
+            SendContent::new(content.into_boxed_slice(), Some(sequence), Some(responder))
+                .with_permit(permit),
+        );
+        let response = async { response.await.unwrap_or(Err(Error::WontRespond)) };
         Ok(response)
     }
 
+    /// Throw away every line queued for the printer that hasn't been written yet,
+    /// leaving the connection open, e.g. to abandon the rest of a print without an `M112`.
+    ///
+    /// Waiting on a thrown away line gives `Error::WontRespond`.
+    /// Lines already written are left to the printer, and lines sent after this
+    /// is called may or may not be thrown away with the rest.
+    pub fn clear_queue(&self) {
+        self.clear.notify_one();
+    }
+
     /// Send any raw sequence of bytes to the printer
+    ///
+    /// Raw bytes don't wait for room in a flow window, so an emergency line like `M112`
+    /// isn't held up behind the lines the printer is still working through.
     pub async fn send_raw(&self, gcode: &[u8]) -> Result<(), Error> {
         let sender = self.sender.reserve().await?;
         sender.send(SendContent::new(
@@ -146,7 +567,7 @@ impl Socket {
         Ok(())
     }
 
-    /// Send any raw sequence of bytes to the printer
+    /// Send any raw sequence of bytes to the printer, see `send_raw`
     pub fn try_send_raw(&self, gcode: &[u8]) -> Result<(), Error> {
         let sender = self.sender.try_reserve()?;
         sender.send(SendContent::new(
@@ -157,28 +578,370 @@ impl Socket {
         Ok(())
     }
 
+    /// Serialize many structs into one buffer and send them to the printer in a single write.
+    ///
+    /// Each line gets its own sequence number and checksum the same as with `send`,
+    /// and can be resent if the printer asks, but queueing and writing once for the whole
+    /// batch avoids the per line overhead when streaming dense Gcode.
+    /// With a flow window, lines are sent in as large chunks as the window has room for.
+    ///
+    /// Gives the sequence numbers of the lines in order, which is empty for Klipper
+    /// since its lines aren't numbered.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn send_batch<T: Serialize>(
+        &self,
+        gcodes: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<i32>, Error> {
+        let gcodes: Vec<T> = gcodes.into_iter().collect();
+        let protocol = self.protocol();
+        let serializer = self.serializer_for(protocol);
+        let mut sequences = Vec::with_capacity(gcodes.len());
+        let mut remaining = gcodes.as_slice();
+        while !remaining.is_empty() {
+            let mut permits = vec![self.acquire_flow().await?];
+            if self.flow_window.is_some() {
+                // send whatever fits in the window now rather than waiting for room for everything
+                while permits.len() < remaining.len() {
+                    match self.try_acquire_flow() {
+                        Ok(permit) => permits.push(permit),
+                        Err(_) => break,
+                    }
+                }
+            } else {
+                permits.resize_with(remaining.len(), || None);
+            }
+            let (chunk, rest) = remaining.split_at(permits.len());
+            remaining = rest;
+            let send_slot = self.sender.reserve().await?;
+            let mut content = Vec::new();
+            let mut batch = Vec::with_capacity(chunk.len());
+            for (gcode, permit) in chunk.iter().zip(permits) {
+                let sequence = match protocol {
+                    Protocol::Marlin => Some(serializer.serialize_append(gcode, &mut content)),
+                    Protocol::Klipper => {
+                        serializer.serialize_unsequenced_append(gcode, &mut content);
+                        None
+                    }
+                };
+                sequences.extend(sequence);
+                batch.push(BatchedLine {
+                    sequence,
+                    end: content.len(),
+                    permit,
+                });
+            }
+            send_slot.send(SendContent::batch(content.into_boxed_slice(), batch));
+        }
+        Ok(sequences)
+    }
+
     /// Read the next line from the printer
     ///
     /// May not recieve all lines, if calls to this function are spaced
     /// far apart, the buffer may overfill and the oldest messages will
     /// be dropped. In this case the oldest available message is returned.
+    /// The number of dropped lines can be checked with `lagged_lines`.
     pub async fn read_next_line(&mut self) -> Result<Arc<str>, Error> {
-        let line = self.responses.recv().await?;
-        Ok(line)
+        loop {
+            match self.responses.recv().await {
+                Err(broadcast::error::RecvError::Lagged(skipped)) => self.record_lag(skipped),
+                line => return Ok(line?),
+            }
+        }
     }
 
     /// See `read_next_line`
     ///
     /// Where that method would await, this will return immediately with an error.
     pub fn try_read_next_line(&mut self) -> Result<Arc<str>, Error> {
-        let line = self.responses.try_recv()?;
-        Ok(line)
+        loop {
+            match self.responses.try_recv() {
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => self.record_lag(skipped),
+                line => return Ok(line?),
+            }
+        }
+    }
+
+    /// Total number of lines this socket missed by not reading them fast enough.
+    ///
+    /// Cloned sockets start counting from 0.
+    pub fn lagged_lines(&self) -> u64 {
+        self.lagged
+    }
+
+    fn record_lag(&mut self, skipped: u64) {
+        tracing::warn!("Fell behind printer output, skipped {skipped} lines");
+        self.lagged += skipped;
     }
 
     /// Obtain a broadcast receiver returning all lines received by the printer
     pub fn subscribe_lines(&self) -> Result<LineStream, Error> {
         Ok(self.responses.resubscribe())
     }
+
+    /// Lines received by the printer from now on as a `Stream`, for use with stream combinators.
+    ///
+    /// Lines dropped for not being read fast enough show up as a `StreamedLine::Skipped`
+    /// item and the stream carries on with the oldest line still available.
+    /// The stream ends when the connection does.
+    pub fn lines_stream(&self) -> impl Stream<Item = StreamedLine> + Send + Unpin {
+        BroadcastStream::new(self.responses.resubscribe()).map(|line| match line {
+            Ok(line) => StreamedLine::Line(line),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                tracing::warn!("Fell behind printer output, skipped {skipped} lines");
+                StreamedLine::Skipped(skipped)
+            }
+        })
+    }
+
+    /// Obtain a broadcast receiver returning every line once it's been written to the printer,
+    /// exactly as written with any line number and checksum, including lines resent when asked.
+    ///
+    /// Lines are only decoded for broadcast while there are subscribers, so this costs
+    /// nothing until it's used. Lines continue across `Printer::reconnect`.
+    pub fn subscribe_sent(&self) -> Result<LineStream, Error> {
+        Ok(self.sent.subscribe())
+    }
+
+    /// Sequence numbers of lines sent at least `window` ago that the printer hasn't
+    /// acknowledged with an `ok`, oldest first, to spot dropped lines before the
+    /// printer asks for a resend.
+    ///
+    /// An `ok` with a line number acknowledges that line, and one without acknowledges
+    /// the oldest line still waiting. Lines sent before the sequence was changed,
+    /// such as by `resync`, are no longer tracked.
+    pub fn missing_acks(&self, window: Duration) -> Vec<i32> {
+        self.unacked
+            .lock()
+            .expect("ack tracking never panics")
+            .missing(window)
+    }
+
+    /// Positioning mode and units the printer was last told to use with `G90`/`G91` and
+    /// `G20`/`G21`, to check before sending moves, like a relative jog.
+    ///
+    /// Tracked from lines as they're written to the printer, by any socket, so it reflects
+    /// what the host commanded rather than what the firmware reports.
+    pub fn motion_mode(&self) -> MotionMode {
+        *self.motion.lock().expect("motion tracking never panics")
+    }
+
+    /// Copy every line received from the printer from now on into each of `writers`,
+    /// such as a log file and the screen at the same time.
+    ///
+    /// Every writer gets its own subscription, so each sees every line unless it falls
+    /// further behind than the printer's response capacity. Lines missed that way are
+    /// counted separately for each writer, see `Tee::lagged_lines`.
+    pub fn tee<W>(&self, writers: Vec<W>) -> Tee
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut tee = Tee {
+            lagged: Vec::with_capacity(writers.len()),
+            tasks: Vec::with_capacity(writers.len()),
+        };
+        for mut writer in writers {
+            let mut lines = self.responses.resubscribe();
+            let lagged = Arc::new(AtomicU64::new(0));
+            tee.lagged.push(lagged.clone());
+            tee.tasks.push(tokio::spawn(async move {
+                loop {
+                    match lines.recv().await {
+                        Ok(line) => {
+                            writer.write_all(line.as_bytes()).await?;
+                            writer.flush().await?;
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            tracing::warn!(
+                                "Tee fell behind printer output, skipped {skipped} lines"
+                            );
+                            lagged.fetch_add(skipped, Ordering::Relaxed);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+                writer.flush().await
+            }));
+        }
+        tee
+    }
+
+    /// Send a query like `M114` or `M503` and collect every line received up to and including the next `ok`.
+    ///
+    /// The `ok` is kept since some replies, like `M105` from Marlin, put their answer on the same line.
+    /// Lines are collected from when the command is sent, so any other commands still waiting
+    /// for their `ok` may end the collection early.
+    ///
+    /// Gives `Error::Timeout` if the `ok` doesn't arrive within `timeout`.
+    pub async fn send_and_collect(
+        &self,
+        gcode: impl Serialize + Debug,
+        timeout: Duration,
+    ) -> Result<Vec<Arc<str>>, Error> {
+        let mut lines = self.subscribe_lines()?;
+        let _ = self.send_unsequenced(gcode).await?;
+        let collect = async {
+            let mut collected = Vec::new();
+            loop {
+                match lines.recv().await {
+                    Ok(line) => {
+                        let is_ok =
+                            matches!(self.protocol().classify(line.as_bytes()), Response::Ok(_));
+                        collected.push(line);
+                        if is_ok {
+                            return Ok(collected);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Missed {skipped} lines of reply from printer");
+                    }
+                    Err(e) => return Err(Error::from(e)),
+                }
+            }
+        };
+        tokio::time::timeout(timeout, collect)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Bring the printer's line number back in step with this socket's, such as after
+    /// connecting to a printer that was already talked to, and wait for it to acknowledge.
+    ///
+    /// Sends `M110 N0` without a line number and restarts the sequence at `SEQUENCE_START`,
+    /// so the next numbered line is the one the printer expects. Lines sent by other clones
+    /// keep the old numbering if they're queued before the `M110` and the new numbering after.
+    /// Firmware without `M110`, like RepRapFirmware, may never reply, in which case this gives
+    /// `Error::Timeout` after `timeout` with the sequence still restarted. Does nothing for
+    /// Klipper, which doesn't use line numbers.
+    pub async fn resync(&self, timeout: Duration) -> Result<(), Error> {
+        if self.protocol() == Protocol::Klipper {
+            return Ok(());
+        }
+        let (responder, response) = oneshot::channel();
+        let permit = self.acquire_flow().await?;
+        let send_slot = self.sender.reserve().await?;
+        send_slot.send(self.restart_sequence(Some(responder)).with_permit(permit));
+        let reply = async { response.await.unwrap_or(Err(Error::WontRespond)) };
+        tokio::time::timeout(timeout, reply)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Restart the sequence, giving the `M110` that tells the printer.
+    ///
+    /// Only call with a send slot already in hand, so the `M110` is queued straight after
+    /// and no line numbered either side of the restart ends up on the wrong side of it.
+    fn restart_sequence(&self, responder: Option<Responder>) -> SendContent {
+        self.serializer.set_sequence(SEQUENCE_START);
+        let bytes = self
+            .serializer
+            .serialize_unsequenced(format!("M110 N{}", SEQUENCE_START - 1));
+        SendContent::new(bytes, None, responder)
+    }
+
+    /// Ask for the current temperatures with `M105` and wait for the report.
+    ///
+    /// Gives `Error::Timeout` if no reply arrives within `DEFAULT_QUERY_TIMEOUT`.
+    pub async fn read_temperatures(&self) -> Result<TemperatureReport, Error> {
+        self.send_and_collect("M105", DEFAULT_QUERY_TIMEOUT)
+            .await?
+            .iter()
+            .find_map(|line| temperatures(line.as_bytes()))
+            .ok_or(Error::NoTemperatures)
+    }
+
+    /// Ask where the toolhead is with `M114` and wait for the report,
+    /// including stepper positions if the printer gives them.
+    ///
+    /// Gives `Error::Timeout` if no reply arrives within `DEFAULT_QUERY_TIMEOUT`.
+    pub async fn read_position(&self) -> Result<Position, Error> {
+        self.send_and_collect("M114", DEFAULT_QUERY_TIMEOUT)
+            .await?
+            .iter()
+            .find_map(|line| position(line.as_bytes()))
+            .ok_or(Error::NoPosition)
+    }
+
+    /// Heat the hotend with `M109` and wait until it's at `temperature`, calling `on_progress`
+    /// with each hotend temperature the printer reports while heating.
+    ///
+    /// The printer only sends the `ok` once heating is done, so this can take minutes,
+    /// wrap it in `tokio::time::timeout` to give up sooner.
+    pub async fn set_hotend_and_wait(
+        &self,
+        temperature: f32,
+        on_progress: impl FnMut(Temperature),
+    ) -> Result<(), Error> {
+        self.heat_and_wait(
+            format!("M109 S{temperature}"),
+            |report| report.hotend.or_else(|| report.extruders.first().copied()),
+            on_progress,
+        )
+        .await
+    }
+
+    /// Heat the bed with `M190` and wait until it's at `temperature`, calling `on_progress`
+    /// with each bed temperature the printer reports while heating.
+    ///
+    /// See `set_hotend_and_wait`
+    pub async fn set_bed_and_wait(
+        &self,
+        temperature: f32,
+        on_progress: impl FnMut(Temperature),
+    ) -> Result<(), Error> {
+        self.heat_and_wait(
+            format!("M190 S{temperature}"),
+            |report| report.bed,
+            on_progress,
+        )
+        .await
+    }
+
+    /// Send a heating command which holds its `ok` until done, passing the heater
+    /// picked out of each temperature report to `on_progress` until then
+    async fn heat_and_wait(
+        &self,
+        gcode: String,
+        heater: impl Fn(&TemperatureReport) -> Option<Temperature>,
+        mut on_progress: impl FnMut(Temperature),
+    ) -> Result<(), Error> {
+        let mut lines = self.subscribe_lines()?;
+        // without a line number, so a plain `ok` counts as the reply
+        let heated = self.send_unsequenced(gcode).await?;
+        tokio::pin!(heated);
+        loop {
+            tokio::select! {
+                // every report comes in before the ok, so take them all first
+                biased;
+                line = lines.recv() => match line {
+                    Ok(line) => {
+                        if let Some(temperature) =
+                            temperatures(line.as_bytes()).as_ref().and_then(&heater)
+                        {
+                            on_progress(temperature);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Missed {skipped} lines while heating");
+                    }
+                    Err(e) => return Err(Error::from(e)),
+                },
+                done = &mut heated => return done,
+            }
+        }
+    }
+}
+
+/// Change to make to the transport a printer is connected through, see `Printer::configure_transport`
+struct TransportControl {
+    configure: Box<dyn FnOnce(&mut dyn Any) -> Result<(), Error> + Send>,
+    done: oneshot::Sender<Result<(), Error>>,
+}
+
+impl Debug for TransportControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransportControl").finish_non_exhaustive()
+    }
 }
 
 /// Handle for asynchronous serial communication with a 3D printer
@@ -189,17 +952,35 @@ pub enum Printer {
     Connected {
         socket: Socket,
         com_task: tokio::task::JoinHandle<()>,
+        response_sender: broadcast::Sender<Arc<str>>,
+        status: Status,
+        config: PrinterConfig,
+        /// Tells the background task to finish writing queued lines and stop
+        shutdown: Option<oneshot::Sender<()>>,
+        /// Reaches the transport inside the background task
+        control: mpsc::Sender<TransportControl>,
     },
 }
 
 impl Drop for Printer {
     fn drop(&mut self) {
-        if let Self::Connected { com_task, .. } = self {
-            com_task.abort()
+        if let Self::Connected {
+            com_task, status, ..
+        } = self
+        {
+            stop_com_task(com_task, status);
         }
     }
 }
 
+/// Abort background communication, telling subscribers if it was still running
+fn stop_com_task(com_task: &JoinHandle<()>, status: &Status) {
+    if !com_task.is_finished() {
+        com_task.abort();
+        status.disconnected();
+    }
+}
+
 impl PartialEq for Printer {
     fn eq(&self, other: &Self) -> bool {
         core::mem::discriminant(self) == core::mem::discriminant(other)
@@ -226,6 +1007,33 @@ pub enum Error {
     #[error("Ok not received")]
     WontRespond,
 
+    #[error("Printer didn't answer in time")]
+    Timeout,
+
+    #[error("Printer reported an error: {0}")]
+    Firmware(String),
+
+    #[error("Printer didn't report any temperatures")]
+    NoTemperatures,
+
+    #[error("Printer didn't report its position")]
+    NoPosition,
+
+    #[error("Connection doesn't support this setting")]
+    UnsupportedTransport,
+
+    #[error("Moonraker connection failed: {0}")]
+    Moonraker(String),
+
+    #[error("Too many commands waiting for ok, try again")]
+    FlowWindowFull,
+
+    #[error("Line doesn't have a valid checksum: {0}")]
+    Checksum(#[from] ChecksumError),
+
+    #[error("Line doesn't start with a line number")]
+    MissingSequence,
+
     #[error("No responses recieved, try again")]
     TryReadLine(#[from] broadcast::error::TryRecvError),
 
@@ -233,74 +1041,574 @@ pub enum Error {
     ReadLine(#[from] broadcast::error::RecvError),
 }
 
-/// Loop for handling sending/receiving in the background with possible split senders/receivers
-async fn printer_com_task(
-    mut transport: impl AsyncBufRead + AsyncWrite + Unpin,
-    mut gcoderx: mpsc::Receiver<SendContent>,
-    responsetx: broadcast::Sender<Arc<str>>,
-) {
-    tracing::debug!("Started background printer communications");
-    let mut buf = String::new();
-    let mut pending_responses = BTreeMap::new();
-    loop {
-        tokio::select! {
-            Some(SendContent{content, sequence, responder}) = gcoderx.recv(), if pending_responses.len() < 4 => {
-                if transport.write_all(&content).await.is_err() {return;}
-                if transport.flush().await.is_err() {return;}
-                tracing::debug!("Sent `{}` to printer", String::from_utf8_lossy(&content).trim());
-                if let Some(responder) = responder {
-                    // dropping anything in slot, gives WontRespond error
-                    pending_responses.insert(sequence, (responder, content));
-                }
-            },
-            Ok(1..) = transport.read_line(&mut buf) => {
-                tracing::debug!("Received `{buf}` from printer");
-                if let Ok(ok_res) = response.parse(buf.as_bytes()) {
-                    match ok_res {
-                        Response::Ok(ref maybe_seq) => {
-                            if let Some((responder, _)) = pending_responses.remove(maybe_seq){
-                                 let _ = responder.send(());
-                            }
-                        },
-                        Response::Resend(ref maybe_seq) => {
-                            if let Some((_, ref line)) = pending_responses.get(maybe_seq) {
-                                if transport.write_all(line).await.is_err() {return;}
-                                if transport.flush().await.is_err() {return;}
-                                tracing::debug!("Resent `{}` to printer", String::from_utf8_lossy(line).trim());
-                            }
-                        },
+/// Default number of sent lines remembered for resending
+pub const DEFAULT_RESEND_DEPTH: usize = 16;
+
+/// Default time to wait for the `ok` to a numbered line, see `PrinterConfig::ack_timeout`
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of numbered lines waiting for an `ok` at once, see `PrinterConfig::ack_window`
+pub const DEFAULT_ACK_WINDOW: usize = 4;
+
+/// Settings for background communication with a printer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrinterConfig {
+    /// Number of commands that can be queued for the printer before senders wait.
+    /// Larger queues let bursts of commands be accepted immediately,
+    /// but every queued command is held in memory until written. Must be nonzero.
+    pub send_capacity: usize,
+    /// Number of received lines kept for each subscriber.
+    /// Subscribers that fall further behind than this miss the oldest lines,
+    /// so fast logging or many subscribers call for more, at the cost of memory. Must be nonzero.
+    pub response_capacity: usize,
+    /// Initial capacity in bytes for the buffer lines from the printer are read into.
+    /// Longer lines still fit, but need a reallocation.
+    pub read_buffer_size: usize,
+    /// Number of recently sent sequenced lines remembered so they can be resent
+    /// automatically when the printer asks. Older lines can't be recovered.
+    pub resend_depth: usize,
+    /// What to do with bytes from the printer that aren't valid UTF-8
+    pub invalid_utf8: InvalidUtf8,
+    /// Also end lines at a lone `\r`, for old firmwares that don't send `\n`.
+    /// `\r\n` is always treated as a single line ending.
+    pub bare_cr_line_endings: bool,
+    /// How long to wait for the `ok` to a numbered line before its future gives
+    /// `Error::Timeout`, so a lost `ok` doesn't leave the sender waiting forever.
+    /// The wait starts over whenever the printer reports it's busy or sends temperatures,
+    /// which it does while working on long commands like homing and heating.
+    /// `None` waits as long as it takes.
+    pub ack_timeout: Option<Duration>,
+    /// How long the printer can stay silent while lines are waiting on it before the
+    /// connection is checked with an empty line, then treated as lost if another
+    /// silence this long follows. Some platforms never fail reads from an unplugged
+    /// USB cable, so without this the link can look alive forever.
+    /// Busy messages and temperature reports count, but firmware that goes quiet during
+    /// long moves needs a generous window. `None` never gives up on a quiet connection.
+    pub idle_timeout: Option<Duration>,
+    /// Send an `M105` whenever the connection has been quiet this long with nothing
+    /// waiting on the printer, so networked links aren't dropped for being idle and a
+    /// dead one is noticed sooner, especially along with `idle_timeout`.
+    /// `None` sends nothing the host didn't ask for.
+    pub keepalive: Option<Duration>,
+    /// Number of numbered lines that can be waiting for their `ok` at once,
+    /// later lines stay queued until one is acknowledged or times out. Must be nonzero.
+    pub ack_window: usize,
+}
+
+impl Default for PrinterConfig {
+    fn default() -> Self {
+        Self {
+            send_capacity: 16,
+            response_capacity: 64,
+            read_buffer_size: 1024,
+            resend_depth: DEFAULT_RESEND_DEPTH,
+            invalid_utf8: InvalidUtf8::default(),
+            bare_cr_line_endings: false,
+            ack_timeout: Some(DEFAULT_ACK_TIMEOUT),
+            idle_timeout: None,
+            keepalive: None,
+            ack_window: DEFAULT_ACK_WINDOW,
+        }
+    }
+}
+
+/// How bytes received from a printer that aren't valid UTF-8 are turned into text
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Replace each invalid sequence with `�`
+    #[default]
+    Replace,
+    /// Write each invalid byte as an escape like `\xff`, so the raw bytes can still be read
+    Escape,
+}
+
+/// Turn a line received from a printer into the text given to subscribers.
+///
+/// This is the only place printer output is decoded, so everything reading lines
+/// sees the same text for the same bytes.
+pub fn decode_line(line: &[u8], policy: InvalidUtf8) -> Arc<str> {
+    if let Ok(line) = std::str::from_utf8(line) {
+        return Arc::from(line);
+    }
+    match policy {
+        InvalidUtf8::Replace => Arc::from(String::from_utf8_lossy(line)),
+        InvalidUtf8::Escape => {
+            let mut decoded = String::with_capacity(line.len());
+            let mut rest = line;
+            loop {
+                match std::str::from_utf8(rest) {
+                    Ok(valid) => {
+                        decoded.push_str(valid);
+                        return Arc::from(decoded);
+                    }
+                    Err(e) => {
+                        let (valid, invalid) = rest.split_at(e.valid_up_to());
+                        decoded.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                        let invalid_len = e.error_len().unwrap_or(invalid.len());
+                        for byte in &invalid[..invalid_len] {
+                            decoded.push_str(&format!("\\x{byte:02x}"));
+                        }
+                        rest = &invalid[invalid_len..];
                     }
                 }
-                if responsetx.send(Arc::from(buf.split_off(0))).is_err() {return;}
-            },
-            else => return,
+            }
         }
     }
 }
 
-impl Printer {
-    /// Create a new printer from a SerialStream.
-    ///
+/// How `Printer::reconnect_with_backoff` spaces out attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackoffPolicy {
+    /// Wait after the first failed attempt, doubled after each further failure
+    pub initial_delay: Duration,
+    /// Longest wait between attempts
+    pub max_delay: Duration,
+    /// Give up after this many attempts, or retry forever if `None`
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(8),
+            max_attempts: Some(10),
+        }
+    }
+}
+
+/// Read a line from the printer into `buf`, normalizing its line ending to a single `\n`.
+///
+/// `after_cr` carries whether the last line ended with a bare `\r`, so the `\n` of a `\r\n`
+/// split across reads isn't taken as an empty line. Anything read is kept in `buf` and
+/// `after_cr` if this is cancelled, so it can be used in `select!`.
+async fn read_printer_line(
+    transport: &mut (impl AsyncBufRead + Unpin),
+    buf: &mut Vec<u8>,
+    bare_cr: bool,
+    after_cr: &mut bool,
+) -> std::io::Result<usize> {
+    loop {
+        let available = transport.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(buf.len());
+        }
+        if std::mem::take(after_cr) && available[0] == b'\n' {
+            transport.consume(1);
+            continue;
+        }
+        match available
+            .iter()
+            .position(|&byte| byte == b'\n' || (bare_cr && byte == b'\r'))
+        {
+            Some(end) => {
+                *after_cr = available[end] == b'\r';
+                buf.extend_from_slice(&available[..end]);
+                transport.consume(end + 1);
+                if buf.last() == Some(&b'\r') {
+                    buf.pop();
+                }
+                buf.push(b'\n');
+                return Ok(buf.len());
+            }
+            None => {
+                let read = available.len();
+                buf.extend_from_slice(available);
+                transport.consume(read);
+            }
+        }
+    }
+}
+
+/// Line number of a line with a valid checksum, like the `12` of `N12 G28*20`
+fn embedded_sequence(line: &[u8]) -> Result<i32, Error> {
+    let payload = verify_checksum(line)?;
+    let start = payload
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(payload.len());
+    let digits = payload[start..]
+        .strip_prefix(b"N")
+        .ok_or(Error::MissingSequence)?;
+    let end = digits
+        .iter()
+        .position(|byte| !byte.is_ascii_digit())
+        .unwrap_or(digits.len());
+    std::str::from_utf8(&digits[..end])
+        .expect("digits are ascii")
+        .parse()
+        .map_err(|_| Error::MissingSequence)
+}
+
+/// Background communication with a printer, sending events when it starts and stops
+async fn printer_com_task(
+    transport: impl AsyncBufRead + AsyncWrite + Unpin + 'static,
+    gcoderx: mpsc::Receiver<SendContent>,
+    responsetx: broadcast::Sender<Arc<str>>,
+    status: Status,
+    config: PrinterConfig,
+    shutdown: oneshot::Receiver<()>,
+    controlrx: mpsc::Receiver<TransportControl>,
+) {
+    status.connected();
+    printer_com_loop(
+        transport, gcoderx, responsetx, &status, config, shutdown, controlrx,
+    )
+    .await;
+    status.disconnected();
+}
+
+/// Keep a sent line to resend if the printer asks for it again
+fn remember_sent(
+    history: &mut VecDeque<(i32, Box<[u8]>)>,
+    resend_depth: usize,
+    sequence: i32,
+    line: Box<[u8]>,
+) {
+    // lines from before a sequence change can't be resent by number
+    if history
+        .back()
+        .is_some_and(|(last, _)| *last + 1 != sequence)
+    {
+        history.clear();
+    }
+    if history.len() >= resend_depth {
+        history.pop_front();
+    }
+    if resend_depth > 0 {
+        history.push_back((sequence, line));
+    }
+}
+
+/// Loop for handling sending/receiving in the background with possible split senders/receivers
+async fn printer_com_loop(
+    mut transport: impl AsyncBufRead + AsyncWrite + Unpin + 'static,
+    mut gcoderx: mpsc::Receiver<SendContent>,
+    responsetx: broadcast::Sender<Arc<str>>,
+    status: &Status,
+    config: PrinterConfig,
+    mut shutdown: oneshot::Receiver<()>,
+    mut controlrx: mpsc::Receiver<TransportControl>,
+) {
+    tracing::debug!("Started background printer communications");
+    let mut buf = Vec::with_capacity(config.read_buffer_size);
+    let mut after_cr = false;
+    let mut pending_responses = BTreeMap::new();
+    // unsequenced lines can only be matched to oks by counting them in order,
+    // each kept with whether it holds a flow window slot
+    let mut unsequenced_responses: VecDeque<(Option<Responder>, bool)> = VecDeque::new();
+    // flow window slots, along with the line number of the line holding each where it has one
+    let mut in_flight: VecDeque<(Option<i32>, OwnedSemaphorePermit)> = VecDeque::new();
+    let mut history: VecDeque<(i32, Box<[u8]>)> = VecDeque::with_capacity(config.resend_depth);
+    // busy messages and temperature reports show the printer is still working on a line
+    let mut last_busy = Instant::now();
+    // silence only counts while something is waiting on the printer
+    let mut last_heard = Instant::now();
+    let mut probed = false;
+    let mut last_traffic = Instant::now();
+    let mut keepalive = config.keepalive.map(|period| {
+        let mut keepalive = tokio::time::interval_at((Instant::now() + period).into(), period);
+        keepalive.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        keepalive
+    });
+    loop {
+        let ack_deadline = config.ack_timeout.and_then(|timeout| {
+            let oldest = pending_responses.values().map(|(sent, _)| *sent).min()?;
+            Some(Instant::max(oldest, last_busy) + timeout)
+        });
+        let waiting = !(pending_responses.is_empty() && unsequenced_responses.is_empty());
+        let idle_deadline = config
+            .idle_timeout
+            .filter(|_| waiting)
+            .map(|timeout| last_heard + timeout);
+        tokio::select! {
+            Some(SendContent{content, sequence, responder, permit, batch, resent}) = gcoderx.recv(), if pending_responses.len() < config.ack_window => {
+                if transport.write_all(&content).await.is_err() {return;}
+                if transport.flush().await.is_err() {return;}
+                status.wrote(&content);
+                tracing::debug!("Sent `{}` to printer", String::from_utf8_lossy(&content).trim());
+                if !waiting {
+                    last_heard = Instant::now();
+                }
+                last_traffic = Instant::now();
+                if !batch.is_empty() {
+                    let mut start = 0;
+                    for BatchedLine{sequence, end, permit} in batch {
+                        let holds_slot = permit.is_some();
+                        in_flight.extend(permit.map(|permit| (sequence, permit)));
+                        match sequence {
+                            Some(sequence) => {
+                                status.unacked.lock().expect("ack tracking never panics").sent(sequence);
+                                remember_sent(&mut history, config.resend_depth, sequence, content[start..end].into());
+                            },
+                            None => unsequenced_responses.push_back((None, holds_slot)),
+                        }
+                        start = end;
+                    }
+                    continue;
+                }
+                let holds_slot = permit.is_some();
+                if let Some(permit) = permit {
+                    in_flight.push_back((sequence, permit));
+                }
+                match (sequence, responder) {
+                    (None, responder) => unsequenced_responses.push_back((responder, holds_slot)),
+                    (Some(sequence), Some(responder)) => {
+                        // dropping anything in slot, gives WontRespond error
+                        pending_responses.insert(sequence, (Instant::now(), responder));
+                    },
+                    (Some(_), None) => {},
+                }
+                // a resent line's number is out of order, which would look like a sequence change
+                if let Some(sequence) = sequence.filter(|_| !resent) {
+                    status.unacked.lock().expect("ack tracking never panics").sent(sequence);
+                    remember_sent(&mut history, config.resend_depth, sequence, content);
+                }
+            },
+            read = read_printer_line(&mut transport, &mut buf, config.bare_cr_line_endings, &mut after_cr) => {
+                // nothing more can be read once the connection has closed or failed
+                if !matches!(read, Ok(1..)) {return;}
+                let line = decode_line(&buf, config.invalid_utf8);
+                buf.clear();
+                last_heard = Instant::now();
+                last_traffic = last_heard;
+                probed = false;
+                tracing::debug!("Received `{line}` from printer");
+                let protocol = *status.protocol.borrow();
+                let response = protocol.classify(line.as_bytes());
+                match response {
+                    Response::Ok(maybe_seq) => {
+                        let mut unacked = status.unacked.lock().expect("ack tracking never panics");
+                        let (acknowledged, holds_slot, responder) = match maybe_seq {
+                            Some(sequence) => {
+                                unacked.acknowledged(sequence);
+                                (Some(sequence), true, pending_responses.remove(&sequence).map(|(_, responder)| responder))
+                            },
+                            None => match unsequenced_responses.pop_front() {
+                                Some((responder, holds_slot)) => (None, holds_slot, responder),
+                                None => {
+                                    // the same line has to be acknowledged for its sender as for `missing_acks`
+                                    let oldest = unacked.acknowledged_oldest();
+                                    (oldest, oldest.is_some(), oldest.and_then(|sequence| pending_responses.remove(&sequence)).map(|(_, responder)| responder))
+                                },
+                            },
+                        };
+                        drop(unacked);
+                        // each ok frees the flow window slot of the line it's for, if that line has one,
+                        // so lines sent around the window, or given up on, don't free anyone else's
+                        let slot = match acknowledged {
+                            Some(sequence) => in_flight.iter().position(|(sent, _)| *sent == Some(sequence)),
+                            // unnumbered lines are acknowledged in order, so the oldest of them holding a slot is this one
+                            None if holds_slot => in_flight.iter().position(|(sent, _)| sent.is_none()),
+                            None => None,
+                        };
+                        if let Some(slot) = slot {
+                            in_flight.remove(slot);
+                        }
+                        if let Some(responder) = responder {
+                             let _ = responder.send(Ok(()));
+                        }
+                    },
+                    Response::Resend(Some(sequence)) => {
+                        match history.iter().position(|(sent, _)| *sent == sequence) {
+                            Some(start) => {
+                                tracing::warn!("Printer requested resend from line {sequence}");
+                                for (_, line) in history.range(start..) {
+                                    if transport.write_all(line).await.is_err() {return;}
+                                    if transport.flush().await.is_err() {return;}
+                                    status.wrote(line);
+                                    tracing::debug!("Resent `{}` to printer", String::from_utf8_lossy(line).trim());
+                                }
+                            },
+                            None => tracing::warn!("Printer requested resend of line {sequence}, which is no longer available"),
+                        }
+                    },
+                    // the line is still being worked on, so it keeps its flow window slot and only its timeout restarts
+                    Response::Busy(_) | Response::Temperature(_) => last_busy = Instant::now(),
+                    Response::Error(ref message) if protocol == Protocol::Klipper => {
+                        tracing::error!("Printer rejected a command: {message}");
+                        // Klipper still sends an ok for the rejected line, so it keeps its place for that ok
+                        if let Some(responder) = unsequenced_responses.front_mut().and_then(|(responder, _)| responder.take()) {
+                            let _ = responder.send(Err(Error::Firmware(message.clone())));
+                        }
+                    },
+                    Response::Error(ref message) if response.is_fatal() => {
+                        tracing::error!("Printer reported an error: {message}");
+                        for (_, (_, responder)) in std::mem::take(&mut pending_responses) {
+                            let _ = responder.send(Err(Error::Firmware(message.clone())));
+                        }
+                        // nothing in flight will be acknowledged now, so the flow window opens up again
+                        in_flight.clear();
+                        // the slots stay, in case oks still follow for these lines
+                        for (responder, holds_slot) in unsequenced_responses.iter_mut() {
+                            *holds_slot = false;
+                            if let Some(responder) = responder.take() {
+                                let _ = responder.send(Err(Error::Firmware(message.clone())));
+                            }
+                        }
+                    },
+                    _ => {},
+                }
+                let _ = status.events.send(PrinterEvent::Response(response));
+                if responsetx.send(line).is_err() {return;}
+            },
+            _ = tokio::time::sleep_until(ack_deadline.unwrap_or_else(Instant::now).into()), if ack_deadline.is_some() => {
+                let timeout = config.ack_timeout.unwrap_or_default();
+                let now = Instant::now();
+                let expired: Vec<i32> = pending_responses
+                    .iter()
+                    .filter(|(_, (sent, _))| now >= Instant::max(*sent, last_busy) + timeout)
+                    .map(|(sequence, _)| *sequence)
+                    .collect();
+                for sequence in expired {
+                    tracing::warn!("No ok for line {sequence} after {}s, giving up on it", timeout.as_secs_f32());
+                    // given up on, so the line no longer holds up others in the flow window
+                    in_flight.retain(|(sent, _)| *sent != Some(sequence));
+                    if let Some((_, responder)) = pending_responses.remove(&sequence) {
+                        let _ = responder.send(Err(Error::Timeout));
+                    }
+                }
+            },
+            _ = tokio::time::sleep_until(idle_deadline.unwrap_or_else(Instant::now).into()), if idle_deadline.is_some() => {
+                let timeout = config.idle_timeout.unwrap_or_default().as_secs_f32();
+                if probed {
+                    tracing::error!("Printer silent for {timeout}s after being checked, treating the connection as lost");
+                    return;
+                }
+                tracing::warn!("Printer silent for {timeout}s while lines are waiting, checking the connection");
+                // firmware ignores empty lines, but writing one fails on a dead link
+                if transport.write_all(b"\n").await.is_err() {return;}
+                if transport.flush().await.is_err() {return;}
+                probed = true;
+                last_heard = Instant::now();
+            },
+            _ = async { keepalive.as_mut().expect("only polled when enabled").tick().await }, if keepalive.is_some() => {
+                let period = config.keepalive.unwrap_or_default();
+                if waiting || last_traffic.elapsed() < period {
+                    continue;
+                }
+                const KEEPALIVE: &[u8] = b"M105\n";
+                if transport.write_all(KEEPALIVE).await.is_err() {return;}
+                if transport.flush().await.is_err() {return;}
+                status.wrote(KEEPALIVE);
+                tracing::debug!("Sent keepalive to printer");
+                // its ok isn't for anyone, like a batched line's
+                unsequenced_responses.push_back((None, false));
+                last_heard = Instant::now();
+                last_traffic = last_heard;
+            },
+            _ = status.clear.notified() => {
+                let mut cleared = 0;
+                while let Ok(SendContent{batch, ..}) = gcoderx.try_recv() {
+                    cleared += batch.len().max(1);
+                }
+                tracing::warn!("Threw away {cleared} lines queued for the printer");
+            },
+            Some(TransportControl{configure, done}) = controlrx.recv() => {
+                // anything already sent has been flushed, so the change only affects later lines
+                let _ = done.send(configure(&mut transport));
+            },
+            _ = &mut shutdown => {
+                // write out everything already queued, without waiting for oks
+                gcoderx.close();
+                while let Some(SendContent{content, ..}) = gcoderx.recv().await {
+                    if transport.write_all(&content).await.is_err() {return;}
+                    status.wrote(&content);
+                }
+                let _ = transport.flush().await;
+                tracing::debug!("Stopped background printer communications");
+                return;
+            },
+            else => return,
+        }
+    }
+}
+
+impl Printer {
+    /// Create a new printer from a SerialStream.
+    ///
     /// Starts a local task to handle printer communication asynchronously
     #[tracing::instrument(level = "debug")]
     pub fn new<S>(port: S) -> Self
     where
         S: AsyncBufRead + AsyncWrite + Unpin + Send + 'static + Debug,
     {
-        let (sender, gcoderx) = mpsc::channel::<SendContent>(16);
-        let (response_sender, responses) = broadcast::channel(64);
-        let com_task = tokio::task::spawn(printer_com_task(port, gcoderx, response_sender));
+        Self::new_with_config(port, PrinterConfig::default())
+    }
+
+    /// Create a new printer from a SerialStream, with non-default communication settings.
+    ///
+    /// Starts a local task to handle printer communication asynchronously
+    #[tracing::instrument(level = "debug")]
+    pub fn new_with_config<S>(port: S, config: PrinterConfig) -> Self
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin + Send + 'static + Debug,
+    {
+        let (sender, gcoderx) = mpsc::channel::<SendContent>(config.send_capacity);
+        let (response_sender, responses) = broadcast::channel(config.response_capacity);
+        let status = Status::new(config.response_capacity);
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        let (control, controlrx) = mpsc::channel(1);
+        let com_task = tokio::task::spawn(printer_com_task(
+            port,
+            gcoderx,
+            response_sender.clone(),
+            status.clone(),
+            config,
+            shutdown_receiver,
+            controlrx,
+        ));
         let serializer = Sequenced::default();
         Self::Connected {
             socket: Socket {
                 sender,
                 serializer,
+                flow_window: None,
+                protocol: status.protocol.clone(),
+                lagged: 0,
+                unacked: status.unacked.clone(),
+                motion: status.motion.clone(),
+                sent: status.sent.clone(),
+                clear: status.clear.clone(),
                 responses,
             },
             com_task,
+            response_sender,
+            status,
+            config,
+            shutdown: Some(shutdown),
+            control,
         }
     }
 
+    /// Connect to a networked printer or serial bridge (ESP3D, ser2net, etc.) over TCP.
+    pub async fn connect_tcp(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).await?;
+        Self::from_tcp(stream)
+    }
+
+    /// Create a new printer from an already connected TCP stream.
+    ///
+    /// Disables Nagle's algorithm, since each line waits on a response from the printer.
+    pub fn from_tcp(stream: TcpStream) -> Result<Self, Error> {
+        stream.set_nodelay(true)?;
+        Ok(Self::new(BufReader::new(stream)))
+    }
+
+    /// Connect to a printer exposed on a Unix domain socket, like a local serial bridge
+    #[cfg(unix)]
+    pub async fn connect_unix(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        Ok(Self::new(BufReader::new(stream)))
+    }
+
+    /// Connect to a Klipper printer through the Moonraker server at `url`, like
+    /// `http://printer.local:7125`, sending Gcode over its websocket API
+    pub async fn connect_moonraker(url: &str) -> Result<Self, Error> {
+        let transport = moonraker::connect_moonraker(url).await?;
+        Ok(Self::new(transport).with_protocol(Protocol::Klipper))
+    }
+
     /// Connect to a device
     pub fn connect<S>(&mut self, port: S)
     where
@@ -309,6 +1617,111 @@ impl Printer {
         *self = Printer::new(port);
     }
 
+    /// Frame lines sent through this printer's socket, and every socket already cloned
+    /// from it, for the given firmware protocol, see `Socket::with_protocol`, and read what
+    /// the printer sends back the way that firmware means it, see `Protocol::classify`
+    pub fn with_protocol(self, protocol: Protocol) -> Self {
+        if let Self::Connected { ref status, .. } = self {
+            status.protocol.send_replace(protocol);
+        }
+        self
+    }
+
+    /// Replace the connection to the printer, keeping existing line subscriptions alive.
+    ///
+    /// Sockets cloned from the old connection can no longer send.
+    /// The sequence counter restarts at 1 and an `M110` is queued so the printer agrees.
+    /// If there was no connection, this is the same as `connect`.
+    pub fn reconnect<S>(&mut self, port: S)
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin + Send + 'static + Debug,
+    {
+        let Self::Connected {
+            socket,
+            com_task,
+            response_sender,
+            status,
+            config,
+            shutdown,
+            control,
+        } = self
+        else {
+            return self.connect(port);
+        };
+        stop_com_task(com_task, status);
+        // a clear asked of the old connection mustn't reach the new one
+        status.clear = Default::default();
+        socket.clear = status.clear.clone();
+        let (sender, gcoderx) = mpsc::channel::<SendContent>(config.send_capacity);
+        let (new_shutdown, shutdown_receiver) = oneshot::channel();
+        let (new_control, controlrx) = mpsc::channel(1);
+        *com_task = tokio::task::spawn(printer_com_task(
+            port,
+            gcoderx,
+            response_sender.clone(),
+            status.clone(),
+            *config,
+            shutdown_receiver,
+            controlrx,
+        ));
+        *shutdown = Some(new_shutdown);
+        *control = new_control;
+        socket.sender = sender;
+        // the same restart as `Socket::resync`, which the new connection's empty queue has room for
+        if socket.protocol() == Protocol::Marlin
+            && socket
+                .sender
+                .try_send(socket.restart_sequence(None))
+                .is_err()
+        {
+            tracing::warn!("Couldn't reset printer line number after reconnecting");
+        }
+    }
+
+    /// Keep trying to reconnect with transports from `factory`, waiting longer between each attempt.
+    ///
+    /// See `reconnect` for what is kept from the old connection.
+    /// Returns the last error if `policy` runs out of attempts.
+    pub async fn reconnect_with_backoff<S, E, F, Fut>(
+        &mut self,
+        mut factory: F,
+        policy: BackoffPolicy,
+    ) -> Result<(), E>
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin + Send + 'static + Debug,
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<S, E>>,
+    {
+        let mut delay = policy.initial_delay;
+        let mut attempt = 1;
+        if let Self::Connected { status, .. } = self {
+            status.state.send_replace(ConnectionState::Reconnecting);
+        }
+        loop {
+            match factory().await {
+                Ok(port) => {
+                    self.reconnect(port);
+                    return Ok(());
+                }
+                Err(e) if policy.max_attempts.is_some_and(|max| attempt >= max) => {
+                    if let Self::Connected { status, .. } = self {
+                        status.state.send_replace(ConnectionState::Disconnected);
+                    }
+                    return Err(e);
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        "Reconnect attempt {attempt} failed: {e}, retrying in {delay:?}"
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(policy.max_delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
     /// Obtain a cloneable socket handle to talk to printer
     pub fn socket(&self) -> Result<&Socket, Error> {
         match self {
@@ -326,10 +1739,58 @@ impl Printer {
     }
 
     /// Disconnect the printer and shutdown background communication
+    ///
+    /// Anything still being written is cut off, see `shutdown` to let queued lines finish first.
     pub fn disconnect(&mut self) {
         core::mem::take(self);
     }
 
+    /// Write out every line already queued for the printer, then disconnect.
+    ///
+    /// Unlike `disconnect`, lines aren't cut off part way through,
+    /// so final commands like `M84` or `M104 S0` reach the printer.
+    /// No new lines are accepted once this is called, including from cloned sockets.
+    /// If the queue isn't written within `timeout`, the connection is aborted with `Error::Timeout`.
+    pub async fn shutdown(&mut self, timeout: Duration) -> Result<(), Error> {
+        let Self::Connected {
+            com_task, shutdown, ..
+        } = self
+        else {
+            return Ok(());
+        };
+        if let Some(shutdown) = shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let finished = tokio::time::timeout(timeout, com_task).await;
+        self.disconnect();
+        finished.map(|_| ()).map_err(|_| Error::Timeout)
+    }
+
+    /// Change something about the transport the printer is connected through, like the
+    /// baud rate of a serial port, without disturbing line subscriptions or sockets.
+    ///
+    /// `configure` is run by the background task with the transport given to `new` or
+    /// `reconnect`, between lines, so anything already sent was written beforehand.
+    /// The returned future finishes once the change is made,
+    /// giving `Error::UnsupportedTransport` if the transport isn't a `T`.
+    pub fn configure_transport<T: 'static>(
+        &self,
+        configure: impl FnOnce(&mut T) -> Result<(), Error> + Send + 'static,
+    ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
+        let Self::Connected { control, .. } = self else {
+            return Err(Error::Disconnected);
+        };
+        let control_slot = control.try_reserve()?;
+        let (done, configured) = oneshot::channel();
+        let configure = Box::new(move |transport: &mut dyn Any| {
+            transport
+                .downcast_mut::<T>()
+                .map_or(Err(Error::UnsupportedTransport), configure)
+        });
+        control_slot.send(TransportControl { configure, done });
+        Ok(async move { configured.await.map_err(|_| Error::Disconnected)? })
+    }
+
     /// Check if there is an active connection, convenience method for testing enum state.
     pub fn is_connected(&self) -> bool {
         match self {
@@ -393,6 +1854,19 @@ impl Printer {
         self.socket()?.try_send_unsequenced(gcode)
     }
 
+    /// Send a line which is already numbered and checksummed, see `Socket::send_prechecksummed`
+    pub async fn send_prechecksummed(
+        &self,
+        line: &[u8],
+    ) -> Result<impl Future<Output = Result<(), Error>>, Error> {
+        self.socket()?.send_prechecksummed(line).await
+    }
+
+    /// Throw away lines queued for the printer, see `Socket::clear_queue`
+    pub fn clear_queue(&self) -> Result<(), Error> {
+        self.socket().map(Socket::clear_queue)
+    }
+
     /// Send any raw sequence of bytes to the printer
     pub async fn send_raw(&self, gcode: &[u8]) -> Result<(), Error> {
         self.socket()?.send_raw(gcode).await
@@ -403,6 +1877,14 @@ impl Printer {
         self.socket()?.try_send_raw(gcode)
     }
 
+    /// Send many lines in a single write, see `Socket::send_batch`
+    pub async fn send_batch<T: Serialize>(
+        &self,
+        gcodes: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<i32>, Error> {
+        self.socket()?.send_batch(gcodes).await
+    }
+
     /// Read the next line from the printer
     ///
     /// May not recieve all lines, if calls to this function are spaced
@@ -412,15 +1894,118 @@ impl Printer {
         self.socket_mut()?.read_next_line().await
     }
 
+    /// Positioning mode and units last commanded, see `Socket::motion_mode`
+    pub fn motion_mode(&self) -> Result<MotionMode, Error> {
+        Ok(self.socket()?.motion_mode())
+    }
+
+    /// Lines sent at least `window` ago still waiting for an `ok`, see `Socket::missing_acks`
+    pub fn missing_acks(&self, window: Duration) -> Result<Vec<i32>, Error> {
+        Ok(self.socket()?.missing_acks(window))
+    }
+
     /// Non blocking, non-async version of `read_next_line`, instantly returns an error where that method would wait
     pub fn try_read_next_line(&mut self) -> Result<Arc<str>, Error> {
         self.socket_mut()?.try_read_next_line()
     }
 
+    /// Total number of lines missed by `read_next_line` not being called fast enough
+    pub fn lagged_lines(&self) -> Result<u64, Error> {
+        Ok(self.socket()?.lagged_lines())
+    }
+
     /// Obtain a broadcast receiver returning all lines received by the printer
     pub fn subscribe_lines(&self) -> Result<LineStream, Error> {
         self.socket()?.subscribe_lines()
     }
+
+    /// Lines received by the printer as a `Stream`, see `Socket::lines_stream`
+    pub fn lines_stream(&self) -> Result<impl Stream<Item = StreamedLine> + Send + Unpin, Error> {
+        Ok(self.socket()?.lines_stream())
+    }
+
+    /// Obtain a broadcast receiver returning every line written to the printer,
+    /// see `Socket::subscribe_sent`
+    pub fn subscribe_sent(&self) -> Result<LineStream, Error> {
+        self.socket()?.subscribe_sent()
+    }
+
+    /// Obtain a broadcast receiver of events on this connection, such as each classified
+    /// response and the connection stopping, so they don't need to be parsed from lines.
+    ///
+    /// Events continue across `reconnect`.
+    pub fn subscribe_events(&self) -> Result<EventStream, Error> {
+        match self {
+            Self::Disconnected => Err(Error::Disconnected),
+            Self::Connected { status, .. } => Ok(status.events.subscribe()),
+        }
+    }
+
+    /// Current state of the connection, which unlike `is_connected` notices when
+    /// the connection closes or fails without being disconnected
+    pub fn connection_state(&self) -> ConnectionState {
+        match self {
+            Self::Disconnected => ConnectionState::Disconnected,
+            Self::Connected { status, .. } => *status.state.borrow(),
+        }
+    }
+
+    /// Watch the state of the connection as it changes, continuing across `reconnect`.
+    ///
+    /// For a disconnected printer this stays `Disconnected`,
+    /// a new watch is needed once it's connected.
+    pub fn watch_connection(&self) -> watch::Receiver<ConnectionState> {
+        match self {
+            Self::Disconnected => watch::channel(ConnectionState::Disconnected).1,
+            Self::Connected { status, .. } => status.state.subscribe(),
+        }
+    }
+
+    /// Send a query and collect its reply, see `Socket::send_and_collect`
+    pub async fn send_and_collect(
+        &self,
+        gcode: impl Serialize + Debug,
+        timeout: Duration,
+    ) -> Result<Vec<Arc<str>>, Error> {
+        self.socket()?.send_and_collect(gcode, timeout).await
+    }
+
+    /// Ask for the current temperatures with `M105`, see `Socket::read_temperatures`
+    pub async fn read_temperatures(&self) -> Result<TemperatureReport, Error> {
+        self.socket()?.read_temperatures().await
+    }
+
+    /// Ask where the toolhead is with `M114`, see `Socket::read_position`
+    pub async fn read_position(&self) -> Result<Position, Error> {
+        self.socket()?.read_position().await
+    }
+
+    /// Reset the printer's line number to match, see `Socket::resync`
+    pub async fn resync(&self, timeout: Duration) -> Result<(), Error> {
+        self.socket()?.resync(timeout).await
+    }
+
+    /// Heat the hotend and wait with progress, see `Socket::set_hotend_and_wait`
+    pub async fn set_hotend_and_wait(
+        &self,
+        temperature: f32,
+        on_progress: impl FnMut(Temperature),
+    ) -> Result<(), Error> {
+        self.socket()?
+            .set_hotend_and_wait(temperature, on_progress)
+            .await
+    }
+
+    /// Heat the bed and wait with progress, see `Socket::set_bed_and_wait`
+    pub async fn set_bed_and_wait(
+        &self,
+        temperature: f32,
+        on_progress: impl FnMut(Temperature),
+    ) -> Result<(), Error> {
+        self.socket()?
+            .set_bed_and_wait(temperature, on_progress)
+            .await
+    }
 }
 
 impl From<Option<Printer>> for Printer {
@@ -448,6 +2033,25 @@ impl<'a> From<&'a Printer> for Option<&'a Socket> {
 mod test {
     use super::*;
 
+    /// A printer keeping only `capacity` received lines for each subscriber, with the sender
+    /// its lines are broadcast on, to hand its sockets lines without going through the connection
+    fn printer_with_lines(capacity: usize) -> (Printer, Socket, broadcast::Sender<Arc<str>>) {
+        let config = PrinterConfig {
+            response_capacity: capacity,
+            ..Default::default()
+        };
+        let (printer, socket, _) = duplex_printer_with_config(config);
+        let Printer::Connected {
+            ref response_sender,
+            ..
+        } = printer
+        else {
+            unreachable!("printers start out connected");
+        };
+        let response_sender = response_sender.clone();
+        (printer, socket, response_sender)
+    }
+
     #[test]
     fn disconnected_is_disconnected() {
         let mut disconnected = Printer::Disconnected;
@@ -470,6 +2074,905 @@ mod test {
         assert!(matches!(disconnected.socket(), Err(Error::Disconnected)));
     }
 
+    #[tokio::test]
+    async fn flow_window() {
+        use tokio::io::AsyncReadExt;
+
+        let (printer, socket, mut host_side) = duplex_printer();
+        let socket = socket.with_flow_window(2);
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        let _ = socket.try_send_unsequenced("G0").unwrap();
+        let _ = socket.try_send_unsequenced("G0").unwrap();
+        assert!(matches!(
+            socket.try_send_unsequenced("G0"),
+            Err(Error::FlowWindowFull)
+        ));
+        // raw lines go around the window
+        socket.try_send_raw(b"M112\n").unwrap();
+
+        let mut sent = [0u8; 11];
+        host_side.read_exact(&mut sent).await.unwrap();
+        assert_eq!(&sent, b"G0\nG0\nM112\n");
+        host_side.write_all(b"ok\n").await.unwrap();
+        lines.recv().await.unwrap();
+
+        let _ = socket.try_send_unsequenced("G0").unwrap();
+        assert!(matches!(
+            socket.try_send_unsequenced("G0"),
+            Err(Error::FlowWindowFull)
+        ));
+    }
+
+    #[tokio::test]
+    async fn automatic_resend() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        for _ in 0..3 {
+            let _ = printer.send("G0").await.unwrap();
+        }
+        let mut line = String::new();
+        for _ in 0..3 {
+            host_side.read_line(&mut line).await.unwrap();
+        }
+
+        host_side.get_mut().write_all(b"Resend: 2\n").await.unwrap();
+        lines.recv().await.unwrap();
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("N2G0"));
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("N3G0"));
+    }
+
+    #[tokio::test]
+    async fn batched_lines() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        let sequences = printer.send_batch(["G0", "G1", "G2"]).await.unwrap();
+        assert_eq!(sequences, vec![1, 2, 3]);
+        let mut line = String::new();
+        for expected in ["N1G0", "N2G1", "N3G2"] {
+            line.clear();
+            host_side.read_line(&mut line).await.unwrap();
+            assert!(line.starts_with(expected));
+        }
+
+        // batched lines can be resent individually
+        host_side.get_mut().write_all(b"Resend: 3\n").await.unwrap();
+        lines.recv().await.unwrap();
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("N3G2"));
+
+        // a batch bigger than the flow window goes out as the window has room
+        let socket = printer.socket().unwrap().clone().with_flow_window(2);
+        let sequences = socket.send_batch(["G0", "G1", "G2"]);
+        let host = async {
+            let mut line = String::new();
+            for _ in 0..2 {
+                host_side.read_line(&mut line).await.unwrap();
+            }
+            host_side.get_mut().write_all(b"ok\n").await.unwrap();
+            line.clear();
+            host_side.read_line(&mut line).await.unwrap();
+            assert!(line.starts_with("N6G2"));
+        };
+        let (sequences, _) = tokio::join!(sequences, host);
+        assert_eq!(sequences.unwrap(), vec![4, 5, 6]);
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_lines() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        let sequences = printer.send_batch(["G0", "G1", "G2", "G3"]).await.unwrap();
+        assert_eq!(sequences, vec![1, 2, 3, 4]);
+        let mut line = String::new();
+        for _ in 0..4 {
+            host_side.read_line(&mut line).await.unwrap();
+        }
+        host_side
+            .get_mut()
+            .write_all(b"ok N1\nok N3\n")
+            .await
+            .unwrap();
+        lines.recv().await.unwrap();
+        lines.recv().await.unwrap();
+        assert_eq!(printer.missing_acks(Duration::ZERO).unwrap(), vec![2, 4]);
+        assert!(printer
+            .missing_acks(Duration::from_secs(60))
+            .unwrap()
+            .is_empty());
+
+        // a plain ok is for the oldest line waiting
+        host_side.get_mut().write_all(b"ok\n").await.unwrap();
+        lines.recv().await.unwrap();
+        assert_eq!(printer.missing_acks(Duration::ZERO).unwrap(), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn plain_oks_complete_sends() {
+        let (printer, _, mut host_side) = duplex_printer();
+
+        let homing = printer.send("G28").await.unwrap();
+        let moving = printer.send("G1 X10").await.unwrap();
+        let mut line = String::new();
+        for _ in 0..2 {
+            host_side.read_line(&mut line).await.unwrap();
+        }
+        host_side.get_mut().write_all(b"ok\n").await.unwrap();
+        homing.await.unwrap();
+        assert_eq!(printer.missing_acks(Duration::ZERO).unwrap(), vec![2]);
+
+        host_side.get_mut().write_all(b"ok\n").await.unwrap();
+        moving.await.unwrap();
+        assert!(printer.missing_acks(Duration::ZERO).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn prechecksummed_lines() {
+        let (printer, _, mut host_side) = duplex_printer();
+
+        let relayed = Sequenced::default();
+        relayed.set_sequence(40);
+        let (_, line) = relayed.serialize("G28");
+        let homed = printer.send_prechecksummed(&line).await.unwrap();
+        let _ = printer.send("G1 X10").await.unwrap();
+        let mut sent = String::new();
+        host_side.read_line(&mut sent).await.unwrap();
+        assert_eq!(sent.as_bytes(), &*line);
+        sent.clear();
+        host_side.read_line(&mut sent).await.unwrap();
+        assert!(sent.starts_with("N41G1"), "{sent}");
+        host_side.get_mut().write_all(b"ok N40\n").await.unwrap();
+        homed.await.unwrap();
+
+        assert!(matches!(
+            embedded_sequence(b"N7 G28*0"),
+            Err(Error::Checksum(ChecksumError::Mismatch { .. }))
+        ));
+        assert!(matches!(
+            embedded_sequence(b"G28*77"),
+            Err(Error::MissingSequence)
+        ));
+        assert_eq!(embedded_sequence(&line).unwrap(), 40);
+    }
+
+    #[tokio::test]
+    async fn clearing_queued_lines() {
+        let (printer, _, mut host_side) = duplex_printer();
+
+        let mut responses = Vec::new();
+        for gcode in ["G0", "G1", "G2", "G3", "G4", "G5"] {
+            responses.push(printer.send(gcode).await.unwrap());
+        }
+        // only 4 lines are written before oks come back
+        let mut line = String::new();
+        for _ in 0..4 {
+            host_side.read_line(&mut line).await.unwrap();
+        }
+        printer.clear_queue().unwrap();
+        let queued = responses.split_off(4);
+        for response in queued {
+            assert!(matches!(response.await, Err(Error::WontRespond)));
+        }
+
+        // the connection carries on with the next line sent
+        host_side
+            .get_mut()
+            .write_all(b"ok N1\nok N2\nok N3\nok N4\n")
+            .await
+            .unwrap();
+        for response in responses {
+            response.await.unwrap();
+        }
+        let _ = printer.send("M105").await.unwrap();
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.contains("M105"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn temperature_query() {
+        let (printer, _, mut host_side) = duplex_printer();
+
+        let host = async {
+            let mut line = String::new();
+            host_side.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "M105\n");
+            host_side
+                .get_mut()
+                .write_all(b"ok T:200.0 /210.0 B:59.5 /60.0 T0:200.0 /210.0 T1:25.0 /0.0\n")
+                .await
+                .unwrap();
+        };
+        let (report, _) = tokio::join!(printer.read_temperatures(), host);
+        let report = report.unwrap();
+        assert_eq!(report.hotend.unwrap().target, Some(210.0));
+        assert_eq!(report.bed.unwrap().current, 59.5);
+        assert_eq!(report.extruders.len(), 2);
+        assert_eq!(report.extruders[1].current, 25.0);
+    }
+
+    #[tokio::test]
+    async fn resync_line_numbers() {
+        let (_printer, socket, mut host_side) = duplex_printer();
+        let _ = socket.send("G28").await.unwrap();
+        let _ = socket.send("G28").await.unwrap();
+
+        let host = async {
+            let mut line = String::new();
+            for _ in 0..3 {
+                line.clear();
+                host_side.read_line(&mut line).await.unwrap();
+            }
+            assert_eq!(line, "M110 N0\n");
+            host_side.get_mut().write_all(b"ok\n").await.unwrap();
+        };
+        let (resynced, _) = tokio::join!(socket.resync(DEFAULT_QUERY_TIMEOUT), host);
+        resynced.unwrap();
+        let _ = socket.send("G28").await.unwrap();
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("N1G28"));
+
+        let timed_out = socket.resync(Duration::from_millis(10)).await;
+        assert!(matches!(timed_out, Err(Error::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn collect_query_reply() {
+        let (printer, _, mut host_side) = duplex_printer();
+
+        let host = async {
+            let mut line = String::new();
+            host_side.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "M114\n");
+            host_side
+                .get_mut()
+                .write_all(b"X:1.00 Y:2.00 Z:3.00 E:0.00 Count X:80 Y:160 Z:1200\nok\n")
+                .await
+                .unwrap();
+        };
+        let (reply, _) = tokio::join!(
+            printer.send_and_collect("M114", DEFAULT_QUERY_TIMEOUT),
+            host
+        );
+        let reply = reply.unwrap();
+        assert_eq!(reply.len(), 2);
+        assert!(reply[0].starts_with("X:1.00 Y:2.00"));
+        assert_eq!(reply[1].trim(), "ok");
+    }
+
+    #[tokio::test]
+    async fn collect_times_out() {
+        let (printer, _, _host_side) = duplex_printer();
+        assert!(matches!(
+            printer
+                .send_and_collect("M503", Duration::from_millis(10))
+                .await,
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn shutdown_writes_queued_lines() {
+        use tokio::io::AsyncReadExt;
+
+        let (mut printer, socket, mut host_side) = duplex_printer();
+        socket.try_send_raw(b"M104 S0\n").unwrap();
+        socket.try_send_raw(b"M84\n").unwrap();
+
+        printer.shutdown(DEFAULT_QUERY_TIMEOUT).await.unwrap();
+        assert!(!printer.is_connected());
+        assert!(socket.try_send_raw(b"G28\n").is_err());
+
+        let mut written = String::new();
+        host_side.read_to_string(&mut written).await.unwrap();
+        assert_eq!(written, "M104 S0\nM84\n");
+    }
+
+    #[tokio::test]
+    async fn tcp_connection() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let printer = Printer::connect_tcp(addr).await.unwrap();
+        let (host_side, _) = listener.accept().await.unwrap();
+        let mut host_side = BufReader::new(host_side);
+
+        let _ = printer.send_unsequenced("G28").await.unwrap();
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "G28\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_connection() {
+        let path = std::env::temp_dir().join(format!("print3rs-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let printer = Printer::connect_unix(&path).await.unwrap();
+        let (host_side, _) = listener.accept().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut host_side = BufReader::new(host_side);
+
+        let _ = printer.send_unsequenced("G28").await.unwrap();
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "G28\n");
+    }
+
+    #[tokio::test]
+    async fn lagged_reads_skip_ahead() {
+        let (_printer, mut socket, response_sender) = printer_with_lines(4);
+        for i in 0..10 {
+            response_sender.send(Arc::from(i.to_string())).unwrap();
+        }
+
+        assert_eq!(&*socket.read_next_line().await.unwrap(), "6");
+        assert_eq!(socket.lagged_lines(), 6);
+        assert_eq!(&*socket.try_read_next_line().unwrap(), "7");
+        assert_eq!(socket.lagged_lines(), 6);
+    }
+
+    #[tokio::test]
+    async fn streamed_lines_report_skips() {
+        let (printer, socket, response_sender) = printer_with_lines(4);
+        let lines = socket.lines_stream();
+        for i in 0..6 {
+            response_sender.send(Arc::from(i.to_string())).unwrap();
+        }
+        drop(socket);
+        drop(response_sender);
+        drop(printer);
+
+        let lines: Vec<StreamedLine> = lines.collect().await;
+        assert_eq!(
+            lines,
+            vec![
+                StreamedLine::Skipped(2),
+                StreamedLine::Line(Arc::from("2")),
+                StreamedLine::Line(Arc::from("3")),
+                StreamedLine::Line(Arc::from("4")),
+                StreamedLine::Line(Arc::from("5")),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_invalid_utf8() {
+        let line = b"echo:\xffbusy\xe2\x82\n";
+        assert_eq!(&*decode_line(b"ok\n", InvalidUtf8::Replace), "ok\n");
+        assert_eq!(
+            &*decode_line(line, InvalidUtf8::Replace),
+            "echo:\u{fffd}busy\u{fffd}\n"
+        );
+        assert_eq!(
+            &*decode_line(line, InvalidUtf8::Escape),
+            "echo:\\xffbusy\\xe2\\x82\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_lines_still_received() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        host_side.write_all(b"\x01\xfe status\nok\n").await.unwrap();
+        assert_eq!(&*lines.recv().await.unwrap(), "\u{1}\u{fffd} status\n");
+        assert_eq!(&*lines.recv().await.unwrap(), "ok\n");
+    }
+
+    #[tokio::test]
+    async fn printer_events() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut events = printer.subscribe_events().unwrap();
+
+        host_side.write_all(b"ok\nResend: 3\n").await.unwrap();
+        drop(host_side);
+        assert_eq!(events.recv().await.unwrap(), PrinterEvent::Connected);
+        assert_eq!(
+            events.recv().await.unwrap(),
+            PrinterEvent::Response(Response::Ok(None))
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            PrinterEvent::Response(Response::Resend(Some(3)))
+        );
+        assert_eq!(events.recv().await.unwrap(), PrinterEvent::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn connection_state_changes() {
+        let (mut printer, _, host_side) = duplex_printer();
+        let mut state = printer.watch_connection();
+        assert_eq!(*state.borrow(), ConnectionState::Connected);
+
+        drop(host_side);
+        state
+            .wait_for(|state| *state == ConnectionState::Disconnected)
+            .await
+            .unwrap();
+        assert_eq!(printer.connection_state(), ConnectionState::Disconnected);
+        assert!(printer.is_connected());
+
+        let (printer_side, _host_side) = tokio::io::duplex(1024);
+        printer.reconnect(tokio::io::BufReader::new(printer_side));
+        state
+            .wait_for(|state| *state == ConnectionState::Connected)
+            .await
+            .unwrap();
+        assert_eq!(
+            Printer::Disconnected.connection_state(),
+            ConnectionState::Disconnected
+        );
+    }
+
+    #[tokio::test]
+    async fn crlf_line_endings() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        host_side.write_all(b"ok\r\nT:20.0\r\n").await.unwrap();
+        assert_eq!(&*lines.recv().await.unwrap(), "ok\n");
+        assert_eq!(&*lines.recv().await.unwrap(), "T:20.0\n");
+    }
+
+    #[tokio::test]
+    async fn lost_ok_times_out() {
+        let config = PrinterConfig {
+            ack_timeout: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let (printer, _, mut host_side) = duplex_printer_with_config(config);
+
+        let lost = printer.send("G28").await.unwrap();
+        assert!(matches!(lost.await, Err(Error::Timeout)));
+        let answered = printer.send("G1 X10").await.unwrap();
+        host_side.write_all(b"ok N2\n").await.unwrap();
+        answered.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ack_window_holds_back_lines() {
+        let config = PrinterConfig {
+            ack_window: 1,
+            ..Default::default()
+        };
+        let (printer, _, mut host_side) = duplex_printer_with_config(config);
+
+        let homing = printer.send("G28").await.unwrap();
+        let moving = printer.send("G1 X10").await.unwrap();
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.contains("G28"));
+        line.clear();
+        let held =
+            tokio::time::timeout(Duration::from_millis(30), host_side.read_line(&mut line)).await;
+        assert!(held.is_err(), "sent before the first line was acknowledged");
+
+        host_side.get_mut().write_all(b"ok N1\n").await.unwrap();
+        homing.await.unwrap();
+        host_side.read_line(&mut line).await.unwrap();
+        assert!(line.contains("G1 X10"));
+        host_side.get_mut().write_all(b"ok N2\n").await.unwrap();
+        moving.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resent_lines_keep_their_number() {
+        let (_printer, socket, mut host_side) = duplex_printer();
+
+        let (line, _) = socket.send_resendable("G28").await.unwrap();
+        assert_eq!(line.sequence(), Some(1));
+        let _ = socket.send("G1 X10").await.unwrap();
+        let homed = socket.resend(&line).await.unwrap();
+        let mut sent = String::new();
+        host_side.read_line(&mut sent).await.unwrap();
+        let first = std::mem::take(&mut sent);
+        host_side.read_line(&mut sent).await.unwrap();
+        assert!(sent.starts_with("N2G1"), "{sent}");
+        let second = std::mem::take(&mut sent);
+        host_side.read_line(&mut sent).await.unwrap();
+        assert_eq!(sent, first);
+
+        // resending doesn't lose track of the lines before it
+        assert_eq!(socket.missing_acks(Duration::ZERO), vec![1, 2]);
+        host_side.get_mut().write_all(b"Resend: 1\n").await.unwrap();
+        for expected in [first, second] {
+            sent.clear();
+            host_side.read_line(&mut sent).await.unwrap();
+            assert_eq!(sent, expected);
+        }
+        host_side.get_mut().write_all(b"ok N1\n").await.unwrap();
+        homed.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lost_oks_free_flow_window() {
+        let config = PrinterConfig {
+            ack_timeout: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let (_printer, socket, _host_side) = duplex_printer_with_config(config);
+        let socket = socket.with_flow_window(2);
+
+        // each round fills the window with lines that are never acknowledged
+        for _ in 0..3 {
+            let first = socket.send("G28").await.unwrap();
+            let second = socket.send("G28").await.unwrap();
+            assert!(matches!(first.await, Err(Error::Timeout)));
+            assert!(matches!(second.await, Err(Error::Timeout)));
+        }
+        let sending = tokio::time::timeout(Duration::from_millis(100), socket.send("G1 X10")).await;
+        assert!(
+            sending.is_ok(),
+            "flow window still full after its lines timed out"
+        );
+    }
+
+    #[tokio::test]
+    async fn sent_lines_are_broadcast() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut sent = printer.socket().unwrap().clone().subscribe_sent().unwrap();
+
+        let unnumbered = printer.send_unsequenced("G28").await.unwrap();
+        let numbered = printer.send("M105").await.unwrap();
+        assert_eq!(&*sent.recv().await.unwrap(), "G28\n");
+        let line = sent.recv().await.unwrap();
+        assert!(line.starts_with("N1M105*"), "{line}");
+
+        host_side.write_all(b"Resend: 1\n").await.unwrap();
+        assert_eq!(sent.recv().await.unwrap(), line);
+        host_side.write_all(b"ok\nok N1\n").await.unwrap();
+        unnumbered.await.unwrap();
+        numbered.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn motion_mode_follows_sent_lines() {
+        let (transport, _) = MockPrinter::new().start();
+        let printer = Printer::new(transport);
+        assert_eq!(printer.motion_mode().unwrap(), MotionMode::default());
+
+        printer.send("G91").await.unwrap().await.unwrap();
+        printer
+            .send_unsequenced("G20")
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let mode = printer.socket().unwrap().clone().motion_mode();
+        assert_eq!(mode.positioning, Positioning::Relative);
+        assert_eq!(mode.units, Units::Inches);
+    }
+
+    #[tokio::test]
+    async fn configure_live_transport() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        printer
+            .configure_transport(|_: &mut BufReader<tokio::io::DuplexStream>| Ok(()))
+            .unwrap()
+            .await
+            .unwrap();
+        let wrong_type = printer
+            .configure_transport(|_: &mut TcpStream| Ok(()))
+            .unwrap()
+            .await;
+        assert!(matches!(wrong_type, Err(Error::UnsupportedTransport)));
+
+        host_side.write_all(b"still listening\n").await.unwrap();
+        assert_eq!(&*lines.recv().await.unwrap(), "still listening\n");
+        assert!(matches!(
+            Printer::Disconnected.configure_transport(|_: &mut TcpStream| Ok(())),
+            Err(Error::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn klipper_responses_follow_protocol() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let printer = printer.with_protocol(Protocol::Klipper);
+
+        let homing = printer.send("G28").await.unwrap();
+        // only Marlin reports errors like this, Klipper would use `!!`
+        host_side
+            .write_all(b"Error:not from klipper\nok\n")
+            .await
+            .unwrap();
+        homing.await.unwrap();
+
+        let moving = printer.send("G1 X500").await.unwrap();
+        host_side
+            .write_all(b"!! Move out of range: 500.000 0.000 0.000 [0.000]\n")
+            .await
+            .unwrap();
+        assert!(matches!(moving.await, Err(Error::Firmware(_))));
+    }
+
+    #[tokio::test]
+    async fn klipper_errors_fail_one_line() {
+        let (printer, _, mut host_side) = duplex_printer();
+        let printer = printer.with_protocol(Protocol::Klipper);
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        let rejected = printer.send("G1 X10").await.unwrap();
+        let next = printer.send("G1 X20").await.unwrap();
+        tokio::pin!(next);
+        host_side
+            .write_all(b"!! Must home axis first: 10.000 0.000 0.000 [0.000]\nok\n")
+            .await
+            .unwrap();
+        assert!(matches!(rejected.await, Err(Error::Firmware(_))));
+        lines.recv().await.unwrap();
+        lines.recv().await.unwrap();
+        // the ok after the error was for the rejected line
+        assert!(tokio::time::timeout(Duration::from_millis(20), &mut next)
+            .await
+            .is_err());
+
+        host_side.write_all(b"ok\n").await.unwrap();
+        next.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn busy_extends_wait_for_ok() {
+        let config = PrinterConfig {
+            ack_timeout: Some(Duration::from_millis(60)),
+            ..Default::default()
+        };
+        let (_printer, socket, mut host_side) = duplex_printer_with_config(config);
+        let socket = socket.with_flow_window(1);
+
+        let homing = socket.send("G28").await.unwrap();
+        tokio::pin!(homing);
+        // well past the ack timeout in total, but never that long between busy messages
+        for _ in 0..5 {
+            tokio::time::sleep(Duration::from_millis(25)).await;
+            host_side
+                .write_all(b"echo:busy: processing\n")
+                .await
+                .unwrap();
+            let waiting = tokio::time::timeout(Duration::from_millis(1), &mut homing).await;
+            assert!(waiting.is_err(), "resolved before the ok");
+            assert!(matches!(
+                socket.try_send("G1 X10"),
+                Err(Error::FlowWindowFull)
+            ));
+        }
+        host_side.write_all(b"ok N1\n").await.unwrap();
+        homing.await.unwrap();
+        assert!(socket.try_send("G1 X10").is_ok());
+    }
+
+    #[tokio::test]
+    async fn silent_printer_is_disconnected() {
+        let config = PrinterConfig {
+            idle_timeout: Some(Duration::from_millis(30)),
+            ack_timeout: None,
+            ..Default::default()
+        };
+        let (printer, _, mut host_side) = duplex_printer_with_config(config);
+        let mut state = printer.watch_connection();
+
+        // nothing is waiting on the printer, so it can stay quiet
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(printer.connection_state(), ConnectionState::Connected);
+
+        let homing = printer.send("G28").await.unwrap();
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "\n", "connection checked with an empty line");
+        state
+            .wait_for(|state| *state == ConnectionState::Disconnected)
+            .await
+            .unwrap();
+        assert!(matches!(homing.await, Err(Error::WontRespond)));
+    }
+
+    #[tokio::test]
+    async fn keepalive_when_idle() {
+        let config = PrinterConfig {
+            keepalive: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let (printer, _, mut host_side) = duplex_printer_with_config(config);
+
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "M105\n");
+        host_side.get_mut().write_all(b"ok\n").await.unwrap();
+
+        // the keepalive's ok isn't taken for a host line's
+        let homing = printer.send_unsequenced("G28").await.unwrap();
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "G28\n");
+        let waiting = tokio::time::timeout(Duration::from_millis(50), homing).await;
+        assert!(waiting.is_err(), "resolved before its own ok");
+    }
+
+    #[tokio::test]
+    async fn fatal_error_fails_waiting_lines() {
+        let (_printer, socket, mut host_side) = duplex_printer();
+        let socket = socket.with_flow_window(2);
+
+        let numbered = socket.send("G28").await.unwrap();
+        let unnumbered = socket.send_unsequenced("M105").await.unwrap();
+        host_side
+            .write_all(b"Error:Printer halted. kill() called!\n")
+            .await
+            .unwrap();
+        for result in [numbered.await, unnumbered.await] {
+            assert!(matches!(result, Err(Error::Firmware(message)) if message.contains("halted")));
+        }
+        assert!(socket.try_send_unsequenced("M999").is_ok());
+    }
+
+    #[tokio::test]
+    async fn bare_cr_line_endings() {
+        let config = PrinterConfig {
+            bare_cr_line_endings: true,
+            ..Default::default()
+        };
+        let (printer, _, mut host_side) = duplex_printer_with_config(config);
+        let mut lines = printer.subscribe_lines().unwrap();
+
+        host_side.write_all(b"start\rok\r").await.unwrap();
+        host_side.write_all(b"\nok\n").await.unwrap();
+        assert_eq!(&*lines.recv().await.unwrap(), "start\n");
+        assert_eq!(&*lines.recv().await.unwrap(), "ok\n");
+        assert_eq!(&*lines.recv().await.unwrap(), "ok\n");
+    }
+
+    #[tokio::test]
+    async fn tee_writes_every_line() {
+        use tokio::io::AsyncReadExt;
+
+        let (printer, socket, response_sender) = printer_with_lines(4);
+        let (screen, mut screen_output) = tokio::io::duplex(1024);
+        let (file, mut file_output) = tokio::io::duplex(1024);
+        let mut tee = socket.tee(vec![screen, file]);
+        for i in 0..6 {
+            response_sender.send(Arc::from(format!("{i}\n"))).unwrap();
+        }
+        drop(response_sender);
+        drop(printer);
+
+        assert!(tee.finished().await.iter().all(Result::is_ok));
+        assert_eq!(tee.lagged_lines(), [2, 2]);
+        for output in [&mut screen_output, &mut file_output] {
+            let mut written = String::new();
+            output.read_to_string(&mut written).await.unwrap();
+            assert_eq!(written, "2\n3\n4\n5\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_keeps_subscriptions() {
+        let (mut printer, _, _old_host) = duplex_printer();
+        let mut lines = printer.subscribe_lines().unwrap();
+        let _ = printer.send("G0").await.unwrap();
+
+        let (printer_side, host_side) = tokio::io::duplex(1024);
+        printer.reconnect(tokio::io::BufReader::new(printer_side));
+        let mut host_side = tokio::io::BufReader::new(host_side);
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "M110 N0\n");
+
+        host_side.get_mut().write_all(b"ok\n").await.unwrap();
+        assert!(lines.recv().await.unwrap().starts_with("ok"));
+    }
+
+    #[tokio::test]
+    async fn reconnect_backoff_retries() {
+        let mut printer = Printer::Disconnected;
+        let mut attempts = 0;
+        let policy = BackoffPolicy {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+            max_attempts: Some(3),
+        };
+        printer
+            .reconnect_with_backoff(
+                || {
+                    attempts += 1;
+                    let attempt = attempts;
+                    async move {
+                        if attempt < 3 {
+                            Err("not yet")
+                        } else {
+                            Ok(tokio::io::BufReader::new(tokio::io::duplex(64).0))
+                        }
+                    }
+                },
+                policy,
+            )
+            .await
+            .unwrap();
+        assert!(printer.is_connected());
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn klipper_counts_oks() {
+        // sockets cloned before the protocol is set follow it as well
+        let (printer, socket, mut host_side) = duplex_printer();
+        let _printer = printer.with_protocol(Protocol::Klipper);
+        assert_eq!(socket.protocol(), Protocol::Klipper);
+
+        let first = socket.send("G28").await.unwrap();
+        let second = socket.send("G0 X1").await.unwrap();
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "G28\n");
+        line.clear();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "G0 X1\n");
+
+        host_side.get_mut().write_all(b"ok\nok\n").await.unwrap();
+        first.await.unwrap();
+        second.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn klipper_extended_commands() {
+        #[derive(Debug, Serialize)]
+        #[serde(rename = "SET_HEATER_TEMPERATURE")]
+        struct SetHeaterTemperature {
+            #[serde(rename = "HEATER")]
+            heater: &'static str,
+            #[serde(rename = "TARGET")]
+            target: u16,
+        }
+        let (printer, _, mut host_side) = duplex_printer();
+        let printer = printer.with_protocol(Protocol::Klipper);
+
+        let _ = printer
+            .send(SetHeaterTemperature {
+                heater: "extruder",
+                target: 210,
+            })
+            .await
+            .unwrap();
+        let mut line = String::new();
+        host_side.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "SET_HEATER_TEMPERATURE HEATER=extruder TARGET=210\n");
+    }
+
+    #[tokio::test]
+    async fn heating_progress() {
+        let (printer, _, mut host_side) = duplex_printer();
+
+        let mut progress = Vec::new();
+        let heating =
+            printer.set_hotend_and_wait(210.0, |temperature| progress.push(temperature.current));
+        let heater = async {
+            let mut line = String::new();
+            host_side.read_line(&mut line).await.unwrap();
+            assert!(line.contains("M109 S210"));
+            host_side
+                .get_mut()
+                .write_all(b" T:180.5 /210.0 B:60.0 /60.0 @:127\n echo:busy: processing\n T:209.9 /210.0 B:60.0 /60.0 @:64\nok\n")
+                .await
+                .unwrap();
+        };
+        let (heated, _) = tokio::join!(heating, heater);
+        heated.unwrap();
+        assert_eq!(progress, [180.5, 209.9]);
+    }
+
     #[test]
     fn conversion() {
         let disconnected: Printer = None.into();
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream},
+    task::JoinHandle,
+};
+
+use crate::{Printer, PrinterConfig, Socket};
+
+/// Transport connected to a `MockPrinter`, give it to `Printer::new` in place of a serial port
+pub type LoopbackTransport = BufReader<DuplexStream>;
+
+/// A `Printer` connected to an in-memory pipe, with a socket to it and the far end of the
+/// pipe to play the printer's part by hand
+pub fn duplex_printer() -> (Printer, Socket, LoopbackTransport) {
+    duplex_printer_with_config(PrinterConfig::default())
+}
+
+/// Like `duplex_printer`, with non-default communication settings
+pub fn duplex_printer_with_config(config: PrinterConfig) -> (Printer, Socket, LoopbackTransport) {
+    let (printer_side, host_side) = tokio::io::duplex(1024);
+    let printer = Printer::new_with_config(BufReader::new(printer_side), config);
+    let socket = printer.socket().unwrap().clone();
+    (printer, socket, BufReader::new(host_side))
+}
+
+/// Temperature heaters cool down to when turned off
+const AMBIENT: f32 = 20.0;
+
+/// A simulated heater, which moves toward its target one step at a time
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Heater {
+    current: f32,
+    target: f32,
+}
+
+impl Default for Heater {
+    fn default() -> Self {
+        Self {
+            current: AMBIENT,
+            target: 0.0,
+        }
+    }
+}
+
+impl Heater {
+    fn step(&mut self, step: f32) {
+        let goal = self.target.max(AMBIENT);
+        self.current = if self.current < goal {
+            (self.current + step).min(goal)
+        } else {
+            (self.current - step).max(goal)
+        };
+    }
+
+    fn reached(&self) -> bool {
+        self.current >= self.target
+    }
+}
+
+/// A printer simulated in memory, to test anything using a `Printer` without hardware.
+///
+/// Every line gets an `ok`, with its line number if it had one. `M105` reports
+/// temperatures, which move toward the targets set by `M104` and `M140` a step each
+/// time they're reported, and `M109` and `M190` report the temperature every step
+/// until it's reached before their `ok`. `M115` reports firmware and capabilities,
+/// and any command can be given its own canned reply with `reply`.
+#[derive(Debug, Clone)]
+pub struct MockPrinter {
+    replies: HashMap<String, Vec<String>>,
+    firmware: Vec<String>,
+    hotend: Heater,
+    bed: Heater,
+    heating_step: f32,
+}
+
+impl Default for MockPrinter {
+    fn default() -> Self {
+        Self {
+            replies: HashMap::new(),
+            firmware: vec![
+                "FIRMWARE_NAME:Marlin 2.1.2 (mock) PROTOCOL_VERSION:1.0 MACHINE_TYPE:Mock EXTRUDER_COUNT:1".to_string(),
+                "Cap:EEPROM:1".to_string(),
+                "Cap:AUTOREPORT_TEMP:1".to_string(),
+            ],
+            hotend: Heater::default(),
+            bed: Heater::default(),
+            heating_step: 10.0,
+        }
+    }
+}
+
+impl MockPrinter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lines sent before the `ok` whenever `command`, like `M114`, is received
+    pub fn reply<'a>(mut self, command: &str, lines: impl IntoIterator<Item = &'a str>) -> Self {
+        self.replies.insert(
+            command.to_ascii_uppercase(),
+            lines.into_iter().map(str::to_owned).collect(),
+        );
+        self
+    }
+
+    /// Lines sent in reply to `M115` instead of the default Marlin ones
+    pub fn firmware<'a>(mut self, lines: impl IntoIterator<Item = &'a str>) -> Self {
+        self.firmware = lines.into_iter().map(str::to_owned).collect();
+        self
+    }
+
+    /// Degrees heaters change by each time their temperature is reported, 10 by default
+    pub fn heating_step(mut self, step: f32) -> Self {
+        self.heating_step = step;
+        self
+    }
+
+    /// Start simulating, giving the transport to connect a `Printer` to.
+    ///
+    /// The handle finishes with every command received, without line numbers or
+    /// checksums, once the transport is dropped.
+    pub fn start(mut self) -> (LoopbackTransport, JoinHandle<Vec<String>>) {
+        let (host, device) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let (reader, mut writer) = tokio::io::split(device);
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let (sequence, command) = split_line(&line);
+                let mut reply = String::new();
+                for line in self.respond(command) {
+                    reply.push_str(&line);
+                    reply.push('\n');
+                }
+                match sequence {
+                    Some(sequence) => reply.push_str(&format!("ok N{sequence}\n")),
+                    None => reply.push_str("ok\n"),
+                }
+                received.push(command.to_string());
+                if writer.write_all(reply.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+            received
+        });
+        (BufReader::new(host), task)
+    }
+
+    fn heater(&mut self, hotend: bool) -> &mut Heater {
+        if hotend {
+            &mut self.hotend
+        } else {
+            &mut self.bed
+        }
+    }
+
+    fn report(&self) -> String {
+        format!(
+            " T:{:.1} /{:.1} B:{:.1} /{:.1} @:0 B@:0",
+            self.hotend.current, self.hotend.target, self.bed.current, self.bed.target
+        )
+    }
+
+    /// Lines to send back for a command, before its `ok`
+    fn respond(&mut self, command: &str) -> Vec<String> {
+        let (word, params) = split_command(command);
+        if let Some(reply) = self.replies.get(&word) {
+            return reply.clone();
+        }
+        let step = self.heating_step;
+        match word.as_str() {
+            "M105" => {
+                self.hotend.step(step);
+                self.bed.step(step);
+                vec![self.report()]
+            }
+            "M104" | "M109" | "M140" | "M190" => {
+                let hotend = matches!(word.as_str(), "M104" | "M109");
+                if let Some(target) = param(params, 'S') {
+                    self.heater(hotend).target = target;
+                }
+                let mut reports = Vec::new();
+                if matches!(word.as_str(), "M109" | "M190") {
+                    while !self.heater(hotend).reached() {
+                        self.heater(hotend).step(step);
+                        reports.push(self.report());
+                    }
+                }
+                reports
+            }
+            "M115" => self.firmware.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Line number and command of a line sent by the host, without its checksum
+fn split_line(line: &str) -> (Option<i32>, &str) {
+    let line = line.trim();
+    let line = match line.rsplit_once('*') {
+        Some((line, _checksum)) => line,
+        None => line,
+    };
+    if let Some(numbered) = line.strip_prefix('N') {
+        let digits = numbered
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(numbered.len());
+        if let Ok(sequence) = numbered[..digits].parse() {
+            return (Some(sequence), numbered[digits..].trim());
+        }
+    }
+    (None, line)
+}
+
+/// Uppercased letter and number of a command, like `M104`, and the parameters after them
+fn split_command(command: &str) -> (String, &str) {
+    let end = command
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| !c.is_ascii_digit())
+        .map_or(command.len(), |(index, _)| index);
+    (command[..end].to_ascii_uppercase(), &command[end..])
+}
+
+/// Value of a parameter like `S200` in a command's parameters
+fn param(params: &str, letter: char) -> Option<f32> {
+    let start = params.find(|c: char| c.eq_ignore_ascii_case(&letter))? + 1;
+    let value = &params[start..];
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Temperature;
+
+    #[test]
+    fn host_lines() {
+        assert_eq!(split_line("N12G1 X10*87"), (Some(12), "G1 X10"));
+        assert_eq!(split_line("M105"), (None, "M105"));
+        assert_eq!(split_command("m109 S210"), ("M109".to_string(), " S210"));
+        assert_eq!(param(" S210 T0", 'S'), Some(210.0));
+        assert_eq!(param("S60.5", 's'), Some(60.5));
+    }
+
+    #[tokio::test]
+    async fn canned_replies() {
+        let (transport, received) = MockPrinter::new()
+            .reply(
+                "M114",
+                ["X:1.00 Y:2.00 Z:3.00 E:0.00 Count X:80 Y:160 Z:1200"],
+            )
+            .start();
+        let printer = Printer::new(transport);
+        let reply = printer
+            .send_and_collect("M114", crate::DEFAULT_QUERY_TIMEOUT)
+            .await
+            .unwrap();
+        assert!(reply[0].starts_with("X:1.00"));
+        let position = printer.read_position().await.unwrap();
+        assert_eq!(position.counts, [('X', 80), ('Y', 160), ('Z', 1200)]);
+        let sent = printer.send("G28").await.unwrap();
+        sent.await.unwrap();
+        drop(printer);
+        assert_eq!(received.await.unwrap(), ["M114", "M114", "G28"]);
+    }
+
+    #[tokio::test]
+    async fn heating_ramp() {
+        let (transport, _) = MockPrinter::new().heating_step(50.0).start();
+        let printer = Printer::new(transport);
+        let mut progress = Vec::new();
+        printer
+            .set_hotend_and_wait(200.0, |Temperature { current, .. }| progress.push(current))
+            .await
+            .unwrap();
+        assert_eq!(progress, [70.0, 120.0, 170.0, 200.0]);
+        let report = printer.read_temperatures().await.unwrap();
+        assert_eq!(report.hotend.unwrap().target, Some(200.0));
+    }
+}
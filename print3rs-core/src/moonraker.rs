@@ -0,0 +1,408 @@
+//! Klipper printers reached through Moonraker's JSON-RPC websocket API instead of a serial port.
+//!
+//! Each line written by the background task becomes a `printer.gcode.script` request,
+//! answered with an `ok` once Moonraker replies, and everything Klipper responds with
+//! arrives through `notify_gcode_response` as lines, just as over its serial port.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+use base64::Engine;
+use serde_json::{json, Value};
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+        DuplexStream,
+    },
+    net::TcpStream,
+    sync::mpsc,
+};
+
+use crate::Error;
+
+/// Transport connected to a Moonraker server, give it to `Printer::new` in place of a serial port
+pub type MoonrakerTransport = BufReader<DuplexStream>;
+
+/// Port Moonraker listens on unless configured otherwise
+pub const DEFAULT_MOONRAKER_PORT: u16 = 7125;
+
+/// Where a Moonraker server's websocket is
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+/// Find the websocket of the Moonraker server at a URL like `http://printer.local:7125`.
+///
+/// `http://`, `ws://` or no scheme are all accepted, secure connections aren't supported.
+/// The websocket is at `/websocket` unless the URL gives a path.
+fn endpoint(url: &str) -> Result<Endpoint, Error> {
+    let url = url.trim();
+    let address = match url.split_once("://") {
+        Some(("http" | "ws", address)) => address,
+        Some((scheme, _)) => {
+            return Err(Error::Moonraker(format!(
+                "{scheme}:// isn't supported, use http://"
+            )))
+        }
+        None => url,
+    };
+    let (authority, path) = match address.find('/') {
+        Some(slash) if slash + 1 < address.len() => address.split_at(slash),
+        Some(slash) => (&address[..slash], "/websocket"),
+        None => (address, "/websocket"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| Error::Moonraker(format!("invalid port {port}")))?,
+        ),
+        None => (authority, DEFAULT_MOONRAKER_PORT),
+    };
+    if host.is_empty() {
+        return Err(Error::Moonraker(format!("no host in {url}")));
+    }
+    Ok(Endpoint {
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+/// Random bytes for websocket keys and masks, which only need to be unpredictable
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// A single websocket frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Encode a whole message as one frame, masked as frames from a client must be
+fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| byte ^ mask),
+    );
+    frame
+}
+
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Frame> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header).await?;
+    let len = match header[1] & 0x7F {
+        126 => u64::from(reader.read_u16().await?),
+        127 => reader.read_u64().await?,
+        len => u64::from(len),
+    };
+    let mask = if header[1] & 0x80 != 0 {
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask).await?;
+        Some(mask)
+    } else {
+        None
+    };
+    let len = usize::try_from(len).map_err(std::io::Error::other)?;
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+    if let Some(mask) = mask {
+        for (byte, mask) in payload.iter_mut().zip(mask.iter().cycle()) {
+            *byte ^= mask;
+        }
+    }
+    Ok(Frame {
+        fin: header[0] & 0x80 != 0,
+        opcode: header[0] & 0x0F,
+        payload,
+    })
+}
+
+/// Open the websocket, leaving the connection ready for frames
+async fn handshake(endpoint: &Endpoint) -> Result<BufReader<TcpStream>, Error> {
+    let stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port)).await?;
+    stream.set_nodelay(true)?;
+    let mut stream = BufReader::new(stream);
+    let key = base64::engine::general_purpose::STANDARD.encode(random_bytes::<16>());
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host}:{port}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path = endpoint.path,
+        host = endpoint.host,
+        port = endpoint.port,
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+    let mut status = String::new();
+    stream.read_line(&mut status).await?;
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(Error::Moonraker(format!(
+            "websocket refused: {}",
+            status.trim()
+        )));
+    }
+    // skip the rest of the headers
+    let mut header = String::new();
+    while stream.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+    Ok(stream)
+}
+
+/// Read messages from the websocket, answering pings, until it closes
+async fn read_messages(
+    mut reader: impl AsyncRead + Unpin,
+    messages: mpsc::Sender<String>,
+    frames: mpsc::Sender<Vec<u8>>,
+) -> std::io::Result<()> {
+    let mut message = Vec::new();
+    loop {
+        let frame = read_frame(&mut reader).await?;
+        match frame.opcode {
+            OPCODE_TEXT | OPCODE_CONTINUATION => {
+                message.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    let text = String::from_utf8_lossy(&std::mem::take(&mut message)).into_owned();
+                    if messages.send(text).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            OPCODE_PING => {
+                let pong = encode_frame(OPCODE_PONG, &frame.payload, random_bytes());
+                if frames.send(pong).await.is_err() {
+                    return Ok(());
+                }
+            }
+            OPCODE_CLOSE => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// Write frames to the websocket until there are none left to send
+async fn write_frames(
+    mut writer: impl AsyncWrite + Unpin,
+    mut frames: mpsc::Receiver<Vec<u8>>,
+) -> std::io::Result<()> {
+    while let Some(frame) = frames.recv().await {
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+/// What the printer should see for a message from Moonraker, as lines
+#[derive(Debug, Default)]
+struct Translator {
+    next_id: u64,
+    /// Last error Klipper reported as a `!!` line, which Moonraker repeats in its reply
+    reported_error: Option<String>,
+}
+
+impl Translator {
+    /// JSON-RPC request running a line of Gcode, `None` for a blank line
+    fn request(&mut self, line: &str) -> Option<String> {
+        let script = line.trim();
+        if script.is_empty() {
+            return None;
+        }
+        self.next_id += 1;
+        Some(
+            json!({
+                "jsonrpc": "2.0",
+                "method": "printer.gcode.script",
+                "params": {"script": script},
+                "id": self.next_id,
+            })
+            .to_string(),
+        )
+    }
+
+    /// Lines for the printer from a message, an `ok` for each reply to a request
+    fn lines(&mut self, message: &str) -> String {
+        let Ok(message) = serde_json::from_str::<Value>(message) else {
+            tracing::warn!("Moonraker sent something that isn't JSON: {message}");
+            return String::new();
+        };
+        let mut lines = String::new();
+        if message["method"] == "notify_gcode_response" {
+            for line in message["params"].as_array().into_iter().flatten() {
+                let Some(line) = line.as_str() else {
+                    continue;
+                };
+                if let Some(error) = line.strip_prefix("!!") {
+                    self.reported_error = Some(error.trim().to_string());
+                }
+                lines.push_str(line);
+                lines.push('\n');
+            }
+        } else if message.get("id").is_some() {
+            if let Some(error) = message["error"]["message"].as_str() {
+                if self.reported_error.take().as_deref() != Some(error.trim()) {
+                    lines.push_str(&format!("!! {error}\n"));
+                }
+            }
+            self.reported_error = None;
+            lines.push_str("ok\n");
+        }
+        lines
+    }
+}
+
+/// Pass lines between the printer's background task and Moonraker until either side closes
+async fn bridge(printer_side: DuplexStream, websocket: BufReader<TcpStream>) {
+    let (websocket_reader, websocket_writer) = tokio::io::split(websocket);
+    let (frames, outgoing) = mpsc::channel(16);
+    let (incoming, mut messages) = mpsc::channel(16);
+    let writing = tokio::spawn(write_frames(websocket_writer, outgoing));
+    let reading = tokio::spawn(read_messages(websocket_reader, incoming, frames.clone()));
+    let (printer_reader, mut printer_writer) = tokio::io::split(printer_side);
+    let mut sent = BufReader::new(printer_reader).lines();
+    let mut translator = Translator::default();
+    loop {
+        tokio::select! {
+            line = sent.next_line() => {
+                let Ok(Some(line)) = line else { break };
+                let Some(request) = translator.request(&line) else { continue };
+                if frames.send(encode_frame(OPCODE_TEXT, request.as_bytes(), random_bytes())).await.is_err() {
+                    break;
+                }
+            },
+            message = messages.recv() => {
+                let Some(message) = message else { break };
+                let lines = translator.lines(&message);
+                if printer_writer.write_all(lines.as_bytes()).await.is_err() {
+                    break;
+                }
+            },
+        }
+    }
+    let _ = frames
+        .send(encode_frame(OPCODE_CLOSE, &[], random_bytes()))
+        .await;
+    drop(frames);
+    reading.abort();
+    let _ = writing.await;
+    tracing::debug!("Stopped talking to Moonraker");
+}
+
+/// Connect to the Moonraker server at `url`, like `http://printer.local:7125`, giving the
+/// transport to talk to its Klipper printer through. Use `Protocol::Klipper` with it.
+pub async fn connect_moonraker(url: &str) -> Result<MoonrakerTransport, Error> {
+    let endpoint = endpoint(url)?;
+    let websocket = handshake(&endpoint).await?;
+    let (host_side, printer_side) = tokio::io::duplex(4096);
+    tokio::spawn(bridge(printer_side, websocket));
+    Ok(BufReader::new(host_side))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(
+            endpoint("http://printer.local:7125").unwrap(),
+            Endpoint {
+                host: "printer.local".to_string(),
+                port: 7125,
+                path: "/websocket".to_string()
+            }
+        );
+        let bare = endpoint("192.168.1.20").unwrap();
+        assert_eq!(bare.port, DEFAULT_MOONRAKER_PORT);
+        assert_eq!(endpoint("ws://voron:80/ws").unwrap().path, "/ws");
+        assert!(endpoint("https://printer.local").is_err());
+        assert!(endpoint("http://:7125").is_err());
+    }
+
+    #[tokio::test]
+    async fn frame_round_trip() {
+        for len in [0, 125, 126, 70_000] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encoded = encode_frame(OPCODE_TEXT, &payload, [1, 2, 3, 4]);
+            let frame = read_frame(&mut encoded.as_slice()).await.unwrap();
+            assert_eq!(
+                frame,
+                Frame {
+                    fin: true,
+                    opcode: OPCODE_TEXT,
+                    payload
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn translation() {
+        let mut translator = Translator::default();
+        let request: Value = serde_json::from_str(&translator.request("G28\n").unwrap()).unwrap();
+        assert_eq!(request["params"]["script"], "G28");
+        assert_eq!(request["id"], 1);
+        assert!(translator.request("  \n").is_none());
+
+        assert_eq!(
+            translator.lines(r#"{"jsonrpc":"2.0","method":"notify_gcode_response","params":["// Klipper state: Ready"]}"#),
+            "// Klipper state: Ready\n"
+        );
+        assert_eq!(
+            translator.lines(r#"{"jsonrpc":"2.0","result":"ok","id":1}"#),
+            "ok\n"
+        );
+        // Klipper's error is only passed on once, whether or not it was already reported
+        translator.lines(r#"{"jsonrpc":"2.0","method":"notify_gcode_response","params":["!! Must home axis first"]}"#);
+        assert_eq!(
+            translator.lines(
+                r#"{"jsonrpc":"2.0","error":{"code":400,"message":"Must home axis first"},"id":2}"#
+            ),
+            "ok\n"
+        );
+        assert_eq!(
+            translator.lines(
+                r#"{"jsonrpc":"2.0","error":{"code":503,"message":"Klippy Disconnected"},"id":3}"#
+            ),
+            "!! Klippy Disconnected\nok\n"
+        );
+        assert_eq!(
+            translator
+                .lines(r#"{"jsonrpc":"2.0","method":"notify_proc_stat_update","params":[{}]}"#),
+            ""
+        );
+    }
+}
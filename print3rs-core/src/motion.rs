@@ -0,0 +1,95 @@
+/// Whether moves are to coordinates or by distances, set by `G90` and `G91`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Positioning {
+    #[default]
+    Absolute,
+    Relative,
+}
+
+/// Units move distances are given in, set by `G20` and `G21`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    #[default]
+    Millimeters,
+    Inches,
+}
+
+/// How the printer interprets moves, as last commanded by the host, see `Socket::motion_mode`.
+///
+/// Starts out with the usual firmware defaults of absolute positioning in millimeters.
+/// This only reflects lines sent through the connection, so a mode set by the printer's
+/// own menus or a file printed from its SD card isn't noticed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MotionMode {
+    pub positioning: Positioning,
+    pub units: Units,
+}
+
+impl MotionMode {
+    /// Update the mode from a line written to the printer, ignoring any line number,
+    /// checksum and comment
+    pub fn track(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let code = line.split([';', '*']).next().unwrap_or_default();
+        for word in words(code) {
+            match word {
+                ('G', 90) => self.positioning = Positioning::Absolute,
+                ('G', 91) => self.positioning = Positioning::Relative,
+                ('G', 20) => self.units = Units::Inches,
+                ('G', 21) => self.units = Units::Millimeters,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Letters and whole numbers of the words in a line of Gcode, with or without spaces
+/// between them, like `N12G91` or `G1 X10`. Fractional parts are dropped and
+/// words without a plain number, like `Y-3`, are skipped.
+fn words(code: &str) -> impl Iterator<Item = (char, u32)> + '_ {
+    let mut chars = code.char_indices().peekable();
+    std::iter::from_fn(move || loop {
+        let (start, letter) = chars.next()?;
+        if !letter.is_ascii_alphabetic() {
+            continue;
+        }
+        let mut end = start + 1;
+        while let Some(&(index, digit)) = chars.peek() {
+            if !digit.is_ascii_digit() {
+                break;
+            }
+            end = index + 1;
+            chars.next();
+        }
+        if let Ok(number) = code[start + 1..end].parse() {
+            return Some((letter.to_ascii_uppercase(), number));
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gcode_words() {
+        let found: Vec<_> = words("N12G91*40").collect();
+        assert_eq!(found, [('N', 12), ('G', 91)]);
+        let found: Vec<_> = words("g1 X10.5 Y-3 E2").collect();
+        assert_eq!(found, [('G', 1), ('X', 10), ('E', 2)]);
+    }
+
+    #[test]
+    fn tracks_commanded_mode() {
+        let mut mode = MotionMode::default();
+        mode.track(b"N3G91*12\n");
+        assert_eq!(mode.positioning, Positioning::Relative);
+        mode.track(b"G20 ; inches\n");
+        assert_eq!(mode.units, Units::Inches);
+        mode.track(b"G1 X10 ; G90 in a comment\n");
+        assert_eq!(mode.positioning, Positioning::Relative);
+        mode.track(b"G90\n");
+        mode.track(b"g21\n");
+        assert_eq!(mode, MotionMode::default());
+    }
+}
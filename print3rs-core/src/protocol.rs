@@ -0,0 +1,29 @@
+//! How each kind of firmware frames and acknowledges lines, and what its responses mean
+
+use crate::response::{self, Response};
+
+pub mod klipper;
+
+/// How lines are framed for, and acknowledged by, the printer's firmware
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// `send` adds line numbers and checksums, and oks may be matched by line number.
+    /// Used by Marlin, RepRapFirmware, Prusa, and most other firmware.
+    #[default]
+    Marlin,
+    /// `send` behaves like `send_unsequenced`, since Klipper rejects numbered lines.
+    /// Oks are matched to lines by counting them in order.
+    /// Struct fields are sent as `KEY=VALUE` words for extended commands like
+    /// `SET_HEATER_TEMPERATURE HEATER=extruder TARGET=210`.
+    Klipper,
+}
+
+impl Protocol {
+    /// Sort a full line from the printer into the kind of `Response` it is for this firmware
+    pub fn classify(self, line: &[u8]) -> Response {
+        match self {
+            Protocol::Marlin => response::classify(line),
+            Protocol::Klipper => klipper::classify(line),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Responses as Klipper sends them.
+//!
+//! Klipper acknowledges every line with a plain `ok`, in order, since it doesn't take
+//! line numbers. It never asks for a line to be resent, reports errors with `!!`,
+//! which always mean the command failed, and gives information in `//` comments.
+
+use {
+    crate::response::{
+        comment_response, echo_response, message, ok_response, temperature_response, Response,
+    },
+    winnow::{
+        ascii::space0,
+        combinator::{alt, preceded},
+        prelude::*,
+    },
+};
+
+/// `ok`, which only acknowledges the oldest line waiting. Any line number is ignored,
+/// Klipper doesn't number its oks, so one would come from something else entirely.
+fn ok(input: &mut &[u8]) -> PResult<Response> {
+    ok_response.map(|_| Response::Ok(None)).parse_next(input)
+}
+
+/// `!!`, always for a command that failed
+fn error(input: &mut &[u8]) -> PResult<Response> {
+    preceded((space0, "!!"), message)
+        .map(Response::Error)
+        .parse_next(input)
+}
+
+/// try to parse a `Response` from Klipper out of a byte stream
+pub fn response(input: &mut &[u8]) -> PResult<Response> {
+    alt((
+        ok,
+        temperature_response,
+        error,
+        comment_response,
+        echo_response,
+    ))
+    .parse_next(input)
+}
+
+/// Sort a full line from Klipper into the kind of `Response` it is.
+///
+/// Unlike `crate::classify`, Marlin's `Resend:`, `busy:` and `Error:` aren't recognized,
+/// so they can't be mistaken for something Klipper meant.
+pub fn classify(line: &[u8]) -> Response {
+    response.parse(line).unwrap_or_else(|_| {
+        Response::Unrecognized(String::from_utf8_lossy(line).trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn klipper_responses() {
+        let cases: &[(&[u8], Response)] = &[
+            (b"ok\n", Response::Ok(None)),
+            (b"ok B:60.0 /60.0 T0:210.0 /210.0\n", Response::Ok(None)),
+            (
+                b"!! Must home axis first: 10.000 10.000 0.300 [0.000]\n",
+                Response::Error("Must home axis first: 10.000 10.000 0.300 [0.000]".into()),
+            ),
+            (
+                b"// Klipper state: Ready\n",
+                Response::Comment("Klipper state: Ready".into()),
+            ),
+            (b"echo: Hello\n", Response::Echo("Hello".into())),
+            (b"Resend: 6\n", Response::Unrecognized("Resend: 6".into())),
+            (
+                b"Error:Printer halted\n",
+                Response::Unrecognized("Error:Printer halted".into()),
+            ),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(
+                &classify(line),
+                expected,
+                "{}",
+                String::from_utf8_lossy(line)
+            );
+        }
+        assert!(classify(b"!! Shutdown due to webhooks request\n").is_fatal());
+        assert!(matches!(
+            classify(b"T0:20.0 /0.0 B:21.0 /0.0\n"),
+            Response::Temperature(_)
+        ));
+    }
+}
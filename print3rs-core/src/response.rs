@@ -1,22 +1,89 @@
 use winnow::{
-    ascii::{dec_int, multispace0, space0, Caseless},
-    combinator::{alt, opt, preceded, terminated},
+    ascii::{dec_int, dec_uint, float, multispace0, space0, space1, Caseless},
+    combinator::{alt, not, opt, preceded, repeat, rest, separated, terminated},
     prelude::*,
+    token::{one_of, take_till},
 };
 
 /// Response from connected device to indicate if a command
 /// * has finished execution, possibly with a sequence number
 /// * failed parsing, possibly with a sequence number
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// or to report some state of the device without being asked
+#[derive(Debug, Clone, PartialEq)]
 pub enum Response {
     Ok(Option<i32>),
     Resend(Option<i32>),
+    Temperature(TemperatureReport),
+    /// `echo:` informational message
+    Echo(String),
+    /// `Error:` from Marlin or `!!` from Klipper
+    Error(String),
+    /// `//` comment from Klipper
+    Comment(String),
+    /// Still working on a long command, with the reason given
+    Busy(String),
+    /// Any line not matching a known kind of response
+    Unrecognized(String),
 }
 
-fn ok_response(input: &mut &[u8]) -> PResult<Response> {
+impl Response {
+    /// An error that stops the printer or the command it was working on,
+    /// like Marlin halting or a command Klipper rejects.
+    /// A rejected Klipper command is still acknowledged with an `ok`, unlike halted Marlin's lines.
+    ///
+    /// Marlin's errors about line numbers and checksums aren't fatal, the line is resent.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Response::Error(message) => !message.contains("Last Line"),
+            _ => false,
+        }
+    }
+}
+
+/// Current and target temperature of a single heater
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Temperature {
+    pub current: f32,
+    pub target: Option<f32>,
+}
+
+/// Temperatures reported by the device, such as the periodic reports enabled with `M155`
+///
+/// `hotend` is the active extruder, any individually numbered extruders (`T0:`, `T1:`...)
+/// are in `extruders` at their index.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TemperatureReport {
+    pub hotend: Option<Temperature>,
+    pub bed: Option<Temperature>,
+    pub extruders: Vec<Temperature>,
+}
+
+/// Position of the toolhead reported by `M114`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    /// Extruder position, if reported
+    pub e: Option<f32>,
+    /// Where each stepper is in steps, reported by Marlin after `Count`, by axis letter.
+    /// CoreXY machines may give `A` and `B` in place of `X` and `Y`.
+    pub counts: Vec<(char, i32)>,
+}
+
+/// `ok`, which may be followed by more information like `M105` temperatures or `ADVANCED_OK` buffer space
+pub(crate) fn ok_response(input: &mut &[u8]) -> PResult<Response> {
     preceded(
-        (space0, Caseless("ok"), opt(":"), space0, opt(b'N')),
-        terminated(opt(dec_int), multispace0),
+        (
+            space0,
+            Caseless("ok"),
+            not(one_of(|c: u8| c.is_ascii_alphanumeric())),
+            opt(":"),
+            space0,
+            opt(b'N'),
+        ),
+        terminated(opt(dec_int), rest),
     )
     .map(Response::Ok)
     .parse_next(input)
@@ -31,15 +98,192 @@ fn resend_response(input: &mut &[u8]) -> PResult<Response> {
     .parse_next(input)
 }
 
+fn heater(input: &mut &[u8]) -> PResult<Temperature> {
+    (float, opt(preceded((space1, b'/'), float)))
+        .map(|(current, target)| Temperature { current, target })
+        .parse_next(input)
+}
+
+#[derive(Debug)]
+enum Reading {
+    Hotend(Temperature),
+    Extruder(usize, Temperature),
+    Bed(Temperature),
+    Other,
+}
+
+fn reading(input: &mut &[u8]) -> PResult<Reading> {
+    alt((
+        preceded("T:", heater).map(Reading::Hotend),
+        (preceded(b'T', dec_uint), preceded(b':', heater))
+            .map(|(index, temperature): (u8, _)| Reading::Extruder(index.into(), temperature)),
+        preceded("B:", heater).map(Reading::Bed),
+        (
+            take_till(1.., [b':', b' ', b'\t', b'\r', b'\n']),
+            b':',
+            take_till(0.., [b' ', b'\t', b'\r', b'\n']),
+        )
+            .map(|_| Reading::Other),
+    ))
+    .parse_next(input)
+}
+
+pub(crate) fn temperature_response(input: &mut &[u8]) -> PResult<Response> {
+    let first = preceded(space0, reading)
+        .verify(|first: &Reading| matches!(first, Reading::Hotend(_) | Reading::Extruder(..)))
+        .parse_next(input)?;
+    let rest: Vec<Reading> =
+        terminated(repeat(0.., preceded(space1, reading)), multispace0).parse_next(input)?;
+    let mut report = TemperatureReport::default();
+    for reading in std::iter::once(first).chain(rest) {
+        match reading {
+            Reading::Hotend(temperature) => report.hotend = Some(temperature),
+            Reading::Bed(temperature) => report.bed = Some(temperature),
+            Reading::Extruder(index, temperature) => {
+                if report.extruders.len() <= index {
+                    report.extruders.resize(index + 1, Temperature::default());
+                }
+                report.extruders[index] = temperature;
+            }
+            Reading::Other => {}
+        }
+    }
+    Ok(Response::Temperature(report))
+}
+
+pub(crate) fn message(input: &mut &[u8]) -> PResult<String> {
+    rest.map(|message: &[u8]| String::from_utf8_lossy(message).trim().to_string())
+        .parse_next(input)
+}
+
+fn busy_response(input: &mut &[u8]) -> PResult<Response> {
+    preceded((space0, opt(Caseless("echo:")), Caseless("busy:")), message)
+        .map(Response::Busy)
+        .parse_next(input)
+}
+
+pub(crate) fn echo_response(input: &mut &[u8]) -> PResult<Response> {
+    preceded((space0, Caseless("echo:")), message)
+        .map(Response::Echo)
+        .parse_next(input)
+}
+
+fn error_response(input: &mut &[u8]) -> PResult<Response> {
+    preceded((space0, alt((Caseless("Error:"), "!!"))), message)
+        .map(Response::Error)
+        .parse_next(input)
+}
+
+pub(crate) fn comment_response(input: &mut &[u8]) -> PResult<Response> {
+    preceded((space0, "//"), message)
+        .map(Response::Comment)
+        .parse_next(input)
+}
+
 /// try to parse a `Response` out of a byte stream
 pub fn response(input: &mut &[u8]) -> PResult<Response> {
-    alt((ok_response, resend_response)).parse_next(input)
+    alt((
+        ok_response,
+        resend_response,
+        temperature_response,
+        busy_response,
+        echo_response,
+        error_response,
+        comment_response,
+    ))
+    .parse_next(input)
+}
+
+/// An axis letter and its value, like `X:10.00`, or `X: 10` from older firmware
+fn axis_value(input: &mut &[u8]) -> PResult<(char, f32)> {
+    (one_of(|c: u8| c.is_ascii_alphabetic()), b':', space0, float)
+        .map(|(axis, _, _, value): (u8, _, _, f32)| (char::from(axis), value))
+        .parse_next(input)
+}
+
+fn position_response(input: &mut &[u8]) -> PResult<Position> {
+    let axes: Vec<(char, f32)> =
+        preceded(space0, separated(1.., axis_value, space1)).parse_next(input)?;
+    let counts: Option<Vec<(char, f32)>> = opt(preceded(
+        (space1, Caseless("Count"), space1),
+        separated(1.., axis_value, space0),
+    ))
+    .parse_next(input)?;
+    rest.parse_next(input)?;
+    let axis = |letter: char| {
+        axes.iter()
+            .find_map(|(axis, value)| axis.eq_ignore_ascii_case(&letter).then_some(*value))
+    };
+    let (Some(x), Some(y), Some(z)) = (axis('X'), axis('Y'), axis('Z')) else {
+        return winnow::combinator::fail.parse_next(input);
+    };
+    Ok(Position {
+        x,
+        y,
+        z,
+        e: axis('E'),
+        counts: counts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(axis, steps)| (axis.to_ascii_uppercase(), steps.round() as i32))
+            .collect(),
+    })
+}
+
+/// Position reported in a line replying to `M114`, like `X:1.00 Y:2.00 Z:3.00 E:0.00 Count X:80 Y:160 Z:1200`
+pub fn position(line: &[u8]) -> Option<Position> {
+    position_response.parse(line).ok()
+}
+
+/// Temperatures reported in a line, either unprompted or in the `ok` replying to `M105`
+pub fn temperatures(line: &[u8]) -> Option<TemperatureReport> {
+    match preceded(opt((space0, Caseless("ok"), space1)), temperature_response).parse(line) {
+        Ok(Response::Temperature(report)) => Some(report),
+        _ => None,
+    }
+}
+
+/// Sort a full line from the device into the kind of `Response` it is
+pub fn classify(line: &[u8]) -> Response {
+    response.parse(line).unwrap_or_else(|_| {
+        Response::Unrecognized(String::from_utf8_lossy(line).trim().to_string())
+    })
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn positions() {
+        assert_eq!(
+            position(b"X:1.00 Y:2.00 Z:3.00 E:0.50 Count X:80 Y:160 Z:1200\n"),
+            Some(Position {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+                e: Some(0.5),
+                counts: vec![('X', 80), ('Y', 160), ('Z', 1200)],
+            })
+        );
+        let klipper = position(b"X:10.000 Y:20.000 Z:0.300 E:0.000\n").unwrap();
+        assert_eq!((klipper.x, klipper.y, klipper.z), (10.0, 20.0, 0.3));
+        assert!(klipper.counts.is_empty());
+        let old = position(b"X:0.00 Y:0.00 Z:0.00 E:0.00 Count X: 0.00Y:0.00Z:0.00\n").unwrap();
+        assert_eq!(old.counts, [('X', 0), ('Y', 0), ('Z', 0)]);
+        assert_eq!(position(b"ok\n"), None);
+        assert_eq!(position(b"echo:busy: processing\n"), None);
+    }
+
+    #[test]
+    fn fatal_errors() {
+        assert!(classify(b"Error:Printer halted. kill() called!\n").is_fatal());
+        assert!(classify(b"Error: MINTEMP triggered, system stopped! Heater_ID: 0\n").is_fatal());
+        assert!(classify(b"!! Move out of range: 300.000 0.000 0.300 [0.000]\n").is_fatal());
+        assert!(!classify(b"Error:checksum mismatch, Last Line: 5\n").is_fatal());
+        assert!(!classify(b"echo:Unknown command: \"G999\"\n").is_fatal());
+    }
+
     #[test]
     fn test_ok_response() {
         let ok = ok_response.parse(b"ok").unwrap();
@@ -67,4 +311,120 @@ mod test {
         let ok = response.parse(b"Resend: 100").unwrap();
         assert_eq!(ok, Response::Resend(Some(100)));
     }
+
+    #[test]
+    fn test_temperature_response() {
+        let report = response
+            .parse(b" T:210.00 /210.00 B:60.00 /60.00 @:0 B@:0\n")
+            .unwrap();
+        assert_eq!(
+            report,
+            Response::Temperature(TemperatureReport {
+                hotend: Some(Temperature {
+                    current: 210.0,
+                    target: Some(210.0)
+                }),
+                bed: Some(Temperature {
+                    current: 60.0,
+                    target: Some(60.0)
+                }),
+                extruders: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_multi_extruder_temperature() {
+        let Response::Temperature(report) = response
+            .parse(
+                b"T:200.0 /200.0 B:55.2 /60.0 T0:200.0 /200.0 T1:24.5 /0.0 @:12 B@:127 @0:12 @1:0",
+            )
+            .unwrap()
+        else {
+            panic!("not a temperature report");
+        };
+        assert_eq!(report.bed.unwrap().current, 55.2);
+        assert_eq!(report.extruders.len(), 2);
+        assert_eq!(
+            report.extruders[1],
+            Temperature {
+                current: 24.5,
+                target: Some(0.0)
+            }
+        );
+    }
+
+    #[test]
+    fn test_m105_reply() {
+        let report = temperatures(b"ok T:210.00 /210.00 B:60.00 /60.00 @:0 B@:0\n").unwrap();
+        assert_eq!(report.hotend.unwrap().current, 210.0);
+        assert_eq!(report.bed.unwrap().target, Some(60.0));
+        assert_eq!(
+            temperatures(b" T:20.0 /0.0")
+                .unwrap()
+                .hotend
+                .unwrap()
+                .current,
+            20.0
+        );
+        assert!(temperatures(b"ok\n").is_none());
+        assert!(temperatures(b"echo:busy: processing").is_none());
+    }
+
+    #[test]
+    fn test_not_temperature() {
+        assert!(response.parse(b"X:1.00 Y:2.00 Z:3.00 E:0.00").is_err());
+        assert!(!matches!(
+            response.parse(b"echo:busy: processing"),
+            Ok(Response::Temperature(_))
+        ));
+    }
+
+    #[test]
+    fn test_classify() {
+        let cases: &[(&[u8], Response)] = &[
+            (b"ok\n", Response::Ok(None)),
+            (b"ok N12\n", Response::Ok(Some(12))),
+            (b"ok N12 P15 B3\n", Response::Ok(Some(12))),
+            (b"ok T:210.00 /210.00 B:60.00 /60.00\n", Response::Ok(None)),
+            (b"okay\n", Response::Unrecognized("okay".into())),
+            (b"Resend: 6\n", Response::Resend(Some(6))),
+            (
+                b"echo:busy: processing\n",
+                Response::Busy("processing".into()),
+            ),
+            (
+                b"busy: paused for user\n",
+                Response::Busy("paused for user".into()),
+            ),
+            (b"echo:SD card ok\n", Response::Echo("SD card ok".into())),
+            (
+                b"Error:Line Number is not Last Line Number+1, Last Line: 5\n",
+                Response::Error("Line Number is not Last Line Number+1, Last Line: 5".into()),
+            ),
+            (
+                b"!! Move out of range: 300.000 0.000 0.300 [0.000]\n",
+                Response::Error("Move out of range: 300.000 0.000 0.300 [0.000]".into()),
+            ),
+            (
+                b"// Klipper state: Ready\n",
+                Response::Comment("Klipper state: Ready".into()),
+            ),
+            (
+                b"X:1.00 Y:2.00 Z:3.00 E:0.00 Count X:80 Y:160 Z:1200\n",
+                Response::Unrecognized(
+                    "X:1.00 Y:2.00 Z:3.00 E:0.00 Count X:80 Y:160 Z:1200".into(),
+                ),
+            ),
+            (b"start\n", Response::Unrecognized("start".into())),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(
+                &classify(line),
+                expected,
+                "{}",
+                String::from_utf8_lossy(line)
+            );
+        }
+    }
 }
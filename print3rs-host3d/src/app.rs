@@ -6,7 +6,9 @@ use cosmic::{
     Application, Command,
 };
 use {
-    crate::components, print3rs_commands::commander::Commander, print3rs_core::Printer,
+    crate::components,
+    print3rs_commands::commander::Commander,
+    print3rs_core::{ConnectionState, Positioning, Printer},
     std::sync::Arc,
 };
 use {crate::components::Console, print3rs_commands::commands::connect::Connection};
@@ -30,6 +32,42 @@ pub(crate) struct App {
     pub(crate) jog_scale: f32,
 }
 
+impl App {
+    /// Send gcodes to the printer in order through the commander, toasting any error
+    fn send_gcodes(&mut self, gcodes: Vec<String>) -> Command<cosmic::app::Message<Message>> {
+        let gcodes = print3rs_commands::commands::Command::Gcodes(gcodes);
+        if let Err(msg) = self.commander.dispatch(&gcodes) {
+            self.toasts
+                .push(Toast::new(msg.0))
+                .map(cosmic::app::Message::App)
+        } else {
+            Command::none()
+        }
+    }
+
+    /// Send gcodes to the printer queued as one batch, so no other lines can be sent between them
+    fn send_gcodes_together(
+        &mut self,
+        gcodes: Vec<String>,
+    ) -> Command<cosmic::app::Message<Message>> {
+        let socket = match self.commander.printer().socket() {
+            Ok(socket) => socket.clone(),
+            Err(e) => {
+                return self
+                    .toasts
+                    .push(Toast::new(e.to_string()))
+                    .map(cosmic::app::Message::App)
+            }
+        };
+        Command::perform(async move { socket.send_batch(gcodes).await }, |sent| {
+            cosmic::app::Message::App(match sent {
+                Ok(_) => Message::NoOp,
+                Err(e) => Message::PushToast(e.to_string()),
+            })
+        })
+    }
+}
+
 impl Application for App {
     type Executor = cosmic::executor::Default;
     type Message = Message;
@@ -84,25 +122,28 @@ impl Application for App {
     fn update(&mut self, message: Self::Message) -> Command<cosmic::app::Message<Self::Message>> {
         match message {
             Message::Jog(JogMove { x, y, z }) => {
-                if let Err(msg) = self
-                    .commander
-                    .printer()
-                    .try_send_unsequenced(format!("G7X{x}Y{y}Z{z}"))
-                {
-                    self.toasts
-                        .push(Toast::new(msg.to_string()))
-                        .map(cosmic::app::Message::App)
-                } else {
-                    Command::none()
+                let mut jog = "G0".to_string();
+                for (axis, distance) in [('X', x), ('Y', y), ('Z', z)] {
+                    if distance != 0.0 {
+                        jog.push_str(&format!(" {axis}{distance}"));
+                    }
                 }
+                // move relative to where the head is, then go back to the positioning the printer
+                // was left in, without anything else sent in between taking the wrong mode
+                let restore = match self.commander.printer().motion_mode() {
+                    Ok(mode) if mode.positioning == Positioning::Relative => "G91",
+                    _ => "G90",
+                };
+                self.send_gcodes_together(vec!["G91".to_string(), jog, restore.to_string()])
             }
             Message::ToggleConnect => {
-                if self.commander.printer().is_connected() {
+                if self.commander.printer().connection_state() == ConnectionState::Connected {
                     self.commander.set_printer(Printer::Disconnected);
                 } else if let Err(msg) =
                     self.commander
                         .dispatch(print3rs_commands::commands::Command::Connect(
                             self.connection.to_borrowed(),
+                            Default::default(),
                         ))
                 {
                     return self
@@ -161,20 +202,20 @@ impl Application for App {
                 }
             }
             Message::ConsoleAppend(s) => {
-                use widget::text_editor::{Action, Edit};
-                for c in s.chars() {
-                    let action = Action::Edit(Edit::Insert(c));
-                    self.console.output.perform(action)
-                }
-                self.console.output.perform(Action::Edit(Edit::Enter));
+                self.console.append(&s);
                 Command::none()
             }
-            Message::AutoConnectComplete(a_printer) => {
+            Message::AutoConnectComplete(session, a_printer, info) => {
                 let printer = Arc::into_inner(a_printer)
                     .unwrap_or_default()
                     .into_inner()
                     .unwrap_or_default();
-                self.commander.set_printer(printer);
+                self.commander
+                    .set_session_printer(&session, printer, Arc::unwrap_or_clone(info));
+                Command::none()
+            }
+            Message::WaitOver => {
+                self.commander.finish_waiting();
                 Command::none()
             }
             Message::ClearConsole => {
@@ -192,6 +233,7 @@ impl Application for App {
                     Some(file) => cosmic::app::Message::App(Message::ProcessCommand(
                         print3rs_commands::commands::Command::Print(
                             file.path().to_string_lossy().into_owned(),
+                            Default::default(),
                         ),
                     )),
                     None => cosmic::app::Message::App(Message::NoOp),
@@ -237,17 +279,7 @@ impl Application for App {
                     crate::messages::MoveAxis::Z => "Z",
                     crate::messages::MoveAxis::All => "",
                 };
-                if let Err(msg) = self
-                    .commander
-                    .printer()
-                    .try_send_unsequenced(format!("G28{arg}"))
-                {
-                    self.toasts
-                        .push(Toast::new(msg.to_string()))
-                        .map(cosmic::app::Message::App)
-                } else {
-                    Command::none()
-                }
+                self.send_gcodes(vec![format!("G28 {arg}").trim_end().to_string()])
             }
             Message::SelectProtocol(proto) => {
                 self.connection = match proto {
@@ -266,6 +298,9 @@ impl Application for App {
                         in_topic: None,
                         out_topic: None,
                     },
+                    components::Protocol::Moonraker => Connection::Moonraker {
+                        url: "".to_string(),
+                    },
                 };
                 Command::none()
             }
@@ -274,9 +309,9 @@ impl Application for App {
                 Command::none()
             }
             Message::DoMacro(index) => {
-                if let Some((_name, commands)) = self.commander.macros.iter().nth(index) {
+                if let Some((name, _commands)) = self.commander.macros.iter().nth(index) {
                     cosmic::command::message(Message::ProcessCommand(
-                        print3rs_commands::commands::Command::Gcodes(commands.clone()),
+                        print3rs_commands::commands::Command::Gcodes(vec![name.clone()]),
                     ))
                 } else {
                     Command::none()
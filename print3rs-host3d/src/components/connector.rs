@@ -9,6 +9,7 @@ use {
 };
 
 use print3rs_commands::commands::connect::Connection;
+use print3rs_core::ConnectionState;
 
 use crate::app::App;
 use crate::messages::Message;
@@ -19,6 +20,7 @@ pub(crate) enum Protocol {
     Serial,
     Tcp,
     Mqtt,
+    Moonraker,
 }
 
 impl Protocol {
@@ -28,6 +30,7 @@ impl Protocol {
             Connection::Serial { .. } => Protocol::Serial,
             Connection::Tcp { .. } => Protocol::Tcp,
             Connection::Mqtt { .. } => Protocol::Mqtt,
+            Connection::Moonraker { .. } => Protocol::Moonraker,
             _ => todo!(),
         }
     }
@@ -139,6 +142,9 @@ pub(crate) fn connector(app: &App) -> Element<'_, Message> {
             .spacing(5)
         }
         .into(),
+        Connection::Moonraker { url } => text_input("http://hostname:7125", url)
+            .on_input(|url| Message::ChangeConnection(Connection::Moonraker { url }))
+            .into(),
         _ => todo!(),
     };
     let auto = radio(
@@ -169,17 +175,26 @@ pub(crate) fn connector(app: &App) -> Element<'_, Message> {
         Message::SelectProtocol,
     )
     .spacing(5);
-    let protocol_selector = row!["Protocol:", auto, serial, tcp, mqtt]
+    let moonraker = radio(
+        "Moonraker",
+        Protocol::Moonraker,
+        Some(Protocol::from_connection(&app.connection)),
+        Message::SelectProtocol,
+    )
+    .spacing(5);
+    let protocol_selector = row!["Protocol:", auto, serial, tcp, mqtt, moonraker]
         .spacing(20.0)
         .align_items(cosmic::iced::Alignment::Center);
     column![
         protocol_selector,
         connection_details,
-        centered_row![button(if app.commander.printer().is_connected() {
-            "disconnect"
-        } else {
-            "connect"
-        })
+        centered_row![button(
+            if app.commander.printer().connection_state() == ConnectionState::Connected {
+                "disconnect"
+            } else {
+                "connect"
+            }
+        )
         .on_press(Message::ToggleConnect)]
     ]
     .spacing(10)
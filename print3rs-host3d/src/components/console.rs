@@ -1,14 +1,22 @@
 use {
     cosmic::{
         iced_widget::{button, column, row},
-        widget::{combo_box::State as ComboState, text_editor, text_editor::Content, text_input},
+        widget::{
+            combo_box::State as ComboState,
+            text_editor,
+            text_editor::{Action, Content, Edit, Motion},
+            text_input,
+        },
         Element,
     },
-    std::collections::VecDeque,
+    std::{collections::VecDeque, sync::Arc},
 };
 
 use crate::messages::Message;
 
+/// Most lines of output kept in the console, older lines are dropped
+const MAX_OUTPUT_LINES: usize = 2000;
+
 #[derive(Debug)]
 pub(crate) struct State {
     pub(crate) output: Content,
@@ -29,6 +37,25 @@ impl Default for State {
 }
 
 impl State {
+    /// Add text to the end of the output, scrolling down to show it
+    pub(crate) fn append(&mut self, text: &str) {
+        // moving to the end first keeps output in order even if the user clicked elsewhere
+        self.output.perform(Action::Move(Motion::DocumentEnd));
+        let text = text.trim_end_matches(['\r', '\n']);
+        self.output
+            .perform(Action::Edit(Edit::Paste(Arc::new(text.to_string()))));
+        self.output.perform(Action::Edit(Edit::Enter));
+        // trim in chunks rather than rebuilding the output for every line
+        if self.output.line_count() > MAX_OUTPUT_LINES + MAX_OUTPUT_LINES / 10 {
+            let output = self.output.text();
+            let lines: Vec<&str> = output.lines().collect();
+            let kept = &lines[lines.len().saturating_sub(MAX_OUTPUT_LINES)..];
+            self.output = Content::with_text(&kept.join("\n"));
+            self.output.perform(Action::Move(Motion::DocumentEnd));
+            self.output.perform(Action::Edit(Edit::Enter));
+        }
+    }
+
     pub(crate) fn view(&self) -> Element<'_, Message> {
         let content = text_editor(&self.output)
             .font(cosmic::font::Font::MONOSPACE)
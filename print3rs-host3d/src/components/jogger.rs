@@ -1,10 +1,13 @@
 use crate::messages::{JogMove, Message, MoveAxis};
-use cosmic::iced_widget::{button, column, row};
-use cosmic::widget::{container, slider, text, Space};
+use cosmic::iced_widget::{button, column, pick_list, row};
+use cosmic::widget::{container, text, Space};
 use cosmic::Element;
 use {super::centered_row::centered_row, cosmic::iced::alignment};
 use {crate::app::App, cosmic::iced::Alignment};
 
+/// Distances in mm a jog button can move
+const JOG_STEPS: [f32; 3] = [0.1, 1.0, 10.0];
+
 pub(crate) fn jogger(app: &App) -> Element<'_, Message> {
     enum Jog {
         X(f32),
@@ -17,10 +20,7 @@ pub(crate) fn jogger(app: &App) -> Element<'_, Message> {
         let (label, jogmove) = match jog {
             Jog::X(scale) => (text(format!("X{scale:+}")), JogMove::x(scale)),
             Jog::Y(scale) => (text(format!("Y{scale:+}")), JogMove::y(scale)),
-            Jog::Z(scale) => (
-                text(format!("Z{:+.1}", scale / 10.0)),
-                JogMove::z(scale / 10.0),
-            ),
+            Jog::Z(scale) => (text(format!("Z{scale:+}")), JogMove::z(scale)),
         };
         button(
             label
@@ -30,7 +30,7 @@ pub(crate) fn jogger(app: &App) -> Element<'_, Message> {
         .on_press_maybe(if_connected(Message::Jog(jogmove)))
         .width(BUTTON_WIDTH)
     };
-    let scale = app.jog_scale.round().max(1.0);
+    let scale = app.jog_scale;
     let xy_buttons = column![
         jog_button(Jog::Y(scale)),
         row![
@@ -58,9 +58,12 @@ pub(crate) fn jogger(app: &App) -> Element<'_, Message> {
             ]
             .spacing(10.0)
             .align_items(Alignment::Center),
-            slider(0.0..=100.0, app.jog_scale, Message::JogScale)
-                .step(1.0)
-                .width(240),
+            centered_row![
+                text("step (mm)"),
+                pick_list(&JOG_STEPS, Some(app.jog_scale), Message::JogScale),
+            ]
+            .spacing(10.0)
+            .align_items(Alignment::Center),
             centered_row![
                 button(text("home").horizontal_alignment(alignment::Horizontal::Center))
                     .width(BUTTON_WIDTH)
@@ -4,7 +4,7 @@ use {
         commands::{connect::Connection, Command},
         response::Response,
     },
-    print3rs_core::Printer,
+    print3rs_core::{Printer, PrinterInfo},
     std::{
         path::PathBuf,
         sync::{Arc, Mutex},
@@ -66,7 +66,8 @@ pub(crate) enum Message {
     SaveDialog,
     SaveConsole(PathBuf),
     ConsoleAppend(String),
-    AutoConnectComplete(Arc<Mutex<Printer>>),
+    AutoConnectComplete(Arc<str>, Arc<Mutex<Printer>>, Arc<PrinterInfo>),
+    WaitOver,
     PushToast(String),
     PopToast(ToastId),
     OutputAction(cosmic::widget::text_editor::Action),
@@ -80,7 +81,11 @@ impl From<Response> for Message {
         match value {
             Response::Output(s) => Message::ConsoleAppend(s.to_string()),
             Response::Error(e) => Message::PushToast(e.0),
-            Response::AutoConnect(a) => Message::AutoConnectComplete(a),
+            Response::Alert(s) => Message::PushToast(format!("Alert: {s}")),
+            Response::AutoConnect(session, a, info) => {
+                Message::AutoConnectComplete(session, a, info)
+            }
+            Response::WaitOver => Message::WaitOver,
             Response::Clear => Message::ClearConsole,
             Response::Quit => Message::Quit,
         }
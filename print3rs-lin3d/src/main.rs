@@ -3,8 +3,11 @@
 //!
 
 use {
-    print3rs_commands::{commander::Commander, commands::version::VERSION, response::Response},
-    print3rs_core::Printer,
+    print3rs_commands::{
+        commander::{self, Commander},
+        commands::version::VERSION,
+        response::Response,
+    },
     std::{fmt::Debug, sync::Arc},
 };
 
@@ -27,12 +30,45 @@ enum AppError {
     Writer(#[from] futures_util::io::Error),
 }
 
-fn prompt_string(printer: &Printer) -> String {
-    let status = match printer {
-        print3rs_core::Printer::Disconnected => "Disconnected",
-        print3rs_core::Printer::Connected { .. } => "Connected",
+/// Prompt showing the selected session and whether it's connected, or with `details`
+/// where it's connected, like `[COM3@115200]> `, when that's known
+fn prompt_string(commander: &Commander, details: bool) -> String {
+    let connection = details
+        .then(|| commander.printer_info().connection())
+        .flatten();
+    let status = match (commander.printer(), connection) {
+        (print3rs_core::Printer::Disconnected, _) => "Disconnected".to_string(),
+        (print3rs_core::Printer::Connected { .. }, Some(connection)) => connection,
+        (print3rs_core::Printer::Connected { .. }, None) => "Connected".to_string(),
     };
-    format!("[{status}]> ")
+    match commander.selected() {
+        commander::DEFAULT_SESSION => format!("[{status}]> "),
+        session => format!("[{session}: {status}]> "),
+    }
+}
+
+/// The partly typed line of `complete <partial>`, which lists how it could be completed
+///
+/// rustyline-async has no hook to complete on Tab, so completions are asked for with a command
+/// of their own, since any character that could mark a line for completion may also be in Gcode
+fn completion_request(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("complete")?;
+    match rest.strip_prefix(' ') {
+        Some(partial) => Some(partial),
+        None => rest.is_empty().then_some(rest),
+    }
+}
+
+/// Each way of completing the end of a partly typed line, written out in full
+fn completion_list(commander: &Commander, partial: &str) -> String {
+    let (start, candidates) = commander.completions(partial);
+    if candidates.is_empty() {
+        return "No completions\n".to_string();
+    }
+    candidates
+        .iter()
+        .map(|candidate| format!("{}{candidate}\n", &partial[..start]))
+        .collect()
 }
 
 fn setup_logging(writer: SharedWriter) {
@@ -51,20 +87,48 @@ fn setup_logging(writer: SharedWriter) {
     }
 }
 
+/// File of commands to run at startup, given with `--script <file>`
+fn script_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--script" {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// Whether the prompt should show where the printer is connected, given with `--prompt-details`
+fn prompt_details_arg() -> bool {
+    std::env::args()
+        .skip(1)
+        .any(|arg| arg == "--prompt-details")
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), AppError> {
     let mut commander = Commander::new();
+    let prompt_details = prompt_details_arg();
 
-    let (mut readline, mut writer) = Readline::new(prompt_string(commander.printer()))?;
+    let (mut readline, mut writer) = Readline::new(prompt_string(&commander, prompt_details))?;
 
     writer.write_all(VERSION.as_bytes()).await?;
     writer
-        .write_all(b"\ntype `help` for a list of commands\n")
+        .write_all(
+            b"\ntype `help` for a list of commands, or `complete <partial line>` to see how a line could be completed\n",
+        )
         .await?;
     setup_logging(writer.clone());
 
     let mut responses = commander.subscribe_responses();
 
+    if let Some(script) = script_arg() {
+        if let Err(e) = commander.dispatch(commands::Command::Source(script.as_str(), false)) {
+            writer.write_all(e.0.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+        }
+    }
+
     loop {
         tokio::select! {
             Ok(response) = responses.recv() => {
@@ -75,8 +139,14 @@ async fn main() -> Result<(), AppError> {
                     Response::Error(e) => {
                         writer.write_all(format!("Error: {}", e.0).as_bytes()).await?;
                     },
-                    Response::AutoConnect(a_printer) => {
-                        commander.set_printer(Arc::into_inner(a_printer).unwrap_or_default().into_inner().unwrap_or_default());
+                    Response::Alert(s) => {
+                        writer.write_all(format!("\x1b[1;31mAlert: {s}\x1b[0m").as_bytes()).await?;
+                    },
+                    Response::AutoConnect(session, a_printer, info) => {
+                        commander.set_session_printer(&session, Arc::into_inner(a_printer).unwrap_or_default().into_inner().unwrap_or_default(), Arc::unwrap_or_clone(info));
+                    },
+                    Response::WaitOver => {
+                        commander.finish_waiting();
                     },
                     Response::Clear => {
                         readline.clear()?;
@@ -92,6 +162,10 @@ async fn main() -> Result<(), AppError> {
                     ReadlineEvent::Line(line) => line,
                     _ => {readline.flush()?; return Ok(());}
                 };
+                if let Some(partial) = completion_request(&line) {
+                    writer.write_all(completion_list(&commander, partial).as_bytes()).await?;
+                    continue;
+                }
                 let command = match commands::parse_command.parse(&line) {
                     Ok(command) => command,
                     Err(_e) => {
@@ -106,6 +180,6 @@ async fn main() -> Result<(), AppError> {
                 readline.add_history_entry(line);
             },
         }
-        readline.update_prompt(&prompt_string(commander.printer()))?;
+        readline.update_prompt(&prompt_string(&commander, prompt_details))?;
     }
 }
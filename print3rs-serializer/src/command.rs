@@ -0,0 +1,415 @@
+use core::str::FromStr;
+use serde::{ser::SerializeTuple, Serialize, Serializer};
+
+/// Destination and feedrate of a `G0` or `G1` move, only axes given are moved
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+    pub e: Option<f32>,
+    /// Feedrate, in units per minute
+    pub f: Option<f32>,
+}
+
+/// Frequently used Gcodes with typed parameters.
+///
+/// Serializes the same way as the equivalent `Gcode` would, and parses from a line with `FromStr`:
+/// ```
+/// # use print3rs_serializer::{serialize_unsequenced, GcodeCommand};
+/// let heat = GcodeCommand::SetHotendTemperature { temperature: 210.0, tool: None };
+/// assert_eq!(*serialize_unsequenced(heat), *b"M104S210.0\n");
+/// assert_eq!("m104 s210".parse::<GcodeCommand>(), Ok(heat));
+/// ```
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GcodeCommand {
+    /// `G0` rapid move
+    RapidMove(Move),
+    /// `G1` linear move
+    LinearMove(Move),
+    /// `G28` auto home the axes picked, or every axis if none are
+    Home { x: bool, y: bool, z: bool },
+    /// `G90` positions are absolute
+    AbsolutePositioning,
+    /// `G91` positions are relative to the current one
+    RelativePositioning,
+    /// `M104` set the hotend temperature in degrees Celsius without waiting
+    SetHotendTemperature { temperature: f32, tool: Option<u8> },
+    /// `M109` set the hotend temperature in degrees Celsius and wait for it
+    WaitHotendTemperature { temperature: f32, tool: Option<u8> },
+    /// `M140` set the bed temperature in degrees Celsius without waiting
+    SetBedTemperature { temperature: f32 },
+    /// `M190` set the bed temperature in degrees Celsius and wait for it
+    WaitBedTemperature { temperature: f32 },
+    /// `M105` report temperatures
+    ReportTemperatures,
+    /// `M114` report the current position
+    ReportPosition,
+    /// `M112` emergency stop
+    EmergencyStop,
+}
+
+/// Value given to a parameter
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Float(f32),
+    Int(u8),
+}
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Number::Float(v) => serializer.serialize_f32(v),
+            Number::Int(v) => serializer.serialize_u8(v),
+        }
+    }
+}
+
+/// Parameter letter with its value, if it has one
+type Param = (char, Option<Number>);
+
+fn float(letter: char, value: Option<f32>) -> Option<Param> {
+    value.map(|value| (letter, Some(Number::Float(value))))
+}
+
+fn flag(letter: char, set: bool) -> Option<Param> {
+    set.then_some((letter, None))
+}
+
+impl GcodeCommand {
+    /// Letter and number of the command, like `('G', 28)`
+    pub fn code(&self) -> (char, u16) {
+        match self {
+            GcodeCommand::RapidMove(_) => ('G', 0),
+            GcodeCommand::LinearMove(_) => ('G', 1),
+            GcodeCommand::Home { .. } => ('G', 28),
+            GcodeCommand::AbsolutePositioning => ('G', 90),
+            GcodeCommand::RelativePositioning => ('G', 91),
+            GcodeCommand::SetHotendTemperature { .. } => ('M', 104),
+            GcodeCommand::WaitHotendTemperature { .. } => ('M', 109),
+            GcodeCommand::SetBedTemperature { .. } => ('M', 140),
+            GcodeCommand::WaitBedTemperature { .. } => ('M', 190),
+            GcodeCommand::ReportTemperatures => ('M', 105),
+            GcodeCommand::ReportPosition => ('M', 114),
+            GcodeCommand::EmergencyStop => ('M', 112),
+        }
+    }
+
+    /// Parameters in the order they're written, the ones not given are `None`
+    fn params(&self) -> [Option<Param>; 5] {
+        match *self {
+            GcodeCommand::RapidMove(to) | GcodeCommand::LinearMove(to) => [
+                float('X', to.x),
+                float('Y', to.y),
+                float('Z', to.z),
+                float('E', to.e),
+                float('F', to.f),
+            ],
+            GcodeCommand::Home { x, y, z } => {
+                [flag('X', x), flag('Y', y), flag('Z', z), None, None]
+            }
+            GcodeCommand::SetHotendTemperature { temperature, tool }
+            | GcodeCommand::WaitHotendTemperature { temperature, tool } => [
+                float('S', Some(temperature)),
+                tool.map(|tool| ('T', Some(Number::Int(tool)))),
+                None,
+                None,
+                None,
+            ],
+            GcodeCommand::SetBedTemperature { temperature }
+            | GcodeCommand::WaitBedTemperature { temperature } => {
+                [float('S', Some(temperature)), None, None, None, None]
+            }
+            GcodeCommand::AbsolutePositioning
+            | GcodeCommand::RelativePositioning
+            | GcodeCommand::ReportTemperatures
+            | GcodeCommand::ReportPosition
+            | GcodeCommand::EmergencyStop => [None; 5],
+        }
+    }
+}
+
+impl Serialize for GcodeCommand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (letter, number) = self.code();
+        let params = self.params();
+        let mut tuple = serializer.serialize_tuple(2 + params.iter().flatten().count())?;
+        tuple.serialize_element(&letter)?;
+        tuple.serialize_element(&number)?;
+        for param in params.iter().flatten() {
+            tuple.serialize_element(param)?;
+        }
+        tuple.end()
+    }
+}
+
+/// Reasons a line can't be parsed into a `GcodeCommand`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseGcodeError {
+    /// Line doesn't start with a G or M code
+    NotAGcode,
+    /// G or M code which `GcodeCommand` doesn't cover
+    Unsupported(char, u16),
+    /// Parameter the command doesn't take, or whose value isn't valid for it
+    InvalidParameter(char),
+    /// Parameter the command needs isn't given
+    MissingParameter(char),
+}
+
+impl core::fmt::Display for ParseGcodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParseGcodeError::NotAGcode => f.write_str("line isn't a G or M code"),
+            ParseGcodeError::Unsupported(letter, number) => {
+                write!(f, "{letter}{number} isn't a supported command")
+            }
+            ParseGcodeError::InvalidParameter(letter) => write!(f, "invalid parameter {letter}"),
+            ParseGcodeError::MissingParameter(letter) => write!(f, "parameter {letter} is needed"),
+        }
+    }
+}
+
+impl serde::ser::StdError for ParseGcodeError {}
+
+/// Letters of a line, each with the text of the number after it, like `X` and `-1.5` from `X-1.5`.
+///
+/// Words may or may not be separated by spaces.
+struct Words<'a>(&'a str);
+
+impl<'a> Iterator for Words<'a> {
+    type Item = (char, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chars = self.0.trim_start().chars();
+        let letter = chars.next()?;
+        let rest = chars.as_str();
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+            .unwrap_or(rest.len());
+        self.0 = &rest[end..];
+        Some((letter.to_ascii_uppercase(), &rest[..end]))
+    }
+}
+
+fn letter_index(letter: char) -> usize {
+    (letter as u8 - b'A') as usize
+}
+
+impl FromStr for GcodeCommand {
+    type Err = ParseGcodeError;
+
+    /// Parse a line like `G1 X10 Y20` or `G1X10.0Y20.0`, letters can be any case.
+    ///
+    /// A line number, checksum or `;` comment around the command is skipped.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let code = line.split([';', '*']).next().unwrap_or_default();
+        let mut words = Words(code);
+        let (mut letter, mut number) = words.next().ok_or(ParseGcodeError::NotAGcode)?;
+        if letter == 'N' {
+            (letter, number) = words.next().ok_or(ParseGcodeError::NotAGcode)?;
+        }
+        let number: u16 = number.parse().map_err(|_| ParseGcodeError::NotAGcode)?;
+        let allowed = match (letter, number) {
+            ('G', 0 | 1) => "XYZEF",
+            ('G', 28) => "XYZ",
+            ('M', 104 | 109) => "ST",
+            ('M', 140 | 190) => "S",
+            ('G', 90 | 91) | ('M', 105 | 112 | 114) => "",
+            ('G' | 'M', _) => return Err(ParseGcodeError::Unsupported(letter, number)),
+            _ => return Err(ParseGcodeError::NotAGcode),
+        };
+
+        let mut params: [Option<&str>; 26] = [None; 26];
+        for (param, value) in words {
+            if !allowed.contains(param) {
+                return Err(ParseGcodeError::InvalidParameter(param));
+            }
+            params[letter_index(param)] = Some(value);
+        }
+        let given = |letter: char| params[letter_index(letter)].is_some();
+        let float = |letter: char| {
+            params[letter_index(letter)]
+                .map(|value| {
+                    value
+                        .parse::<f32>()
+                        .map_err(|_| ParseGcodeError::InvalidParameter(letter))
+                })
+                .transpose()
+        };
+        let temperature = || -> Result<f32, ParseGcodeError> {
+            float('S')?.ok_or(ParseGcodeError::MissingParameter('S'))
+        };
+        let tool = || {
+            params[letter_index('T')]
+                .map(|value| {
+                    value
+                        .parse::<u8>()
+                        .map_err(|_| ParseGcodeError::InvalidParameter('T'))
+                })
+                .transpose()
+        };
+        let to = || -> Result<Move, ParseGcodeError> {
+            Ok(Move {
+                x: float('X')?,
+                y: float('Y')?,
+                z: float('Z')?,
+                e: float('E')?,
+                f: float('F')?,
+            })
+        };
+
+        Ok(match (letter, number) {
+            ('G', 0) => GcodeCommand::RapidMove(to()?),
+            ('G', 1) => GcodeCommand::LinearMove(to()?),
+            ('G', 28) => GcodeCommand::Home {
+                x: given('X'),
+                y: given('Y'),
+                z: given('Z'),
+            },
+            ('G', 90) => GcodeCommand::AbsolutePositioning,
+            ('G', 91) => GcodeCommand::RelativePositioning,
+            ('M', 104) => GcodeCommand::SetHotendTemperature {
+                temperature: temperature()?,
+                tool: tool()?,
+            },
+            ('M', 109) => GcodeCommand::WaitHotendTemperature {
+                temperature: temperature()?,
+                tool: tool()?,
+            },
+            ('M', 140) => GcodeCommand::SetBedTemperature {
+                temperature: temperature()?,
+            },
+            ('M', 190) => GcodeCommand::WaitBedTemperature {
+                temperature: temperature()?,
+            },
+            ('M', 105) => GcodeCommand::ReportTemperatures,
+            ('M', 112) => GcodeCommand::EmergencyStop,
+            // M114, the only code left after checking them above
+            _ => GcodeCommand::ReportPosition,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod test {
+    use super::*;
+    use crate::{serialize_unsequenced, Gcode};
+
+    #[test]
+    fn same_as_builder() {
+        let to = Move {
+            x: Some(10.0),
+            y: Some(-2.0),
+            e: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(
+            serialize_unsequenced(GcodeCommand::LinearMove(to)),
+            serialize_unsequenced(Gcode::linear_move().x(10.0).y(-2.0).e(0.5))
+        );
+        assert_eq!(
+            serialize_unsequenced(GcodeCommand::Home {
+                x: true,
+                y: false,
+                z: true
+            }),
+            serialize_unsequenced(Gcode::home().axis('x').axis('z'))
+        );
+        assert_eq!(
+            serialize_unsequenced(GcodeCommand::SetHotendTemperature {
+                temperature: 200.0,
+                tool: Some(1)
+            }),
+            serialize_unsequenced(Gcode::set_hotend_temperature(200.0).t(1))
+        );
+        assert_eq!(
+            *serialize_unsequenced(GcodeCommand::EmergencyStop),
+            *b"M112\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let commands = [
+            GcodeCommand::RapidMove(Move {
+                x: Some(1.5),
+                f: Some(600.0),
+                ..Default::default()
+            }),
+            GcodeCommand::LinearMove(Move {
+                z: Some(-0.25),
+                ..Default::default()
+            }),
+            GcodeCommand::Home {
+                x: false,
+                y: true,
+                z: false,
+            },
+            GcodeCommand::AbsolutePositioning,
+            GcodeCommand::RelativePositioning,
+            GcodeCommand::SetHotendTemperature {
+                temperature: 215.0,
+                tool: None,
+            },
+            GcodeCommand::WaitHotendTemperature {
+                temperature: 180.5,
+                tool: Some(2),
+            },
+            GcodeCommand::SetBedTemperature { temperature: 60.0 },
+            GcodeCommand::WaitBedTemperature { temperature: 0.0 },
+            GcodeCommand::ReportTemperatures,
+            GcodeCommand::ReportPosition,
+            GcodeCommand::EmergencyStop,
+        ];
+        for command in commands {
+            let line = serialize_unsequenced(command);
+            let line = core::str::from_utf8(&line).unwrap();
+            assert_eq!(
+                line.trim_end().parse::<GcodeCommand>(),
+                Ok(command),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn typed_lines() {
+        assert_eq!(
+            "N5 g1 x10 Y20 ; move*42".parse::<GcodeCommand>(),
+            Ok(GcodeCommand::LinearMove(Move {
+                x: Some(10.0),
+                y: Some(20.0),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            "G28 X0".parse::<GcodeCommand>(),
+            Ok(GcodeCommand::Home {
+                x: true,
+                y: false,
+                z: false
+            })
+        );
+        assert_eq!(
+            "G29".parse::<GcodeCommand>(),
+            Err(ParseGcodeError::Unsupported('G', 29))
+        );
+        assert_eq!(
+            "G1 X10 Q1".parse::<GcodeCommand>(),
+            Err(ParseGcodeError::InvalidParameter('Q'))
+        );
+        assert_eq!(
+            "G1 X".parse::<GcodeCommand>(),
+            Err(ParseGcodeError::InvalidParameter('X'))
+        );
+        assert_eq!(
+            "M104 T0".parse::<GcodeCommand>(),
+            Err(ParseGcodeError::MissingParameter('S'))
+        );
+        assert_eq!(
+            "ok T:20".parse::<GcodeCommand>(),
+            Err(ParseGcodeError::NotAGcode)
+        );
+    }
+}
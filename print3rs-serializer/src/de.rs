@@ -0,0 +1,417 @@
+use serde::{
+    de::{self, value::BorrowedStrDeserializer, DeserializeSeed, MapAccess, Visitor},
+    forward_to_deserialize_any, Deserialize,
+};
+
+use {
+    alloc::{
+        borrow::ToOwned,
+        string::{String, ToString},
+    },
+    core::{fmt, str::FromStr},
+};
+
+/// Errors that can occur while deserializing a line from a device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Input was not valid UTF-8
+    Utf8,
+    /// A value could not be parsed as the type requested
+    Parse {
+        expected: &'static str,
+        found: String,
+    },
+    /// Error raised by the type being deserialized
+    Custom(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Utf8 => f.write_str("input is not valid UTF-8"),
+            Error::Parse { expected, found } => {
+                write!(f, "expected {expected}, found `{found}`")
+            }
+            Error::Custom(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl serde::ser::StdError for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
+
+/// Deserialize a line such as `ok X:1.0 Y:2.0 Z:3.0` into a type implementing Deserialize.
+///
+/// Struct fields are matched to words by the uppercased first character of the field name,
+/// mirroring how structs are serialized. Only the first word for each letter is used,
+/// fields missing from the line are left to the type to default (e.g. `Option` becomes `None`).
+pub fn from_bytes<'de, T>(input: &'de [u8]) -> Result<T, Error>
+where
+    T: Deserialize<'de>,
+{
+    T::deserialize(Deserializer::from_bytes(input)?)
+}
+
+/// Deserializer for lines of Gcode-like `<letter><value>` words
+#[derive(Debug, Clone, Copy)]
+pub struct Deserializer<'de> {
+    input: &'de str,
+}
+
+impl<'de> Deserializer<'de> {
+    /// Create a deserializer over some text, ignoring surrounding whitespace and a leading `ok`
+    pub fn new(input: &'de str) -> Self {
+        Self {
+            input: strip_ok(input),
+        }
+    }
+
+    /// Create a deserializer over raw bytes, which must be valid UTF-8
+    pub fn from_bytes(input: &'de [u8]) -> Result<Self, Error> {
+        let input = core::str::from_utf8(input).map_err(|_| Error::Utf8)?;
+        Ok(Self::new(input))
+    }
+}
+
+fn strip_ok(input: &str) -> &str {
+    let input = input.trim();
+    match input.get(..2) {
+        Some(ok) if ok.eq_ignore_ascii_case("ok") => {
+            let rest = &input[2..];
+            if rest.is_empty() || rest.starts_with(|c: char| c.is_ascii_whitespace()) {
+                rest.trim_start()
+            } else {
+                input
+            }
+        }
+        _ => input,
+    }
+}
+
+/// Iterator over `<letter><value>` words, a value runs until whitespace or the next letter.
+/// An optional `:` between the letter and value is skipped, anything else is ignored.
+#[derive(Debug, Clone)]
+struct Words<'de> {
+    input: &'de str,
+}
+
+impl<'de> Iterator for Words<'de> {
+    type Item = (char, &'de str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.input.find(|c: char| c.is_ascii_alphabetic())?;
+        let letter = self.input.as_bytes()[start].to_ascii_uppercase() as char;
+        let rest = &self.input[start + 1..];
+        let rest = rest.strip_prefix(':').unwrap_or(rest);
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let (value, remaining) = rest.split_at(end);
+        self.input = remaining;
+        Some((letter, value))
+    }
+}
+
+macro_rules! forward_to_value {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            Value(self.input).$method(visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    forward_to_value! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_option deserialize_unit
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(WordMap {
+            words: Words { input: self.input },
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(FieldMap {
+            words: Words { input: self.input },
+            fields: fields.iter(),
+            value: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit_struct seq tuple tuple_struct enum identifier ignored_any
+    }
+}
+
+/// Every word in a line, keyed by its letter
+struct WordMap<'de> {
+    words: Words<'de>,
+    value: Option<&'de str>,
+}
+
+impl<'de> MapAccess<'de> for WordMap<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.words.next() {
+            Some((letter, value)) => {
+                self.value = Some(value);
+                seed.deserialize(de::value::CharDeserializer::new(letter))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| <Error as de::Error>::custom("value requested before key"))?;
+        seed.deserialize(Value(value))
+    }
+}
+
+/// Words matched up with the fields of a struct, only fields present in the line are visited
+struct FieldMap<'de> {
+    words: Words<'de>,
+    fields: core::slice::Iter<'static, &'static str>,
+    value: Option<&'de str>,
+}
+
+impl<'de> MapAccess<'de> for FieldMap<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        for field in self.fields.by_ref() {
+            let Some(key) = field.chars().next().map(|c| c.to_ascii_uppercase()) else {
+                continue;
+            };
+            if let Some((_, value)) = self.words.clone().find(|(letter, _)| *letter == key) {
+                self.value = Some(value);
+                return seed
+                    .deserialize(BorrowedStrDeserializer::new(field))
+                    .map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| <Error as de::Error>::custom("value requested before key"))?;
+        seed.deserialize(Value(value))
+    }
+}
+
+/// A single value following a letter
+struct Value<'de>(&'de str);
+
+impl<'de> Value<'de> {
+    fn parse<T: FromStr>(&self, expected: &'static str) -> Result<T, Error> {
+        self.0.parse().map_err(|_| Error::Parse {
+            expected,
+            found: self.0.to_owned(),
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident: $ty:ty,)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Value<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if let Ok(int) = self.0.parse::<i64>() {
+            visitor.visit_i64(int)
+        } else if let Ok(float) = self.0.parse::<f64>() {
+            visitor.visit_f64(float)
+        } else {
+            visitor.visit_borrowed_str(self.0)
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            "0" => visitor.visit_bool(false),
+            "1" => visitor.visit_bool(true),
+            _ => Err(Error::Parse {
+                expected: "bool",
+                found: self.0.to_owned(),
+            }),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+        deserialize_char => visit_char: char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::serialize_unsequenced;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+        z: f32,
+        e: Option<f32>,
+    }
+
+    #[test]
+    fn m114_position() {
+        let position: Position =
+            from_bytes(b"X:1.00 Y:2.00 Z:3.00 E:0.00 Count X:100 Y:200 Z:300\n").unwrap();
+        assert_eq!(
+            position,
+            Position {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+                e: Some(0.0)
+            }
+        );
+    }
+
+    #[test]
+    fn leading_ok_and_whitespace() {
+        let position: Position = from_bytes(b"  ok   X:1.5\t Y:-2  Z:0.25 \r\n").unwrap();
+        assert_eq!(
+            position,
+            Position {
+                x: 1.5,
+                y: -2.0,
+                z: 0.25,
+                e: None
+            }
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct G1 {
+            x: i32,
+            y: f32,
+            feedrate: Option<u16>,
+        }
+        let g1 = G1 {
+            x: -1,
+            y: 2.3,
+            feedrate: Some(1200),
+        };
+        let bytes = serialize_unsequenced(&g1);
+        assert_eq!(from_bytes::<G1>(&bytes).unwrap(), g1);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            from_bytes::<Position>(b"X:1.0 Y:1.2.3 Z:3.0"),
+            Err(Error::Parse {
+                expected: "f32",
+                found: "1.2.3".to_string()
+            })
+        );
+        assert!(from_bytes::<Position>(b"X:1.0 Z:3.0").is_err());
+        assert_eq!(from_bytes::<Position>(b"X:\xff"), Err(Error::Utf8));
+    }
+
+    #[test]
+    fn map_and_value() {
+        let map: std::collections::BTreeMap<char, f64> = from_bytes(b"ok T:21.5 B:60").unwrap();
+        assert_eq!(map.get(&'T'), Some(&21.5));
+        assert_eq!(map.get(&'B'), Some(&60.0));
+        assert_eq!(from_bytes::<u8>(b"42\n"), Ok(42));
+    }
+}
@@ -0,0 +1,200 @@
+use {
+    alloc::vec::Vec,
+    serde::{ser::SerializeTuple, Serialize, Serializer},
+};
+
+/// Value given to a parameter of a `Gcode` command
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Int(i64),
+    Float(f32),
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Value::Int(v) => serializer.serialize_i64(v),
+            Value::Float(v) => serializer.serialize_f32(v),
+        }
+    }
+}
+
+/// A Gcode command built up one parameter at a time, for sending ad-hoc commands
+/// without deriving `Serialize` on a struct for each one.
+///
+/// Parameters are written in the order they're added, serializing the same way as
+/// a struct with the same fields would:
+/// ```
+/// # use print3rs_serializer::{serialize_unsequenced, Gcode};
+/// let gcode = Gcode::g(1).x(10.0).y(20.0).f(3000.0);
+/// assert_eq!(*serialize_unsequenced(gcode), *b"G1X10.0Y20.0F3000.0\n");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gcode {
+    letter: char,
+    number: u16,
+    params: Vec<(char, Option<Value>)>,
+}
+
+impl Gcode {
+    /// Command with the given letter and number, without any parameters
+    pub fn new(letter: char, number: u16) -> Self {
+        Self {
+            letter: letter.to_ascii_uppercase(),
+            number,
+            params: Vec::new(),
+        }
+    }
+
+    /// `G<number>` command
+    pub fn g(number: u16) -> Self {
+        Self::new('G', number)
+    }
+
+    /// `M<number>` command
+    pub fn m(number: u16) -> Self {
+        Self::new('M', number)
+    }
+
+    /// `G0` rapid move, give the destination with `x`, `y`, `z` and the feedrate with `f`
+    pub fn rapid_move() -> Self {
+        Self::g(0)
+    }
+
+    /// `G1` linear move, give the destination with `x`, `y`, `z`, `e` and the feedrate with `f`
+    pub fn linear_move() -> Self {
+        Self::g(1)
+    }
+
+    /// `G28` auto home, every axis unless some are picked with `axis`
+    pub fn home() -> Self {
+        Self::g(28)
+    }
+
+    /// `M104` set the hotend temperature in degrees Celsius without waiting
+    pub fn set_hotend_temperature(temperature: f32) -> Self {
+        Self::m(104).s(temperature)
+    }
+
+    /// `M140` set the bed temperature in degrees Celsius without waiting
+    pub fn set_bed_temperature(temperature: f32) -> Self {
+        Self::m(140).s(temperature)
+    }
+
+    /// Add a parameter with a decimal value
+    pub fn param(mut self, letter: char, value: f32) -> Self {
+        self.params
+            .push((letter.to_ascii_uppercase(), Some(Value::Float(value))));
+        self
+    }
+
+    /// Add a parameter with a whole number value, like a tool index
+    pub fn int_param(mut self, letter: char, value: i64) -> Self {
+        self.params
+            .push((letter.to_ascii_uppercase(), Some(Value::Int(value))));
+        self
+    }
+
+    /// Add a parameter letter without a value, like the axes to home with `G28 X Y`
+    pub fn axis(mut self, letter: char) -> Self {
+        self.params.push((letter.to_ascii_uppercase(), None));
+        self
+    }
+
+    /// X axis position
+    pub fn x(self, x: f32) -> Self {
+        self.param('X', x)
+    }
+
+    /// Y axis position
+    pub fn y(self, y: f32) -> Self {
+        self.param('Y', y)
+    }
+
+    /// Z axis position
+    pub fn z(self, z: f32) -> Self {
+        self.param('Z', z)
+    }
+
+    /// Extruder position
+    pub fn e(self, e: f32) -> Self {
+        self.param('E', e)
+    }
+
+    /// Feedrate, in units per minute
+    pub fn f(self, f: f32) -> Self {
+        self.param('F', f)
+    }
+
+    /// `S` parameter, usually a temperature or speed
+    pub fn s(self, s: f32) -> Self {
+        self.param('S', s)
+    }
+
+    /// `P` parameter, usually a time or index
+    pub fn p(self, p: f32) -> Self {
+        self.param('P', p)
+    }
+
+    /// Tool, or hotend, index
+    pub fn t(self, tool: u8) -> Self {
+        self.int_param('T', tool.into())
+    }
+}
+
+impl Serialize for Gcode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(2 + self.params.len())?;
+        tuple.serialize_element(&self.letter)?;
+        tuple.serialize_element(&self.number)?;
+        for param in &self.params {
+            tuple.serialize_element(param)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{serialize_unsequenced, Sequenced};
+
+    #[test]
+    fn common_commands() {
+        assert_eq!(
+            *serialize_unsequenced(Gcode::rapid_move().x(1.5).f(600.0)),
+            *b"G0X1.5F600.0\n"
+        );
+        assert_eq!(
+            *serialize_unsequenced(Gcode::linear_move().x(10.0).y(-2.0).e(0.5)),
+            *b"G1X10.0Y-2.0E0.5\n"
+        );
+        assert_eq!(*serialize_unsequenced(Gcode::home()), *b"G28\n");
+        assert_eq!(
+            *serialize_unsequenced(Gcode::home().axis('x').axis('y')),
+            *b"G28XY\n"
+        );
+        assert_eq!(
+            *serialize_unsequenced(Gcode::set_hotend_temperature(200.0).t(1)),
+            *b"M104S200.0T1\n"
+        );
+        assert_eq!(
+            *serialize_unsequenced(Gcode::set_bed_temperature(60.0)),
+            *b"M140S60.0\n"
+        );
+    }
+
+    #[test]
+    fn same_as_struct() {
+        #[derive(Serialize)]
+        struct G1 {
+            x: f32,
+            y: f32,
+        }
+        let sequenced = Sequenced::new();
+        let (_, from_struct) = sequenced.serialize(G1 { x: 10.0, y: 20.0 });
+        sequenced.set_sequence(1);
+        let (_, from_builder) = sequenced.serialize(Gcode::g(1).x(10.0).y(20.0));
+        assert_eq!(from_struct, from_builder);
+    }
+}
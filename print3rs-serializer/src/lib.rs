@@ -1,34 +1,229 @@
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 use serde::{
-    ser::{self, SerializeStruct},
+    ser::{self, SerializeStruct, StdError},
     Serialize,
 };
 
-use std::sync::{atomic::AtomicI32 as Ai32, atomic::Ordering, Arc};
+#[cfg(feature = "alloc")]
+use {
+    alloc::{boxed::Box, sync::Arc, vec::Vec},
+    core::sync::atomic::{AtomicI32 as Ai32, Ordering},
+};
+
+#[cfg(feature = "alloc")]
+pub mod de;
+
+#[cfg(feature = "alloc")]
+pub use de::{from_bytes, Deserializer};
+
+#[cfg(feature = "alloc")]
+mod gcode;
+
+#[cfg(feature = "alloc")]
+pub use gcode::Gcode;
+
+mod command;
+
+pub use command::{GcodeCommand, Move, ParseGcodeError};
 
 /// Default start point for new sequencers
 pub const SEQUENCE_START: i32 = 1;
 
+/// Errors produced when a line can't be serialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Formatted line, including sequence number, checksum and newline, exceeds the configured limit
+    LineTooLong { length: usize, max: usize },
+    /// Fixed size buffer given was too small to hold the line
+    BufferFull,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::LineTooLong { length, max } => {
+                write!(
+                    f,
+                    "line is {length} bytes, longer than the maximum of {max}"
+                )
+            }
+            Error::BufferFull => f.write_str("buffer is too small to hold the line"),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+/// Algorithm used to compute the `*<sum>` appended to sequenced lines
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// XOR of every byte in the line, as used by Marlin and most firmwares
+    #[default]
+    Xor,
+    /// CRC-8 with polynomial 0x07 and zero initial value
+    Crc8,
+    /// No checksum is appended
+    None,
+}
+
+impl ChecksumMode {
+    /// Fold the given bytes into a running checksum
+    fn update(self, mut sum: u8, buf: &[u8]) -> u8 {
+        match self {
+            ChecksumMode::Xor => {
+                for byte in buf {
+                    sum ^= byte;
+                }
+            }
+            ChecksumMode::Crc8 => {
+                for byte in buf {
+                    sum ^= byte;
+                    for _ in 0..8 {
+                        sum = if sum & 0x80 != 0 {
+                            (sum << 1) ^ 0x07
+                        } else {
+                            sum << 1
+                        };
+                    }
+                }
+            }
+            ChecksumMode::None => {}
+        }
+        sum
+    }
+}
+
+/// Reasons a received line fails `verify_checksum`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumError {
+    /// Line has no `*<sum>` suffix
+    Missing,
+    /// Text after the `*` isn't a number from 0 to 255
+    Malformed,
+    /// Checksum in the line doesn't match the one computed from the payload
+    Mismatch { expected: u8, found: u8 },
+}
+
+impl core::fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ChecksumError::Missing => f.write_str("line has no checksum"),
+            ChecksumError::Malformed => f.write_str("checksum is not a number from 0 to 255"),
+            ChecksumError::Mismatch { expected, found } => {
+                write!(f, "checksum is {found}, but the line sums to {expected}")
+            }
+        }
+    }
+}
+
+impl StdError for ChecksumError {}
+
+/// Check the XOR `*<sum>` at the end of a line, like those produced by `Sequenced::serialize`,
+/// giving the payload before the `*` if it matches.
+///
+/// A trailing newline, with or without a carriage return, is ignored.
+pub fn verify_checksum(line: &[u8]) -> Result<&[u8], ChecksumError> {
+    verify_checksum_with(line, ChecksumMode::Xor)
+}
+
+/// Same as `verify_checksum` for lines summed with the given algorithm.
+///
+/// With `ChecksumMode::None` there's nothing to check, so the line is given back
+/// without its newline.
+pub fn verify_checksum_with(line: &[u8], mode: ChecksumMode) -> Result<&[u8], ChecksumError> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if mode == ChecksumMode::None {
+        return Ok(line);
+    }
+    let star = line
+        .iter()
+        .rposition(|&byte| byte == b'*')
+        .ok_or(ChecksumError::Missing)?;
+    let (payload, sum) = (&line[..star], &line[star + 1..]);
+    let found = core::str::from_utf8(sum)
+        .ok()
+        .filter(|sum| sum.bytes().all(|byte| byte.is_ascii_digit()))
+        .and_then(|sum| sum.parse::<u8>().ok())
+        .ok_or(ChecksumError::Malformed)?;
+    let expected = mode.update(0, payload);
+    if found != expected {
+        return Err(ChecksumError::Mismatch { expected, found });
+    }
+    Ok(payload)
+}
+
+/// How the names of struct fields are written in front of their values
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FieldNames {
+    /// Uppercased first character of the name, like `X` for a field `x`, as Marlin expects
+    #[default]
+    FirstLetter,
+    /// The whole name as given, including any `#[serde(rename)]`, directly followed by the value
+    Full,
+    /// The whole name as given, then `=` and the value, separated from the previous word
+    /// by a space, like Klipper's `SET_HEATER_TEMPERATURE HEATER=extruder TARGET=200`
+    KeyValue,
+}
+
 /// An automatically sequenced serializer that can be cloned and sent between threads while guaranteeing strict sequence
+#[cfg(feature = "alloc")]
 #[derive(Debug, Clone)]
 pub struct Sequenced {
     sequence: Arc<Ai32>,
+    checksum: ChecksumMode,
+    max_line_length: Option<usize>,
+    strip_comments: bool,
+    wraps_at: Option<i32>,
+    field_names: FieldNames,
 }
 
+#[cfg(feature = "alloc")]
 impl Default for Sequenced {
     fn default() -> Self {
         Self {
             sequence: Arc::new(SEQUENCE_START.into()),
+            checksum: ChecksumMode::default(),
+            max_line_length: None,
+            strip_comments: false,
+            wraps_at: None,
+            field_names: FieldNames::default(),
         }
     }
 }
 
 /// Serialize anything, provides no sequencing, thus no traceability
+#[cfg(feature = "alloc")]
 pub fn serialize_unsequenced(t: impl Serialize) -> Box<[u8]> {
     let mut line = GcodeLine::new();
-    line.serialize(t);
+    line.serialize(t).expect("Infallible");
     line.finish()
 }
 
+/// Serialize anything into the given buffer without allocating, provides no sequencing.
+///
+/// Returns the number of bytes written, or `Error::BufferFull` if the line doesn't fit.
+pub fn serialize_unsequenced_into(t: impl Serialize, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut line = GcodeLine::from_buffer(FixedBuffer::new(buf), ChecksumMode::default());
+    line.serialize(t)?;
+    line.terminate()
+}
+
+/// Serialize anything into the given buffer without allocating, with the given sequence number
+/// and an XOR checksum. Keeping track of the sequence is left to the caller.
+///
+/// Returns the number of bytes written, or `Error::BufferFull` if the line doesn't fit.
+pub fn serialize_into(sequence: i32, t: impl Serialize, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut line = GcodeLine::from_buffer(FixedBuffer::new(buf), ChecksumMode::default());
+    line.serialize(('N', sequence, t))?;
+    line.terminate_checked(None)
+}
+
+#[cfg(feature = "alloc")]
 impl Sequenced {
     /// Format the given serializable into the internal buffer, then split
     /// off the bytes and return a handle to them.
@@ -36,19 +231,125 @@ impl Sequenced {
     /// Sequence number (N<seq>) and checksum (*<sum>) are automatically handled,
     /// the sequence number of the line is returned with the output for external tracking.
     pub fn serialize(&self, t: impl Serialize) -> (i32, Box<[u8]>) {
-        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
-        let mut line = GcodeLine::new();
-        line.serialize(('N', sequence, t));
-        let bytes = line.finish_with_checksum();
+        let (sequence, _, bytes) = self.serialize_with_wrap(t);
         (sequence, bytes)
     }
 
+    /// Same as `serialize`, additionally reporting if the sequence counter wrapped
+    /// back to the start to number this line, see `next_sequence_wraps_at`.
+    ///
+    /// When `true` is returned, the device needs to be told the line number changed
+    /// (e.g. `M110`) before this line is sent.
+    pub fn serialize_with_wrap(&self, t: impl Serialize) -> (i32, bool, Box<[u8]>) {
+        let (claimed, sequence) = self.next_sequence();
+        let mut line = self.line(Vec::new());
+        line.serialize(('N', sequence, t)).expect("Infallible");
+        let bytes = line.finish_with_checksum();
+        (sequence, claimed != sequence, bytes)
+    }
+
+    /// Like `serialize`, but fails rather than producing a line longer than the
+    /// limit set with `with_max_line_length`.
+    ///
+    /// If the line is rejected, the sequence number it would have used is released
+    /// for the next line, unless another clone has serialized a line in the meantime.
+    pub fn try_serialize(&self, t: impl Serialize) -> Result<(i32, Box<[u8]>), Error> {
+        let mut line = self.line(Vec::new());
+        let sequence = self.serialize_sequenced(t, &mut line)?;
+        Ok((sequence, line.buffer.into_boxed_slice()))
+    }
+
+    /// Format the given serializable into a fixed size buffer without allocating,
+    /// returning the sequence number of the line and the number of bytes written.
+    ///
+    /// Fails with `Error::BufferFull` if the line doesn't fit, and respects the limit
+    /// set with `with_max_line_length`. Rejected lines release their sequence number
+    /// the same way as `try_serialize`.
+    pub fn serialize_into(&self, t: impl Serialize, buf: &mut [u8]) -> Result<(i32, usize), Error> {
+        let mut line = self.line(FixedBuffer::new(buf));
+        let sequence = self.serialize_sequenced(t, &mut line)?;
+        Ok((sequence, line.buffer.len()))
+    }
+
+    /// write a complete sequenced line, releasing the sequence number if it can't be written
+    fn serialize_sequenced<B: LineBuffer>(
+        &self,
+        t: impl Serialize,
+        line: &mut GcodeLine<B>,
+    ) -> Result<i32, Error> {
+        let (claimed, sequence) = self.next_sequence();
+        let written = line
+            .serialize(('N', sequence, t))
+            .and_then(|line| line.terminate_checked(self.max_line_length));
+        if let Err(e) = written {
+            let _ = self.sequence.compare_exchange(
+                sequence + 1,
+                claimed,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+            return Err(e);
+        }
+        Ok(sequence)
+    }
+
     /// Format the given serializable into the internal buffer, then split
     /// off the bytes and return the handle to them.
     ///
     /// No sequnce number or checksum are added, internal state does not change.
     pub fn serialize_unsequenced(&self, t: impl Serialize) -> Box<[u8]> {
-        serialize_unsequenced(t)
+        let mut line = self.line(Vec::new());
+        line.serialize(t).expect("Infallible");
+        line.finish()
+    }
+
+    /// Same as `serialize`, but appends the line to the end of `buf` rather than
+    /// giving it its own allocation, so many lines can be collected in one buffer.
+    ///
+    /// Returns the sequence number of the appended line.
+    pub fn serialize_append(&self, t: impl Serialize, buf: &mut Vec<u8>) -> i32 {
+        let (_, sequence) = self.next_sequence();
+        let mut line = self.line(core::mem::take(buf));
+        line.serialize(('N', sequence, t)).expect("Infallible");
+        line.append_checksum().expect("Infallible");
+        line.terminate().expect("Infallible");
+        *buf = line.buffer;
+        sequence
+    }
+
+    /// Same as `serialize_unsequenced`, but appends the line to the end of `buf`
+    pub fn serialize_unsequenced_append(&self, t: impl Serialize, buf: &mut Vec<u8>) {
+        let mut line = self.line(core::mem::take(buf));
+        line.serialize(t).expect("Infallible");
+        line.terminate().expect("Infallible");
+        *buf = line.buffer;
+    }
+
+    /// Take a sequence number for a new line, giving the raw counter value claimed
+    /// and the sequence number to use, which differ only if the counter wrapped.
+    fn next_sequence(&self) -> (i32, i32) {
+        match self.wraps_at {
+            None => {
+                let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+                (sequence, sequence)
+            }
+            Some(max) => {
+                let wrap = |seq: i32| if seq > max { SEQUENCE_START } else { seq };
+                let (Ok(claimed) | Err(claimed)) =
+                    self.sequence
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |seq| {
+                            Some(wrap(seq) + 1)
+                        });
+                (claimed, wrap(claimed))
+            }
+        }
+    }
+
+    fn line<B: LineBuffer>(&self, buffer: B) -> GcodeLine<B> {
+        let mut line = GcodeLine::from_buffer(buffer, self.checksum);
+        line.strip_comments = self.strip_comments;
+        line.field_names = self.field_names;
+        line
     }
 
     /// Crate a new serializer
@@ -56,6 +357,58 @@ impl Sequenced {
         Default::default()
     }
 
+    /// Use the given checksum algorithm for sequenced lines, XOR is used by default.
+    ///
+    /// With `ChecksumMode::None` lines still carry a sequence number, but no `*<sum>`.
+    pub fn with_checksum(mut self, checksum: ChecksumMode) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Limit the length in bytes of lines produced by `try_serialize`,
+    /// counting the sequence number, checksum and newline.
+    ///
+    /// Marlin's default command buffer holds lines up to 96 bytes.
+    pub fn with_max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = Some(max_line_length);
+        self
+    }
+
+    /// Drop anything after an unescaped `;` in string input before writing and checksumming,
+    /// a `\;` is kept as a plain `;`.
+    ///
+    /// Only applies to strings serialized directly, strings within structs are kept verbatim.
+    /// Disabled by default.
+    pub fn strip_comments(mut self, strip_comments: bool) -> Self {
+        self.strip_comments = strip_comments;
+        self
+    }
+
+    /// Write struct field names the given way, `FieldNames::FirstLetter` by default.
+    pub fn with_field_names(mut self, field_names: FieldNames) -> Self {
+        self.field_names = field_names;
+        self
+    }
+
+    /// Wrap the sequence counter back to `SEQUENCE_START` after a line numbered `max`,
+    /// rather than counting up until overflow.
+    ///
+    /// Use `serialize_with_wrap` to find out when a wrap happens.
+    pub fn next_sequence_wraps_at(mut self, max: i32) -> Self {
+        self.wraps_at = Some(max);
+        self
+    }
+
+    /// Sequence number the next serialized line will be given.
+    /// Lines may be serialized by other clones before this number is used.
+    pub fn current_sequence(&self) -> i32 {
+        let sequence = self.sequence.load(Ordering::SeqCst);
+        match self.wraps_at {
+            Some(max) if sequence > max => SEQUENCE_START,
+            _ => sequence,
+        }
+    }
+
     /// Sets the internal sequence counter to the provided integer.
     /// This also affects all serializers cloned from this instance.
     ///
@@ -70,48 +423,179 @@ impl Sequenced {
     }
 }
 
-#[derive(Debug, Default)]
-struct GcodeLine {
-    buffer: Vec<u8>,
+/// Storage a line can be written into
+trait LineBuffer {
+    /// append bytes, failing if there's no space for them
+    fn put(&mut self, bytes: &[u8]) -> Result<(), core::fmt::Error>;
+    fn len(&self) -> usize;
+}
+
+#[cfg(feature = "alloc")]
+impl LineBuffer for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), core::fmt::Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+}
+
+/// Borrowed fixed size storage for serializing without allocation
+#[derive(Debug)]
+struct FixedBuffer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> FixedBuffer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+}
+
+impl LineBuffer for FixedBuffer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), core::fmt::Error> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[derive(Debug)]
+struct GcodeLine<B> {
+    buffer: B,
     checksum: u8,
+    mode: ChecksumMode,
+    strip_comments: bool,
+    field_names: FieldNames,
+    /// name of the struct field being serialized, written only once the field has a value
+    pending_key: Option<&'static str>,
 }
 
-impl GcodeLine {
-    fn new() -> Self {
+impl<B: LineBuffer> GcodeLine<B> {
+    fn from_buffer(buffer: B, mode: ChecksumMode) -> Self {
         Self {
-            buffer: Vec::new(),
+            buffer,
             checksum: 0,
+            mode,
+            strip_comments: false,
+            field_names: FieldNames::default(),
+            pending_key: None,
         }
     }
     fn checksum(&mut self, buf: &[u8]) {
-        for byte in buf {
-            self.checksum ^= byte;
-        }
+        self.checksum = self.mode.update(self.checksum, buf);
     }
-    fn write(&mut self, buf: &[u8]) {
-        self.buffer.extend_from_slice(buf);
+    fn write(&mut self, buf: &[u8]) -> Result<(), core::fmt::Error> {
+        self.commit_key()?;
+        self.buffer.put(buf)?;
         self.checksum(buf);
+        Ok(())
     }
-    fn serialize(&mut self, t: impl Serialize) -> &mut Self {
-        t.serialize(&mut *self).expect("Infallible");
-        self
+    /// write everything before the first `;` not preceded by a `\`, dropping the `\` of escaped ones
+    fn write_uncommented(&mut self, line: &str) -> Result<(), core::fmt::Error> {
+        let mut escaped = false;
+        let mut start = 0;
+        for (i, c) in line.char_indices() {
+            match c {
+                ';' if escaped => {
+                    self.write(line[start..i - 1].as_bytes())?;
+                    start = i;
+                    escaped = false;
+                }
+                ';' => return self.write(line[start..i].as_bytes()),
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        self.write(line[start..].as_bytes())
+    }
+    /// write out the name of the field being serialized, if it hasn't been already
+    fn commit_key(&mut self) -> Result<(), core::fmt::Error> {
+        let Some(key) = self.pending_key.take() else {
+            return Ok(());
+        };
+        let mut letter = [0; 4];
+        let parts: [&[u8]; 3] = match self.field_names {
+            FieldNames::FirstLetter => {
+                let first = key.chars().next().unwrap_or_default();
+                [
+                    first
+                        .to_ascii_uppercase()
+                        .encode_utf8(&mut letter)
+                        .as_bytes(),
+                    b"",
+                    b"",
+                ]
+            }
+            FieldNames::Full => [key.as_bytes(), b"", b""],
+            FieldNames::KeyValue => [b" ", key.as_bytes(), b"="],
+        };
+        for part in parts {
+            self.buffer.put(part)?;
+            self.checksum(part);
+        }
+        Ok(())
+    }
+    fn serialize(&mut self, t: impl Serialize) -> Result<&mut Self, Error> {
+        t.serialize(&mut *self).map_err(|_| Error::BufferFull)?;
+        Ok(self)
     }
 
-    fn finish_with_checksum(mut self) -> Box<[u8]> {
-        self.buffer.push(b'*');
+    fn append_checksum(&mut self) -> Result<(), Error> {
+        if self.mode == ChecksumMode::None {
+            return Ok(());
+        }
+        self.buffer.put(b"*").map_err(|_| Error::BufferFull)?;
         self.buffer
-            .extend_from_slice(itoa::Buffer::new().format(self.checksum).as_bytes());
+            .put(itoa::Buffer::new().format(self.checksum).as_bytes())
+            .map_err(|_| Error::BufferFull)
+    }
+
+    /// end the line, giving the complete length
+    fn terminate(&mut self) -> Result<usize, Error> {
+        self.buffer.put(b"\n").map_err(|_| Error::BufferFull)?;
+        Ok(self.buffer.len())
+    }
+
+    /// end the line with a checksum, rejecting it if the complete line is longer than `max_length`
+    fn terminate_checked(&mut self, max_length: Option<usize>) -> Result<usize, Error> {
+        self.append_checksum()?;
+        let length = self.terminate()?;
+        match max_length {
+            Some(max) if length > max => Err(Error::LineTooLong { length, max }),
+            _ => Ok(length),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl GcodeLine<Vec<u8>> {
+    fn new() -> Self {
+        Self::from_buffer(Vec::new(), ChecksumMode::default())
+    }
+
+    fn finish_with_checksum(mut self) -> Box<[u8]> {
+        self.append_checksum().expect("Infallible");
         self.finish()
     }
 
     /// finish the current line and give the sequence number of it for tracking, 0 for unsequenced
     fn finish(mut self) -> Box<[u8]> {
-        self.buffer.push(b'\n');
+        self.terminate().expect("Infallible");
         self.buffer.into_boxed_slice()
     }
 }
 
-impl ser::Serializer for &mut GcodeLine {
+impl<B: LineBuffer> ser::Serializer for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -133,96 +617,85 @@ impl ser::Serializer for &mut GcodeLine {
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v as u8).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
         let mut buf = itoa::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
         let mut buf = ryu::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
         let mut buf = ryu::Buffer::new();
         let buf = buf.format(v).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
         let mut buffer = [0; 4];
         let buf = v.encode_utf8(&mut buffer).as_bytes();
-        self.write(buf);
-        Ok(())
+        self.write(buf)
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        let buf = v.as_bytes();
-        self.write(buf);
-        Ok(())
+        if self.strip_comments {
+            self.write_uncommented(v)
+        } else {
+            self.write(v.as_bytes())
+        }
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.write(v);
-        Ok(())
+        self.write(v)
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
@@ -233,11 +706,14 @@ impl ser::Serializer for &mut GcodeLine {
     where
         T: serde::Serialize,
     {
+        // a field that is present is written even if its value is empty, e.g. `Some(())` as a flag
+        self.commit_key()?;
         value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        // a unit field is a bare flag like the `X` in `G28 X`, so only its letter is written
+        self.commit_key()
     }
 
     fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
@@ -312,6 +788,7 @@ impl ser::Serializer for &mut GcodeLine {
         name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
+        self.strip_comments = false;
         name.serialize(&mut *self)?;
         Ok(self)
     }
@@ -327,7 +804,7 @@ impl ser::Serializer for &mut GcodeLine {
     }
 }
 
-impl ser::SerializeSeq for &mut GcodeLine {
+impl<B: LineBuffer> ser::SerializeSeq for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -344,7 +821,7 @@ impl ser::SerializeSeq for &mut GcodeLine {
     }
 }
 
-impl ser::SerializeMap for &mut GcodeLine {
+impl<B: LineBuffer> ser::SerializeMap for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -368,7 +845,7 @@ impl ser::SerializeMap for &mut GcodeLine {
     }
 }
 
-impl ser::SerializeStruct for &mut GcodeLine {
+impl<B: LineBuffer> ser::SerializeStruct for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -381,13 +858,11 @@ impl ser::SerializeStruct for &mut GcodeLine {
     where
         T: Serialize,
     {
-        key.chars()
-            .nth(0)
-            .unwrap()
-            .to_ascii_uppercase()
-            .serialize(&mut **self)
-            .expect("Infallible");
-        value.serialize(&mut **self)
+        // `None` writes nothing, so the letter waits to be written along with the value
+        self.pending_key = Some(key);
+        value.serialize(&mut **self)?;
+        self.pending_key = None;
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -395,7 +870,7 @@ impl ser::SerializeStruct for &mut GcodeLine {
     }
 }
 
-impl ser::SerializeStructVariant for &mut GcodeLine {
+impl<B: LineBuffer> ser::SerializeStructVariant for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -416,7 +891,7 @@ impl ser::SerializeStructVariant for &mut GcodeLine {
     }
 }
 
-impl ser::SerializeTuple for &mut GcodeLine {
+impl<B: LineBuffer> ser::SerializeTuple for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -433,7 +908,7 @@ impl ser::SerializeTuple for &mut GcodeLine {
     }
 }
 
-impl ser::SerializeTupleStruct for &mut GcodeLine {
+impl<B: LineBuffer> ser::SerializeTupleStruct for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -450,7 +925,7 @@ impl ser::SerializeTupleStruct for &mut GcodeLine {
     }
 }
 
-impl ser::SerializeTupleVariant for &mut GcodeLine {
+impl<B: LineBuffer> ser::SerializeTupleVariant for &mut GcodeLine<B> {
     type Ok = ();
 
     type Error = core::fmt::Error;
@@ -467,7 +942,7 @@ impl ser::SerializeTupleVariant for &mut GcodeLine {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod test {
     use super::*;
     use serde::Serialize;
@@ -532,6 +1007,263 @@ mod test {
         assert_eq!(seq, 1001);
     }
 
+    #[test]
+    fn checksum_modes() {
+        let xor = Sequenced::new().with_checksum(ChecksumMode::Xor);
+        assert_eq!(
+            *xor.serialize(G1234 { x: -1, y: 2.3 }).1,
+            *Sequenced::new().serialize(G1234 { x: -1, y: 2.3 }).1
+        );
+        let crc = Sequenced::new().with_checksum(ChecksumMode::Crc8);
+        assert_eq!(
+            *crc.serialize(G1234 { x: -1, y: 2.3 }).1,
+            *b"N1G1234X-1Y2.3*109\n"
+        );
+        let none = Sequenced::new().with_checksum(ChecksumMode::None);
+        assert_eq!(
+            *none.serialize(G1234 { x: -1, y: 2.3 }).1,
+            *b"N1G1234X-1Y2.3\n"
+        );
+    }
+
+    #[test]
+    fn current_sequence() {
+        let sequenced = Sequenced::new();
+        assert_eq!(sequenced.current_sequence(), SEQUENCE_START);
+        sequenced.serialize(M1234);
+        assert_eq!(sequenced.current_sequence(), 2);
+        sequenced.set_sequence(50);
+        assert_eq!(sequenced.clone().current_sequence(), 50);
+    }
+
+    #[test]
+    fn sequence_wrap() {
+        let sequenced = Sequenced::new().next_sequence_wraps_at(3);
+        assert_eq!(sequenced.serialize_with_wrap(M1234).0, 1);
+        assert!(!sequenced.serialize_with_wrap(M1234).1);
+        let (seq, wrapped, _) = sequenced.serialize_with_wrap(M1234);
+        assert_eq!((seq, wrapped), (3, false));
+        assert_eq!(sequenced.current_sequence(), 1);
+        let (seq, wrapped, bytes) = sequenced.serialize_with_wrap(M1234);
+        assert_eq!((seq, wrapped), (1, true));
+        assert_eq!(&bytes[..7], b"N1M1234");
+        let (seq, wrapped, _) = sequenced.serialize_with_wrap(M1234);
+        assert_eq!((seq, wrapped), (2, false));
+
+        sequenced.set_sequence(10);
+        assert_eq!(sequenced.serialize_with_wrap(M1234).0, 1);
+    }
+
+    #[test]
+    fn line_length_limit() {
+        let sequenced = Sequenced::new().with_max_line_length(18);
+        let (seq, bytes) = sequenced.try_serialize(G1234 { x: -1, y: 2.3 }).unwrap();
+        assert_eq!(seq, 1);
+        assert_eq!(*bytes, *b"N1G1234X-1Y2.3*14\n");
+
+        let sequenced = Sequenced::new().with_max_line_length(17);
+        assert_eq!(
+            sequenced.try_serialize(G1234 { x: -1, y: 2.3 }),
+            Err(Error::LineTooLong {
+                length: 18,
+                max: 17
+            })
+        );
+        // rejected line doesn't consume a sequence number
+        let (seq, _) = sequenced.serialize(G1234 { x: -1, y: 2.3 });
+        assert_eq!(seq, 1);
+    }
+
+    #[test]
+    fn comment_stripping() {
+        let sequenced = Sequenced::new();
+        assert_eq!(
+            *sequenced.serialize("G1 X1 ; move").1,
+            *b"N1G1 X1 ; move*106\n"
+        );
+        let sequenced = Sequenced::new().strip_comments(true);
+        assert_eq!(*sequenced.serialize("G1 X1 ; move").1, *b"N1G1 X1 *96\n");
+        assert_eq!(
+            *sequenced.serialize_unsequenced("M117 a\\;b ;c"),
+            *b"M117 a;b \n"
+        );
+        assert_eq!(
+            *sequenced.serialize_unsequenced("M117 \\\\;c"),
+            *b"M117 \\\\\n"
+        );
+    }
+
+    #[test]
+    fn fixed_buffer() {
+        let mut buf = [0u8; 32];
+        let len = serialize_unsequenced_into(G1234 { x: -1, y: 2.3 }, &mut buf).unwrap();
+        assert_eq!(buf[..len], *b"G1234X-1Y2.3\n");
+        let len = serialize_into(1, G1234 { x: -1, y: 2.3 }, &mut buf).unwrap();
+        assert_eq!(buf[..len], *b"N1G1234X-1Y2.3*14\n");
+
+        let mut small = [0u8; 8];
+        assert_eq!(
+            serialize_unsequenced_into(G1234 { x: -1, y: 2.3 }, &mut small),
+            Err(Error::BufferFull)
+        );
+
+        let sequenced = Sequenced::new();
+        let mut buf = [0u8; 17];
+        assert_eq!(
+            sequenced.serialize_into(G1234 { x: -1, y: 2.3 }, &mut buf),
+            Err(Error::BufferFull)
+        );
+        let mut buf = [0u8; 18];
+        assert_eq!(
+            sequenced.serialize_into(G1234 { x: -1, y: 2.3 }, &mut buf),
+            Ok((1, 18))
+        );
+        assert_eq!(buf, *b"N1G1234X-1Y2.3*14\n");
+    }
+
+    #[test]
+    fn append_lines() {
+        let sequenced = Sequenced::new();
+        let mut buf = Vec::new();
+        assert_eq!(
+            sequenced.serialize_append(G1234 { x: -1, y: 2.3 }, &mut buf),
+            1
+        );
+        sequenced.serialize_unsequenced_append(M1234, &mut buf);
+        assert_eq!(
+            sequenced.serialize_append(G1234 { x: -1, y: 2.3 }, &mut buf),
+            2
+        );
+        assert_eq!(
+            buf,
+            b"N1G1234X-1Y2.3*14\nM1234\nN2G1234X-1Y2.3*13\n".as_slice()
+        );
+    }
+
+    #[test]
+    fn optional_fields() {
+        #[derive(Serialize)]
+        struct G1 {
+            x: Option<f32>,
+            y: Option<f32>,
+            z: Option<f32>,
+            e: Option<f32>,
+        }
+        let move_xz = G1 {
+            x: Some(1.5),
+            y: None,
+            z: Some(-2.0),
+            e: None,
+        };
+        assert_eq!(*serialize_unsequenced(move_xz), *b"G1X1.5Z-2.0\n");
+        let nothing = G1 {
+            x: None,
+            y: None,
+            z: None,
+            e: None,
+        };
+        assert_eq!(*serialize_unsequenced(nothing), *b"G1\n");
+
+        #[derive(Serialize)]
+        struct G28 {
+            x: Option<()>,
+            y: Option<()>,
+        }
+        let home_x = G28 {
+            x: Some(()),
+            y: None,
+        };
+        assert_eq!(*serialize_unsequenced(home_x), *b"G28X\n");
+    }
+
+    #[test]
+    fn unit_fields() {
+        #[derive(Serialize)]
+        struct G28 {
+            x: (),
+            z: (),
+        }
+        assert_eq!(*serialize_unsequenced(G28 { x: (), z: () }), *b"G28XZ\n");
+        let sequenced = Sequenced::new();
+        assert_eq!(
+            *sequenced.serialize(G28 { x: (), z: () }).1,
+            *b"N1G28XZ*48\n"
+        );
+    }
+
+    #[test]
+    fn full_field_names() {
+        #[derive(Serialize)]
+        #[serde(rename = "SET_HEATER_TEMPERATURE")]
+        struct SetHeaterTemperature {
+            #[serde(rename = "HEATER")]
+            heater: &'static str,
+            #[serde(rename = "TARGET")]
+            target: Option<u16>,
+        }
+        let set = |target| SetHeaterTemperature {
+            heater: "extruder",
+            target,
+        };
+        let key_value = Sequenced::new().with_field_names(FieldNames::KeyValue);
+        assert_eq!(
+            *key_value.serialize_unsequenced(set(Some(200))),
+            *b"SET_HEATER_TEMPERATURE HEATER=extruder TARGET=200\n"
+        );
+        assert_eq!(
+            *key_value.serialize_unsequenced(set(None)),
+            *b"SET_HEATER_TEMPERATURE HEATER=extruder\n"
+        );
+
+        #[derive(Serialize)]
+        struct M104 {
+            #[serde(rename = "T")]
+            tool: u8,
+            #[serde(rename = "S")]
+            temperature: u16,
+        }
+        let full = Sequenced::new().with_field_names(FieldNames::Full);
+        let m104 = M104 {
+            tool: 0,
+            temperature: 200,
+        };
+        assert_eq!(*full.serialize_unsequenced(m104), *b"M104T0S200\n");
+    }
+
+    #[test]
+    fn checksum_verification() {
+        let sequenced = Sequenced::new();
+        let (_, line) = sequenced.serialize(G1234 { x: -1, y: 2.3 });
+        assert_eq!(*line, *b"N1G1234X-1Y2.3*14\n");
+        assert_eq!(verify_checksum(&line), Ok(b"N1G1234X-1Y2.3".as_slice()));
+        assert_eq!(
+            verify_checksum(b"N3G1234X-1Y2.3*12\r\n"),
+            Ok(b"N3G1234X-1Y2.3".as_slice())
+        );
+        assert_eq!(
+            verify_checksum(b"N1G1234X-1Y2.3*13\n"),
+            Err(ChecksumError::Mismatch {
+                expected: 14,
+                found: 13
+            })
+        );
+        assert_eq!(verify_checksum(b"G1234\n"), Err(ChecksumError::Missing));
+        assert_eq!(verify_checksum(b"G1*256"), Err(ChecksumError::Malformed));
+        assert_eq!(verify_checksum(b"G1*+1"), Err(ChecksumError::Malformed));
+
+        let crc = Sequenced::new().with_checksum(ChecksumMode::Crc8);
+        let (_, line) = crc.serialize(G1234 { x: -1, y: 2.3 });
+        assert_eq!(
+            verify_checksum_with(&line, ChecksumMode::Crc8),
+            Ok(b"N1G1234X-1Y2.3".as_slice())
+        );
+    }
+
+    #[test]
+    fn crc8_check_value() {
+        assert_eq!(ChecksumMode::Crc8.update(0, b"123456789"), 0xF4);
+    }
+
     #[test]
     fn data_model() {
         #[derive(Debug, Default, Serialize)]
@@ -554,7 +1286,7 @@ mod test {
         let mut test = Test::default();
         test.map.insert("test".to_string(), 65535u16);
         assert_eq!(
-            *b"TestT0000A00000Mtest65535MEOne\n",
+            *b"TestT0000A00000Mtest65535EOne\n",
             *serialize_unsequenced(test)
         );
         assert_eq!(*b"0\n", *serialize_unsequenced(TestEnum::Two(0)));